@@ -1,6 +1,9 @@
 use std::str::FromStr;
 
-use tokki_api::{TokkiClient, get_records::GetRecordsRequest, put_record::PutRecordsRequest};
+use tokki_api::{
+    TokkiClient, get_records::GetRecordsRequest, put_record::PutRecordsRequest,
+    topics::CreateTopicRequest,
+};
 use tokki_common::{Offset, Record};
 use url::Url;
 
@@ -11,26 +14,31 @@ async fn main() {
     let client_3 = TokkiClient::new(Url::from_str("http://127.0.0.1:9999").expect("Parse URL"));
 
     client_1
-        .put_record(PutRecordsRequest::single(Record::new("foo", "bar")))
+        .create_topic(CreateTopicRequest::new("test"))
+        .await
+        .expect("Create topic");
+
+    client_1
+        .put_record(PutRecordsRequest::single("test", Record::new("foo", "bar")))
         .await
         .expect("Put record");
 
     let records = client_1
-        .get_records(GetRecordsRequest::new(Offset::new(0), 10))
+        .get_records(GetRecordsRequest::new("test", Offset::new(0), 10))
         .await
         .expect("Get records");
 
     println!("Records: {:?}", records);
 
     let records = client_2
-        .get_records(GetRecordsRequest::new(Offset::new(0), 10))
+        .get_records(GetRecordsRequest::new("test", Offset::new(0), 10))
         .await
         .expect("Get records");
 
     println!("Records: {:?}", records);
 
     let records = client_3
-        .get_records(GetRecordsRequest::new(Offset::new(0), 10))
+        .get_records(GetRecordsRequest::new("test", Offset::new(0), 10))
         .await
         .expect("Get records");
 
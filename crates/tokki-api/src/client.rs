@@ -1,20 +1,35 @@
-use reqwest::{Client, Response, Url};
+use reqwest::{Client, Response, StatusCode, Url};
 use serde::de::DeserializeOwned;
-use snafu::ResultExt;
+use snafu::{OptionExt as _, ResultExt};
+use tokki_common::Offset;
 #[cfg(feature = "clustering")]
 use tokki_common::hmac::HmacForm;
 
 #[cfg(feature = "clustering")]
 use crate::{
     ApiErrorResponse, ClientError,
-    client_error::{JsonParseSnafu, ReqwestSnafu, UrlPathParseSnafu},
-    clustering::{ReplicateLogRequest, ReplicateLogResponse},
-    get_records::{GetRecordsRequest, GetRecordsResponse},
+    client_error::{
+        FrameDecodeSnafu, JsonParseSnafu, MissingHeaderSnafu, ReqwestSnafu, UrlPathParseSnafu,
+    },
+    cluster::MemberRequest,
+    clustering::{
+        LeaderHeartbeatRequest, LeaderHeartbeatResponse, ReplicateLogRequest, ReplicateLogResponse,
+        VoteRequest, VoteResponse,
+    },
+    get_records::{GetRecordsRequest, GetRecordsResponse, NEXT_OFFSET_HEADER},
     put_record::{PutRecordsRequest, PutRecordsResponse},
 };
 use crate::{
+    cluster::{ClusterMembers, InSyncReplicasResponse},
+    get_offset::{GetOffsetForTimestampRequest, GetOffsetForTimestampResponse},
+    groups::{
+        Assignment, CommitOffsetsRequest, CommittedOffsetsResponse, GetCommittedOffsetsRequest,
+        GetGroupLagRequest, GroupDescription, GroupLagResponse, JoinGroupRequest,
+    },
     healthcheck::{HealthcheckRequest, HealthcheckResponse},
     profiling::FinishProfilingResponse,
+    shards::ShardsResponse,
+    topics::{CreateTopicRequest, ListTopicsResponse, TopicDescription},
 };
 
 #[derive(Clone)]
@@ -35,7 +50,7 @@ impl TokkiClient {
         &self.base_url
     }
 
-    fn api_url(&self, path: &'static str) -> Result<Url, ClientError> {
+    fn api_url(&self, path: &str) -> Result<Url, ClientError> {
         self.base_url
             .join(path)
             .with_context(|_| UrlPathParseSnafu {
@@ -44,6 +59,10 @@ impl TokkiClient {
             })
     }
 
+    fn topic_url(&self, topic: &str, path: &str) -> Result<Url, ClientError> {
+        self.api_url(&format!("topics/{topic}/{path}"))
+    }
+
     async fn process_json_response<T: DeserializeOwned>(
         &self,
         res: Response,
@@ -54,17 +73,26 @@ impl TokkiClient {
                 base_url: self.base_url.to_string(),
             })
         } else {
-            tracing::debug!(?res, "Got failure");
-            let response =
-                res.json::<ApiErrorResponse>()
-                    .await
-                    .with_context(|_| JsonParseSnafu {
-                        base_url: self.base_url.to_string(),
-                    })?;
-            Err(ClientError::BadResponse {
-                base_url: self.base_url.to_string(),
-                response,
-            })
+            Err(self.process_error_response(res).await)
+        }
+    }
+
+    async fn process_error_response(&self, res: Response) -> ClientError {
+        tracing::debug!(?res, "Got failure");
+        let status = res.status();
+        let base_url = self.base_url.to_string();
+        let response = match res.json::<ApiErrorResponse>().await {
+            Ok(response) => response,
+            Err(source) => return ClientError::JsonParse { base_url, source },
+        };
+        match status {
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                ClientError::OffsetOutOfRange { base_url, response }
+            }
+            StatusCode::UNPROCESSABLE_ENTITY => ClientError::CorruptRecord { base_url, response },
+            StatusCode::NOT_FOUND => ClientError::NotFound { base_url, response },
+            StatusCode::CONFLICT => ClientError::Conflict { base_url, response },
+            _ => ClientError::BadResponse { base_url, response },
         }
     }
 
@@ -85,11 +113,291 @@ impl TokkiClient {
         self.process_json_response(res).await
     }
 
+    /// Get every topic's partitions and the node leading each of them
+    pub async fn get_shards(&self) -> Result<ShardsResponse, ClientError> {
+        let url = self.api_url("shards")?;
+
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Commit offsets for a consumer group, returning every offset the group
+    /// has committed for the topic
+    pub async fn commit_offsets(
+        &self,
+        req: CommitOffsetsRequest,
+    ) -> Result<CommittedOffsetsResponse, ClientError> {
+        let url = self.api_url(&format!("groups/{}/offsets", req.group))?;
+
+        let res = self
+            .client
+            .put(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Get the offsets a consumer group last committed for a topic, to carry
+    /// on reading from where it left off
+    pub async fn get_committed_offsets(
+        &self,
+        req: GetCommittedOffsetsRequest,
+    ) -> Result<CommittedOffsetsResponse, ClientError> {
+        let url = self.api_url(&format!("groups/{}/offsets", req.group))?;
+
+        let res = self
+            .client
+            .get(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Join a consumer group, getting back the partitions this member is assigned
+    pub async fn join_group(&self, req: JoinGroupRequest) -> Result<Assignment, ClientError> {
+        let url = self.api_url(&format!("groups/{}/members", req.group))?;
+
+        let res = self
+            .client
+            .put(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Keep a member in its group, getting back its current assignment. Fails
+    /// with `ClientError::NotFound` once the member's session has timed out,
+    /// after which it has to join again.
+    pub async fn heartbeat(&self, group: &str, member_id: &str) -> Result<Assignment, ClientError> {
+        let url = self.api_url(&format!("groups/{group}/members/{member_id}/heartbeat"))?;
+
+        let res = self
+            .client
+            .put(url)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Leave a group straight away, rather than waiting for the session to time out
+    pub async fn leave_group(
+        &self,
+        group: &str,
+        member_id: &str,
+    ) -> Result<GroupDescription, ClientError> {
+        let url = self.api_url(&format!("groups/{group}/members/{member_id}"))?;
+
+        let res = self
+            .client
+            .delete(url)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    pub async fn describe_group(&self, group: &str) -> Result<GroupDescription, ClientError> {
+        let url = self.api_url(&format!("groups/{group}"))?;
+
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Get how far a consumer group is behind the end of each partition it reads
+    pub async fn get_group_lag(
+        &self,
+        req: GetGroupLagRequest,
+    ) -> Result<GroupLagResponse, ClientError> {
+        let url = self.api_url(&format!("groups/{}/lag", req.group))?;
+
+        let res = self
+            .client
+            .get(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Get which followers of each partition the leader counts as in sync
+    pub async fn get_in_sync_replicas(&self) -> Result<InSyncReplicasResponse, ClientError> {
+        let url = self.api_url("cluster/in-sync")?;
+
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Get the nodes counted towards the leader's required replicas
+    pub async fn list_members(&self) -> Result<ClusterMembers, ClientError> {
+        let url = self.api_url("cluster/members")?;
+
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Start adding a node to the cluster's members. It is counted once it
+    /// has copied everything committed so far.
+    #[cfg(feature = "clustering")]
+    pub async fn add_member(
+        &self,
+        req: MemberRequest,
+        token: &str,
+    ) -> Result<ClusterMembers, ClientError> {
+        let req = HmacForm::new(req, token);
+
+        let url = self.api_url("cluster/members")?;
+
+        let res = self
+            .client
+            .put(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Start removing a node from the cluster's members
+    #[cfg(feature = "clustering")]
+    pub async fn remove_member(
+        &self,
+        req: MemberRequest,
+        token: &str,
+    ) -> Result<ClusterMembers, ClientError> {
+        let req = HmacForm::new(req, token);
+
+        let url = self.api_url("cluster/members")?;
+
+        let res = self
+            .client
+            .delete(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    pub async fn list_topics(&self) -> Result<ListTopicsResponse, ClientError> {
+        let url = self.api_url("topics")?;
+
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Create a topic, failing with `ClientError::Conflict` if it already exists
+    pub async fn create_topic(
+        &self,
+        req: CreateTopicRequest,
+    ) -> Result<TopicDescription, ClientError> {
+        let url = self.api_url(&format!("topics/{}", req.name))?;
+
+        let res = self
+            .client
+            .put(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Delete a topic along with all of its records
+    pub async fn delete_topic(&self, name: &str) -> Result<TopicDescription, ClientError> {
+        let url = self.api_url(&format!("topics/{name}"))?;
+
+        let res = self
+            .client
+            .delete(url)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
     pub async fn put_record(
         &self,
         req: PutRecordsRequest,
     ) -> Result<PutRecordsResponse, ClientError> {
-        let url = self.api_url("records")?;
+        let url = self.topic_url(&req.topic, "records")?;
 
         let res = self
             .client
@@ -108,7 +416,68 @@ impl TokkiClient {
         &self,
         req: GetRecordsRequest,
     ) -> Result<GetRecordsResponse, ClientError> {
-        let url = self.api_url("records")?;
+        let url = self.topic_url(&req.topic, "records")?;
+
+        let res = self
+            .client
+            .get(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Get records as the frames they are stored in, which spares the server
+    /// from copying them or encoding them as JSON. Whole batches are returned,
+    /// so there may be more than `max_records` records.
+    pub async fn get_record_frames(
+        &self,
+        req: GetRecordsRequest,
+    ) -> Result<GetRecordsResponse, ClientError> {
+        let url = self.topic_url(&req.topic, "records/frames")?;
+
+        let res = self
+            .client
+            .get(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        if !res.status().is_success() {
+            return Err(self.process_error_response(res).await);
+        }
+
+        let next_offset = res
+            .headers()
+            .get(NEXT_OFFSET_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map(Offset)
+            .with_context(|| MissingHeaderSnafu {
+                base_url: self.base_url.to_string(),
+                header: NEXT_OFFSET_HEADER,
+            })?;
+        let body = res.bytes().await.with_context(|_| ReqwestSnafu {
+            base_url: self.base_url.to_string(),
+        })?;
+
+        GetRecordsResponse::from_frames(&body, next_offset).with_context(|_| FrameDecodeSnafu {
+            base_url: self.base_url.to_string(),
+        })
+    }
+
+    pub async fn get_offset_for_timestamp(
+        &self,
+        req: GetOffsetForTimestampRequest,
+    ) -> Result<GetOffsetForTimestampResponse, ClientError> {
+        let url = self.topic_url(&req.topic, "offsets")?;
 
         let res = self
             .client
@@ -148,6 +517,54 @@ impl TokkiClient {
         self.process_json_response(res).await
     }
 
+    /// Ask a node for its vote in a leader election
+    #[cfg(feature = "clustering")]
+    pub async fn request_vote(
+        &self,
+        req: VoteRequest,
+        token: &str,
+    ) -> Result<HmacForm<VoteResponse>, ClientError> {
+        let req = HmacForm::new(req, token);
+
+        let url = self.api_url("election/vote")?;
+
+        let res = self
+            .client
+            .put(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
+    /// Tell a node this one leads, keeping it from starting an election
+    #[cfg(feature = "clustering")]
+    pub async fn send_leader_heartbeat(
+        &self,
+        req: LeaderHeartbeatRequest,
+        token: &str,
+    ) -> Result<HmacForm<LeaderHeartbeatResponse>, ClientError> {
+        let req = HmacForm::new(req, token);
+
+        let url = self.api_url("election/heartbeat")?;
+
+        let res = self
+            .client
+            .put(url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|_| ReqwestSnafu {
+                base_url: self.base_url.to_string(),
+            })?;
+
+        self.process_json_response(res).await
+    }
+
     pub async fn start_profiling(&self) -> Result<FinishProfilingResponse, ClientError> {
         let url = self.api_url("/profiling/start")?;
 
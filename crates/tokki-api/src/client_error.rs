@@ -1,4 +1,5 @@
 use snafu::Snafu;
+use tokki_common::DecodeError;
 
 use crate::ApiErrorResponse;
 
@@ -9,7 +10,7 @@ pub enum ClientError {
     #[snafu(display("Failed to parse URL path from {base_url} joining {path:?}"))]
     UrlPathParse {
         base_url: String,
-        path: &'static str,
+        path: String,
         source: url::ParseError,
     },
     #[snafu(display("Failed to parse JSON from {base_url}"))]
@@ -27,6 +28,36 @@ pub enum ClientError {
         base_url: String,
         response: ApiErrorResponse,
     },
+    #[snafu(display("Offset out of range on {base_url}: {response}"))]
+    OffsetOutOfRange {
+        base_url: String,
+        response: ApiErrorResponse,
+    },
+    #[snafu(display("Corrupt record on {base_url}: {response}"))]
+    CorruptRecord {
+        base_url: String,
+        response: ApiErrorResponse,
+    },
+    #[snafu(display("Not found on {base_url}: {response}"))]
+    NotFound {
+        base_url: String,
+        response: ApiErrorResponse,
+    },
+    #[snafu(display("Conflict on {base_url}: {response}"))]
+    Conflict {
+        base_url: String,
+        response: ApiErrorResponse,
+    },
+    #[snafu(display("Response from {base_url} is missing the {header} header"))]
+    MissingHeader {
+        base_url: String,
+        header: &'static str,
+    },
+    #[snafu(display("Failed to decode frames from {base_url}: {source}"))]
+    FrameDecode {
+        base_url: String,
+        source: DecodeError,
+    },
 }
 
 impl ClientError {
@@ -36,6 +67,12 @@ impl ClientError {
             ClientError::JsonParse { base_url, .. } => base_url,
             ClientError::Reqwest { base_url, .. } => base_url,
             ClientError::BadResponse { base_url, .. } => base_url,
+            ClientError::OffsetOutOfRange { base_url, .. } => base_url,
+            ClientError::CorruptRecord { base_url, .. } => base_url,
+            ClientError::NotFound { base_url, .. } => base_url,
+            ClientError::Conflict { base_url, .. } => base_url,
+            ClientError::MissingHeader { base_url, .. } => base_url,
+            ClientError::FrameDecode { base_url, .. } => base_url,
         }
     }
 }
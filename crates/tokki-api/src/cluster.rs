@@ -0,0 +1,112 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use tokki_common::Offset;
+#[cfg(feature = "clustering")]
+use tokki_common::hmac::{HmacSha256, HmacValue};
+
+/// The nodes whose copies of the log count towards the leader's
+/// `required_replicas`, by URL. Followers polling the leader without being
+/// members copy the log all the same, but are never waited for.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClusterMembers {
+    pub members: BTreeSet<String>,
+    /// The members being changed to, while a change is under way. Until it
+    /// completes records need enough copies among both sets of members.
+    pub joining: Option<BTreeSet<String>>,
+}
+
+impl ClusterMembers {
+    pub fn new(members: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            members: members.into_iter().map(Into::into).collect(),
+            joining: None,
+        }
+    }
+
+    /// Every node that is a member, or is becoming one
+    pub fn all(&self) -> BTreeSet<&str> {
+        self.members
+            .iter()
+            .chain(self.joining.iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[cfg(feature = "clustering")]
+impl HmacValue for ClusterMembers {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        update_set_mac(&self.members, mac);
+        match &self.joining {
+            Some(joining) => {
+                true.update_mac(mac);
+                update_set_mac(joining, mac);
+            }
+            None => false.update_mac(mac),
+        }
+    }
+}
+
+/// Counted so members cannot move between neighbouring sets
+#[cfg(feature = "clustering")]
+fn update_set_mac(set: &BTreeSet<String>, mac: &mut HmacSha256) {
+    (set.len() as u64).update_mac(mac);
+    for member in set {
+        member.update_mac(mac);
+    }
+}
+
+/// Add a node to the cluster's members, or remove one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberRequest {
+    /// URL the node polls the leader as
+    pub url: String,
+}
+
+impl MemberRequest {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+#[cfg(feature = "clustering")]
+impl HmacValue for MemberRequest {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.url.update_mac(mac);
+    }
+}
+
+/// Which followers of each partition the leader counts as in sync
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InSyncReplicasResponse {
+    pub partitions: Vec<PartitionReplicas>,
+}
+
+impl InSyncReplicasResponse {
+    pub fn new(partitions: Vec<PartitionReplicas>) -> Self {
+        Self { partitions }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartitionReplicas {
+    pub topic: String,
+    pub partition: u32,
+    pub high_watermark: Offset,
+    /// Offset after the last record on the leader, committed or not
+    pub log_end: Offset,
+    /// Puts fail while fewer members than this are in sync
+    pub min_insync_replicas: usize,
+    pub replicas: Vec<ReplicaStatus>,
+}
+
+/// A member, or a follower copying the log without being one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaStatus {
+    pub url: String,
+    pub member: bool,
+    pub in_sync: bool,
+    /// Last record the follower reported having, if it has reported any
+    pub max_offset: Option<Offset>,
+}
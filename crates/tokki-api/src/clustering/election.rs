@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use tokki_common::{
+    Offset,
+    hmac::{HmacSha256, HmacValue},
+};
+
+use crate::cluster::ClusterMembers;
+
+/// How far a node's copy of one partition goes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionLog {
+    pub topic: String,
+    pub partition: u32,
+    /// Leader epoch of the last record in the log
+    pub last_epoch: Option<u64>,
+    pub log_end: Offset,
+}
+
+impl PartitionLog {
+    pub fn new(
+        topic: impl Into<String>,
+        partition: u32,
+        last_epoch: Option<u64>,
+        log_end: Offset,
+    ) -> Self {
+        Self {
+            topic: topic.into(),
+            partition,
+            last_epoch,
+            log_end,
+        }
+    }
+
+    /// Whether this log has everything `other` has. The later last epoch
+    /// wins, and the longer log within the same one.
+    pub fn up_to_date_with(&self, other: &PartitionLog) -> bool {
+        (self.last_epoch, self.log_end) >= (other.last_epoch, other.log_end)
+    }
+}
+
+impl HmacValue for PartitionLog {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.topic.update_mac(mac);
+        self.partition.update_mac(mac);
+        self.last_epoch.update_mac(mac);
+        self.log_end.update_mac(mac);
+    }
+}
+
+/// A candidate asking for a node's vote to lead in `term`. Nodes only vote
+/// for candidates whose logs are at least as up to date as their own on
+/// every partition.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate_url: String,
+    /// The candidate's partitions, any it does not have are taken to be empty
+    pub logs: Vec<PartitionLog>,
+}
+
+impl VoteRequest {
+    pub fn new(term: u64, candidate_url: String, logs: Vec<PartitionLog>) -> Self {
+        Self {
+            term,
+            candidate_url,
+            logs,
+        }
+    }
+}
+
+impl HmacValue for VoteRequest {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.term.update_mac(mac);
+        self.candidate_url.update_mac(mac);
+        for log in &self.logs {
+            log.update_mac(mac);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VoteResponse {
+    /// Term of the node voting, so a candidate behind the times steps down
+    pub term: u64,
+    pub vote_granted: bool,
+}
+
+impl VoteResponse {
+    pub fn new(term: u64, vote_granted: bool) -> Self {
+        Self { term, vote_granted }
+    }
+}
+
+impl HmacValue for VoteResponse {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.term.update_mac(mac);
+        self.vote_granted.update_mac(mac);
+    }
+}
+
+/// Sent by the leader of `term` to every other node, which follow it and hold
+/// off starting an election for as long as they keep hearing from it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaderHeartbeatRequest {
+    pub term: u64,
+    pub leader_url: String,
+    /// The leader's cluster members, kept by every node so whichever leads
+    /// next counts the same followers
+    pub members: ClusterMembers,
+}
+
+impl LeaderHeartbeatRequest {
+    pub fn new(term: u64, leader_url: String, members: ClusterMembers) -> Self {
+        Self {
+            term,
+            leader_url,
+            members,
+        }
+    }
+}
+
+impl HmacValue for LeaderHeartbeatRequest {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.term.update_mac(mac);
+        self.leader_url.update_mac(mac);
+        self.members.update_mac(mac);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaderHeartbeatResponse {
+    /// Term of the node heartbeated, so a leader behind the times steps down
+    pub term: u64,
+    pub accepted: bool,
+}
+
+impl LeaderHeartbeatResponse {
+    pub fn new(term: u64, accepted: bool) -> Self {
+        Self { term, accepted }
+    }
+}
+
+impl HmacValue for LeaderHeartbeatResponse {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.term.update_mac(mac);
+        self.accepted.update_mac(mac);
+    }
+}
@@ -1,5 +1,11 @@
 //! Code that is only used with the clustering feature turned on
 
+mod election;
 mod replicate_log;
 
-pub use replicate_log::{ReplicateLogRequest, ReplicateLogResponse};
+pub use election::{
+    LeaderHeartbeatRequest, LeaderHeartbeatResponse, PartitionLog, VoteRequest, VoteResponse,
+};
+pub use replicate_log::{
+    Divergence, LeaderEpoch, ReplicateLogRequest, ReplicateLogResponse, ReplicatedBatch,
+};
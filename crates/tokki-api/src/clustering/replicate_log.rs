@@ -1,20 +1,34 @@
 use serde::{Deserialize, Serialize};
 use tokki_common::{
-    Offset, Record,
+    Offset, RecordBatch,
     hmac::{HmacSha256, HmacValue},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ReplicateLogRequest {
     pub follower_url: String,
+    pub topic: String,
+    pub partition: u32,
     pub max_acknowledged_offset: Option<Offset>,
+    /// Leader epoch of the follower's last record, which the leader checks
+    /// against its own log before sending anything more
+    pub last_epoch: Option<u64>,
 }
 
 impl ReplicateLogRequest {
-    pub fn new(follower_url: String, max_acknowledged_offset: Option<Offset>) -> Self {
+    pub fn new(
+        follower_url: String,
+        topic: String,
+        partition: u32,
+        max_acknowledged_offset: Option<Offset>,
+        last_epoch: Option<u64>,
+    ) -> Self {
         Self {
             follower_url,
+            topic,
+            partition,
             max_acknowledged_offset,
+            last_epoch,
         }
     }
 }
@@ -22,25 +36,144 @@ impl ReplicateLogRequest {
 impl HmacValue for ReplicateLogRequest {
     fn update_mac(&self, mac: &mut HmacSha256) {
         self.follower_url.update_mac(mac);
+        self.topic.update_mac(mac);
+        self.partition.update_mac(mac);
         self.max_acknowledged_offset.update_mac(mac);
+        self.last_epoch.update_mac(mac);
+    }
+}
+
+/// Records from `start_offset` on were first written by the leader of `epoch`,
+/// up to where the next epoch starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderEpoch {
+    pub epoch: u64,
+    pub start_offset: Offset,
+}
+
+impl LeaderEpoch {
+    pub fn new(epoch: u64, start_offset: Offset) -> Self {
+        Self {
+            epoch,
+            start_offset,
+        }
+    }
+}
+
+impl HmacValue for LeaderEpoch {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.epoch.update_mac(mac);
+        self.start_offset.update_mac(mac);
+    }
+}
+
+/// The follower's log holds records the leader does not have. The latest
+/// epoch the leader has up to the follower's, if any, ends at `end_offset`
+/// on the leader, and nothing from there on can be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Divergence {
+    pub epoch: Option<u64>,
+    pub end_offset: Offset,
+}
+
+impl Divergence {
+    pub fn new(epoch: Option<u64>, end_offset: Offset) -> Self {
+        Self { epoch, end_offset }
+    }
+}
+
+impl HmacValue for Divergence {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.epoch.update_mac(mac);
+        self.end_offset.update_mac(mac);
+    }
+}
+
+/// A batch as the leader stored it, still compressed, along with the offset
+/// of its first record. Records in a batch have consecutive offsets, but
+/// compaction leaves gaps between batches which followers keep.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplicatedBatch {
+    pub base_offset: Offset,
+    pub batch: RecordBatch,
+}
+
+impl ReplicatedBatch {
+    pub fn new(base_offset: Offset, batch: RecordBatch) -> Self {
+        Self { base_offset, batch }
+    }
+}
+
+impl HmacValue for ReplicatedBatch {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        self.base_offset.update_mac(mac);
+        self.batch.update_mac(mac);
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ReplicateLogResponse {
-    pub records: Vec<Record>,
+    pub batches: Vec<ReplicatedBatch>,
+    /// Offset after the last record committed on the leader, which followers
+    /// cap their own reads at
+    pub high_watermark: Offset,
+    /// Epochs of the records in `batches`, starting with the one the first of
+    /// them belongs to
+    pub epochs: Vec<LeaderEpoch>,
+    /// Set instead of sending batches when the follower has to truncate its
+    /// log before it can carry on copying
+    pub divergence: Option<Divergence>,
+    /// Set instead of sending batches when the records the follower needs
+    /// next have been removed, so it has to start its log over from here
+    pub log_start_offset: Option<Offset>,
 }
 
 impl ReplicateLogResponse {
-    pub fn new(records: Vec<Record>) -> Self {
-        Self { records }
+    pub fn new(
+        batches: Vec<ReplicatedBatch>,
+        high_watermark: Offset,
+        epochs: Vec<LeaderEpoch>,
+    ) -> Self {
+        Self {
+            batches,
+            high_watermark,
+            epochs,
+            divergence: None,
+            log_start_offset: None,
+        }
+    }
+
+    pub fn diverged(divergence: Divergence, high_watermark: Offset) -> Self {
+        Self {
+            batches: Vec::new(),
+            high_watermark,
+            epochs: Vec::new(),
+            divergence: Some(divergence),
+            log_start_offset: None,
+        }
+    }
+
+    pub fn out_of_range(log_start_offset: Offset, high_watermark: Offset) -> Self {
+        Self {
+            batches: Vec::new(),
+            high_watermark,
+            epochs: Vec::new(),
+            divergence: None,
+            log_start_offset: Some(log_start_offset),
+        }
     }
 }
 
 impl HmacValue for ReplicateLogResponse {
     fn update_mac(&self, mac: &mut HmacSha256) {
-        for record in &self.records {
-            record.update_mac(mac);
+        self.high_watermark.update_mac(mac);
+        for batch in &self.batches {
+            batch.update_mac(mac);
+        }
+        for epoch in &self.epochs {
+            epoch.update_mac(mac);
         }
+        self.divergence.update_mac(mac);
+        self.log_start_offset.update_mac(mac);
     }
 }
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use tokki_common::{Offset, Timestamp};
+
+#[derive(Serialize, Deserialize)]
+pub struct GetOffsetForTimestampRequest {
+    /// Topic to search, sent as part of the URL path
+    #[serde(skip)]
+    pub topic: String,
+    #[serde(default)]
+    pub partition: u32,
+    pub timestamp: Timestamp,
+}
+
+impl GetOffsetForTimestampRequest {
+    pub fn new(topic: impl Into<String>, timestamp: Timestamp) -> Self {
+        Self {
+            topic: topic.into(),
+            partition: 0,
+            timestamp,
+        }
+    }
+
+    pub fn with_partition(mut self, partition: u32) -> Self {
+        self.partition = partition;
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetOffsetForTimestampResponse {
+    /// The first offset with a timestamp at or after the one requested, `None`
+    /// if every record is older
+    pub offset: Option<Offset>,
+}
+
+impl GetOffsetForTimestampResponse {
+    pub fn new(offset: Option<Offset>) -> Self {
+        Self { offset }
+    }
+}
@@ -1,20 +1,65 @@
 use serde::{Deserialize, Serialize};
-use tokki_common::{Offset, Record};
+use tokki_common::{DecodeError, Frame, Offset, Record};
+
+/// Content type of `/topics/{name}/records/frames` responses, whose body is stored frames back to back
+pub const FRAMES_CONTENT_TYPE: &str = "application/vnd.tokki.frames";
+/// Header on `/topics/{name}/records/frames` responses holding the offset to continue from
+pub const NEXT_OFFSET_HEADER: &str = "tokki-next-offset";
+
+/// Which records a read can see
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationLevel {
+    /// Only records that reached as many followers as are required, which
+    /// stay in the log if the leader is lost
+    #[default]
+    ReadCommitted,
+    /// Everything the node has, including records whose put may yet fail
+    ReadUncommitted,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct GetRecordsRequest {
+    /// Topic the records are read from, sent as part of the URL path
+    #[serde(skip)]
+    pub topic: String,
+    #[serde(default)]
+    pub partition: u32,
     pub offset: Offset,
     pub max_records: usize,
+    #[serde(default)]
+    pub isolation: IsolationLevel,
 }
 
 impl GetRecordsRequest {
-    pub fn new(offset: Offset, max_records: usize) -> Self {
+    pub fn new(topic: impl Into<String>, offset: Offset, max_records: usize) -> Self {
         Self {
+            topic: topic.into(),
+            partition: 0,
             offset,
             max_records,
+            isolation: IsolationLevel::default(),
         }
     }
 
+    pub fn with_isolation(mut self, isolation: IsolationLevel) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    pub fn with_partition(mut self, partition: u32) -> Self {
+        self.partition = partition;
+        self
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn partition(&self) -> u32 {
+        self.partition
+    }
+
     pub fn offset(&self) -> Offset {
         self.offset
     }
@@ -22,6 +67,10 @@ impl GetRecordsRequest {
     pub fn max_records(&self) -> usize {
         self.max_records
     }
+
+    pub fn isolation(&self) -> IsolationLevel {
+        self.isolation
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +87,26 @@ impl GetRecordsResponse {
         }
     }
 
+    /// Decode a `/topics/{name}/records/frames` response body, unpacking any batches
+    pub fn from_frames(mut buf: &[u8], next_offset: Offset) -> Result<Self, DecodeError> {
+        let mut records = Vec::new();
+        while !buf.is_empty() {
+            let (frame, len) = Frame::from_bytes(buf)?;
+            match frame {
+                Frame::Record(record) => records.push(record),
+                Frame::Batch(batch) => records.extend(batch.records()?),
+                Frame::Encrypted(batch) => {
+                    return Err(DecodeError::UnexpectedEncrypted {
+                        key_id: batch.key_id(),
+                    });
+                }
+            }
+            buf = &buf[len..];
+        }
+
+        Ok(Self::new(records, next_offset))
+    }
+
     pub fn records(&self) -> &[Record] {
         &self.records
     }
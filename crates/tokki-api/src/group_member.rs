@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{
+    ClientError, TokkiClient,
+    groups::{Assignment, JoinGroupRequest},
+};
+
+/// A member of a consumer group, kept in the group by heartbeats sent in the
+/// background. Rebalances show up as a new assignment, which `changed` waits for.
+pub struct GroupMember {
+    client: TokkiClient,
+    group: String,
+    assignment: watch::Receiver<Assignment>,
+    heartbeat_task: JoinHandle<()>,
+}
+
+impl GroupMember {
+    /// Join the group, heartbeating a few times per session timeout from then
+    /// on. A member whose session times out anyway joins again under the same id.
+    pub async fn join(client: TokkiClient, req: JoinGroupRequest) -> Result<Self, ClientError> {
+        let assignment = client.join_group(req.clone()).await?;
+        let (assignment_tx, assignment_rx) = watch::channel(assignment);
+
+        let group = req.group.clone();
+        let heartbeat_task = tokio::task::spawn({
+            let client = client.clone();
+            let interval = Duration::from_millis((req.session_timeout_ms / 3).max(1));
+            async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+
+                    let member_id = assignment_tx.borrow().member_id.clone();
+                    let res = match client.heartbeat(&req.group, &member_id).await {
+                        Err(ClientError::NotFound { .. }) => {
+                            tracing::info!(
+                                group = req.group,
+                                member_id,
+                                "Session expired, rejoining"
+                            );
+                            let req = req.clone().with_member_id(member_id);
+                            client.join_group(req).await
+                        }
+                        res => res,
+                    };
+
+                    match res {
+                        Ok(assignment) => {
+                            assignment_tx.send_if_modified(|current| {
+                                let changed = *current != assignment;
+                                *current = assignment;
+                                changed
+                            });
+                        }
+                        Err(e) => tracing::warn!(group = req.group, "Failed to heartbeat: {}", e),
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            client,
+            group,
+            assignment: assignment_rx,
+            heartbeat_task,
+        })
+    }
+
+    pub fn member_id(&self) -> String {
+        self.assignment.borrow().member_id.clone()
+    }
+
+    /// The partitions this member currently consumes
+    pub fn assignment(&self) -> Assignment {
+        self.assignment.borrow().clone()
+    }
+
+    /// Wait for the next rebalance to change this member's assignment, returning the new one
+    pub async fn changed(&mut self) -> Assignment {
+        self.assignment
+            .changed()
+            .await
+            .expect("Heartbeats run until the member is dropped");
+        self.assignment.borrow_and_update().clone()
+    }
+
+    /// Leave the group straight away, so the rest of the group rebalances
+    /// without waiting for this member's session to time out
+    pub async fn leave(self) -> Result<(), ClientError> {
+        self.heartbeat_task.abort();
+        self.client
+            .leave_group(&self.group, &self.member_id())
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for GroupMember {
+    fn drop(&mut self) {
+        self.heartbeat_task.abort();
+    }
+}
@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokki_common::Offset;
+
+/// The offset a group has committed for one partition of a topic. This is the
+/// next offset the group reads, so one past the last record it processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionOffset {
+    pub partition: u32,
+    pub offset: Offset,
+}
+
+impl PartitionOffset {
+    pub fn new(partition: u32, offset: Offset) -> Self {
+        Self { partition, offset }
+    }
+}
+
+/// Commit offsets for partitions of a topic on behalf of a consumer group.
+/// The offsets are committed together, or not at all.
+#[derive(Serialize, Deserialize)]
+pub struct CommitOffsetsRequest {
+    /// Group committing, sent as part of the URL path
+    #[serde(skip)]
+    pub group: String,
+    pub topic: String,
+    pub offsets: Vec<PartitionOffset>,
+}
+
+impl CommitOffsetsRequest {
+    pub fn new(
+        group: impl Into<String>,
+        topic: impl Into<String>,
+        offsets: Vec<PartitionOffset>,
+    ) -> Self {
+        Self {
+            group: group.into(),
+            topic: topic.into(),
+            offsets,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetCommittedOffsetsRequest {
+    /// Group to look up, sent as part of the URL path
+    #[serde(skip)]
+    pub group: String,
+    pub topic: String,
+}
+
+impl GetCommittedOffsetsRequest {
+    pub fn new(group: impl Into<String>, topic: impl Into<String>) -> Self {
+        Self {
+            group: group.into(),
+            topic: topic.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommittedOffsetsResponse {
+    pub topic: String,
+    /// Every partition the group has committed an offset for, in partition
+    /// order. Partitions without one are left out.
+    pub offsets: Vec<PartitionOffset>,
+}
+
+impl CommittedOffsetsResponse {
+    pub fn new(topic: impl Into<String>, offsets: Vec<PartitionOffset>) -> Self {
+        Self {
+            topic: topic.into(),
+            offsets,
+        }
+    }
+
+    /// The offset committed for `partition`, if there is one
+    pub fn offset(&self, partition: u32) -> Option<Offset> {
+        self.offsets
+            .iter()
+            .find(|offset| offset.partition == partition)
+            .map(|offset| offset.offset)
+    }
+}
+
+/// How the leader splits a topic's partitions among a group's members
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignmentStrategy {
+    /// Each member gets a run of neighbouring partitions
+    #[default]
+    Range,
+    /// Partitions are dealt out to members in turn
+    RoundRobin,
+    /// Members keep the partitions they had before a rebalance wherever the
+    /// split stays even, so as few partitions as possible change hands
+    Sticky,
+}
+
+/// Join a group consuming a topic. Every member of a group consumes the same
+/// topic with the same strategy.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JoinGroupRequest {
+    /// Group to join, sent as part of the URL path
+    #[serde(skip)]
+    pub group: String,
+    pub topic: String,
+    /// Id of a member rejoining, new members are given one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member_id: Option<String>,
+    #[serde(default)]
+    pub strategy: AssignmentStrategy,
+    /// How long the member stays in the group without a heartbeat
+    pub session_timeout_ms: u64,
+}
+
+impl JoinGroupRequest {
+    pub fn new(group: impl Into<String>, topic: impl Into<String>) -> Self {
+        Self {
+            group: group.into(),
+            topic: topic.into(),
+            member_id: None,
+            strategy: AssignmentStrategy::default(),
+            session_timeout_ms: 10_000,
+        }
+    }
+
+    pub fn with_member_id(mut self, member_id: impl Into<String>) -> Self {
+        self.member_id = Some(member_id.into());
+        self
+    }
+
+    pub fn with_strategy(mut self, strategy: AssignmentStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn with_session_timeout(mut self, session_timeout: Duration) -> Self {
+        self.session_timeout_ms = session_timeout.as_millis() as u64;
+        self
+    }
+}
+
+/// The partitions a member consumes. The generation goes up with every
+/// rebalance, so a member seeing a new one knows its partitions may have changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assignment {
+    pub member_id: String,
+    pub generation: u64,
+    pub partitions: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberAssignment {
+    pub member_id: String,
+    pub partitions: Vec<u32>,
+}
+
+/// A group's members and what each of them is assigned
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupDescription {
+    pub group: String,
+    pub topic: String,
+    pub strategy: AssignmentStrategy,
+    pub generation: u64,
+    /// Ordered by member id
+    pub members: Vec<MemberAssignment>,
+}
+
+/// Get how far a group is behind. Without a topic, every topic the group has
+/// committed offsets for is reported.
+#[derive(Serialize, Deserialize)]
+pub struct GetGroupLagRequest {
+    /// Group to look up, sent as part of the URL path
+    #[serde(skip)]
+    pub group: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
+
+impl GetGroupLagRequest {
+    pub fn new(group: impl Into<String>) -> Self {
+        Self {
+            group: group.into(),
+            topic: None,
+        }
+    }
+
+    pub fn with_topic(mut self, topic: impl Into<String>) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+}
+
+/// How far a group is behind on one partition of a topic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionLag {
+    pub topic: String,
+    pub partition: u32,
+    /// Offset the next record put on the partition gets
+    pub log_end_offset: Offset,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committed_offset: Option<Offset>,
+    /// Records between the committed offset and the end of the log, unknown
+    /// until the group commits an offset for the partition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lag: Option<u64>,
+}
+
+impl PartitionLag {
+    pub fn new(
+        topic: impl Into<String>,
+        partition: u32,
+        log_end_offset: Offset,
+        committed_offset: Option<Offset>,
+    ) -> Self {
+        let lag =
+            committed_offset.map(|committed| log_end_offset.0.saturating_sub(committed.0) as u64);
+        Self {
+            topic: topic.into(),
+            partition,
+            log_end_offset,
+            committed_offset,
+            lag,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupLagResponse {
+    pub group: String,
+    /// Ordered by topic, then partition
+    pub partitions: Vec<PartitionLag>,
+}
+
+impl GroupLagResponse {
+    pub fn new(group: impl Into<String>, partitions: Vec<PartitionLag>) -> Self {
+        Self {
+            group: group.into(),
+            partitions,
+        }
+    }
+
+    /// Lag summed over every partition with a committed offset
+    pub fn total_lag(&self) -> u64 {
+        self.partitions
+            .iter()
+            .filter_map(|partition| partition.lag)
+            .sum()
+    }
+}
@@ -1,12 +1,18 @@
 mod api_error_response;
 mod client;
 mod client_error;
+pub mod cluster;
 #[cfg(feature = "clustering")]
 pub mod clustering;
+pub mod get_offset;
 pub mod get_records;
+pub mod group_member;
+pub mod groups;
 pub mod healthcheck;
 pub mod profiling;
 pub mod put_record;
+pub mod shards;
+pub mod topics;
 
 pub use api_error_response::ApiErrorResponse;
 pub use client::TokkiClient;
@@ -1,31 +1,72 @@
 use serde::{Deserialize, Serialize};
-use tokki_common::{Offset, Record};
+use tokki_common::{Compression, Offset, Record};
 
 #[derive(Serialize, Deserialize)]
 pub struct PutRecordsRequest {
+    /// Topic the records are put on, sent as part of the URL path
+    #[serde(skip)]
+    pub topic: String,
     pub records: Vec<Record>,
+    /// Put every record on this partition, rather than on the one its key hashes to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition: Option<u32>,
+    /// How the leader compresses the records as a batch in its log
+    #[serde(default)]
+    pub compression: Compression,
 }
 
 impl PutRecordsRequest {
-    pub fn single(record: Record) -> Self {
+    pub fn single(topic: impl Into<String>, record: Record) -> Self {
+        Self::new(topic, vec![record])
+    }
+
+    pub fn new(topic: impl Into<String>, records: Vec<Record>) -> Self {
         Self {
-            records: vec![record],
+            topic: topic.into(),
+            records,
+            partition: None,
+            compression: Compression::None,
         }
     }
 
-    pub fn new(records: Vec<Record>) -> Self {
-        Self { records }
+    pub fn with_partition(mut self, partition: u32) -> Self {
+        self.partition = Some(partition);
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PutRecordsResponse {
+/// Where the records of a put landed, for one partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionOffsets {
+    pub partition: u32,
+    /// Offset of the first record put on the partition
     pub offset: Offset,
     pub len: usize,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PutRecordsResponse {
+    /// Every partition the records were put on, in partition order. Records
+    /// on the same partition keep the order they were sent in.
+    pub partitions: Vec<PartitionOffsets>,
+}
+
 impl PutRecordsResponse {
-    pub fn new(offset: Offset, len: usize) -> Self {
-        Self { offset, len }
+    pub fn new(partitions: Vec<PartitionOffsets>) -> Self {
+        Self { partitions }
+    }
+
+    /// Number of records put across every partition
+    pub fn len(&self) -> usize {
+        self.partitions.iter().map(|offsets| offsets.len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
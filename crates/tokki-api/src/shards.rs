@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// How every topic is split into partitions, and where each partition is led from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShardsResponse {
+    pub topics: Vec<TopicShards>,
+}
+
+impl ShardsResponse {
+    pub fn new(topics: Vec<TopicShards>) -> Self {
+        Self { topics }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopicShards {
+    pub name: String,
+    pub partitions: Vec<PartitionShard>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartitionShard {
+    pub partition: u32,
+    /// URL of the node taking puts for the partition, `None` if the node
+    /// answering does not know it
+    pub leader: Option<String>,
+}
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+/// Settings a topic is created with, kept for as long as the topic exists.
+/// Unset settings fall back to the server's defaults when the topic is created.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicConfig {
+    /// Number of partitions records are spread across, fixed when the topic is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partitions: Option<u32>,
+    /// Remove records older than this many seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_max_age_secs: Option<u64>,
+    /// Remove the oldest records once the log is larger than this many bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_max_bytes: Option<u64>,
+    /// Remove the oldest records once the log holds more than this many records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_max_records: Option<usize>,
+    /// Compact the log, keeping only the latest record for each key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compaction: Option<bool>,
+    /// How long compaction keeps tombstones, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tombstone_retention_secs: Option<u64>,
+}
+
+impl TopicConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_partitions(mut self, partitions: u32) -> Self {
+        self.partitions = Some(partitions);
+        self
+    }
+
+    pub fn with_retention_max_age_secs(mut self, retention_max_age_secs: u64) -> Self {
+        self.retention_max_age_secs = Some(retention_max_age_secs);
+        self
+    }
+
+    pub fn with_retention_max_bytes(mut self, retention_max_bytes: u64) -> Self {
+        self.retention_max_bytes = Some(retention_max_bytes);
+        self
+    }
+
+    pub fn with_retention_max_records(mut self, retention_max_records: usize) -> Self {
+        self.retention_max_records = Some(retention_max_records);
+        self
+    }
+
+    pub fn with_compaction(mut self, compaction: bool) -> Self {
+        self.compaction = Some(compaction);
+        self
+    }
+
+    pub fn with_tombstone_retention_secs(mut self, tombstone_retention_secs: u64) -> Self {
+        self.tombstone_retention_secs = Some(tombstone_retention_secs);
+        self
+    }
+
+    /// Fill in anything left unset from `defaults`
+    pub fn with_defaults(self, defaults: &TopicConfig) -> Self {
+        Self {
+            partitions: self.partitions.or(defaults.partitions),
+            retention_max_age_secs: self
+                .retention_max_age_secs
+                .or(defaults.retention_max_age_secs),
+            retention_max_bytes: self.retention_max_bytes.or(defaults.retention_max_bytes),
+            retention_max_records: self
+                .retention_max_records
+                .or(defaults.retention_max_records),
+            compaction: self.compaction.or(defaults.compaction),
+            tombstone_retention_secs: self
+                .tombstone_retention_secs
+                .or(defaults.tombstone_retention_secs),
+        }
+    }
+
+    /// Number of partitions, a topic has one unless it says otherwise
+    pub fn partition_count(&self) -> u32 {
+        self.partitions.unwrap_or(1)
+    }
+}
+
+/// A topic as the server knows it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicDescription {
+    pub name: String,
+    /// Picked at random when the topic is created, telling apart a topic from
+    /// one deleted before it under the same name
+    pub id: u64,
+    pub config: TopicConfig,
+}
+
+impl TopicDescription {
+    pub fn new(name: impl Into<String>, id: u64, config: TopicConfig) -> Self {
+        Self {
+            name: name.into(),
+            id,
+            config,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateTopicRequest {
+    /// Sent as part of the URL path
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub config: TopicConfig,
+}
+
+impl CreateTopicRequest {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            config: TopicConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: TopicConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListTopicsResponse {
+    pub topics: Vec<TopicDescription>,
+}
+
+impl ListTopicsResponse {
+    pub fn new(topics: Vec<TopicDescription>) -> Self {
+        Self { topics }
+    }
+}
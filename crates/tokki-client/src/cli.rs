@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use tokki_common::Compression;
 use url::Url;
 
 #[derive(Parser)]
@@ -13,11 +14,89 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum CliCommand {
     LoadTest {
+        /// Topic to write to, created if it doesn't exist
+        #[arg(short, long, default_value = "load-test")]
+        topic: String,
         /// The number of messages to send
         #[arg(short, long)]
         count: usize,
         /// The number of messges per batch
         #[arg(short, long)]
         batch_size: usize,
+        /// How the leader compresses each batch
+        #[arg(long, value_enum, default_value = "none")]
+        compression: CliCompression,
     },
+    /// List every topic along with its settings
+    ListTopics,
+    /// Create a topic, leaving unset settings to the server's defaults
+    CreateTopic {
+        name: String,
+        /// Number of partitions records are spread across by key
+        #[arg(long)]
+        partitions: Option<u32>,
+        /// Remove records older than this many seconds
+        #[arg(long)]
+        retention_max_age_secs: Option<u64>,
+        /// Remove the oldest records once the topic is larger than this many bytes
+        #[arg(long)]
+        retention_max_bytes: Option<u64>,
+        /// Remove the oldest records once the topic holds more than this many records
+        #[arg(long)]
+        retention_max_records: Option<usize>,
+        /// Keep only the latest record for each key
+        #[arg(long)]
+        compaction: Option<bool>,
+        /// How long compaction keeps tombstones, in seconds
+        #[arg(long)]
+        tombstone_retention_secs: Option<u64>,
+    },
+    /// Delete a topic and all of its records
+    DeleteTopic { name: String },
+    /// Show every topic's partitions and the node leading each of them
+    Shards,
+    /// Show how far a consumer group is behind on each partition it reads
+    Lag {
+        group: String,
+        /// Only show this topic, rather than every topic the group committed offsets for
+        #[arg(long)]
+        topic: Option<String>,
+    },
+    /// Show how far each follower of every partition has got, and which are in sync
+    InSync,
+    /// List the followers counted towards the leader's required replicas
+    ListMembers,
+    /// Count a follower towards the required replicas once it has caught up
+    AddMember {
+        /// URL the follower polls the leader as
+        url: Url,
+        /// The cluster's token, which membership changes are signed with
+        #[arg(short, long)]
+        token: String,
+    },
+    /// Stop counting a follower towards the required replicas
+    RemoveMember {
+        /// URL the follower polls the leader as
+        url: Url,
+        /// The cluster's token, which membership changes are signed with
+        #[arg(short, long)]
+        token: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum CliCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl From<CliCompression> for Compression {
+    fn from(compression: CliCompression) -> Self {
+        match compression {
+            CliCompression::None => Compression::None,
+            CliCompression::Lz4 => Compression::Lz4,
+            CliCompression::Zstd => Compression::Zstd,
+        }
+    }
 }
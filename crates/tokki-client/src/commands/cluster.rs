@@ -0,0 +1,82 @@
+use tokki_api::{
+    TokkiClient,
+    cluster::{ClusterMembers, MemberRequest},
+};
+use url::Url;
+
+pub async fn in_sync(base_url: Url) {
+    let client = TokkiClient::new(base_url);
+    let res = client
+        .get_in_sync_replicas()
+        .await
+        .expect("Get in-sync replicas");
+
+    for partition in &res.partitions {
+        let in_sync = partition
+            .replicas
+            .iter()
+            .filter(|replica| replica.in_sync)
+            .count();
+        println!(
+            "{}/{}  high watermark {}, log end {}, {} in sync of {} required",
+            partition.topic,
+            partition.partition,
+            partition.high_watermark.0,
+            partition.log_end.0,
+            in_sync,
+            partition.min_insync_replicas
+        );
+        for replica in &partition.replicas {
+            let status = match (replica.member, replica.in_sync) {
+                (true, true) => "in sync",
+                (true, false) => "out of sync",
+                (false, _) => "not a member",
+            };
+            let max_offset = replica
+                .max_offset
+                .map_or_else(|| "-".to_string(), |offset| offset.0.to_string());
+            println!("  {:<40}  {:>12}  {}", replica.url, max_offset, status);
+        }
+    }
+}
+
+pub async fn list_members(base_url: Url) {
+    let client = TokkiClient::new(base_url);
+    let members = client.list_members().await.expect("List members");
+
+    print_members(&members);
+}
+
+pub async fn add_member(base_url: Url, url: Url, token: &str) {
+    let client = TokkiClient::new(base_url);
+    let members = client
+        .add_member(MemberRequest::new(url), token)
+        .await
+        .expect("Add member");
+
+    println!("Adding member:");
+    print_members(&members);
+}
+
+pub async fn remove_member(base_url: Url, url: Url, token: &str) {
+    let client = TokkiClient::new(base_url);
+    let members = client
+        .remove_member(MemberRequest::new(url), token)
+        .await
+        .expect("Remove member");
+
+    println!("Removing member:");
+    print_members(&members);
+}
+
+fn print_members(members: &ClusterMembers) {
+    for member in &members.members {
+        println!("  {member}");
+    }
+    if let Some(joining) = &members.joining {
+        println!("  changing to:");
+        for member in joining {
+            println!("    {member}");
+        }
+    }
+}
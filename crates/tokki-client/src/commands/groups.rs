@@ -0,0 +1,41 @@
+use tokki_api::{TokkiClient, groups::GetGroupLagRequest};
+use url::Url;
+
+pub async fn lag(base_url: Url, group: String, topic: Option<String>) {
+    let client = TokkiClient::new(base_url);
+    let mut req = GetGroupLagRequest::new(group);
+    if let Some(topic) = topic {
+        req = req.with_topic(topic);
+    }
+    let res = client.get_group_lag(req).await.expect("Get group lag");
+
+    if res.partitions.is_empty() {
+        println!("Group {} has no committed offsets", res.group);
+        return;
+    }
+
+    let width = res
+        .partitions
+        .iter()
+        .map(|partition| partition.topic.len())
+        .max()
+        .unwrap_or_default()
+        .max("TOPIC".len());
+    println!(
+        "{:<width$}  {:>9}  {:>12}  {:>12}  {:>12}",
+        "TOPIC", "PARTITION", "LOG END", "COMMITTED", "LAG"
+    );
+    for partition in &res.partitions {
+        let committed = partition
+            .committed_offset
+            .map_or_else(|| "-".to_string(), |offset| offset.0.to_string());
+        let lag = partition
+            .lag
+            .map_or_else(|| "-".to_string(), |lag| lag.to_string());
+        println!(
+            "{:<width$}  {:>9}  {:>12}  {:>12}  {:>12}",
+            partition.topic, partition.partition, partition.log_end_offset.0, committed, lag
+        );
+    }
+    println!("Total lag: {}", res.total_lag());
+}
@@ -3,16 +3,30 @@ use std::time::Instant;
 use futures::stream::{self, StreamExt};
 use rand::{RngCore as _, SeedableRng};
 use rand_chacha::ChaCha12Rng;
-use tokki_api::{TokkiClient, get_records::GetRecordsRequest, put_record::PutRecordsRequest};
-use tokki_common::Record;
+use tokki_api::{
+    ClientError, TokkiClient, get_records::GetRecordsRequest, put_record::PutRecordsRequest,
+    topics::CreateTopicRequest,
+};
+use tokki_common::{Compression, Record};
 use url::Url;
 
 const PARALLELISM: usize = 32;
-pub async fn load_test(base_url: Url, count: usize, batch_size: usize) {
+pub async fn load_test(
+    base_url: Url,
+    topic: String,
+    count: usize,
+    batch_size: usize,
+    compression: Compression,
+) {
     // Get baseline
     let batch_count = count / batch_size;
     let client = TokkiClient::new(base_url);
 
+    match client.create_topic(CreateTopicRequest::new(&topic)).await {
+        Ok(_) | Err(ClientError::Conflict { .. }) => {}
+        Err(e) => panic!("Create topic: {e}"),
+    }
+
     let start = Instant::now();
     stream::iter(0..batch_count)
         .map(|_| {
@@ -29,23 +43,27 @@ pub async fn load_test(base_url: Url, count: usize, batch_size: usize) {
     println!("Baseline: {}ms", start.elapsed().as_millis());
 
     for i in 0..100 {
-        let record = Record::new(vec![b't', i], vec![b't', i]);
+        let record = Record::new(vec![b't', i], vec![b't', i])
+            .with_header("producer", "tokki-client")
+            .with_header("sequence", [i]);
         let res = client
-            .put_record(PutRecordsRequest {
-                records: vec![record.clone()],
-            })
+            .put_record(
+                PutRecordsRequest::single(&topic, record.clone()).with_compression(compression),
+            )
             .await
             .unwrap();
-        let offset = res.offset;
+        let put = res.partitions.first().unwrap();
         let res = client
-            .get_records(GetRecordsRequest {
-                offset,
-                max_records: 1,
-            })
+            .get_records(
+                GetRecordsRequest::new(&topic, put.offset, 1).with_partition(put.partition),
+            )
             .await
             .unwrap();
+        // The leader may have stamped the record with its own timestamp
         let response = res.records().first().unwrap();
-        assert_eq!(response, &record);
+        assert_eq!(response.key(), record.key());
+        assert_eq!(response.value(), record.value());
+        assert_eq!(response.headers(), record.headers());
     }
 
     // Actual measurement
@@ -61,7 +79,7 @@ pub async fn load_test(base_url: Url, count: usize, batch_size: usize) {
                 })
                 .collect();
 
-            PutRecordsRequest::new(records)
+            PutRecordsRequest::new(&topic, records).with_compression(compression)
         })
         .collect::<Vec<_>>();
 
@@ -1,3 +1,9 @@
+mod cluster;
+mod groups;
 mod load_test;
+mod topics;
 
+pub use cluster::{add_member, in_sync, list_members, remove_member};
+pub use groups::lag;
 pub use load_test::load_test;
+pub use topics::{create_topic, delete_topic, list_topics, shards};
@@ -0,0 +1,65 @@
+use tokki_api::{
+    TokkiClient,
+    topics::{CreateTopicRequest, TopicConfig, TopicDescription},
+};
+use url::Url;
+
+pub async fn list_topics(base_url: Url) {
+    let client = TokkiClient::new(base_url);
+    let res = client.list_topics().await.expect("List topics");
+
+    for topic in &res.topics {
+        print_topic(topic);
+    }
+}
+
+pub async fn create_topic(base_url: Url, name: String, config: TopicConfig) {
+    let client = TokkiClient::new(base_url);
+    let req = CreateTopicRequest::new(name).with_config(config);
+    let topic = client.create_topic(req).await.expect("Create topic");
+
+    println!("Created topic:");
+    print_topic(&topic);
+}
+
+pub async fn delete_topic(base_url: Url, name: String) {
+    let client = TokkiClient::new(base_url);
+    let topic = client.delete_topic(&name).await.expect("Delete topic");
+
+    println!("Deleted topic:");
+    print_topic(&topic);
+}
+
+pub async fn shards(base_url: Url) {
+    let client = TokkiClient::new(base_url);
+    let res = client.get_shards().await.expect("Get shards");
+
+    for topic in &res.topics {
+        println!("{}", topic.name);
+        for partition in &topic.partitions {
+            let leader = partition.leader.as_deref().unwrap_or("unknown");
+            println!("  Partition {}: led by {leader}", partition.partition);
+        }
+    }
+}
+
+fn print_topic(topic: &TopicDescription) {
+    let config = &topic.config;
+    println!("{} ({:016x})", topic.name, topic.id);
+    println!("  Partitions: {}", config.partition_count());
+    if let Some(secs) = config.retention_max_age_secs {
+        println!("  Retention max age: {secs}s");
+    }
+    if let Some(bytes) = config.retention_max_bytes {
+        println!("  Retention max bytes: {bytes}");
+    }
+    if let Some(records) = config.retention_max_records {
+        println!("  Retention max records: {records}");
+    }
+    if config.compaction == Some(true) {
+        println!("  Compaction: on");
+        if let Some(secs) = config.tombstone_retention_secs {
+            println!("  Tombstone retention: {secs}s");
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use clap::Parser;
+use tokki_api::topics::TopicConfig;
 
 use crate::{
     cli::{Cli, CliCommand},
-    commands::load_test,
+    commands::{
+        add_member, create_topic, delete_topic, in_sync, lag, list_members, list_topics, load_test,
+        remove_member, shards,
+    },
 };
 
 mod cli;
@@ -13,8 +17,38 @@ async fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        CliCommand::LoadTest { count, batch_size } => {
-            load_test(cli.base_url, count, batch_size).await
+        CliCommand::LoadTest {
+            topic,
+            count,
+            batch_size,
+            compression,
+        } => load_test(cli.base_url, topic, count, batch_size, compression.into()).await,
+        CliCommand::ListTopics => list_topics(cli.base_url).await,
+        CliCommand::CreateTopic {
+            name,
+            partitions,
+            retention_max_age_secs,
+            retention_max_bytes,
+            retention_max_records,
+            compaction,
+            tombstone_retention_secs,
+        } => {
+            let config = TopicConfig {
+                partitions,
+                retention_max_age_secs,
+                retention_max_bytes,
+                retention_max_records,
+                compaction,
+                tombstone_retention_secs,
+            };
+            create_topic(cli.base_url, name, config).await
         }
+        CliCommand::DeleteTopic { name } => delete_topic(cli.base_url, name).await,
+        CliCommand::Shards => shards(cli.base_url).await,
+        CliCommand::Lag { group, topic } => lag(cli.base_url, group, topic).await,
+        CliCommand::InSync => in_sync(cli.base_url).await,
+        CliCommand::ListMembers => list_members(cli.base_url).await,
+        CliCommand::AddMember { url, token } => add_member(cli.base_url, url, &token).await,
+        CliCommand::RemoveMember { url, token } => remove_member(cli.base_url, url, &token).await,
     }
 }
@@ -0,0 +1,72 @@
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::DecodeError;
+
+/// Codec a producer asks for a batch of records to be compressed with
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    /// Level zstd compresses at, its own default
+    const ZSTD_LEVEL: i32 = 3;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Identifies the codec inside a frame
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Compression::Zstd => zstd::bulk::compress(data, Self::ZSTD_LEVEL),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => {
+                lz4_flex::decompress_size_prepended(data).map_err(|e| DecodeError::Decompress {
+                    compression: self,
+                    message: e.to_string(),
+                })
+            }
+            Compression::Zstd => {
+                zstd::stream::decode_all(data).map_err(|e| DecodeError::Decompress {
+                    compression: self,
+                    message: e.to_string(),
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::str::Utf8Error;
+
+use snafu::Snafu;
+
+use crate::Compression;
+
+/// Why a frame could not be decoded by `Record::from_bytes` or `Frame::from_bytes`
+#[derive(Debug, Clone, PartialEq, Eq, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum DecodeError {
+    #[snafu(display("Frame needs {needed} bytes but only {available} are available"))]
+    Truncated { needed: usize, available: usize },
+    #[snafu(display("Frame length {len} overflows"))]
+    LengthOverflow { len: u64 },
+    #[snafu(display("Frame is {frame_len} bytes but its fields take {fields_len}"))]
+    LengthMismatch { frame_len: usize, fields_len: usize },
+    #[snafu(display("Varint is too long"))]
+    InvalidVarint,
+    #[snafu(display("Frame version {version} is not supported"))]
+    UnsupportedVersion { version: u8 },
+    #[snafu(display("Frame has unknown flags {flags:#010b}"))]
+    UnknownFlags { flags: u8 },
+    #[snafu(display("Frame has unknown compression {id}"))]
+    UnknownCompression { id: u8 },
+    #[snafu(display("Could not decompress {} batch: {message}", compression.name()))]
+    Decompress {
+        compression: Compression,
+        message: String,
+    },
+    #[snafu(display("Batch should hold {expected} records but has {actual}"))]
+    RecordCountMismatch { expected: usize, actual: usize },
+    #[snafu(display("Expected a single record but found a batch"))]
+    UnexpectedBatch,
+    #[snafu(display("Batch is encrypted with key {key_id}"))]
+    UnexpectedEncrypted { key_id: u32 },
+    #[snafu(display("Header key is not valid UTF-8: {source}"))]
+    InvalidHeaderKey { source: Utf8Error },
+    #[snafu(display(
+        "Checksum mismatch, frame has {expected:#018x} but contents hash to {actual:#018x}"
+    ))]
+    ChecksumMismatch {
+        expected: u64,
+        actual: u64,
+        /// The whole frame was read, so readers can step over it
+        frame_len: usize,
+    },
+}
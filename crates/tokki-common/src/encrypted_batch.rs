@@ -0,0 +1,129 @@
+use std::hash::Hasher as _;
+use std::io;
+
+use gxhash::GxHasher;
+
+use crate::frame;
+use crate::{Compression, Offset, Timestamp};
+
+/// Length of the nonce AES-GCM is used with
+pub const NONCE_LEN: usize = 12;
+
+/// A `RecordBatch` whose data has been encrypted for storing at rest.
+///
+/// Only the batch's data is encrypted, the rest is left in the clear so the
+/// log can be recovered and indexed without the key, and is authenticated
+/// along with the data through `associated_data`. So is where the batch was
+/// written, so it cannot be moved to other offsets or another segment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedBatch {
+    compression: Compression,
+    record_count: usize,
+    max_timestamp: Timestamp,
+    key_id: u32,
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedBatch {
+    pub fn new(
+        compression: Compression,
+        record_count: usize,
+        max_timestamp: Timestamp,
+        key_id: u32,
+        nonce: [u8; NONCE_LEN],
+        ciphertext: Vec<u8>,
+    ) -> Self {
+        Self {
+            compression,
+            record_count,
+            max_timestamp,
+            key_id,
+            nonce,
+            ciphertext,
+        }
+    }
+
+    /// Bytes the encryption must authenticate besides the ciphertext, for a
+    /// batch starting at `base_offset` in the segment starting at `segment_base`
+    pub fn associated_data(
+        segment_base: Offset,
+        base_offset: Offset,
+        compression: Compression,
+        record_count: usize,
+        max_timestamp: Timestamp,
+        key_id: u32,
+    ) -> Vec<u8> {
+        let mut data = Vec::with_capacity(1 + 5 * size_of::<u64>());
+        data.extend_from_slice(&(segment_base.0 as u64).to_le_bytes());
+        data.extend_from_slice(&(base_offset.0 as u64).to_le_bytes());
+        data.push(compression.id());
+        data.extend_from_slice(&(record_count as u64).to_le_bytes());
+        data.extend_from_slice(&max_timestamp.0.to_le_bytes());
+        data.extend_from_slice(&u64::from(key_id).to_le_bytes());
+        data
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.record_count
+    }
+
+    pub fn max_timestamp(&self) -> Timestamp {
+        self.max_timestamp
+    }
+
+    /// Which key the batch was encrypted with
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    pub fn nonce(&self) -> &[u8; NONCE_LEN] {
+        &self.nonce
+    }
+
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
+
+    pub fn checksum(&self) -> u64 {
+        Self::raw_checksum(
+            self.compression,
+            self.record_count,
+            self.max_timestamp,
+            self.key_id,
+            &self.nonce,
+            &self.ciphertext,
+        )
+    }
+
+    /// Checksum of a batch's fields, which may still be borrowed from a frame
+    pub(crate) fn raw_checksum(
+        compression: Compression,
+        record_count: usize,
+        max_timestamp: Timestamp,
+        key_id: u32,
+        nonce: &[u8; NONCE_LEN],
+        ciphertext: &[u8],
+    ) -> u64 {
+        let mut hasher = GxHasher::default();
+        hasher.write_u8(compression.id());
+        hasher.write_usize(record_count);
+        hasher.write_u64(max_timestamp.0);
+        hasher.write_u32(key_id);
+        hasher.write(nonce);
+        hasher.write(ciphertext);
+        hasher.finish()
+    }
+
+    pub fn serialized_len(&self) -> usize {
+        frame::encoded_encrypted_len(self)
+    }
+
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        frame::encode_encrypted(self, buf)
+    }
+}
@@ -0,0 +1,722 @@
+use std::hash::Hasher as _;
+use std::io::{self, Cursor, Write as _};
+
+use gxhash::GxHasher;
+use snafu::{OptionExt as _, ResultExt as _, ensure};
+
+use crate::{
+    Compression, DecodeError, EncryptedBatch, Header, Record, RecordBatch, Timestamp,
+    decode_error::{
+        ChecksumMismatchSnafu, InvalidHeaderKeySnafu, InvalidVarintSnafu, LengthMismatchSnafu,
+        LengthOverflowSnafu, TruncatedSnafu, UnexpectedBatchSnafu, UnknownCompressionSnafu,
+        UnknownFlagsSnafu, UnsupportedVersionSnafu,
+    },
+    encrypted_batch::NONCE_LEN,
+};
+
+/// Marks the start of a versioned frame. Read as the key length of a legacy
+/// frame it would be over 4 GiB, so the two layouts cannot be confused.
+const MAGIC: [u8; 4] = [0xf0, b'T', b'K', 0xff];
+const VERSION: u8 = 1;
+/// Magic, version, flags and the frame length
+const HEADER_LEN: usize = MAGIC.len() + 2 * size_of::<u8>() + size_of::<u64>();
+
+/// The frame has a headers section
+const FLAG_HEADERS: u8 = 1 << 0;
+/// The frame holds a `RecordBatch` rather than a single record
+const FLAG_BATCH: u8 = 1 << 1;
+/// The batch in the frame is an `EncryptedBatch`
+const FLAG_ENCRYPTED: u8 = 1 << 2;
+const KNOWN_FLAGS: u8 = FLAG_HEADERS | FLAG_BATCH | FLAG_ENCRYPTED;
+
+/// A single entry in the log, taking up one offset per record it holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Record(Record),
+    Batch(RecordBatch),
+    Encrypted(EncryptedBatch),
+}
+
+impl Frame {
+    /// Decode a frame from the start of `buf`, returning it and the number of bytes it took up
+    pub fn from_bytes(buf: &[u8]) -> Result<(Self, usize), DecodeError> {
+        decode(buf)
+    }
+
+    /// Check the frame at the start of `buf`, checksum and all, without
+    /// copying anything out of it, so stored frames can be served as they are
+    pub fn inspect(buf: &[u8]) -> Result<FrameInfo, DecodeError> {
+        inspect(buf)
+    }
+
+    pub fn record_count(&self) -> usize {
+        match self {
+            Frame::Record(_) => 1,
+            Frame::Batch(batch) => batch.record_count(),
+            Frame::Encrypted(batch) => batch.record_count(),
+        }
+    }
+
+    /// The newest timestamp of any record in the frame
+    pub fn max_timestamp(&self) -> Timestamp {
+        match self {
+            Frame::Record(record) => record.timestamp(),
+            Frame::Batch(batch) => batch.max_timestamp(),
+            Frame::Encrypted(batch) => batch.max_timestamp(),
+        }
+    }
+}
+
+/// What `Frame::inspect` found at the start of a buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub kind: FrameKind,
+    pub record_count: usize,
+    /// Bytes the whole frame takes up
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Record,
+    Batch,
+    Encrypted,
+}
+
+/// Number of bytes `record` takes up once encoded
+pub fn encoded_len(record: &Record) -> usize {
+    let headers_len = if record.headers().is_empty() {
+        0
+    } else {
+        varint_len(record.headers().len() as u64)
+            + record
+                .headers()
+                .iter()
+                .map(|header| bytes_len(header.key().as_bytes()) + bytes_len(header.value()))
+                .sum::<usize>()
+    };
+
+    HEADER_LEN
+        + size_of::<u64>() // Timestamp
+        + bytes_len(record.key())
+        + bytes_len(record.value())
+        + headers_len
+        + size_of::<u64>() // Checksum
+}
+
+/// Write `record` as a current version frame:
+///
+/// - magic, version and flags bytes
+/// - length of the whole frame as a `u64`
+/// - timestamp as a `u64`
+/// - key and value, each prefixed with its varint length
+/// - with `FLAG_HEADERS`, a varint count of headers followed by each
+///   header's key and value, prefixed with their varint lengths
+/// - checksum as a `u64`
+///
+/// Fixed width integers are little-endian.
+pub fn encode(record: &Record, buf: &mut [u8]) -> io::Result<usize> {
+    let frame_len = encoded_len(record);
+    let flags = if record.headers().is_empty() {
+        0
+    } else {
+        FLAG_HEADERS
+    };
+
+    let mut cursor = Cursor::new(buf);
+
+    cursor.write_all(&MAGIC)?;
+    cursor.write_all(&[VERSION, flags])?;
+    cursor.write_all(&(frame_len as u64).to_le_bytes())?;
+    cursor.write_all(&record.timestamp().0.to_le_bytes())?;
+    write_bytes(&mut cursor, record.key())?;
+    write_bytes(&mut cursor, record.value())?;
+    if flags & FLAG_HEADERS != 0 {
+        write_varint(&mut cursor, record.headers().len() as u64)?;
+        for header in record.headers() {
+            write_bytes(&mut cursor, header.key().as_bytes())?;
+            write_bytes(&mut cursor, header.value())?;
+        }
+    }
+    cursor.write_all(&record.stored_checksum().to_le_bytes())?;
+
+    Ok(cursor.position() as usize)
+}
+
+/// Number of bytes `batch` takes up once encoded
+pub fn encoded_batch_len(batch: &RecordBatch) -> usize {
+    HEADER_LEN
+        + size_of::<u64>() // Max timestamp
+        + size_of::<u8>() // Compression
+        + varint_len(batch.record_count() as u64)
+        + bytes_len(batch.data())
+        + size_of::<u64>() // Checksum
+}
+
+/// Write `batch` as a frame with `FLAG_BATCH` set. It has the same header
+/// as a record frame, followed by:
+///
+/// - newest timestamp in the batch as a `u64`
+/// - compression codec as a `u8`
+/// - varint count of records
+/// - compressed record frames, prefixed with their varint length
+/// - checksum as a `u64`
+pub fn encode_batch(batch: &RecordBatch, buf: &mut [u8]) -> io::Result<usize> {
+    let frame_len = encoded_batch_len(batch);
+    let mut cursor = Cursor::new(buf);
+
+    cursor.write_all(&MAGIC)?;
+    cursor.write_all(&[VERSION, FLAG_BATCH])?;
+    cursor.write_all(&(frame_len as u64).to_le_bytes())?;
+    cursor.write_all(&batch.max_timestamp().0.to_le_bytes())?;
+    cursor.write_all(&[batch.compression().id()])?;
+    write_varint(&mut cursor, batch.record_count() as u64)?;
+    write_bytes(&mut cursor, batch.data())?;
+    cursor.write_all(&batch.checksum().to_le_bytes())?;
+
+    Ok(cursor.position() as usize)
+}
+
+/// Number of bytes `batch` takes up once encoded
+pub fn encoded_encrypted_len(batch: &EncryptedBatch) -> usize {
+    HEADER_LEN
+        + size_of::<u64>() // Max timestamp
+        + size_of::<u8>() // Compression
+        + varint_len(batch.record_count() as u64)
+        + varint_len(u64::from(batch.key_id()))
+        + NONCE_LEN
+        + bytes_len(batch.ciphertext())
+        + size_of::<u64>() // Checksum
+}
+
+/// Write `batch` as a frame with `FLAG_BATCH` and `FLAG_ENCRYPTED` set. It
+/// is laid out like a plain batch frame, but with the varint id of the key
+/// and the nonce between the record count and the data.
+pub fn encode_encrypted(batch: &EncryptedBatch, buf: &mut [u8]) -> io::Result<usize> {
+    let frame_len = encoded_encrypted_len(batch);
+    let mut cursor = Cursor::new(buf);
+
+    cursor.write_all(&MAGIC)?;
+    cursor.write_all(&[VERSION, FLAG_BATCH | FLAG_ENCRYPTED])?;
+    cursor.write_all(&(frame_len as u64).to_le_bytes())?;
+    cursor.write_all(&batch.max_timestamp().0.to_le_bytes())?;
+    cursor.write_all(&[batch.compression().id()])?;
+    write_varint(&mut cursor, batch.record_count() as u64)?;
+    write_varint(&mut cursor, u64::from(batch.key_id()))?;
+    cursor.write_all(batch.nonce())?;
+    write_bytes(&mut cursor, batch.ciphertext())?;
+    cursor.write_all(&batch.checksum().to_le_bytes())?;
+
+    Ok(cursor.position() as usize)
+}
+
+/// Read a single record frame, in the current or any of the legacy layouts
+pub fn decode_record(buf: &[u8]) -> Result<(Record, usize), DecodeError> {
+    match decode(buf)? {
+        (Frame::Record(record), len) => Ok((record, len)),
+        (Frame::Batch(_) | Frame::Encrypted(_), _) => UnexpectedBatchSnafu.fail(),
+    }
+}
+
+/// Read a frame in the current or any of the legacy layouts from the start of `buf`
+fn decode(buf: &[u8]) -> Result<(Frame, usize), DecodeError> {
+    let (frame, len) = decode_raw(buf)?;
+    Ok((frame.to_frame(), len))
+}
+
+/// Check the frame at the start of `buf` without copying any of it
+fn inspect(buf: &[u8]) -> Result<FrameInfo, DecodeError> {
+    let (frame, len) = decode_raw(buf)?;
+    let (kind, record_count) = match frame {
+        RawFrame::Record { .. } => (FrameKind::Record, 1),
+        RawFrame::Batch { record_count, .. } => (FrameKind::Batch, record_count),
+        RawFrame::Encrypted { record_count, .. } => (FrameKind::Encrypted, record_count),
+    };
+
+    Ok(FrameInfo {
+        kind,
+        record_count,
+        len,
+    })
+}
+
+/// A frame decoded in place, with its fields borrowed from the buffer. Its
+/// checksum has already been verified.
+enum RawFrame<'a> {
+    Record {
+        key: &'a [u8],
+        value: &'a [u8],
+        headers: RawHeaders<'a>,
+        timestamp: Timestamp,
+        checksum: u64,
+    },
+    Batch {
+        compression: Compression,
+        record_count: usize,
+        max_timestamp: Timestamp,
+        data: &'a [u8],
+    },
+    Encrypted {
+        compression: Compression,
+        record_count: usize,
+        max_timestamp: Timestamp,
+        key_id: u32,
+        nonce: [u8; NONCE_LEN],
+        ciphertext: &'a [u8],
+    },
+}
+
+impl RawFrame<'_> {
+    fn to_frame(&self) -> Frame {
+        match *self {
+            RawFrame::Record {
+                key,
+                value,
+                headers,
+                timestamp,
+                checksum,
+            } => Frame::Record(Record::from_parts(
+                key.to_vec(),
+                value.to_vec(),
+                headers
+                    .iter()
+                    .map(|(key, value)| Header::new(key, value))
+                    .collect(),
+                timestamp,
+                checksum,
+            )),
+            RawFrame::Batch {
+                compression,
+                record_count,
+                max_timestamp,
+                data,
+            } => Frame::Batch(RecordBatch::from_parts(
+                compression,
+                record_count,
+                max_timestamp,
+                data.to_vec(),
+            )),
+            RawFrame::Encrypted {
+                compression,
+                record_count,
+                max_timestamp,
+                key_id,
+                nonce,
+                ciphertext,
+            } => Frame::Encrypted(EncryptedBatch::new(
+                compression,
+                record_count,
+                max_timestamp,
+                key_id,
+                nonce,
+                ciphertext.to_vec(),
+            )),
+        }
+    }
+}
+
+/// The headers section of a record frame, which has already been checked
+#[derive(Clone, Copy)]
+struct RawHeaders<'a> {
+    buf: &'a [u8],
+    count: usize,
+    legacy: bool,
+}
+
+impl<'a> RawHeaders<'a> {
+    fn empty() -> Self {
+        Self {
+            buf: &[],
+            count: 0,
+            legacy: true,
+        }
+    }
+
+    /// Take `count` headers from `buf` at `position`. Every header takes up
+    /// space in the frame, so a garbage count runs out of buffer rather than
+    /// looping forever.
+    fn take(
+        buf: &'a [u8],
+        position: &mut usize,
+        count: u64,
+        legacy: bool,
+    ) -> Result<Self, DecodeError> {
+        let count = usize::try_from(count)
+            .ok()
+            .context(LengthOverflowSnafu { len: count })?;
+
+        let start = *position;
+        for _ in 0..count {
+            Self::take_header(buf, position, legacy)?;
+        }
+
+        Ok(Self {
+            buf: &buf[start..*position],
+            count,
+            legacy,
+        })
+    }
+
+    fn take_header(
+        buf: &'a [u8],
+        position: &mut usize,
+        legacy: bool,
+    ) -> Result<(&'a str, &'a [u8]), DecodeError> {
+        let take = if legacy {
+            take_legacy_bytes
+        } else {
+            take_bytes
+        };
+
+        let key = take(buf, position)?;
+        let key = std::str::from_utf8(key).context(InvalidHeaderKeySnafu)?;
+        let value = take(buf, position)?;
+        Ok((key, value))
+    }
+
+    fn iter(self) -> impl ExactSizeIterator<Item = (&'a str, &'a [u8])> {
+        let mut position = 0;
+        (0..self.count).map(move |_| {
+            Self::take_header(self.buf, &mut position, self.legacy)
+                .expect("Checked when the frame was decoded")
+        })
+    }
+}
+
+fn decode_raw(buf: &[u8]) -> Result<(RawFrame<'_>, usize), DecodeError> {
+    match buf.get(..MAGIC.len()) {
+        Some(magic) if magic == MAGIC => decode_versioned(buf),
+        Some(_) => decode_legacy(buf),
+        None => TruncatedSnafu {
+            needed: MAGIC.len(),
+            available: buf.len(),
+        }
+        .fail(),
+    }
+}
+
+fn decode_versioned(buf: &[u8]) -> Result<(RawFrame<'_>, usize), DecodeError> {
+    let mut position = MAGIC.len();
+
+    let [version, flags] = take_array(buf, &mut position)?;
+    ensure!(version == VERSION, UnsupportedVersionSnafu { version });
+    ensure!(flags & !KNOWN_FLAGS == 0, UnknownFlagsSnafu { flags });
+
+    let frame_len = u64::from_le_bytes(take_array(buf, &mut position)?);
+    let frame_len = usize::try_from(frame_len)
+        .ok()
+        .context(LengthOverflowSnafu { len: frame_len })?;
+
+    // The whole frame has to be buffered before its fields are read, so
+    // running out of bytes after this means the lengths disagree
+    let frame = buf.get(..frame_len).context(TruncatedSnafu {
+        needed: frame_len,
+        available: buf.len(),
+    })?;
+    let fields_mismatch = |e| match e {
+        DecodeError::Truncated { needed, .. } => DecodeError::LengthMismatch {
+            frame_len,
+            fields_len: needed,
+        },
+        e => e,
+    };
+
+    let timestamp = Timestamp(u64::from_le_bytes(
+        take_array(frame, &mut position).map_err(fields_mismatch)?,
+    ));
+
+    if flags & FLAG_BATCH != 0 {
+        let [id] = take_array(frame, &mut position).map_err(fields_mismatch)?;
+        let compression = Compression::from_id(id).context(UnknownCompressionSnafu { id })?;
+        let record_count = take_varint(frame, &mut position).map_err(fields_mismatch)?;
+        let record_count = usize::try_from(record_count)
+            .ok()
+            .context(LengthOverflowSnafu { len: record_count })?;
+        let encryption = if flags & FLAG_ENCRYPTED != 0 {
+            let key_id = take_varint(frame, &mut position).map_err(fields_mismatch)?;
+            let key_id = u32::try_from(key_id)
+                .ok()
+                .context(LengthOverflowSnafu { len: key_id })?;
+            let nonce: [u8; NONCE_LEN] =
+                take_array(frame, &mut position).map_err(fields_mismatch)?;
+            Some((key_id, nonce))
+        } else {
+            None
+        };
+        let data = take_bytes(frame, &mut position).map_err(fields_mismatch)?;
+        let checksum =
+            u64::from_le_bytes(take_array(frame, &mut position).map_err(fields_mismatch)?);
+        ensure!(
+            position == frame_len,
+            LengthMismatchSnafu {
+                frame_len,
+                fields_len: position,
+            }
+        );
+
+        let (actual, batch) = match encryption {
+            Some((key_id, nonce)) => (
+                EncryptedBatch::raw_checksum(
+                    compression,
+                    record_count,
+                    timestamp,
+                    key_id,
+                    &nonce,
+                    data,
+                ),
+                RawFrame::Encrypted {
+                    compression,
+                    record_count,
+                    max_timestamp: timestamp,
+                    key_id,
+                    nonce,
+                    ciphertext: data,
+                },
+            ),
+            None => (
+                RecordBatch::raw_checksum(compression, record_count, timestamp, data),
+                RawFrame::Batch {
+                    compression,
+                    record_count,
+                    max_timestamp: timestamp,
+                    data,
+                },
+            ),
+        };
+        ensure!(
+            actual == checksum,
+            ChecksumMismatchSnafu {
+                expected: checksum,
+                actual,
+                frame_len,
+            }
+        );
+
+        return Ok((batch, frame_len));
+    }
+
+    let key = take_bytes(frame, &mut position).map_err(fields_mismatch)?;
+    let value = take_bytes(frame, &mut position).map_err(fields_mismatch)?;
+
+    let header_count = if flags & FLAG_HEADERS != 0 {
+        take_varint(frame, &mut position).map_err(fields_mismatch)?
+    } else {
+        0
+    };
+    let headers =
+        RawHeaders::take(frame, &mut position, header_count, false).map_err(fields_mismatch)?;
+
+    let checksum = u64::from_le_bytes(take_array(frame, &mut position).map_err(fields_mismatch)?);
+    ensure!(
+        position == frame_len,
+        LengthMismatchSnafu {
+            frame_len,
+            fields_len: position,
+        }
+    );
+
+    verified(key, value, headers, timestamp, checksum, frame_len)
+}
+
+/// Frames written before versioning, with native `usize` lengths. Every log
+/// written so far came from a 64-bit little-endian machine.
+///
+/// The layout changed twice without a marker, so the newest is tried first
+/// and older ones only if it does not decode with a matching checksum. If
+/// none of them do, the error is the newest layout's.
+fn decode_legacy(buf: &[u8]) -> Result<(RawFrame<'_>, usize), DecodeError> {
+    decode_legacy_headers(buf).or_else(|e| {
+        decode_legacy_timestamped(buf)
+            .or_else(|_| decode_legacy_original(buf))
+            .map_err(|_| e)
+    })
+}
+
+/// Key, value, a `u64` count of headers and the headers themselves, then
+/// the timestamp and checksum
+fn decode_legacy_headers(buf: &[u8]) -> Result<(RawFrame<'_>, usize), DecodeError> {
+    let mut position = 0;
+
+    let key = take_legacy_bytes(buf, &mut position)?;
+    let value = take_legacy_bytes(buf, &mut position)?;
+
+    let header_count = u64::from_le_bytes(take_array(buf, &mut position)?);
+    let headers = RawHeaders::take(buf, &mut position, header_count, true)?;
+
+    let timestamp = Timestamp(u64::from_le_bytes(take_array(buf, &mut position)?));
+    let checksum = u64::from_le_bytes(take_array(buf, &mut position)?);
+
+    verified(key, value, headers, timestamp, checksum, position)
+}
+
+/// Key, value, timestamp and checksum, from before records had headers
+fn decode_legacy_timestamped(buf: &[u8]) -> Result<(RawFrame<'_>, usize), DecodeError> {
+    let mut position = 0;
+
+    let key = take_legacy_bytes(buf, &mut position)?;
+    let value = take_legacy_bytes(buf, &mut position)?;
+    let timestamp = Timestamp(u64::from_le_bytes(take_array(buf, &mut position)?));
+    let checksum = u64::from_le_bytes(take_array(buf, &mut position)?);
+
+    let mut hasher = GxHasher::default();
+    hasher.write(key);
+    hasher.write(value);
+    hasher.write_u64(timestamp.0);
+    verified_legacy(key, value, timestamp, checksum, hasher.finish(), position)
+}
+
+/// Key, value and checksum, the original layout from before records had timestamps
+fn decode_legacy_original(buf: &[u8]) -> Result<(RawFrame<'_>, usize), DecodeError> {
+    let mut position = 0;
+
+    let key = take_legacy_bytes(buf, &mut position)?;
+    let value = take_legacy_bytes(buf, &mut position)?;
+    let checksum = u64::from_le_bytes(take_array(buf, &mut position)?);
+
+    let mut hasher = GxHasher::default();
+    hasher.write(key);
+    hasher.write(value);
+    verified_legacy(
+        key,
+        value,
+        Timestamp::default(),
+        checksum,
+        hasher.finish(),
+        position,
+    )
+}
+
+/// Check a frame from before headers against the checksum of its own
+/// layout. The record gets the current checksum, so it can be written out
+/// again in the current layout.
+fn verified_legacy<'a>(
+    key: &'a [u8],
+    value: &'a [u8],
+    timestamp: Timestamp,
+    checksum: u64,
+    actual: u64,
+    frame_len: usize,
+) -> Result<(RawFrame<'a>, usize), DecodeError> {
+    ensure!(
+        actual == checksum,
+        ChecksumMismatchSnafu {
+            expected: checksum,
+            actual,
+            frame_len,
+        }
+    );
+
+    let headers = RawHeaders::empty();
+    let record = RawFrame::Record {
+        key,
+        value,
+        headers,
+        timestamp,
+        checksum: Record::raw_checksum(key, value, headers.iter(), timestamp),
+    };
+    Ok((record, frame_len))
+}
+
+fn verified<'a>(
+    key: &'a [u8],
+    value: &'a [u8],
+    headers: RawHeaders<'a>,
+    timestamp: Timestamp,
+    checksum: u64,
+    frame_len: usize,
+) -> Result<(RawFrame<'a>, usize), DecodeError> {
+    let actual = Record::raw_checksum(key, value, headers.iter(), timestamp);
+    ensure!(
+        actual == checksum,
+        ChecksumMismatchSnafu {
+            expected: checksum,
+            actual,
+            frame_len,
+        }
+    );
+
+    let record = RawFrame::Record {
+        key,
+        value,
+        headers,
+        timestamp,
+        checksum,
+    };
+    Ok((record, frame_len))
+}
+
+fn varint_len(value: u64) -> usize {
+    (u64::BITS - value.leading_zeros()).max(1).div_ceil(7) as usize
+}
+
+fn bytes_len(bytes: &[u8]) -> usize {
+    varint_len(bytes.len() as u64) + bytes.len()
+}
+
+/// LEB128, seven bits at a time with the high bit set on all but the last byte
+fn write_varint(cursor: &mut Cursor<&mut [u8]>, mut value: u64) -> io::Result<()> {
+    while value >= 0x80 {
+        cursor.write_all(&[(value as u8) | 0x80])?;
+        value >>= 7;
+    }
+    cursor.write_all(&[value as u8])
+}
+
+fn write_bytes(cursor: &mut Cursor<&mut [u8]>, bytes: &[u8]) -> io::Result<()> {
+    write_varint(cursor, bytes.len() as u64)?;
+    cursor.write_all(bytes)
+}
+
+fn take_varint(buf: &[u8], position: &mut usize) -> Result<u64, DecodeError> {
+    let mut value = 0;
+    for shift in (0..u64::BITS).step_by(7) {
+        let [byte] = take_array(buf, position)?;
+        // The tenth byte only has room for the top bit
+        ensure!(shift < 63 || byte <= 1, InvalidVarintSnafu);
+
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    InvalidVarintSnafu.fail()
+}
+
+/// Take a varint length followed by that many bytes. Lengths come from the
+/// frame itself so they are checked against the buffer before anything is
+/// copied, a torn or garbage frame must not be able to request a huge buffer.
+fn take_bytes<'a>(buf: &'a [u8], position: &mut usize) -> Result<&'a [u8], DecodeError> {
+    let len = take_varint(buf, position)?;
+    let len = usize::try_from(len)
+        .ok()
+        .context(LengthOverflowSnafu { len })?;
+    take(buf, position, len)
+}
+
+fn take_legacy_bytes<'a>(buf: &'a [u8], position: &mut usize) -> Result<&'a [u8], DecodeError> {
+    let len = u64::from_le_bytes(take_array(buf, position)?);
+    let len = usize::try_from(len)
+        .ok()
+        .context(LengthOverflowSnafu { len })?;
+    take(buf, position, len)
+}
+
+/// Take the next `len` bytes of a frame starting at `position`
+fn take<'a>(buf: &'a [u8], position: &mut usize, len: usize) -> Result<&'a [u8], DecodeError> {
+    let end = position
+        .checked_add(len)
+        .context(LengthOverflowSnafu { len: len as u64 })?;
+    let bytes = buf.get(*position..end).context(TruncatedSnafu {
+        needed: end,
+        available: buf.len(),
+    })?;
+
+    *position = end;
+    Ok(bytes)
+}
+
+fn take_array<const N: usize>(buf: &[u8], position: &mut usize) -> Result<[u8; N], DecodeError> {
+    let bytes = take(buf, position, N)?;
+    Ok(bytes.try_into().expect("Took exactly N bytes"))
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// A piece of metadata attached to a record, such as a content type or trace id
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    key: String,
+    value: Vec<u8>,
+}
+
+impl Header {
+    pub fn new(key: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    pub fn serialized_len(&self) -> usize {
+        size_of::<usize>() // Key length
+            + self.key.len() // Key bytes
+            + size_of::<usize>() // Value length
+            + self.value.len() // Value bytes
+    }
+}
@@ -8,6 +8,8 @@ pub trait HmacValue {
 
 impl HmacValue for String {
     fn update_mac(&self, mac: &mut HmacSha256) {
+        // Length prefixed so neighbouring strings cannot trade bytes
+        mac.update(&(self.len() as u64).to_be_bytes());
         mac.update(self.as_bytes());
     }
 }
@@ -28,3 +30,21 @@ where
         }
     }
 }
+
+impl HmacValue for u32 {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        mac.update(&self.to_be_bytes());
+    }
+}
+
+impl HmacValue for u64 {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        mac.update(&self.to_be_bytes());
+    }
+}
+
+impl HmacValue for bool {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        mac.update(&[u8::from(*self)]);
+    }
+}
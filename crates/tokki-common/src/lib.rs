@@ -1,6 +1,20 @@
+mod compression;
+mod decode_error;
+pub mod encrypted_batch;
+mod frame;
+mod header;
 pub mod hmac;
 mod offset;
 mod record;
+mod record_batch;
+mod timestamp;
 
+pub use compression::Compression;
+pub use decode_error::DecodeError;
+pub use encrypted_batch::EncryptedBatch;
+pub use frame::{Frame, FrameInfo, FrameKind};
+pub use header::Header;
 pub use offset::Offset;
 pub use record::Record;
+pub use record_batch::RecordBatch;
+pub use timestamp::Timestamp;
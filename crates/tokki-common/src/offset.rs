@@ -8,8 +8,8 @@ use crate::hmac::HmacValue;
 pub struct Offset(pub usize);
 
 impl Offset {
-    pub fn new(_0: usize) -> Self {
-        Self(_0)
+    pub fn new(offset: usize) -> Self {
+        Self(offset)
     }
 }
 impl std::ops::Add for Offset {
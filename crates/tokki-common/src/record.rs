@@ -1,17 +1,22 @@
-use std::hash::BuildHasher as _;
 use std::hash::Hasher as _;
 
-use gxhash::{GxBuildHasher, GxHasher};
+use gxhash::GxHasher;
 use hmac::digest::Update as _;
 use serde::{Deserialize, Serialize};
 
+use crate::frame;
 use crate::hmac::HmacSha256;
 use crate::hmac::HmacValue;
+use crate::{DecodeError, Header, Timestamp};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Record {
     key: Vec<u8>,
     value: Vec<u8>,
+    #[serde(default)]
+    headers: Vec<Header>,
+    #[serde(default)]
+    timestamp: Timestamp,
     checksum: u64,
 }
 
@@ -19,27 +24,70 @@ impl Record {
     pub fn new(key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
         let key = key.into();
         let value = value.into();
-        let checksum = Self::raw_checksum(&key, &value);
+        let headers = Vec::new();
+        let timestamp = Timestamp::default();
+        let checksum = Self::raw_checksum(&key, &value, [].into_iter(), timestamp);
         Self {
             key,
             value,
+            headers,
+            timestamp,
             checksum,
         }
     }
 
+    /// Attach a header after any already on the record
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        self.headers.push(Header::new(key, value));
+        self.checksum = self.checksum();
+        self
+    }
+
+    /// Set when the record was created, the broker fills it in on append if left unset
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.set_timestamp(timestamp);
+        self
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: Timestamp) {
+        self.timestamp = timestamp;
+        self.checksum = self.checksum();
+    }
+
     pub fn checksum(&self) -> u64 {
-        Self::raw_checksum(&self.key, &self.value)
+        let headers = self
+            .headers
+            .iter()
+            .map(|header| (header.key(), header.value()));
+        Self::raw_checksum(&self.key, &self.value, headers, self.timestamp)
     }
 
-    fn raw_checksum(key: &[u8], value: &[u8]) -> u64 {
+    /// Checksum of a record's fields, which may still be borrowed from a frame
+    pub(crate) fn raw_checksum<'a>(
+        key: &[u8],
+        value: &[u8],
+        headers: impl ExactSizeIterator<Item = (&'a str, &'a [u8])>,
+        timestamp: Timestamp,
+    ) -> u64 {
         let mut hasher = GxHasher::default();
         hasher.write(key);
         hasher.write(value);
+        // Length prefixed so moving bytes between headers changes the checksum
+        hasher.write_usize(headers.len());
+        for (key, value) in headers {
+            hasher.write_usize(key.len());
+            hasher.write(key.as_bytes());
+            hasher.write_usize(value.len());
+            hasher.write(value);
+        }
+        hasher.write_u64(timestamp.0);
         hasher.finish()
     }
 
+    /// Hash of the key alone, the same for every record with this key in
+    /// every process, so it can decide which partition a record goes on
     pub fn hash_key(&self) -> u64 {
-        GxBuildHasher::default().hash_one(&self.key)
+        gxhash::gxhash64(&self.key, 0)
     }
 
     pub fn key(&self) -> &[u8] {
@@ -50,70 +98,75 @@ impl Record {
         &self.value
     }
 
-    pub fn serialized_len(&self) -> usize {
-        size_of::<usize>() // Key length
-            + self.key.len() // Key bytes
-            + size_of::<usize>() // Value length
-            + self.value.len() // Value bytes
-            + size_of::<u64>() // Checksum
+    pub fn headers(&self) -> &[Header] {
+        &self.headers
     }
 
-    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
-        use std::io::{Cursor, Write};
-
-        let mut cursor = Cursor::new(buf);
-
-        cursor.write_all(&self.key.len().to_le_bytes())?;
-        cursor.write_all(&self.key)?;
-        cursor.write_all(&self.value.len().to_le_bytes())?;
-        cursor.write_all(&self.value)?;
-        cursor.write_all(&self.checksum.to_le_bytes())?;
-
-        Ok(cursor.position() as usize)
+    /// The value of the first header with `key`
+    pub fn header(&self, key: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|header| header.key() == key)
+            .map(Header::value)
     }
 
-    pub fn from_bytes(buf: &[u8]) -> Result<(Self, usize), std::io::Error> {
-        use std::io::{Cursor, Read};
-
-        let mut cursor = Cursor::new(buf);
-
-        let mut key_len_bytes = [0u8; size_of::<usize>()];
-        cursor.read_exact(&mut key_len_bytes)?;
-        let key_len = usize::from_le_bytes(key_len_bytes);
-
-        let mut key = vec![0u8; key_len];
-        cursor.read_exact(&mut key)?;
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
 
-        let mut value_len_bytes = [0u8; size_of::<usize>()];
-        cursor.read_exact(&mut value_len_bytes)?;
-        let value_len = usize::from_le_bytes(value_len_bytes);
+    /// A record with no value marks its key as deleted once the log is compacted
+    pub fn is_tombstone(&self) -> bool {
+        self.value.is_empty()
+    }
 
-        let mut value = vec![0u8; value_len];
-        cursor.read_exact(&mut value)?;
+    pub fn serialized_len(&self) -> usize {
+        frame::encoded_len(self)
+    }
 
-        let mut checksum_bytes = [0u8; 8];
-        cursor.read_exact(&mut checksum_bytes)?;
-        let checksum = u64::from_le_bytes(checksum_bytes);
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        frame::encode(self, buf)
+    }
 
-        if Record::raw_checksum(&key, &value) != checksum {
-            // TODO this should be an error
-            panic!("Checksum fail");
-        }
+    /// Decode a frame from the start of `buf`, returning the record and the
+    /// number of bytes it took up. The current layout and every legacy one are read.
+    pub fn from_bytes(buf: &[u8]) -> Result<(Self, usize), DecodeError> {
+        frame::decode_record(buf)
+    }
 
-        let record = Self {
+    pub(crate) fn from_parts(
+        key: Vec<u8>,
+        value: Vec<u8>,
+        headers: Vec<Header>,
+        timestamp: Timestamp,
+        checksum: u64,
+    ) -> Self {
+        Self {
             key,
             value,
+            headers,
+            timestamp,
             checksum,
-        };
+        }
+    }
 
-        Ok((record, cursor.position() as usize))
+    /// The checksum the record arrived with, which may not match its contents
+    pub(crate) fn stored_checksum(&self) -> u64 {
+        self.checksum
     }
 }
 
 impl HmacValue for Record {
     fn update_mac(&self, mac: &mut HmacSha256) {
         mac.update(&self.checksum().to_le_bytes());
-        mac.update(&self.key());
-        mac.update(&self.value());
+        mac.update(self.key());
+        mac.update(self.value());
+        mac.update(&(self.headers.len() as u64).to_le_bytes());
+        for header in &self.headers {
+            mac.update(&(header.key().len() as u64).to_le_bytes());
+            mac.update(header.key().as_bytes());
+            mac.update(&(header.value().len() as u64).to_le_bytes());
+            mac.update(header.value());
+        }
+        self.timestamp.update_mac(mac);
     }
 }
@@ -0,0 +1,149 @@
+use std::hash::Hasher as _;
+use std::io;
+
+use gxhash::GxHasher;
+use hmac::digest::Update as _;
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+use snafu::ensure;
+
+use crate::decode_error::RecordCountMismatchSnafu;
+use crate::frame;
+use crate::hmac::{HmacSha256, HmacValue};
+use crate::{Compression, DecodeError, Record, Timestamp};
+
+/// Records stored and replicated as a single unit, compressed together.
+///
+/// The records are encoded back to back as frames and the whole run is
+/// compressed at once, so values that repeat across records compress well.
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RecordBatch {
+    compression: Compression,
+    record_count: usize,
+    max_timestamp: Timestamp,
+    #[serde_as(as = "Base64")]
+    data: Vec<u8>,
+}
+
+impl RecordBatch {
+    pub fn new(records: &[Record], compression: Compression) -> io::Result<Self> {
+        let mut frames = vec![0; records.iter().map(Record::serialized_len).sum()];
+        let mut position = 0;
+        for record in records {
+            position += record.to_bytes(&mut frames[position..])?;
+        }
+
+        Ok(Self {
+            compression,
+            record_count: records.len(),
+            max_timestamp: records
+                .iter()
+                .map(Record::timestamp)
+                .max()
+                .unwrap_or_default(),
+            data: compression.compress(&frames)?,
+        })
+    }
+
+    /// Put a batch back together from data that is already compressed
+    pub fn from_parts(
+        compression: Compression,
+        record_count: usize,
+        max_timestamp: Timestamp,
+        data: Vec<u8>,
+    ) -> Self {
+        Self {
+            compression,
+            record_count,
+            max_timestamp,
+            data,
+        }
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.record_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.record_count == 0
+    }
+
+    /// The newest timestamp of any record in the batch
+    pub fn max_timestamp(&self) -> Timestamp {
+        self.max_timestamp
+    }
+
+    /// The records' frames as compressed
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decompress the batch and decode every record in it
+    pub fn records(&self) -> Result<Vec<Record>, DecodeError> {
+        let frames = self.compression.decompress(&self.data)?;
+
+        let mut records = Vec::new();
+        let mut position = 0;
+        while position < frames.len() {
+            let (record, len) = Record::from_bytes(&frames[position..])?;
+            records.push(record);
+            position += len;
+        }
+
+        ensure!(
+            records.len() == self.record_count,
+            RecordCountMismatchSnafu {
+                expected: self.record_count,
+                actual: records.len(),
+            }
+        );
+
+        Ok(records)
+    }
+
+    pub fn checksum(&self) -> u64 {
+        Self::raw_checksum(
+            self.compression,
+            self.record_count,
+            self.max_timestamp,
+            &self.data,
+        )
+    }
+
+    /// Checksum of a batch's fields, which may still be borrowed from a frame
+    pub(crate) fn raw_checksum(
+        compression: Compression,
+        record_count: usize,
+        max_timestamp: Timestamp,
+        data: &[u8],
+    ) -> u64 {
+        let mut hasher = GxHasher::default();
+        hasher.write_u8(compression.id());
+        hasher.write_usize(record_count);
+        hasher.write_u64(max_timestamp.0);
+        hasher.write(data);
+        hasher.finish()
+    }
+
+    pub fn serialized_len(&self) -> usize {
+        frame::encoded_batch_len(self)
+    }
+
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        frame::encode_batch(self, buf)
+    }
+}
+
+impl HmacValue for RecordBatch {
+    fn update_mac(&self, mac: &mut HmacSha256) {
+        mac.update(&[self.compression.id()]);
+        mac.update(&(self.record_count as u64).to_le_bytes());
+        self.max_timestamp.update_mac(mac);
+        mac.update(&self.data);
+    }
+}
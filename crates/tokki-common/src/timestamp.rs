@@ -0,0 +1,43 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::Mac;
+use serde::{Deserialize, Serialize};
+
+use crate::hmac::HmacValue;
+
+/// Milliseconds since the Unix epoch. Zero means no timestamp has been set.
+#[derive(
+    Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(transparent)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    pub fn new(millis: u64) -> Self {
+        Self(millis)
+    }
+
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0 != 0
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis() as u64)
+            .unwrap_or_default();
+        Self(millis)
+    }
+}
+
+impl HmacValue for Timestamp {
+    fn update_mac(&self, mac: &mut crate::hmac::HmacSha256) {
+        mac.update(&self.0.to_le_bytes());
+    }
+}
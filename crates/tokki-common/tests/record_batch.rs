@@ -0,0 +1,119 @@
+use tokki_common::{Compression, DecodeError, Frame, Record, RecordBatch, Timestamp};
+
+const CODECS: [Compression; 3] = [Compression::None, Compression::Lz4, Compression::Zstd];
+
+fn records() -> Vec<Record> {
+    (0..50)
+        .map(|i| {
+            Record::new(
+                format!("user-{i}"),
+                format!(r#"{{"id":{i},"name":"user-{i}","active":true,"tags":["a","b"]}}"#),
+            )
+            .with_header("content-type", "application/json")
+            .with_timestamp(Timestamp(1_700_000_000_000 + i))
+        })
+        .collect()
+}
+
+fn frame(batch: &RecordBatch) -> Vec<u8> {
+    let mut buf = vec![0; batch.serialized_len()];
+    let len = batch.to_bytes(&mut buf).unwrap();
+    assert_eq!(len, buf.len());
+    buf
+}
+
+#[test]
+fn round_trips_every_codec() {
+    let records = records();
+
+    for compression in CODECS {
+        let batch = RecordBatch::new(&records, compression).unwrap();
+        assert_eq!(batch.compression(), compression);
+        assert_eq!(batch.record_count(), records.len());
+        assert_eq!(batch.max_timestamp(), Timestamp(1_700_000_000_049));
+        assert_eq!(batch.records().unwrap(), records);
+
+        let buf = frame(&batch);
+        assert_eq!(
+            Frame::from_bytes(&buf),
+            Ok((Frame::Batch(batch.clone()), buf.len()))
+        );
+
+        let json = serde_json::to_string(&batch).unwrap();
+        assert_eq!(serde_json::from_str::<RecordBatch>(&json).unwrap(), batch);
+    }
+}
+
+#[test]
+fn compresses_similar_values() {
+    let records = records();
+    let uncompressed = RecordBatch::new(&records, Compression::None).unwrap();
+
+    for compression in [Compression::Lz4, Compression::Zstd] {
+        let batch = RecordBatch::new(&records, compression).unwrap();
+        assert!(
+            batch.data().len() * 2 < uncompressed.data().len(),
+            "{} only got {} bytes down to {}",
+            compression.name(),
+            uncompressed.data().len(),
+            batch.data().len()
+        );
+    }
+}
+
+#[test]
+fn single_records_are_frames_too() {
+    let record = Record::new("key", "value");
+    let mut buf = vec![0; record.serialized_len()];
+    record.to_bytes(&mut buf).unwrap();
+
+    let (frame, len) = Frame::from_bytes(&buf).unwrap();
+    assert_eq!(len, buf.len());
+    assert_eq!(frame.record_count(), 1);
+    assert_eq!(frame, Frame::Record(record));
+}
+
+#[test]
+fn record_from_bytes_rejects_batches() {
+    let batch = RecordBatch::new(&records(), Compression::Lz4).unwrap();
+
+    assert_eq!(
+        Record::from_bytes(&frame(&batch)),
+        Err(DecodeError::UnexpectedBatch)
+    );
+}
+
+#[test]
+fn checksum_covers_data() {
+    let batch = RecordBatch::new(&records(), Compression::Zstd).unwrap();
+    let mut buf = frame(&batch);
+    let len = buf.len();
+    buf[len - 9] ^= 0xff;
+
+    assert!(matches!(
+        Frame::from_bytes(&buf),
+        Err(DecodeError::ChecksumMismatch { frame_len, .. }) if frame_len == len
+    ));
+}
+
+#[test]
+fn unknown_compression() {
+    let batch = RecordBatch::new(&records(), Compression::Lz4).unwrap();
+    let mut buf = frame(&batch);
+    // Follows the frame header and the max timestamp
+    buf[14 + 8] = 9;
+
+    assert_eq!(
+        Frame::from_bytes(&buf),
+        Err(DecodeError::UnknownCompression { id: 9 })
+    );
+}
+
+#[test]
+fn empty_batch() {
+    for compression in CODECS {
+        let batch = RecordBatch::new(&[], compression).unwrap();
+        assert!(batch.is_empty());
+        assert_eq!(batch.records().unwrap(), Vec::new());
+    }
+}
@@ -0,0 +1,338 @@
+use tokki_common::{
+    Compression, DecodeError, Frame, FrameInfo, FrameKind, Header, Record, RecordBatch, Timestamp,
+};
+
+fn frame(record: &Record) -> Vec<u8> {
+    let mut buf = vec![0; record.serialized_len()];
+    record.to_bytes(&mut buf).unwrap();
+    buf
+}
+
+fn with_headers() -> Record {
+    Record::new("key", "value")
+        .with_header("content-type", "text/plain")
+        .with_header("trace-id", [0xde, 0xad, 0xbe, 0xef])
+        .with_header("empty", [])
+}
+
+#[test]
+fn round_trips() {
+    for record in [Record::new("key", "value"), with_headers()] {
+        let buf = frame(&record);
+        assert_eq!(buf.len(), record.serialized_len());
+        assert_eq!(Record::from_bytes(&buf), Ok((record, buf.len())));
+    }
+}
+
+#[test]
+fn headers_keep_their_order() {
+    let record = with_headers();
+    let keys: Vec<_> = record.headers().iter().map(Header::key).collect();
+
+    assert_eq!(keys, ["content-type", "trace-id", "empty"]);
+    assert_eq!(
+        record.header("trace-id"),
+        Some(&[0xde, 0xad, 0xbe, 0xef][..])
+    );
+    assert_eq!(record.header("missing"), None);
+}
+
+#[test]
+fn checksum_covers_headers() {
+    let a = Record::new("key", "value").with_header("ab", "c");
+    let b = Record::new("key", "value").with_header("a", "bc");
+
+    assert_ne!(a.checksum(), b.checksum());
+    assert_ne!(a.checksum(), Record::new("key", "value").checksum());
+}
+
+#[test]
+fn invalid_header_key() {
+    let mut buf = frame(&Record::new("k", "v").with_header("h", "v"));
+    // Frame header, timestamp, key, value, header count and header key length come first
+    let header_key = 14 + 8 + 2 + 2 + 1 + 1;
+    buf[header_key] = 0xff;
+
+    assert!(matches!(
+        Record::from_bytes(&buf),
+        Err(DecodeError::InvalidHeaderKey { .. })
+    ));
+}
+
+#[test]
+fn truncated_frame() {
+    let buf = frame(&with_headers());
+
+    for cut in 0..buf.len() {
+        let err = Record::from_bytes(&buf[..cut]).unwrap_err();
+        let DecodeError::Truncated { needed, available } = err else {
+            panic!("Expected truncated frame, got {err:?}");
+        };
+        assert_eq!(available, cut);
+        assert!(needed > cut && needed <= buf.len());
+    }
+}
+
+#[test]
+fn length_overflow() {
+    let mut buf = legacy_frame(&Record::new("key", "value"));
+    buf[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    assert!(matches!(
+        Record::from_bytes(&buf),
+        Err(DecodeError::LengthOverflow { .. })
+    ));
+}
+
+#[test]
+fn length_mismatch() {
+    let mut buf = frame(&Record::new("key", "value"));
+    let len = buf.len();
+
+    // Fields running past the end of the frame
+    buf[6..14].copy_from_slice(&(len as u64 - 1).to_le_bytes());
+    assert!(matches!(
+        Record::from_bytes(&buf),
+        Err(DecodeError::LengthMismatch { frame_len, .. }) if frame_len == len - 1
+    ));
+
+    // Bytes left over after the fields
+    buf.push(0);
+    buf[6..14].copy_from_slice(&(len as u64 + 1).to_le_bytes());
+    assert!(matches!(
+        Record::from_bytes(&buf),
+        Err(DecodeError::LengthMismatch { frame_len, fields_len }) if frame_len == len + 1 && fields_len == len
+    ));
+}
+
+#[test]
+fn unsupported_version() {
+    let mut buf = frame(&Record::new("key", "value"));
+    buf[4] = 2;
+
+    assert_eq!(
+        Record::from_bytes(&buf),
+        Err(DecodeError::UnsupportedVersion { version: 2 })
+    );
+}
+
+#[test]
+fn unknown_flags() {
+    let mut buf = frame(&Record::new("key", "value"));
+    buf[5] |= 0b1000_0000;
+
+    assert_eq!(
+        Record::from_bytes(&buf),
+        Err(DecodeError::UnknownFlags { flags: 0b1000_0000 })
+    );
+}
+
+#[test]
+fn invalid_varint() {
+    let record = Record::new(vec![b'k'; 64], "value");
+    let mut buf = frame(&record);
+    // Replace the key with a varint that never ends
+    buf[22..22 + 11].fill(0xff);
+
+    assert_eq!(Record::from_bytes(&buf), Err(DecodeError::InvalidVarint));
+}
+
+#[test]
+fn large_lengths() {
+    let record = Record::new(vec![b'k'; 300], vec![b'v'; 70_000]).with_header("h", vec![0; 200]);
+    let buf = frame(&record);
+
+    // Two byte key length, three byte value length
+    assert_eq!(
+        buf.len(),
+        14 + 8 + 2 + 300 + 3 + 70_000 + 1 + 2 + 2 + 200 + 8
+    );
+    assert_eq!(Record::from_bytes(&buf), Ok((record, buf.len())));
+}
+
+/// Frame layout from before versioning, with `usize` lengths and the
+/// timestamp after the headers
+fn legacy_frame(record: &Record) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let put_bytes = |buf: &mut Vec<u8>, bytes: &[u8]| {
+        buf.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        buf.extend_from_slice(bytes);
+    };
+
+    put_bytes(&mut buf, record.key());
+    put_bytes(&mut buf, record.value());
+    buf.extend_from_slice(&(record.headers().len() as u64).to_le_bytes());
+    for header in record.headers() {
+        put_bytes(&mut buf, header.key().as_bytes());
+        put_bytes(&mut buf, header.value());
+    }
+    buf.extend_from_slice(&record.timestamp().0.to_le_bytes());
+    buf.extend_from_slice(&record.checksum().to_le_bytes());
+    buf
+}
+
+#[test]
+fn reads_legacy_frames() {
+    let records = [
+        Record::new("key", "value"),
+        with_headers().with_timestamp(Timestamp(1_700_000_000_000)),
+    ];
+    let mut buf: Vec<u8> = records.iter().flat_map(legacy_frame).collect();
+    // Old and new frames can sit side by side in a segment
+    buf.extend(frame(&records[0]));
+
+    let mut position = 0;
+    for record in records.iter().chain([&records[0]]) {
+        let (decoded, len) = Record::from_bytes(&buf[position..]).unwrap();
+        assert_eq!(&decoded, record);
+        position += len;
+    }
+    assert_eq!(position, buf.len());
+}
+
+/// `Record::new("key", "value")` as written by the original layout, from
+/// before records had timestamps
+const ORIGINAL_FRAME: [u8; 32] = [
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x65, 0x79, 0x05, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x76, 0x61, 0x6c, 0x75, 0x65, 0x61, 0xf8, 0x2e, 0xc5, 0xf0, 0x94, 0xcc, 0xd8,
+];
+
+#[test]
+fn reads_original_frames() {
+    let record = Record::new("key", "value");
+    let mut buf = ORIGINAL_FRAME.repeat(2);
+    buf.extend(frame(&record));
+
+    let mut position = 0;
+    for _ in 0..3 {
+        let (decoded, len) = Record::from_bytes(&buf[position..]).unwrap();
+        assert_eq!(decoded, record);
+        position += len;
+    }
+    assert_eq!(position, buf.len());
+
+    let mut corrupt = ORIGINAL_FRAME;
+    corrupt[10] ^= 0xff;
+    assert!(Record::from_bytes(&corrupt).is_err());
+}
+
+/// `Record::new("key", "value")` with a timestamp of `1_700_000_000_000`, as
+/// written once records had timestamps but before they had headers
+const TIMESTAMPED_FRAME: [u8; 40] = [
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x65, 0x79, 0x05, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x76, 0x61, 0x6c, 0x75, 0x65, 0x00, 0x68, 0xe5, 0xcf, 0x8b, 0x01, 0x00, 0x00,
+    0x11, 0xd3, 0x1e, 0x74, 0x69, 0x8c, 0x12, 0xd7,
+];
+
+#[test]
+fn reads_timestamped_frames() {
+    let record = Record::new("key", "value").with_timestamp(Timestamp(1_700_000_000_000));
+    let mut buf = TIMESTAMPED_FRAME.to_vec();
+    buf.extend(frame(&record));
+
+    let (decoded, len) = Record::from_bytes(&buf).unwrap();
+    assert_eq!((&decoded, len), (&record, TIMESTAMPED_FRAME.len()));
+    assert_eq!(
+        Record::from_bytes(&buf[len..]),
+        Ok((record, buf.len() - len))
+    );
+
+    // Its checksum is brought up to date so it can be written out again
+    let reencoded = frame(&decoded);
+    assert_eq!(
+        Record::from_bytes(&reencoded),
+        Ok((decoded, reencoded.len()))
+    );
+}
+
+#[test]
+fn timestamped_checksum_mismatch() {
+    let mut buf = TIMESTAMPED_FRAME;
+    buf[10] ^= 0xff;
+
+    assert!(Record::from_bytes(&buf).is_err());
+}
+
+/// `Record::new("key", "value")` with a header `h: v` and a timestamp of
+/// `1_700_000_000_000`, as written once records had headers but before
+/// frames were versioned
+const HEADERS_FRAME: [u8; 66] = [
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x65, 0x79, 0x05, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x76, 0x61, 0x6c, 0x75, 0x65, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x68, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x76, 0x00, 0x68, 0xe5, 0xcf, 0x8b, 0x01, 0x00, 0x00, 0xf7, 0xfc, 0x3c, 0x63, 0xa4, 0x9e,
+    0x26, 0x7d,
+];
+
+#[test]
+fn reads_headers_frames() {
+    let record = Record::new("key", "value")
+        .with_header("h", "v")
+        .with_timestamp(Timestamp(1_700_000_000_000));
+
+    assert_eq!(
+        Record::from_bytes(&HEADERS_FRAME),
+        Ok((record, HEADERS_FRAME.len()))
+    );
+}
+
+#[test]
+fn legacy_checksum_mismatch() {
+    let mut buf = legacy_frame(&Record::new("key", "value"));
+    buf[10] ^= 0xff;
+
+    assert!(matches!(
+        Record::from_bytes(&buf),
+        Err(DecodeError::ChecksumMismatch { frame_len, .. }) if frame_len == buf.len()
+    ));
+}
+
+#[test]
+fn checksum_mismatch() {
+    let mut buf = frame(&Record::new("key", "value"));
+    let len = buf.len();
+    buf[len - 9] ^= 0xff;
+
+    let Err(DecodeError::ChecksumMismatch {
+        expected,
+        actual,
+        frame_len,
+    }) = Record::from_bytes(&buf)
+    else {
+        panic!("Expected checksum mismatch");
+    };
+    assert_ne!(expected, actual);
+    assert_eq!(frame_len, len);
+}
+
+#[test]
+fn inspects_frames_in_place() {
+    let record = with_headers();
+    let batch = RecordBatch::new(&[record.clone(), record.clone()], Compression::Lz4).unwrap();
+    let mut batch_frame = vec![0; batch.serialized_len()];
+    batch.to_bytes(&mut batch_frame).unwrap();
+
+    let cases = [
+        (frame(&record), FrameKind::Record, 1),
+        (legacy_frame(&record), FrameKind::Record, 1),
+        (batch_frame, FrameKind::Batch, 2),
+    ];
+    for (buf, kind, record_count) in cases {
+        let info = FrameInfo {
+            kind,
+            record_count,
+            len: buf.len(),
+        };
+        assert_eq!(Frame::inspect(&buf), Ok(info));
+        assert_eq!(Frame::from_bytes(&buf).unwrap().1, buf.len());
+    }
+
+    // Checksums are verified just as when decoding
+    let mut buf = frame(&Record::new("key", "value"));
+    let len = buf.len();
+    buf[len - 9] ^= 0xff;
+    assert!(matches!(
+        Frame::inspect(&buf),
+        Err(DecodeError::ChecksumMismatch { frame_len, .. }) if frame_len == len
+    ));
+}
@@ -0,0 +1,75 @@
+//! Compares serving a read by cloning records out of storage and encoding
+//! them as JSON, as `/records` does, against handing out the stored frames
+//! as they are, as `/records/frames` does, for the disk and lock-free engines.
+//!
+//! Run with `cargo bench -p tokki --bench read_path`.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use tokio::runtime::Runtime;
+use tokki::storage::{DiskStorage, DiskStorageConfig, InMemoryLockFree, Storage};
+use tokki_api::get_records::GetRecordsResponse;
+use tokki_common::{Offset, Record};
+
+const RECORDS: usize = 10_000;
+const VALUE_LEN: usize = 256;
+
+fn read_path(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    let disk = DiskStorage::open(DiskStorageConfig::new(data_dir.path())).unwrap();
+    bench_engine(c, &rt, "read_path", &disk);
+
+    bench_engine(c, &rt, "read_path_lockfree", &InMemoryLockFree::new());
+}
+
+fn bench_engine(c: &mut Criterion, rt: &Runtime, name: &str, storage: &dyn Storage) {
+    rt.block_on(async {
+        let value = vec![b'x'; VALUE_LEN];
+        for chunk in (0..RECORDS).collect::<Vec<_>>().chunks(1_000) {
+            let records = chunk
+                .iter()
+                .map(|i| Record::new(format!("key-{i}"), value.clone()))
+                .collect();
+            storage.put_records(records).await.unwrap();
+        }
+    });
+
+    let mut group = c.benchmark_group(name);
+    for max_records in [10, 100, 1_000] {
+        group.throughput(Throughput::Elements(max_records as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("clone_json", max_records),
+            &max_records,
+            |b, &max_records| {
+                b.iter(|| {
+                    let (records, next_offset) = rt
+                        .block_on(storage.get_records(Offset(0), max_records))
+                        .unwrap();
+                    let body =
+                        serde_json::to_vec(&GetRecordsResponse::new(records, next_offset)).unwrap();
+                    black_box(body)
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("frames", max_records),
+            &max_records,
+            |b, &max_records| {
+                b.iter(|| {
+                    let (frames, next_offset) = rt
+                        .block_on(storage.get_frames(Offset(0), max_records))
+                        .unwrap();
+                    black_box((frames.into_chunks(), next_offset))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, read_path);
+criterion_main!(benches);
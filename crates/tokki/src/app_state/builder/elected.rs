@@ -0,0 +1,120 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use crate::{
+    app_state::{
+        AppState,
+        builder::{Set, Unset},
+        state::AppStateInner,
+    },
+    cluster::ClusterMembership,
+    election::{Election, ElectionConfig, ElectionError},
+    groups::ConsumerGroups,
+    timestamp_type::TimestampType,
+    topics::Topics,
+};
+
+#[derive(Default)]
+pub struct ElectedBuilder<TokenStatus, TopicsStatus, ElectionStatus> {
+    token: String,
+    topics: Option<Arc<Topics>>,
+    election: Option<ElectionConfig>,
+    profiling_enabled: bool,
+    timestamp_type: TimestampType,
+    membership: Option<Arc<ClusterMembership>>,
+    marker: PhantomData<(TokenStatus, TopicsStatus, ElectionStatus)>,
+}
+
+impl<T, S, E> ElectedBuilder<T, S, E> {
+    pub fn with_profiling_enabled(mut self, profiling_enabled: bool) -> Self {
+        self.profiling_enabled = profiling_enabled;
+        self
+    }
+
+    /// Which time this node stores in record timestamps while it leads
+    pub fn with_timestamp_type(mut self, timestamp_type: TimestampType) -> Self {
+        self.timestamp_type = timestamp_type;
+        self
+    }
+
+    /// Followers counted towards the required replicas while this node
+    /// leads. Every node in the election is a member unless given.
+    pub fn with_membership(mut self, membership: Arc<ClusterMembership>) -> Self {
+        self.membership = Some(membership);
+        self
+    }
+}
+
+impl<S, E> ElectedBuilder<Unset, S, E> {
+    pub fn with_token(self, token: impl Into<String>) -> ElectedBuilder<Set, S, E> {
+        ElectedBuilder {
+            token: token.into(),
+            topics: self.topics,
+            election: self.election,
+            profiling_enabled: self.profiling_enabled,
+            timestamp_type: self.timestamp_type,
+            membership: self.membership,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, E> ElectedBuilder<T, Unset, E> {
+    pub fn with_topics(self, topics: Arc<Topics>) -> ElectedBuilder<T, Set, E> {
+        ElectedBuilder {
+            token: self.token,
+            topics: Some(topics),
+            election: self.election,
+            profiling_enabled: self.profiling_enabled,
+            timestamp_type: self.timestamp_type,
+            membership: self.membership,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, S> ElectedBuilder<T, S, Unset> {
+    pub fn with_election(self, election: ElectionConfig) -> ElectedBuilder<T, S, Set> {
+        ElectedBuilder {
+            token: self.token,
+            topics: self.topics,
+            election: Some(election),
+            profiling_enabled: self.profiling_enabled,
+            timestamp_type: self.timestamp_type,
+            membership: self.membership,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl ElectedBuilder<Set, Set, Set> {
+    /// Start out following no one, and hold elections with the peers in the
+    /// background. Fails if the persisted term and vote cannot be read.
+    pub fn build(self) -> Result<AppState, ElectionError> {
+        let topics = self.topics.unwrap();
+        let config = self.election.unwrap();
+        let membership = self
+            .membership
+            .unwrap_or_else(|| Arc::new(ClusterMembership::in_memory(config.nodes())));
+        let election = Arc::new(Election::open(
+            config,
+            self.token.clone(),
+            self.timestamp_type,
+        )?);
+
+        let inner = AppStateInner::Electing {
+            token: self.token,
+            groups: Arc::new(ConsumerGroups::new(topics.clone())),
+            topics,
+        };
+        let app_state = AppState::new(
+            self.profiling_enabled,
+            inner,
+            Some(election.clone()),
+            membership.clone(),
+        );
+        membership.start(app_state.clone(), Some(election.advertised_url()));
+        election.start(app_state.clone());
+
+        Ok(app_state)
+    }
+}
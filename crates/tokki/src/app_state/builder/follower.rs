@@ -1,7 +1,14 @@
-use std::{marker::PhantomData, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use tokio::time::sleep;
-use tokki_api::{TokkiClient, clustering::ReplicateLogRequest};
+use tokio::{task::JoinHandle, time::sleep};
+use tokki_api::{ClientError, TokkiClient, clustering::ReplicateLogRequest};
+use tokki_common::Offset;
 use url::Url;
 
 use crate::{
@@ -9,16 +16,21 @@ use crate::{
         AppState, AppStateInner,
         builder::{Set, Unset},
     },
-    storage::Storage,
+    groups::ConsumerGroups,
+    topics::{Partition, Topic, Topics},
 };
 
+/// How often the follower picks up topics created or deleted on the leader
+const TOPIC_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Default)]
 pub struct FollowerBuilder<A, T, S, L> {
     addr: Option<SocketAddr>,
     token: String,
-    storage: Option<Arc<dyn Storage>>,
+    topics: Option<Arc<Topics>>,
     profiling_enabled: bool,
     leader: Option<Url>,
+    advertised_url: Option<Url>,
     marker: PhantomData<(A, T, S, L)>,
 }
 
@@ -27,15 +39,23 @@ impl<A, T, S, L> FollowerBuilder<A, T, S, L> {
         self.profiling_enabled = profiling_enabled;
         self
     }
+
+    /// URL the leader knows this follower by, which the cluster's members
+    /// name it with. Defaults to the socket address.
+    pub fn with_advertised_url(mut self, advertised_url: Url) -> Self {
+        self.advertised_url = Some(advertised_url);
+        self
+    }
 }
 impl<T, S, L> FollowerBuilder<Unset, T, S, L> {
     pub fn with_socket_addr(self, addr: SocketAddr) -> FollowerBuilder<Set, T, S, L> {
         FollowerBuilder {
             addr: Some(addr),
             token: self.token,
-            storage: self.storage,
+            topics: self.topics,
             profiling_enabled: self.profiling_enabled,
             leader: self.leader,
+            advertised_url: self.advertised_url,
             marker: PhantomData,
         }
     }
@@ -46,22 +66,24 @@ impl<A, S, L> FollowerBuilder<A, Unset, S, L> {
         FollowerBuilder {
             addr: self.addr,
             token: token.into(),
-            storage: self.storage,
+            topics: self.topics,
             profiling_enabled: self.profiling_enabled,
             leader: self.leader,
+            advertised_url: self.advertised_url,
             marker: PhantomData,
         }
     }
 }
 
 impl<A, T, L> FollowerBuilder<A, T, Unset, L> {
-    pub fn with_storage(self, storage: Arc<dyn Storage>) -> FollowerBuilder<A, T, Set, L> {
+    pub fn with_topics(self, topics: Arc<Topics>) -> FollowerBuilder<A, T, Set, L> {
         FollowerBuilder {
             addr: self.addr,
             token: self.token,
-            storage: Some(storage),
+            topics: Some(topics),
             profiling_enabled: self.profiling_enabled,
             leader: self.leader,
+            advertised_url: self.advertised_url,
             marker: PhantomData,
         }
     }
@@ -72,9 +94,10 @@ impl<A, T, S> FollowerBuilder<A, T, S, Unset> {
         FollowerBuilder {
             addr: self.addr,
             token: self.token,
-            storage: self.storage,
+            topics: self.topics,
             profiling_enabled: self.profiling_enabled,
             leader: Some(leader),
+            advertised_url: self.advertised_url,
             marker: PhantomData,
         }
     }
@@ -83,54 +106,279 @@ impl<A, T, S> FollowerBuilder<A, T, S, Unset> {
 impl FollowerBuilder<Set, Set, Set, Set> {
     pub fn build(self) -> AppState {
         let addr = self.addr.unwrap();
-        let token = self.token;
-        let storage = self.storage.unwrap();
-        let leader_client = TokkiClient::new(self.leader.unwrap());
-
-        let leader_poll_task = tokio::task::spawn({
-            let follower_url = format!("http://{}", addr);
-            let token = token.clone();
-            let storage = storage.clone();
-            let leader_client = leader_client.clone();
-
-            let mut backoff_ms = 100;
-            async move {
-                loop {
-                    let req = ReplicateLogRequest::new(
-                        follower_url.clone(),
-                        storage.max_offset().await.expect("Get max offset"),
-                    );
-
-                    let res = leader_client
-                        .replicate_records(req, &token)
-                        .await
-                        .expect("Send request")
-                        .into_verified(&token)
-                        .expect("good token");
-
-                    if res.records.is_empty() {
-                        if backoff_ms < 1000 {
-                            backoff_ms += 10;
-                        }
-                    } else {
-                        for r in res.records {
-                            storage.put_record(r).await.expect("put record");
-                        }
-                        backoff_ms = 10;
-                    }
-                    sleep(Duration::from_millis(backoff_ms)).await;
+        let topics = self.topics.unwrap();
+        let groups = Arc::new(ConsumerGroups::new(topics.clone()));
+        let follower_url = self
+            .advertised_url
+            .map_or_else(|| format!("http://{}", addr), String::from);
+
+        let inner = AppStateInner::follower(
+            self.token,
+            topics,
+            groups,
+            self.leader.unwrap(),
+            follower_url,
+        );
+        AppState::new(self.profiling_enabled, inner, None, Arc::default())
+    }
+}
+
+/// Copy the leader's topics and records in the background, identifying this
+/// node to the leader as `follower_url`
+pub(crate) fn spawn_leader_poll(
+    follower_url: String,
+    token: String,
+    topics: Arc<Topics>,
+    leader_client: TokkiClient,
+) -> JoinHandle<()> {
+    let mut backoff_ms = 100;
+    let mut last_sync: Option<Instant> = None;
+    tokio::task::spawn(async move {
+        loop {
+            if last_sync.is_none_or(|last_sync| last_sync.elapsed() >= TOPIC_SYNC_INTERVAL) {
+                match sync_topics(&leader_client, &topics).await {
+                    Ok(()) => last_sync = Some(Instant::now()),
+                    Err(e) => tracing::warn!("Failed to sync topics from leader: {}", e),
                 }
             }
-        });
 
-        AppState {
-            profiling_enabled: self.profiling_enabled,
-            inner: Arc::new(AppStateInner::Follower {
-                token,
-                storage,
-                leader_client,
-                leader_poll_task,
-            }),
+            let mut replicated = false;
+            for topic in topics.all() {
+                replicated |= replicate_topic(&leader_client, &follower_url, &token, &topic).await;
+            }
+            if replicated {
+                backoff_ms = 10;
+            } else if backoff_ms < 1000 {
+                backoff_ms += 10;
+            }
+            sleep(Duration::from_millis(backoff_ms)).await;
+        }
+    })
+}
+
+/// Mirror the leader's topics, creating the ones it has and deleting the ones it does not
+async fn sync_topics(leader_client: &TokkiClient, topics: &Topics) -> Result<(), ClientError> {
+    let leader_topics = leader_client.list_topics().await?.topics;
+
+    let names: HashSet<_> = leader_topics
+        .iter()
+        .map(|description| description.name.clone())
+        .collect();
+    for description in leader_topics {
+        if let Err(e) = topics.ensure(description).await {
+            tracing::error!("Failed to create topic from leader: {}", e);
+        }
+    }
+
+    for topic in topics.all() {
+        if !names.contains(topic.name())
+            && let Err(e) = topics.delete_any(topic.name()).await
+        {
+            tracing::error!("Failed to delete topic removed from leader: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy anything new in `topic` from the leader, returning whether there was anything
+async fn replicate_topic(
+    leader_client: &TokkiClient,
+    follower_url: &str,
+    token: &str,
+    topic: &Topic,
+) -> bool {
+    let mut replicated = false;
+    for partition in topic.partitions() {
+        replicated |=
+            replicate_partition(leader_client, follower_url, token, topic, partition).await;
+    }
+    replicated
+}
+
+/// Copy anything new in one of `topic`'s partitions from the leader, along
+/// with its high watermark, returning whether either moved. If the leader
+/// finds this log has records it does not, they are truncated away first.
+/// Failures are logged and left for a later poll to retry.
+async fn replicate_partition(
+    leader_client: &TokkiClient,
+    follower_url: &str,
+    token: &str,
+    topic: &Topic,
+    partition: &Partition,
+) -> bool {
+    let storage = partition.storage();
+    let acknowledged = match storage.max_offset().await {
+        Ok(max_offset) => max_offset,
+        Err(e) => {
+            tracing::error!(
+                topic = topic.name(),
+                partition = partition.index(),
+                "Failed to read log end: {}",
+                e
+            );
+            return false;
+        }
+    };
+    // Only what is durable can be acknowledged
+    if let Some(acknowledged) = acknowledged
+        && let Err(e) = storage.wait_durable(acknowledged).await
+    {
+        tracing::error!(
+            topic = topic.name(),
+            partition = partition.index(),
+            "Failed to flush records: {}",
+            e
+        );
+        return false;
+    }
+    let last_epoch = partition
+        .leader_epochs()
+        .lock()
+        .expect("not poisoned")
+        .latest()
+        .map(|latest| latest.epoch);
+    let req = ReplicateLogRequest::new(
+        follower_url.to_string(),
+        topic.name().to_string(),
+        partition.index(),
+        acknowledged,
+        last_epoch,
+    );
+
+    let res = match leader_client.replicate_records(req, token).await {
+        Ok(res) => match res.into_verified(token) {
+            Ok(res) => res,
+            Err(e) => {
+                tracing::error!(
+                    topic = topic.name(),
+                    partition = partition.index(),
+                    "Failed to verify replicated records: {}",
+                    e
+                );
+                return false;
+            }
+        },
+        Err(e) => {
+            tracing::warn!(
+                topic = topic.name(),
+                partition = partition.index(),
+                "Failed to replicate: {}",
+                e
+            );
+            return false;
+        }
+    };
+
+    if let Some(divergence) = res.divergence {
+        let log_end = acknowledged.map_or(Offset(0), |max_offset| max_offset + 1);
+        // The leader's epoch may not be one this log has, in which case it
+        // ends where this log moved on to a later one
+        let (_, end_offset) = partition
+            .leader_epochs()
+            .lock()
+            .expect("not poisoned")
+            .end_offset_for(divergence.epoch, log_end);
+        let end_offset = end_offset.min(divergence.end_offset);
+
+        tracing::warn!(
+            topic = topic.name(),
+            partition = partition.index(),
+            leader_epoch = divergence.epoch,
+            "Log diverged from the leader's, truncating from {} to {}",
+            log_end.0,
+            end_offset.0
+        );
+        metrics::counter!("log_truncations", "topic" => topic.name().to_string()).increment(1);
+        if let Err(e) = partition.truncate(end_offset).await {
+            tracing::error!(
+                topic = topic.name(),
+                partition = partition.index(),
+                "Failed to truncate log: {}",
+                e
+            );
+            return false;
+        }
+        return true;
+    }
+
+    if let Some(log_start_offset) = res.log_start_offset {
+        tracing::warn!(
+            topic = topic.name(),
+            partition = partition.index(),
+            "Leader no longer has the records after {:?}, restarting the log from {}",
+            acknowledged.map(|offset| offset.0),
+            log_start_offset.0
+        );
+        if let Err(e) = partition.restart_at(log_start_offset).await {
+            tracing::error!(
+                topic = topic.name(),
+                partition = partition.index(),
+                "Failed to restart log: {}",
+                e
+            );
+            return false;
         }
+        return true;
     }
+
+    let mut max_offset = None;
+    let mut failed = false;
+    // Records go at the leader's offsets, keeping any gaps compaction left
+    for replicated in res.batches {
+        match storage
+            .put_batch_at(replicated.base_offset, replicated.batch)
+            .await
+        {
+            Ok(offsets) if offsets.end > offsets.start => max_offset = Some(offsets.end - 1),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!(
+                    topic = topic.name(),
+                    partition = partition.index(),
+                    "Failed to copy records: {}",
+                    e
+                );
+                failed = true;
+                break;
+            }
+        }
+    }
+
+    if let Some(max_offset) = max_offset {
+        // Only the epochs of records that made it into the log
+        let mut leader_epochs = partition.leader_epochs().lock().expect("not poisoned");
+        for epoch in res.epochs {
+            if epoch.start_offset > max_offset {
+                break;
+            }
+            if let Err(e) = leader_epochs.assign(epoch.epoch, epoch.start_offset) {
+                tracing::error!(
+                    topic = topic.name(),
+                    partition = partition.index(),
+                    "Failed to record leader epoch: {}",
+                    e
+                );
+                failed = true;
+                break;
+            }
+        }
+    }
+    if failed {
+        return false;
+    }
+
+    // Never let readers past what this follower has copied
+    let log_end = max_offset
+        .or(acknowledged)
+        .map_or(Offset(0), |max_offset| max_offset + 1);
+    let high_watermark = res.high_watermark.min(log_end);
+    let previous = {
+        let mut guard = partition.replication().lock().expect("not poisoned");
+        let previous = guard.high_watermark();
+        guard.set_high_watermark(high_watermark);
+        previous
+    };
+
+    max_offset.is_some() || previous != high_watermark
 }
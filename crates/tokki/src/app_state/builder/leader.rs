@@ -1,7 +1,6 @@
-use std::{
-    marker::PhantomData,
-    sync::{Arc, Mutex},
-};
+use std::{marker::PhantomData, sync::Arc};
+
+use url::Url;
 
 use crate::{
     app_state::{
@@ -9,27 +8,44 @@ use crate::{
         builder::{Set, Unset},
         state::AppStateInner,
     },
-    replication::Replication,
-    storage::Storage,
+    cluster::ClusterMembership,
+    groups::ConsumerGroups,
+    timestamp_type::TimestampType,
+    topics::Topics,
 };
 
 #[derive(Default)]
-pub struct LeaderBuilder<TokenStatus, StorageStatus> {
+pub struct LeaderBuilder<TokenStatus, TopicsStatus> {
     token: String,
-    storage: Option<Arc<dyn Storage>>,
-    required_replicas: usize,
+    topics: Option<Arc<Topics>>,
     profiling_enabled: bool,
-    marker: PhantomData<(TokenStatus, StorageStatus)>,
+    timestamp_type: TimestampType,
+    advertised_url: Option<Url>,
+    membership: Option<Arc<ClusterMembership>>,
+    marker: PhantomData<(TokenStatus, TopicsStatus)>,
 }
 
-impl<TokenStatus, StorageStatus> LeaderBuilder<TokenStatus, StorageStatus> {
+impl<TokenStatus, TopicsStatus> LeaderBuilder<TokenStatus, TopicsStatus> {
     pub fn with_profiling_enabled(mut self, profiling_enabled: bool) -> Self {
         self.profiling_enabled = profiling_enabled;
         self
     }
 
-    pub fn with_required_replicas(mut self, required_replicas: usize) -> Self {
-        self.required_replicas = required_replicas;
+    pub fn with_timestamp_type(mut self, timestamp_type: TimestampType) -> Self {
+        self.timestamp_type = timestamp_type;
+        self
+    }
+
+    /// URL clients and followers reach this node on, reported as the leader
+    /// of every partition
+    pub fn with_advertised_url(mut self, advertised_url: Url) -> Self {
+        self.advertised_url = Some(advertised_url);
+        self
+    }
+
+    /// Followers counted towards the required replicas, none unless given
+    pub fn with_membership(mut self, membership: Arc<ClusterMembership>) -> Self {
+        self.membership = Some(membership);
         self
     }
 }
@@ -39,36 +55,46 @@ impl<S> LeaderBuilder<Unset, S> {
         self.token = token.into();
         LeaderBuilder {
             token: self.token,
-            storage: self.storage,
-            required_replicas: self.required_replicas,
+            topics: self.topics,
             profiling_enabled: self.profiling_enabled,
+            timestamp_type: self.timestamp_type,
+            advertised_url: self.advertised_url,
+            membership: self.membership,
             marker: PhantomData,
         }
     }
 }
 
 impl<T> LeaderBuilder<T, Unset> {
-    pub fn with_storage(self, storage: Arc<dyn Storage>) -> LeaderBuilder<T, Set> {
+    pub fn with_topics(self, topics: Arc<Topics>) -> LeaderBuilder<T, Set> {
         LeaderBuilder {
             token: self.token,
-            storage: Some(storage),
-            required_replicas: self.required_replicas,
+            topics: Some(topics),
             profiling_enabled: self.profiling_enabled,
+            timestamp_type: self.timestamp_type,
+            advertised_url: self.advertised_url,
+            membership: self.membership,
             marker: PhantomData,
         }
     }
 }
 
 impl LeaderBuilder<Set, Set> {
+    /// Start leading, keeping track of the members in the background
     pub fn build(self) -> AppState {
-        AppState {
-            profiling_enabled: self.profiling_enabled,
-            inner: Arc::new(AppStateInner::Leader {
-                token: self.token,
-                storage: self.storage.unwrap(),
-                required_replicas: self.required_replicas,
-                replication: Arc::new(Mutex::new(Replication::new(self.required_replicas))),
-            }),
-        }
+        let topics = self.topics.unwrap();
+        let membership = self.membership.unwrap_or_default();
+        let inner = AppStateInner::Leader {
+            token: self.token,
+            groups: Arc::new(ConsumerGroups::new(topics.clone())),
+            topics,
+            timestamp_type: self.timestamp_type,
+            advertised_url: self.advertised_url.clone(),
+            leader_epoch: 0,
+        };
+        let app_state = AppState::new(self.profiling_enabled, inner, None, membership.clone());
+        membership.start(app_state.clone(), self.advertised_url.as_ref());
+
+        app_state
     }
 }
@@ -1,7 +1,10 @@
+mod elected;
 mod follower;
 mod leader;
 
+pub use elected::ElectedBuilder;
 pub use follower::FollowerBuilder;
+pub(crate) use follower::spawn_leader_poll;
 pub use leader::LeaderBuilder;
 
 pub struct AppStateBuilder {}
@@ -11,6 +14,11 @@ impl AppStateBuilder {
         LeaderBuilder::default()
     }
 
+    /// A node whose role is decided by elections with its peers
+    pub fn elected(self) -> ElectedBuilder<Unset, Unset, Unset> {
+        ElectedBuilder::default()
+    }
+
     pub fn follower(self) -> FollowerBuilder<Unset, Unset, Unset, Unset> {
         FollowerBuilder::default()
     }
@@ -1,39 +1,179 @@
-use std::sync::{Arc, Mutex};
-use tokio::task::{JoinError, JoinHandle};
+use std::sync::{Arc, RwLock};
+use tokio::task::JoinHandle;
 use tokki_api::TokkiClient;
+use url::Url;
 
-use crate::{app_state::builder::AppStateBuilder, replication::Replication, storage::Storage};
+use crate::{
+    app_state::builder::{AppStateBuilder, spawn_leader_poll},
+    cluster::ClusterMembership,
+    election::Election,
+    groups::ConsumerGroups,
+    timestamp_type::TimestampType,
+    topics::{Topic, TopicError, Topics},
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub profiling_enabled: bool,
-    pub inner: Arc<AppStateInner>,
+    /// The node's current role, swapped out when an election changes it
+    inner: Arc<RwLock<Arc<AppStateInner>>>,
+    topics: Arc<Topics>,
+    groups: Arc<ConsumerGroups>,
+    election: Option<Arc<Election>>,
+    membership: Arc<ClusterMembership>,
 }
 
 pub enum AppStateInner {
     Leader {
         token: String,
-        storage: Arc<dyn Storage>,
-        replication: Arc<Mutex<Replication>>,
-        required_replicas: usize,
+        topics: Arc<Topics>,
+        groups: Arc<ConsumerGroups>,
+        timestamp_type: TimestampType,
+        advertised_url: Option<Url>,
+        /// Leader epoch records put from now on are written in. Leaders that
+        /// were not elected all write in epoch zero.
+        leader_epoch: u64,
     },
     Follower {
         token: String,
-        storage: Arc<dyn Storage>,
+        topics: Arc<Topics>,
+        groups: Arc<ConsumerGroups>,
         leader_client: TokkiClient,
-        leader_poll_task: JoinHandle<Result<(), JoinError>>,
+        leader_poll_task: JoinHandle<()>,
+    },
+    /// No leader is known while an election is held. Reads are served from
+    /// what the node has, anything that needs the leader fails.
+    Electing {
+        token: String,
+        topics: Arc<Topics>,
+        groups: Arc<ConsumerGroups>,
     },
 }
 
+impl AppStateInner {
+    /// Used for authenticating requests within the cluster
+    pub(crate) fn token(&self) -> &str {
+        match self {
+            AppStateInner::Leader { token, .. }
+            | AppStateInner::Follower { token, .. }
+            | AppStateInner::Electing { token, .. } => token,
+        }
+    }
+
+    /// Follow `leader`, copying its log in the background
+    pub(crate) fn follower(
+        token: String,
+        topics: Arc<Topics>,
+        groups: Arc<ConsumerGroups>,
+        leader: Url,
+        follower_url: String,
+    ) -> Self {
+        let leader_client = TokkiClient::new(leader);
+        let leader_poll_task = spawn_leader_poll(
+            follower_url,
+            token.clone(),
+            topics.clone(),
+            leader_client.clone(),
+        );
+
+        AppStateInner::Follower {
+            token,
+            topics,
+            groups,
+            leader_client,
+            leader_poll_task,
+        }
+    }
+}
+
+/// A follower stops copying from its leader once it no longer follows it
+impl Drop for AppStateInner {
+    fn drop(&mut self) {
+        if let AppStateInner::Follower {
+            leader_poll_task, ..
+        } = self
+        {
+            leader_poll_task.abort();
+        }
+    }
+}
+
 impl AppState {
     pub fn builder() -> AppStateBuilder {
         AppStateBuilder {}
     }
 
-    pub fn storage(&self) -> &dyn Storage {
-        match self.inner.as_ref() {
-            AppStateInner::Leader { storage, .. } => storage.as_ref(),
-            AppStateInner::Follower { storage, .. } => storage.as_ref(),
+    pub(crate) fn new(
+        profiling_enabled: bool,
+        inner: AppStateInner,
+        election: Option<Arc<Election>>,
+        membership: Arc<ClusterMembership>,
+    ) -> Self {
+        let (topics, groups) = match &inner {
+            AppStateInner::Leader { topics, groups, .. }
+            | AppStateInner::Follower { topics, groups, .. }
+            | AppStateInner::Electing { topics, groups, .. } => (topics.clone(), groups.clone()),
+        };
+        Self {
+            profiling_enabled,
+            inner: Arc::new(RwLock::new(Arc::new(inner))),
+            topics,
+            groups,
+            election,
+            membership,
+        }
+    }
+
+    /// The node's role as it is now. A request holds on to the role it
+    /// started out with, even if an election changes it meanwhile.
+    pub fn inner(&self) -> Arc<AppStateInner> {
+        self.inner.read().expect("not poisoned").clone()
+    }
+
+    pub(crate) fn replace_inner(&self, inner: AppStateInner) {
+        let leading = matches!(inner, AppStateInner::Leader { .. });
+        let previous = std::mem::replace(
+            &mut *self.inner.write().expect("not poisoned"),
+            Arc::new(inner),
+        );
+        if !leading && matches!(*previous, AppStateInner::Leader { .. }) {
+            for topic in self.topics.all() {
+                for partition in topic.partitions() {
+                    let mut replication = partition.replication().lock().expect("not poisoned");
+                    replication.stop_leading();
+                }
+            }
+        }
+    }
+
+    pub fn topics(&self) -> &Arc<Topics> {
+        &self.topics
+    }
+
+    pub fn groups(&self) -> &Arc<ConsumerGroups> {
+        &self.groups
+    }
+
+    /// Elections this node takes part in, unless its role was fixed at startup
+    pub fn election(&self) -> Option<&Arc<Election>> {
+        self.election.as_ref()
+    }
+
+    /// Followers counted towards the required replicas while this node leads
+    pub fn membership(&self) -> &Arc<ClusterMembership> {
+        &self.membership
+    }
+
+    pub fn topic(&self, name: &str) -> Result<Arc<Topic>, TopicError> {
+        self.topics().get(name)
+    }
+
+    /// URL of the node taking puts, if known
+    pub fn leader_url(&self) -> Option<Url> {
+        match self.inner().as_ref() {
+            AppStateInner::Leader { advertised_url, .. } => advertised_url.clone(),
+            AppStateInner::Follower { leader_client, .. } => Some(leader_client.base_url().clone()),
+            AppStateInner::Electing { .. } => None,
         }
     }
 }
@@ -1,6 +1,15 @@
+use std::{path::PathBuf, time::Duration};
+
 use clap::{Parser, Subcommand};
+use tokki_api::topics::TopicConfig;
 use url::Url;
 
+use crate::{
+    replication::InSyncConfig,
+    storage::{CorruptRecordPolicy, DurabilityPolicy},
+    timestamp_type::TimestampType,
+};
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
@@ -10,8 +19,83 @@ pub struct Cli {
     /// Port to listen on
     #[arg(short, long, default_value_t = 9999)]
     pub port: u16,
+    /// URL clients and other nodes reach this node on, reported as the leader
+    /// of its partitions and to the leader when following it. Defaults to
+    /// localhost on the port listened on.
+    #[arg(long)]
+    pub advertised_url: Option<Url>,
     #[arg(long)]
     pub storage: CliStorageEngine,
+    /// Directory topics are kept in, required by the disk storage engine.
+    /// Other engines keep only the list of topics there, not their records.
+    #[arg(long, required_if_eq("storage", "disk"))]
+    pub data_dir: Option<PathBuf>,
+    /// When the disk storage engine fsyncs writes before acknowledging them
+    #[arg(long, value_enum, default_value = "group-commit")]
+    pub durability: CliDurability,
+    /// Longest a write waits for a group commit fsync, in milliseconds
+    #[arg(long, default_value_t = 10)]
+    pub group_commit_ms: u64,
+    /// Number of pending bytes that trigger a group commit fsync early
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub group_commit_bytes: u64,
+    /// Number of partitions for topics created without saying otherwise
+    #[arg(long, default_value_t = 1)]
+    pub partitions: u32,
+    /// Remove records older than this many seconds
+    #[arg(long)]
+    pub retention_max_age_secs: Option<u64>,
+    /// Remove the oldest records once a topic is larger than this many bytes
+    #[arg(long)]
+    pub retention_max_bytes: Option<u64>,
+    /// Remove the oldest records once a topic holds more than this many records
+    #[arg(long)]
+    pub retention_max_records: Option<usize>,
+    /// How often retention is enforced, in seconds
+    #[arg(long, default_value_t = 60)]
+    pub retention_check_secs: u64,
+    /// Which time the leader stores in record timestamps
+    #[arg(long, value_enum, default_value = "create-time")]
+    pub timestamp_type: CliTimestampType,
+    /// Encrypt the disk storage engine's log with keys from this file. Each
+    /// line holds a key id and a 32 byte hex key, new data is encrypted with
+    /// the highest id and older keys are kept to read data written before a
+    /// rotation. Other storage engines refuse to start with one.
+    #[arg(long)]
+    pub encryption_key_file: Option<PathBuf>,
+    /// What reads do when a stored record fails to decode
+    #[arg(long, value_enum, default_value = "fail")]
+    pub corrupt_records: CliCorruptRecords,
+    /// Compact topics that don't say otherwise, keeping only the latest record for each key
+    #[arg(long)]
+    pub compaction: bool,
+    /// How long tombstones are kept before compaction removes them, in seconds
+    #[arg(long, default_value_t = 24 * 60 * 60)]
+    pub tombstone_retention_secs: u64,
+    /// How often the log is compacted, in seconds
+    #[arg(long, default_value_t = 60)]
+    pub compaction_check_secs: u64,
+    /// How often consumer group lag is reported to metrics, in seconds
+    #[arg(long, default_value_t = 15)]
+    pub lag_metrics_secs: u64,
+    /// Remove members that go this many seconds without polling the leader,
+    /// idle followers poll about once a second. Members are only removed by
+    /// hand without it.
+    #[arg(long)]
+    pub follower_timeout_secs: Option<u64>,
+    /// Drop members from a partition's in-sync replicas when they go this
+    /// many milliseconds without catching up with the leader
+    #[arg(long, default_value_t = 3000)]
+    pub replica_lag_ms: u64,
+    /// Drop members from a partition's in-sync replicas when they poll while
+    /// more than this many records behind
+    #[arg(long)]
+    pub replica_lag_records: Option<usize>,
+    /// Fail puts while fewer members than this are in sync, rather than
+    /// acknowledge them on fewer copies than required. Defaults to the
+    /// required replicas.
+    #[arg(long)]
+    pub min_insync_replicas: Option<usize>,
     /// Should profiling endpoints be enabled?
     #[arg(long)]
     pub enable_profiling: bool,
@@ -24,6 +108,92 @@ pub enum CliStorageEngine {
     InMemoryMutex,
     InMemoryChannel,
     InMemoryLockFree,
+    Disk,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum CliDurability {
+    /// Fsync every record before acknowledging it
+    Always,
+    /// Batch up fsyncs across concurrent writers
+    GroupCommit,
+    /// Leave flushing to the OS
+    Os,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum CliCorruptRecords {
+    /// Fail the read
+    Fail,
+    /// Skip over corrupt records
+    Skip,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum CliTimestampType {
+    /// Keep the producer's timestamp, stamping records that have none
+    CreateTime,
+    /// Stamp every record with the time the leader appended it
+    LogAppendTime,
+}
+
+impl Cli {
+    pub fn durability_policy(&self) -> DurabilityPolicy {
+        match self.durability {
+            CliDurability::Always => DurabilityPolicy::Always,
+            CliDurability::GroupCommit => DurabilityPolicy::GroupCommit {
+                max_delay: Duration::from_millis(self.group_commit_ms),
+                max_bytes: self.group_commit_bytes,
+            },
+            CliDurability::Os => DurabilityPolicy::Os,
+        }
+    }
+
+    pub fn timestamp_type(&self) -> TimestampType {
+        match self.timestamp_type {
+            CliTimestampType::CreateTime => TimestampType::CreateTime,
+            CliTimestampType::LogAppendTime => TimestampType::LogAppendTime,
+        }
+    }
+
+    pub fn corrupt_record_policy(&self) -> CorruptRecordPolicy {
+        match self.corrupt_records {
+            CliCorruptRecords::Fail => CorruptRecordPolicy::Fail,
+            CliCorruptRecords::Skip => CorruptRecordPolicy::Skip,
+        }
+    }
+
+    /// URL this node is reached on
+    pub fn advertised_url(&self) -> Url {
+        self.advertised_url.clone().unwrap_or_else(|| {
+            Url::parse(&format!("http://localhost:{}", self.port)).expect("valid URL")
+        })
+    }
+
+    /// When members count as in sync with the leader
+    pub fn in_sync_config(&self) -> InSyncConfig {
+        let mut config =
+            InSyncConfig::new().with_max_lag(Duration::from_millis(self.replica_lag_ms));
+        if let Some(replica_lag_records) = self.replica_lag_records {
+            config = config.with_max_lag_records(replica_lag_records);
+        }
+        if let Some(min_insync_replicas) = self.min_insync_replicas {
+            config = config.with_min_insync_replicas(min_insync_replicas);
+        }
+        config
+    }
+
+    /// Settings for topics created without their own
+    pub fn default_topic_config(&self) -> TopicConfig {
+        TopicConfig {
+            partitions: Some(self.partitions),
+            retention_max_age_secs: self.retention_max_age_secs,
+            retention_max_bytes: self.retention_max_bytes,
+            retention_max_records: self.retention_max_records,
+            compaction: Some(self.compaction),
+            tombstone_retention_secs: Some(self.tombstone_retention_secs),
+        }
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -33,6 +203,10 @@ pub enum CliMode {
         /// The number of replicas required for a record before it is considered committed
         #[arg(short, long)]
         required_replicas: usize,
+        /// URLs of the followers counted towards the required replicas,
+        /// separated by commas. Others copy the log without being waited for.
+        #[arg(long, value_delimiter = ',')]
+        member: Vec<Url>,
     },
     /// Start this node as a follower, copies the leaders log
     Follower {
@@ -40,4 +214,24 @@ pub enum CliMode {
         #[arg(short, long)]
         leader: Url,
     },
+    /// Elect a leader among this node and its peers, taking over when the
+    /// leader is lost. Every node is started with the same settings and its
+    /// own advertised URL, which the others list as a peer.
+    Elected {
+        /// URLs of every other node in the cluster, separated by commas. Each
+        /// starts out as a member, and elections are held among whichever
+        /// nodes are members at the time.
+        #[arg(long, value_delimiter = ',', required = true)]
+        peers: Vec<Url>,
+        /// The number of replicas required for a record before it is considered committed
+        #[arg(short, long)]
+        required_replicas: usize,
+        /// Shortest time without hearing from the leader before starting an
+        /// election, in milliseconds
+        #[arg(long, default_value_t = 1500)]
+        election_timeout_ms: u64,
+        /// How often the leader tells the other nodes it is still there, in milliseconds
+        #[arg(long, default_value_t = 500)]
+        heartbeat_interval_ms: u64,
+    },
 }
@@ -0,0 +1,36 @@
+use std::{io, path::PathBuf};
+
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum ClusterError {
+    #[snafu(display("Failed to read cluster members from {}: {source}", path.display()))]
+    ReadMembers { path: PathBuf, source: io::Error },
+    #[snafu(display("Failed to parse cluster members from {}: {source}", path.display()))]
+    ParseMembers {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[snafu(display("Failed to write cluster members to {}: {source}", path.display()))]
+    WriteMembers { path: PathBuf, source: io::Error },
+    #[snafu(display("Invalid member URL {url}: {source}"))]
+    InvalidUrl {
+        url: String,
+        source: url::ParseError,
+    },
+    #[snafu(display("Members are already being changed"))]
+    ChangeInProgress,
+    #[snafu(display("{url} is already a member"))]
+    AlreadyMember { url: String },
+    #[snafu(display("{url} is not a member"))]
+    NotMember { url: String },
+    #[snafu(display(
+        "Removing {url} would leave {members} followers as members, fewer than the {required_replicas} required"
+    ))]
+    TooFewMembers {
+        url: String,
+        members: usize,
+        required_replicas: usize,
+    },
+}
@@ -0,0 +1,419 @@
+mod cluster_error;
+
+pub use cluster_error::ClusterError;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use snafu::{ResultExt as _, ensure};
+use tokio::time::Instant;
+use tokki_api::cluster::ClusterMembers;
+use url::Url;
+
+use crate::{
+    app_state::{AppState, AppStateInner},
+    cluster::cluster_error::{
+        AlreadyMemberSnafu, ChangeInProgressSnafu, InvalidUrlSnafu, NotMemberSnafu,
+        ParseMembersSnafu, ReadMembersSnafu, TooFewMembersSnafu, WriteMembersSnafu,
+    },
+    replication::InSyncChange,
+    state_file::write_atomically,
+    topics::Topics,
+};
+
+/// File in the state directory holding the members
+const MEMBERS_FILE: &str = "cluster-members.json";
+/// How often the leader looks for member changes that can complete, and
+/// for followers that stopped polling
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Which nodes start out as the cluster's members, and how they are kept
+#[derive(Debug, Clone, Default)]
+pub struct MembershipConfig {
+    members: Vec<Url>,
+    state_dir: Option<PathBuf>,
+    follower_timeout: Option<Duration>,
+}
+
+impl MembershipConfig {
+    /// Start out with `members`, unless others were kept in the state directory
+    pub fn new(members: Vec<Url>) -> Self {
+        Self {
+            members,
+            ..Default::default()
+        }
+    }
+
+    /// Keep the members in this directory, so changes to them survive a
+    /// restart. Without one they are kept in memory.
+    pub fn with_state_dir(mut self, state_dir: impl Into<PathBuf>) -> Self {
+        self.state_dir = Some(state_dir.into());
+        self
+    }
+
+    /// Remove members that go this long without polling the leader. Without
+    /// one members are only ever removed by hand. Followers with nothing new
+    /// to copy poll about once a second, so it should be well above that.
+    pub fn with_follower_timeout(mut self, follower_timeout: Duration) -> Self {
+        self.follower_timeout = Some(follower_timeout);
+        self
+    }
+
+    fn members_path(&self) -> Option<PathBuf> {
+        self.state_dir
+            .as_ref()
+            .map(|state_dir| state_dir.join(MEMBERS_FILE))
+    }
+}
+
+struct MembershipState {
+    members: ClusterMembers,
+    /// URL this node is known by, it never counts its own copies
+    own_url: Option<String>,
+    /// When each follower last polled this node while it led
+    last_polled: HashMap<String, Instant>,
+    /// When this node took the lead, or last had no partitions to be polled
+    /// for. Followers are given until the timeout from then to start polling.
+    leading_since: Option<Instant>,
+}
+
+/// The nodes whose copies count towards the leader's required replicas.
+///
+/// Members change one at a time, in two steps so quorums stay correct
+/// throughout. The change starts with the members being changed to joining
+/// the current ones, and while they do records are only committed once they
+/// have enough copies among both. Once the joining members have copies of
+/// everything committed on every partition they replace the current ones.
+/// The leader shares its members with the other nodes it holds elections
+/// with, so whichever leads next counts the same followers.
+pub struct ClusterMembership {
+    config: MembershipConfig,
+    state: Mutex<MembershipState>,
+}
+
+impl Default for ClusterMembership {
+    fn default() -> Self {
+        Self::in_memory(Vec::new())
+    }
+}
+
+impl ClusterMembership {
+    /// Load the members kept in the state directory, or start out with the
+    /// ones configured
+    pub fn open(config: MembershipConfig) -> Result<Self, ClusterError> {
+        let members = match config.members_path() {
+            Some(path) => match fs::read(&path) {
+                Ok(buf) => Some(serde_json::from_slice(&buf).context(ParseMembersSnafu { path })?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(source) => return Err(source).context(ReadMembersSnafu { path }),
+            },
+            None => None,
+        };
+        let members = members
+            .unwrap_or_else(|| ClusterMembers::new(config.members.iter().map(Url::to_string)));
+        tracing::info!(?members, "Opened cluster members");
+
+        Ok(Self {
+            config,
+            state: Mutex::new(MembershipState {
+                members,
+                own_url: None,
+                last_polled: HashMap::new(),
+                leading_since: None,
+            }),
+        })
+    }
+
+    /// Members kept in memory, starting out as `members`
+    pub(crate) fn in_memory(members: Vec<Url>) -> Self {
+        Self::open(MembershipConfig::new(members)).expect("Nothing to read without a state dir")
+    }
+
+    pub fn members(&self) -> ClusterMembers {
+        self.state.lock().expect("not poisoned").members.clone()
+    }
+
+    /// Count the members other than `own_url` on every partition, then
+    /// complete changes, drop members that fall behind from the in-sync
+    /// replicas and remove followers that stop polling in the background
+    /// whenever this node leads
+    pub(crate) fn start(self: &Arc<Self>, app_state: AppState, own_url: Option<&Url>) {
+        {
+            let mut state = self.state.lock().expect("not poisoned");
+            state.own_url = own_url.map(Url::to_string);
+            app_state.topics().set_members(state.counted());
+        }
+
+        let membership = self.clone();
+        tokio::task::spawn(async move {
+            loop {
+                tokio::time::sleep(CHECK_INTERVAL).await;
+                membership.check(&app_state);
+            }
+        });
+    }
+
+    /// Start adding `url` to the members
+    pub fn add(&self, topics: &Topics, url: &str) -> Result<ClusterMembers, ClusterError> {
+        let url = member_url(url)?;
+        let mut state = self.state.lock().expect("not poisoned");
+        ensure!(state.members.joining.is_none(), ChangeInProgressSnafu);
+        ensure!(
+            !state.members.members.contains(&url),
+            AlreadyMemberSnafu { url }
+        );
+
+        let mut joining = state.members.members.clone();
+        joining.insert(url.clone());
+        self.change(&mut state, topics, joining)?;
+
+        tracing::info!(url, "Adding member");
+        Ok(state.members.clone())
+    }
+
+    /// Start removing `url` from the members. The leader never counts towards
+    /// its own required replicas, so members must be left with enough other
+    /// followers.
+    pub fn remove(
+        &self,
+        topics: &Topics,
+        url: &str,
+        leader_url: Option<&Url>,
+    ) -> Result<ClusterMembers, ClusterError> {
+        let url = member_url(url)?;
+        let mut state = self.state.lock().expect("not poisoned");
+        ensure!(state.members.joining.is_none(), ChangeInProgressSnafu);
+        ensure!(state.members.members.contains(&url), NotMemberSnafu { url });
+
+        let mut joining = state.members.members.clone();
+        joining.remove(&url);
+        let leader_url = leader_url.map(Url::to_string);
+        let followers = joining
+            .iter()
+            .filter(|member| Some(*member) != leader_url.as_ref())
+            .count();
+        let required_replicas = topics.required_replicas();
+        ensure!(
+            followers >= required_replicas,
+            TooFewMembersSnafu {
+                url,
+                members: followers,
+                required_replicas,
+            }
+        );
+        self.change(&mut state, topics, joining)?;
+
+        tracing::info!(url, "Removing member");
+        Ok(state.members.clone())
+    }
+
+    /// Note that `follower` polled this node for records
+    pub(crate) fn polled(&self, follower: &str) {
+        let mut state = self.state.lock().expect("not poisoned");
+        state
+            .last_polled
+            .insert(follower.to_string(), Instant::now());
+    }
+
+    /// Take on the members of the leader, as another node that could lead
+    pub(crate) fn follow(&self, topics: &Topics, members: ClusterMembers) {
+        let mut state = self.state.lock().expect("not poisoned");
+        if state.members == members {
+            return;
+        }
+
+        if let Err(e) = self.persist(&members) {
+            tracing::error!("Failed to keep the leader's members: {}", e);
+            return;
+        }
+        tracing::info!(?members, "Following the leader's members");
+        state.members = members;
+        topics.set_members(state.counted());
+    }
+
+    /// Drop members that fell behind from the in-sync replicas, complete a
+    /// change that has caught up, and start removing a member that stopped
+    /// polling, if this node leads
+    fn check(&self, app_state: &AppState) {
+        let inner = app_state.inner();
+        let mut state = self.state.lock().expect("not poisoned");
+        let AppStateInner::Leader { advertised_url, .. } = inner.as_ref() else {
+            state.leading_since = None;
+            return;
+        };
+        let topics = app_state.topics();
+        let now = Instant::now();
+        check_in_sync(topics, now);
+
+        if state.members.joining.is_some() && caught_up(topics) {
+            self.complete(&mut state, topics);
+        }
+
+        let Some(follower_timeout) = self.config.follower_timeout else {
+            return;
+        };
+        // Followers only poll for partitions, so none are quiet without any
+        if topics.all().is_empty() {
+            state.leading_since = Some(now);
+            return;
+        }
+        let leading_since = *state.leading_since.get_or_insert(now);
+        let quiet = |state: &MembershipState, follower: &str| {
+            let last_polled = state
+                .last_polled
+                .get(follower)
+                .map_or(leading_since, |last_polled| {
+                    (*last_polled).max(leading_since)
+                });
+            now.duration_since(last_polled) > follower_timeout
+        };
+
+        // Followers that were never members are only forgotten
+        let members: BTreeSet<_> = state.members.all().into_iter().map(String::from).collect();
+        let gone: Vec<_> = state
+            .last_polled
+            .keys()
+            .filter(|follower| !members.contains(*follower) && quiet(&state, follower))
+            .cloned()
+            .collect();
+        for follower in gone {
+            state.last_polled.remove(&follower);
+            forget(topics, &follower);
+        }
+
+        if state.members.joining.is_some() {
+            return;
+        }
+        let leader_url = advertised_url.as_ref().map(Url::to_string);
+        let Some(member) = state
+            .members
+            .members
+            .iter()
+            .find(|member| Some(*member) != leader_url.as_ref() && quiet(&state, member))
+            .cloned()
+        else {
+            return;
+        };
+        drop(state);
+
+        match self.remove(topics, &member, advertised_url.as_ref()) {
+            Ok(_) => {
+                tracing::warn!(
+                    member,
+                    timeout_ms = follower_timeout.as_millis() as u64,
+                    "Removing member that stopped polling"
+                );
+                metrics::counter!("cluster_members_timed_out").increment(1);
+            }
+            Err(e) => tracing::debug!(member, "Keeping member that stopped polling: {}", e),
+        }
+    }
+
+    /// Start changing the members to `joining`
+    fn change(
+        &self,
+        state: &mut MembershipState,
+        topics: &Topics,
+        joining: BTreeSet<String>,
+    ) -> Result<(), ClusterError> {
+        let mut members = state.members.clone();
+        members.joining = Some(joining);
+        self.persist(&members)?;
+
+        state.members = members;
+        topics.set_members(state.counted());
+        Ok(())
+    }
+
+    /// Count only the joining members from now on
+    fn complete(&self, state: &mut MembershipState, topics: &Topics) {
+        let joining = state.members.joining.clone().expect("A change under way");
+        let members = ClusterMembers::new(joining);
+        if let Err(e) = self.persist(&members) {
+            tracing::error!("Failed to complete member change: {}", e);
+            return;
+        }
+
+        for removed in state.members.members.difference(&members.members) {
+            forget(topics, removed);
+        }
+        tracing::info!(members = ?members.members, "Completed member change");
+        metrics::gauge!("cluster_members").set(members.members.len() as f64);
+        state.members = members;
+        topics.set_members(state.counted());
+    }
+
+    fn persist(&self, members: &ClusterMembers) -> Result<(), ClusterError> {
+        let Some(path) = self.config.members_path() else {
+            return Ok(());
+        };
+
+        // Synced, as the leader acts on a change as soon as it is made
+        let buf = serde_json::to_vec_pretty(members).expect("Cluster members serialize");
+        write_atomically(&path, &buf).context(WriteMembersSnafu { path })
+    }
+}
+
+impl MembershipState {
+    /// The members whose copies this node counts
+    fn counted(&self) -> ClusterMembers {
+        let mut members = self.members.clone();
+        if let Some(own_url) = &self.own_url {
+            members.members.remove(own_url);
+            if let Some(joining) = &mut members.joining {
+                joining.remove(own_url);
+            }
+        }
+        members
+    }
+}
+
+/// Members are known by their URL, written the same way however it was given
+pub(crate) fn member_url(url: &str) -> Result<String, ClusterError> {
+    Url::parse(url)
+        .map(String::from)
+        .context(InvalidUrlSnafu { url })
+}
+
+/// Whether the joining members have caught up on every partition
+fn caught_up(topics: &Topics) -> bool {
+    topics.all().iter().all(|topic| {
+        topic.partitions().iter().all(|partition| {
+            partition
+                .replication()
+                .lock()
+                .expect("not poisoned")
+                .joining_caught_up()
+        })
+    })
+}
+
+/// Drop members that have not caught up in time from every partition's
+/// in-sync replicas
+fn check_in_sync(topics: &Topics, now: Instant) {
+    for topic in topics.all() {
+        for partition in topic.partitions() {
+            let mut replication = partition.replication().lock().expect("not poisoned");
+            let changes = replication.check_in_sync(now);
+            let in_sync = replication.in_sync().count();
+            InSyncChange::record(&changes, topic.name(), partition.index(), in_sync);
+        }
+    }
+}
+
+fn forget(topics: &Topics, follower: &str) {
+    for topic in topics.all() {
+        for partition in topic.partitions() {
+            partition
+                .replication()
+                .lock()
+                .expect("not poisoned")
+                .forget_follower(follower);
+        }
+    }
+}
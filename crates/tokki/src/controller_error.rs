@@ -1,24 +1,50 @@
-use std::{io, string::FromUtf8Error};
+use std::string::FromUtf8Error;
 
 use axum::{Json, body::Body, http::Response, response::IntoResponse};
 use reqwest::StatusCode;
 use snafu::Snafu;
 use tokki_api::{ApiErrorResponse, ClientError};
-use tokki_common::hmac::HmacError;
+use tokki_common::{DecodeError, Offset, hmac::HmacError};
+
+use crate::{
+    cluster::ClusterError, election::ElectionError, groups::GroupError, storage::StorageError,
+    topics::TopicError,
+};
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(crate)))]
 pub enum ControllerError {
     #[snafu(display("Failed to replicate after {timeout_s}s"))]
     Replication { timeout_s: i32 },
+    #[snafu(display("Stopped leading before the records were committed"))]
+    LeadershipLost,
+    #[snafu(display(
+        "Only {in_sync} followers are in sync, fewer than the {min_insync_replicas} required"
+    ))]
+    NotEnoughReplicas {
+        in_sync: usize,
+        min_insync_replicas: usize,
+    },
     #[snafu(display("HMAC signature invalid"))]
     Hmac { source: HmacError },
     #[snafu(display("Failure when forwarding to leader"))]
     LeaderForwarding { source: ClientError, leader: String },
     #[snafu(display("Follower cannot service this request"))]
     IsFollower { leader: String },
-    #[snafu(display("I/O error"))]
-    Io { source: io::Error },
+    #[snafu(display("No leader is known while an election is held"))]
+    NoLeader,
+    #[snafu(display("{source}"))]
+    Election { source: ElectionError },
+    #[snafu(display("Storage error: {source}"))]
+    Storage { source: StorageError },
+    #[snafu(display("Record at offset {} is corrupt: {source}", offset.0))]
+    CorruptRecord { offset: Offset, source: DecodeError },
+    #[snafu(display("{source}"))]
+    Topic { source: TopicError },
+    #[snafu(display("{source}"))]
+    Group { source: GroupError },
+    #[snafu(display("{source}"))]
+    Cluster { source: ClusterError },
     // Profiling
     #[snafu(display("Profiling is disabled"))]
     ProfilingDisabled,
@@ -36,6 +62,31 @@ pub enum ControllerError {
     Flamegraph { source: FromUtf8Error },
 }
 
+/// Corrupt records get their own variant so readers can tell them apart from
+/// other storage failures
+impl From<StorageError> for ControllerError {
+    fn from(source: StorageError) -> Self {
+        match source {
+            StorageError::CorruptRecord { offset, source } => {
+                ControllerError::CorruptRecord { offset, source }
+            }
+            source => ControllerError::Storage { source },
+        }
+    }
+}
+
+/// Group errors caused by a topic or the storage get the same response as
+/// they would anywhere else
+impl From<GroupError> for ControllerError {
+    fn from(source: GroupError) -> Self {
+        match source {
+            GroupError::Topic { source } => ControllerError::Topic { source },
+            GroupError::Storage { source } => source.into(),
+            source => ControllerError::Group { source },
+        }
+    }
+}
+
 impl IntoResponse for ControllerError {
     fn into_response(self) -> Response<Body> {
         let message = self.to_string();
@@ -43,6 +94,8 @@ impl IntoResponse for ControllerError {
 
         let (status, prefer) = match self {
             ControllerError::Replication { .. } => (StatusCode::INTERNAL_SERVER_ERROR, None),
+            ControllerError::LeadershipLost => (StatusCode::SERVICE_UNAVAILABLE, None),
+            ControllerError::NotEnoughReplicas { .. } => (StatusCode::SERVICE_UNAVAILABLE, None),
             ControllerError::Hmac { .. } => (StatusCode::UNAUTHORIZED, None),
             ControllerError::LeaderForwarding { leader, .. } => {
                 (StatusCode::INTERNAL_SERVER_ERROR, Some(leader))
@@ -50,7 +103,58 @@ impl IntoResponse for ControllerError {
             ControllerError::IsFollower { leader } => {
                 (StatusCode::MISDIRECTED_REQUEST, Some(leader))
             }
-            ControllerError::Io { .. } => (StatusCode::INTERNAL_SERVER_ERROR, None),
+            ControllerError::NoLeader => (StatusCode::SERVICE_UNAVAILABLE, None),
+            ControllerError::Election {
+                source: ElectionError::NotElected,
+            } => (StatusCode::NOT_FOUND, None),
+            ControllerError::Election { .. } => (StatusCode::INTERNAL_SERVER_ERROR, None),
+            ControllerError::Storage {
+                source: StorageError::OffsetOutOfRange { .. },
+            } => (StatusCode::RANGE_NOT_SATISFIABLE, None),
+            ControllerError::Storage {
+                source: StorageError::CorruptBatch { .. },
+            } => (StatusCode::UNPROCESSABLE_ENTITY, None),
+            ControllerError::Storage { .. } => (StatusCode::INTERNAL_SERVER_ERROR, None),
+            ControllerError::CorruptRecord { .. } => (StatusCode::UNPROCESSABLE_ENTITY, None),
+            ControllerError::Topic {
+                source:
+                    TopicError::InvalidName { .. }
+                    | TopicError::ReservedName { .. }
+                    | TopicError::InvalidPartitionCount { .. },
+            } => (StatusCode::BAD_REQUEST, None),
+            ControllerError::Topic {
+                source: TopicError::AlreadyExists { .. },
+            } => (StatusCode::CONFLICT, None),
+            ControllerError::Topic {
+                source: TopicError::NotFound { .. } | TopicError::PartitionNotFound { .. },
+            } => (StatusCode::NOT_FOUND, None),
+            ControllerError::Topic { .. } => (StatusCode::INTERNAL_SERVER_ERROR, None),
+            ControllerError::Group {
+                source:
+                    GroupError::InvalidName { .. }
+                    | GroupError::InvalidMemberId { .. }
+                    | GroupError::InvalidSessionTimeout { .. },
+            } => (StatusCode::BAD_REQUEST, None),
+            ControllerError::Group {
+                source: GroupError::UnknownGroup { .. } | GroupError::UnknownMember { .. },
+            } => (StatusCode::NOT_FOUND, None),
+            ControllerError::Group {
+                source: GroupError::Mismatch { .. },
+            } => (StatusCode::CONFLICT, None),
+            ControllerError::Group { .. } => (StatusCode::INTERNAL_SERVER_ERROR, None),
+            ControllerError::Cluster {
+                source: ClusterError::InvalidUrl { .. },
+            } => (StatusCode::BAD_REQUEST, None),
+            ControllerError::Cluster {
+                source:
+                    ClusterError::ChangeInProgress
+                    | ClusterError::AlreadyMember { .. }
+                    | ClusterError::TooFewMembers { .. },
+            } => (StatusCode::CONFLICT, None),
+            ControllerError::Cluster {
+                source: ClusterError::NotMember { .. },
+            } => (StatusCode::NOT_FOUND, None),
+            ControllerError::Cluster { .. } => (StatusCode::INTERNAL_SERVER_ERROR, None),
             ControllerError::ProfilingDisabled => (StatusCode::FORBIDDEN, None),
             ControllerError::ProfilingNotActive => (StatusCode::BAD_REQUEST, None),
             ControllerError::ProfilingActive => (StatusCode::BAD_REQUEST, None),
@@ -0,0 +1,115 @@
+use axum::{Json, extract::State};
+use snafu::ResultExt as _;
+use tokki_api::cluster::{
+    ClusterMembers, InSyncReplicasResponse, MemberRequest, PartitionReplicas,
+};
+use tokki_common::hmac::HmacForm;
+
+use crate::{
+    app_state::{AppState, AppStateInner},
+    controller_error::{
+        ClusterSnafu, ControllerError, HmacSnafu, LeaderForwardingSnafu, NoLeaderSnafu,
+    },
+    topics::Topics,
+};
+
+pub async fn list_members(State(state): State<AppState>) -> Json<ClusterMembers> {
+    Json(state.membership().members())
+}
+
+/// Start adding a member on the leader, it completes once the new member has
+/// caught up
+pub async fn add_member(
+    State(state): State<AppState>,
+    Json(req): Json<HmacForm<MemberRequest>>,
+) -> Result<Json<ClusterMembers>, ControllerError> {
+    let inner = state.inner();
+    let req = req.into_verified(inner.token()).context(HmacSnafu)?;
+    match inner.as_ref() {
+        AppStateInner::Leader { topics, .. } => state
+            .membership()
+            .add(topics, &req.url)
+            .context(ClusterSnafu)
+            .map(Json),
+        AppStateInner::Follower {
+            token,
+            leader_client,
+            ..
+        } => leader_client
+            .add_member(req, token)
+            .await
+            .with_context(|_| LeaderForwardingSnafu {
+                leader: leader_client.base_url().to_string(),
+            })
+            .map(Json),
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+/// Start removing a member on the leader
+pub async fn remove_member(
+    State(state): State<AppState>,
+    Json(req): Json<HmacForm<MemberRequest>>,
+) -> Result<Json<ClusterMembers>, ControllerError> {
+    let inner = state.inner();
+    let req = req.into_verified(inner.token()).context(HmacSnafu)?;
+    match inner.as_ref() {
+        AppStateInner::Leader {
+            topics,
+            advertised_url,
+            ..
+        } => state
+            .membership()
+            .remove(topics, &req.url, advertised_url.as_ref())
+            .context(ClusterSnafu)
+            .map(Json),
+        AppStateInner::Follower {
+            token,
+            leader_client,
+            ..
+        } => leader_client
+            .remove_member(req, token)
+            .await
+            .with_context(|_| LeaderForwardingSnafu {
+                leader: leader_client.base_url().to_string(),
+            })
+            .map(Json),
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+/// How far each follower of every partition has got on the leader, and which
+/// of them are in sync
+pub async fn get_in_sync_replicas(
+    State(state): State<AppState>,
+) -> Result<Json<InSyncReplicasResponse>, ControllerError> {
+    match state.inner().as_ref() {
+        AppStateInner::Leader { topics, .. } => Ok(Json(in_sync_replicas(topics))),
+        AppStateInner::Follower { leader_client, .. } => leader_client
+            .get_in_sync_replicas()
+            .await
+            .with_context(|_| LeaderForwardingSnafu {
+                leader: leader_client.base_url().to_string(),
+            })
+            .map(Json),
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+fn in_sync_replicas(topics: &Topics) -> InSyncReplicasResponse {
+    let mut partitions = Vec::new();
+    for topic in topics.all() {
+        for partition in topic.partitions() {
+            let replication = partition.replication().lock().expect("not poisoned");
+            partitions.push(PartitionReplicas {
+                topic: topic.name().to_string(),
+                partition: partition.index(),
+                high_watermark: replication.high_watermark(),
+                log_end: replication.log_end(),
+                min_insync_replicas: replication.min_insync_replicas(),
+                replicas: replication.replicas(),
+            });
+        }
+    }
+    InSyncReplicasResponse::new(partitions)
+}
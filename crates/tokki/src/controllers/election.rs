@@ -0,0 +1,48 @@
+use axum::{Json, extract::State};
+use snafu::{OptionExt as _, ResultExt as _};
+use tokki_api::clustering::{
+    LeaderHeartbeatRequest, LeaderHeartbeatResponse, VoteRequest, VoteResponse,
+};
+use tokki_common::hmac::HmacForm;
+
+use crate::{
+    app_state::AppState,
+    controller_error::{ControllerError, ElectionSnafu, HmacSnafu},
+    election::NotElectedSnafu,
+};
+
+/// Vote for a candidate in an election, or not
+pub async fn request_vote(
+    State(state): State<AppState>,
+    Json(req): Json<HmacForm<VoteRequest>>,
+) -> Result<Json<HmacForm<VoteResponse>>, ControllerError> {
+    let election = state
+        .election()
+        .context(NotElectedSnafu)
+        .context(ElectionSnafu)?;
+    let req = req.into_verified(election.token()).context(HmacSnafu)?;
+
+    let res = election.vote(&state, req).await.context(ElectionSnafu)?;
+
+    Ok(Json(HmacForm::new(res, election.token())))
+}
+
+/// Hear from the leader, following it if it is new
+pub async fn leader_heartbeat(
+    State(state): State<AppState>,
+    Json(req): Json<HmacForm<LeaderHeartbeatRequest>>,
+) -> Result<Json<HmacForm<LeaderHeartbeatResponse>>, ControllerError> {
+    let election = state
+        .election()
+        .context(NotElectedSnafu)
+        .context(ElectionSnafu)?;
+    let req = req.into_verified(election.token()).context(HmacSnafu)?;
+
+    let members = req.members.clone();
+    let res = election.heartbeat(&state, req).context(ElectionSnafu)?;
+    if res.accepted {
+        state.membership().follow(state.topics(), members);
+    }
+
+    Ok(Json(HmacForm::new(res, election.token())))
+}
@@ -0,0 +1,34 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use snafu::ResultExt as _;
+use tokio::time::Instant;
+use tokki_api::get_offset::{GetOffsetForTimestampRequest, GetOffsetForTimestampResponse};
+
+use crate::{
+    app_state::AppState,
+    controller_error::{ControllerError, TopicSnafu},
+};
+
+pub async fn get_offset_for_timestamp(
+    State(state): State<AppState>,
+    Path(topic): Path<String>,
+    Json(req): Json<GetOffsetForTimestampRequest>,
+) -> Result<Json<GetOffsetForTimestampResponse>, ControllerError> {
+    let start = Instant::now();
+    let topic = state.topic(&topic).context(TopicSnafu)?;
+    let partition = topic.partition(req.partition).context(TopicSnafu)?;
+    let offset = partition
+        .storage()
+        .offset_for_timestamp(req.timestamp)
+        .await?;
+    metrics::histogram!(
+        "get_offset_for_timestamp",
+        "topic" => topic.name().to_string(),
+        "partition" => req.partition.to_string()
+    )
+    .record(start.elapsed());
+
+    Ok(Json(GetOffsetForTimestampResponse::new(offset)))
+}
@@ -1,73 +1,209 @@
-use axum::{Json, extract::State};
+use std::convert::Infallible;
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
 use snafu::ResultExt as _;
 use tokio::time::Instant;
 use tokki_api::{
-    clustering::{ReplicateLogRequest, ReplicateLogResponse},
-    get_records::{GetRecordsRequest, GetRecordsResponse},
+    clustering::{Divergence, ReplicateLogRequest, ReplicateLogResponse, ReplicatedBatch},
+    get_records::{
+        FRAMES_CONTENT_TYPE, GetRecordsRequest, GetRecordsResponse, IsolationLevel,
+        NEXT_OFFSET_HEADER,
+    },
 };
 use tokki_common::hmac::HmacForm;
 
 use crate::{
     app_state::{AppState, AppStateInner},
-    controller_error::{ControllerError, HmacSnafu, IoSnafu},
+    cluster::member_url,
+    controller_error::{ControllerError, HmacSnafu, NoLeaderSnafu, TopicSnafu},
+    replication::InSyncChange,
+    storage::StorageError,
 };
 
 pub async fn get_records(
     State(state): State<AppState>,
+    Path(topic): Path<String>,
     Json(req): Json<GetRecordsRequest>,
 ) -> Result<Json<GetRecordsResponse>, ControllerError> {
     let start = Instant::now();
-    let records = state
-        .storage()
-        .get_records(req.offset, req.max_records)
-        .await
-        .context(IoSnafu)?;
-    metrics::histogram!("get_records").record(start.elapsed());
+    let topic = state.topic(&topic).context(TopicSnafu)?;
+    let partition = topic.partition(req.partition).context(TopicSnafu)?;
+    let records = match req.isolation {
+        IsolationLevel::ReadCommitted => {
+            partition
+                .get_committed_records(req.offset, req.max_records)
+                .await?
+        }
+        IsolationLevel::ReadUncommitted => {
+            partition
+                .storage()
+                .get_records(req.offset, req.max_records)
+                .await?
+        }
+    };
+    metrics::histogram!(
+        "get_records",
+        "topic" => topic.name().to_string(),
+        "partition" => req.partition.to_string()
+    )
+    .record(start.elapsed());
     let res = GetRecordsResponse::new(records.0, records.1);
 
     Ok(Json(res))
 }
 
+/// Serve records as the frames they are stored in, written out to the body
+/// chunk by chunk without being copied or encoded as JSON
+pub async fn get_record_frames(
+    State(state): State<AppState>,
+    Path(topic): Path<String>,
+    Json(req): Json<GetRecordsRequest>,
+) -> Result<Response, ControllerError> {
+    let start = Instant::now();
+    let topic = state.topic(&topic).context(TopicSnafu)?;
+    let partition = topic.partition(req.partition).context(TopicSnafu)?;
+    let (frames, next_offset) = match req.isolation {
+        IsolationLevel::ReadCommitted => {
+            partition
+                .get_committed_frames(req.offset, req.max_records)
+                .await?
+        }
+        IsolationLevel::ReadUncommitted => {
+            partition
+                .storage()
+                .get_frames(req.offset, req.max_records)
+                .await?
+        }
+    };
+    metrics::histogram!(
+        "get_record_frames",
+        "topic" => topic.name().to_string(),
+        "partition" => req.partition.to_string()
+    )
+    .record(start.elapsed());
+
+    let chunks = frames.into_chunks().into_iter().map(Ok::<_, Infallible>);
+    let body = Body::from_stream(futures::stream::iter(chunks));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, FRAMES_CONTENT_TYPE.to_string()),
+            (
+                header::HeaderName::from_static(NEXT_OFFSET_HEADER),
+                next_offset.0.to_string(),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
 pub async fn get_records_for_replication(
     State(state): State<AppState>,
     Json(req): Json<HmacForm<ReplicateLogRequest>>,
 ) -> Result<Json<HmacForm<ReplicateLogResponse>>, ControllerError> {
-    match state.inner.as_ref() {
-        AppStateInner::Leader {
-            token,
-            storage,
-            replication,
-            ..
-        } => {
-            let req = req.into_verified(&token).context(HmacSnafu)?;
+    match state.inner().as_ref() {
+        AppStateInner::Leader { token, topics, .. } => {
+            let req = req.into_verified(token).context(HmacSnafu)?;
+            let topic = topics.get(&req.topic).context(TopicSnafu)?;
+            let partition = topic.partition(req.partition).context(TopicSnafu)?;
+            let follower = member_url(&req.follower_url).unwrap_or(req.follower_url);
+            state.membership().polled(&follower);
 
             tracing::trace!(
-                "{} replicated to {:?}",
-                req.follower_url,
+                "{} replicated {}/{} to {:?}",
+                follower,
+                req.topic,
+                req.partition,
                 req.max_acknowledged_offset
             );
 
-            {
-                let mut guard = replication.lock().expect("not poisoned");
-                guard.update_follower_max_offset(req.follower_url, req.max_acknowledged_offset);
-            }
-
             let next_batch_offset = req
                 .max_acknowledged_offset
                 .map(|offset| offset + 1)
                 .unwrap_or_default();
-            let records = storage
-                .get_records(next_batch_offset, 10)
-                .await
-                .context(IoSnafu)?;
-            let response = ReplicateLogResponse::new(records.0);
 
-            let form = HmacForm::new(response, &token);
+            // Nothing the follower has can be counted, or added to, until it
+            // has dropped whatever this log does not agree with
+            if let Some(last_epoch) = req.last_epoch {
+                let log_end = partition.log_end().await?;
+                let (epoch, end_offset) = partition
+                    .leader_epochs()
+                    .lock()
+                    .expect("not poisoned")
+                    .end_offset_for(Some(last_epoch), log_end);
+
+                if epoch != Some(last_epoch) || end_offset < next_batch_offset {
+                    tracing::info!(
+                        follower,
+                        topic = req.topic,
+                        partition = req.partition,
+                        last_epoch,
+                        log_end = next_batch_offset.0,
+                        "Follower log diverges at {}",
+                        end_offset.0
+                    );
+                    let divergence = Divergence::new(epoch, end_offset);
+                    let response =
+                        ReplicateLogResponse::diverged(divergence, partition.high_watermark());
+                    return Ok(Json(HmacForm::new(response, token)));
+                }
+            }
+
+            let high_watermark = {
+                let mut guard = partition.replication().lock().expect("not poisoned");
+                let changes =
+                    guard.update_follower_max_offset(follower.clone(), req.max_acknowledged_offset);
+                let in_sync = guard.in_sync().count();
+                InSyncChange::record(&changes, topic.name(), partition.index(), in_sync);
+                guard.high_watermark()
+            };
+
+            // Retention may have removed what the follower needs next
+            let (batches, next_offset) =
+                match partition.storage().get_batches(next_batch_offset, 10).await {
+                    Ok(read) => read,
+                    Err(StorageError::OffsetOutOfRange {
+                        log_start_offset, ..
+                    }) => {
+                        tracing::info!(
+                            follower,
+                            topic = req.topic,
+                            partition = req.partition,
+                            log_end = next_batch_offset.0,
+                            "Follower log ends before the log start at {}",
+                            log_start_offset.0
+                        );
+                        let response =
+                            ReplicateLogResponse::out_of_range(log_start_offset, high_watermark);
+                        return Ok(Json(HmacForm::new(response, token)));
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+            let epochs = partition
+                .leader_epochs()
+                .lock()
+                .expect("not poisoned")
+                .between(next_batch_offset, next_offset);
+            let batches = batches
+                .into_iter()
+                .map(|(base_offset, batch)| ReplicatedBatch::new(base_offset, batch))
+                .collect();
+            let response = ReplicateLogResponse::new(batches, high_watermark, epochs);
+
+            let form = HmacForm::new(response, token);
 
             Ok(Json(form))
         }
         AppStateInner::Follower { leader_client, .. } => Err(ControllerError::IsFollower {
             leader: leader_client.base_url().to_string(),
         }),
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
     }
 }
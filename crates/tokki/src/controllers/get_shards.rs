@@ -1,3 +1,27 @@
-pub async fn get_shards() -> &'static str {
-    "GET request received for /shards"
+use axum::{Json, extract::State};
+use tokki_api::shards::{PartitionShard, ShardsResponse, TopicShards};
+
+use crate::app_state::AppState;
+
+/// Every topic's partitions, along with the node leading each of them
+pub async fn get_shards(State(state): State<AppState>) -> Json<ShardsResponse> {
+    let leader = state.leader_url().map(String::from);
+    let topics = state
+        .topics()
+        .all()
+        .iter()
+        .map(|topic| TopicShards {
+            name: topic.name().to_string(),
+            partitions: topic
+                .partitions()
+                .iter()
+                .map(|partition| PartitionShard {
+                    partition: partition.index(),
+                    leader: leader.clone(),
+                })
+                .collect(),
+        })
+        .collect();
+
+    Json(ShardsResponse::new(topics))
 }
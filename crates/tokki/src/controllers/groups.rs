@@ -0,0 +1,176 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use snafu::ResultExt as _;
+use std::time::Duration;
+
+use tokki_api::groups::{
+    Assignment, CommitOffsetsRequest, CommittedOffsetsResponse, GetCommittedOffsetsRequest,
+    GetGroupLagRequest, GroupDescription, GroupLagResponse, JoinGroupRequest,
+};
+use tokki_common::Compression;
+
+use crate::{
+    app_state::{AppState, AppStateInner},
+    controller_error::{ControllerError, LeaderForwardingSnafu, NoLeaderSnafu, TopicSnafu},
+    controllers::put_records::append,
+};
+
+/// Commit offsets for a group on the leader. Commits are acknowledged once
+/// they are replicated like any other put.
+pub async fn commit_offsets(
+    State(state): State<AppState>,
+    Path(group): Path<String>,
+    Json(mut req): Json<CommitOffsetsRequest>,
+) -> Result<Json<CommittedOffsetsResponse>, ControllerError> {
+    match state.inner().as_ref() {
+        AppStateInner::Leader {
+            topics,
+            groups,
+            leader_epoch,
+            ..
+        } => {
+            let topic = topics.get(&req.topic).context(TopicSnafu)?;
+            let records = groups.commit_records(&group, &topic, &req.offsets)?;
+
+            let offsets_topic = groups.offsets_topic().await?;
+            let partition = offsets_topic.partition(0).context(TopicSnafu)?;
+            append(
+                partition,
+                records,
+                Compression::None,
+                topics.required_replicas(),
+                *leader_epoch,
+            )
+            .await?;
+            metrics::counter!("committed_offsets", "group" => group.clone(), "topic" => req.topic)
+                .increment(req.offsets.len() as u64);
+
+            let offsets = groups.committed(&group, &topic).await?;
+            Ok(Json(CommittedOffsetsResponse::new(topic.name(), offsets)))
+        }
+        AppStateInner::Follower { leader_client, .. } => {
+            req.group = group;
+            leader_client
+                .commit_offsets(req)
+                .await
+                .with_context(|_| LeaderForwardingSnafu {
+                    leader: leader_client.base_url().to_string(),
+                })
+                .map(Json)
+        }
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+/// Get the offsets a group has committed for a topic, as far as this node knows
+pub async fn get_committed_offsets(
+    State(state): State<AppState>,
+    Path(group): Path<String>,
+    Json(req): Json<GetCommittedOffsetsRequest>,
+) -> Result<Json<CommittedOffsetsResponse>, ControllerError> {
+    let topic = state.topic(&req.topic).context(TopicSnafu)?;
+    let offsets = state.groups().committed(&group, &topic).await?;
+
+    Ok(Json(CommittedOffsetsResponse::new(topic.name(), offsets)))
+}
+
+/// Get how far a group is behind the end of each partition, from the records
+/// and commits this node has
+pub async fn get_group_lag(
+    State(state): State<AppState>,
+    Path(group): Path<String>,
+    Json(req): Json<GetGroupLagRequest>,
+) -> Result<Json<GroupLagResponse>, ControllerError> {
+    let topic = match &req.topic {
+        Some(topic) => Some(state.topic(topic).context(TopicSnafu)?),
+        None => None,
+    };
+    let partitions = state.groups().lag(&group, topic).await?;
+
+    Ok(Json(GroupLagResponse::new(group, partitions)))
+}
+
+/// Join a group on the leader, which coordinates membership
+pub async fn join_group(
+    State(state): State<AppState>,
+    Path(group): Path<String>,
+    Json(mut req): Json<JoinGroupRequest>,
+) -> Result<Json<Assignment>, ControllerError> {
+    match state.inner().as_ref() {
+        AppStateInner::Leader { topics, groups, .. } => {
+            let topic = topics.get(&req.topic).context(TopicSnafu)?;
+            let assignment = groups.join(
+                &group,
+                &topic,
+                req.member_id.as_deref(),
+                req.strategy,
+                Duration::from_millis(req.session_timeout_ms),
+            )?;
+            Ok(Json(assignment))
+        }
+        AppStateInner::Follower { leader_client, .. } => {
+            req.group = group;
+            leader_client
+                .join_group(req)
+                .await
+                .with_context(|_| LeaderForwardingSnafu {
+                    leader: leader_client.base_url().to_string(),
+                })
+                .map(Json)
+        }
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+pub async fn heartbeat(
+    State(state): State<AppState>,
+    Path((group, member_id)): Path<(String, String)>,
+) -> Result<Json<Assignment>, ControllerError> {
+    match state.inner().as_ref() {
+        AppStateInner::Leader { groups, .. } => Ok(Json(groups.heartbeat(&group, &member_id)?)),
+        AppStateInner::Follower { leader_client, .. } => leader_client
+            .heartbeat(&group, &member_id)
+            .await
+            .with_context(|_| LeaderForwardingSnafu {
+                leader: leader_client.base_url().to_string(),
+            })
+            .map(Json),
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+pub async fn leave_group(
+    State(state): State<AppState>,
+    Path((group, member_id)): Path<(String, String)>,
+) -> Result<Json<GroupDescription>, ControllerError> {
+    match state.inner().as_ref() {
+        AppStateInner::Leader { groups, .. } => Ok(Json(groups.leave(&group, &member_id)?)),
+        AppStateInner::Follower { leader_client, .. } => leader_client
+            .leave_group(&group, &member_id)
+            .await
+            .with_context(|_| LeaderForwardingSnafu {
+                leader: leader_client.base_url().to_string(),
+            })
+            .map(Json),
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+pub async fn describe_group(
+    State(state): State<AppState>,
+    Path(group): Path<String>,
+) -> Result<Json<GroupDescription>, ControllerError> {
+    match state.inner().as_ref() {
+        AppStateInner::Leader { groups, .. } => Ok(Json(groups.describe(&group)?)),
+        AppStateInner::Follower { leader_client, .. } => leader_client
+            .describe_group(&group)
+            .await
+            .with_context(|_| LeaderForwardingSnafu {
+                leader: leader_client.base_url().to_string(),
+            })
+            .map(Json),
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
@@ -1,11 +1,24 @@
+mod cluster;
+mod election;
+mod get_offset;
 mod get_records;
 mod get_shards;
+mod groups;
 mod healthcheck;
 mod profiling;
 mod put_records;
+mod topics;
 
-pub use get_records::{get_records, get_records_for_replication};
+pub use cluster::{add_member, get_in_sync_replicas, list_members, remove_member};
+pub use election::{leader_heartbeat, request_vote};
+pub use get_offset::get_offset_for_timestamp;
+pub use get_records::{get_record_frames, get_records, get_records_for_replication};
 pub use get_shards::get_shards;
+pub use groups::{
+    commit_offsets, describe_group, get_committed_offsets, get_group_lag, heartbeat, join_group,
+    leave_group,
+};
 pub use healthcheck::get_healthcheck;
 pub use profiling::start_profiling;
 pub use put_records::put_records;
+pub use topics::{create_topic, delete_topic, list_topics};
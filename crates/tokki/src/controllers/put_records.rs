@@ -1,61 +1,173 @@
-use std::time::Duration;
+use std::{ops::Range, time::Duration};
 
-use axum::{Json, extract::State};
-use snafu::ResultExt as _;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use futures::future::try_join_all;
+use snafu::{ResultExt as _, ensure};
 use tokio::{sync::oneshot, time::timeout};
-use tokki_api::put_record::{PutRecordsRequest, PutRecordsResponse};
-use tokki_common::Offset;
+use tokki_api::put_record::{PartitionOffsets, PutRecordsRequest, PutRecordsResponse};
+use tokki_common::{Compression, Offset, Record, RecordBatch, Timestamp};
 
 use crate::{
     app_state::{AppState, AppStateInner},
-    controller_error::{ControllerError, IoSnafu, LeaderForwardingSnafu},
+    controller_error::{
+        ControllerError, LeaderForwardingSnafu, LeadershipLostSnafu, NoLeaderSnafu,
+        NotEnoughReplicasSnafu, StorageSnafu, TopicSnafu,
+    },
+    replication::Replication,
+    storage::{Storage, StorageError},
+    topics::Partition,
 };
 
+/// How long a put waits for followers to copy its records before failing
+const REPLICATION_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub async fn put_records(
     State(state): State<AppState>,
-    Json(req): Json<PutRecordsRequest>,
+    Path(topic): Path<String>,
+    Json(mut req): Json<PutRecordsRequest>,
 ) -> Result<Json<PutRecordsResponse>, ControllerError> {
-    match state.inner.as_ref() {
+    match state.inner().as_ref() {
         AppStateInner::Leader {
-            replication,
-            storage,
-            required_replicas,
+            topics,
+            timestamp_type,
+            leader_epoch,
             ..
         } => {
-            let mut max_offset = 0;
-            let mut len = 0;
-
-            for record in req.records {
-                let offset = storage.put_record(record).await.context(IoSnafu)?;
-                max_offset = offset.0;
-                len += 1;
-            }
-
-            if *required_replicas > 0 {
-                let wake_rx = {
-                    let mut guard = replication.lock().expect("not poisoned");
-                    let (wake_tx, wake_rx) = oneshot::channel();
-                    guard.register_wait(Offset(max_offset), wake_tx);
-                    wake_rx
-                };
-
-                if let Err(_) = timeout(Duration::from_secs(5), wake_rx).await {
-                    tracing::error!("Timeout waiting for {}", max_offset);
-                    return Err(ControllerError::Replication { timeout_s: 5 });
-                }
-            }
-
-            let initial_offset = max_offset - (len - 1);
-            let response = PutRecordsResponse::new(Offset(initial_offset), len);
+            let topic = topics.get(&topic).context(TopicSnafu)?;
+            let mut records = req.records;
+            timestamp_type.apply(&mut records, Timestamp::now());
+
+            let routed = topic.route(records, req.partition).context(TopicSnafu)?;
+            let partitions = try_join_all(routed.into_iter().map(|(partition, records)| async {
+                let offsets = append(
+                    partition,
+                    records,
+                    req.compression,
+                    topics.required_replicas(),
+                    *leader_epoch,
+                )
+                .await?;
+                Ok::<_, ControllerError>(PartitionOffsets {
+                    partition: partition.index(),
+                    offset: offsets.start,
+                    len: (offsets.end - offsets.start).0,
+                })
+            }))
+            .await?;
+
+            let response = PutRecordsResponse::new(partitions);
 
             Ok(Json(response))
         }
-        AppStateInner::Follower { leader_client, .. } => leader_client
-            .put_record(req)
-            .await
-            .with_context(|_| LeaderForwardingSnafu {
-                leader: leader_client.base_url().to_string(),
+        AppStateInner::Follower { leader_client, .. } => {
+            req.topic = topic;
+            leader_client
+                .put_record(req)
+                .await
+                .with_context(|_| LeaderForwardingSnafu {
+                    leader: leader_client.base_url().to_string(),
+                })
+                .map(Json)
+        }
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+/// Put `records` on `partition` in `leader_epoch`, returning their offsets
+/// once they are durable and on as many followers as are required. Fails
+/// without waiting while too few followers are in sync to acknowledge them.
+pub(crate) async fn append(
+    partition: &Partition,
+    records: Vec<Record>,
+    compression: Compression,
+    required_replicas: usize,
+    leader_epoch: u64,
+) -> Result<Range<Offset>, ControllerError> {
+    partition
+        .begin_epoch(leader_epoch)
+        .await
+        .context(StorageSnafu)?;
+
+    let storage = partition.storage();
+    let offsets = put(storage.as_ref(), records, compression)
+        .await
+        .context(StorageSnafu)?;
+    if offsets.is_empty() {
+        return Ok(offsets);
+    }
+
+    let max_offset = offsets.end - 1;
+    storage
+        .wait_durable(max_offset)
+        .await
+        .context(StorageSnafu)?;
+
+    let wake_rx = {
+        let mut guard = partition.replication().lock().expect("not poisoned");
+        guard.update_log_end(offsets.end);
+        if required_replicas > 0 {
+            not_enough_replicas(&guard)?;
+            let (wake_tx, wake_rx) = oneshot::channel();
+            guard.register_wait(max_offset, wake_tx);
+            Some(wake_rx)
+        } else {
+            None
+        }
+    };
+
+    let Some(wake_rx) = wake_rx else {
+        return Ok(offsets);
+    };
+    match timeout(REPLICATION_TIMEOUT, wake_rx).await {
+        Ok(Ok(())) => Ok(offsets),
+        // Waits are dropped rather than woken when too few followers are left
+        // in sync, or this node stops leading
+        Ok(Err(_)) => {
+            not_enough_replicas(&partition.replication().lock().expect("not poisoned"))?;
+            LeadershipLostSnafu.fail()
+        }
+        Err(_) => {
+            tracing::error!(
+                partition = partition.index(),
+                "Timeout waiting for {}",
+                max_offset.0
+            );
+            Err(ControllerError::Replication {
+                timeout_s: REPLICATION_TIMEOUT.as_secs() as i32,
             })
-            .map(|r| Json(r)),
+        }
+    }
+}
+
+fn not_enough_replicas(replication: &Replication) -> Result<(), ControllerError> {
+    ensure!(
+        replication.enough_in_sync(),
+        NotEnoughReplicasSnafu {
+            in_sync: replication.in_sync().count(),
+            min_insync_replicas: replication.min_insync_replicas(),
+        }
+    );
+    Ok(())
+}
+
+/// Put the records as they came, or as a single compressed batch if the producer asked for one
+async fn put(
+    storage: &dyn Storage,
+    records: Vec<Record>,
+    compression: Compression,
+) -> Result<Range<Offset>, StorageError> {
+    if compression == Compression::None || records.is_empty() {
+        return storage.put_records(records).await;
     }
+
+    let batch = RecordBatch::new(&records, compression)?;
+
+    let uncompressed_len: usize = records.iter().map(Record::serialized_len).sum();
+    metrics::histogram!("compression_ratio", "compression" => compression.name())
+        .record(uncompressed_len as f64 / batch.data().len().max(1) as f64);
+
+    storage.put_batch(batch).await
 }
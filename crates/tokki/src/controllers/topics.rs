@@ -0,0 +1,60 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use snafu::ResultExt as _;
+use tokki_api::topics::{CreateTopicRequest, ListTopicsResponse, TopicDescription};
+
+use crate::{
+    app_state::{AppState, AppStateInner},
+    controller_error::{ControllerError, LeaderForwardingSnafu, NoLeaderSnafu, TopicSnafu},
+};
+
+pub async fn list_topics(State(state): State<AppState>) -> Json<ListTopicsResponse> {
+    Json(ListTopicsResponse::new(state.topics().list()))
+}
+
+/// Create a topic on the leader, followers pick it up from there
+pub async fn create_topic(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(mut req): Json<CreateTopicRequest>,
+) -> Result<Json<TopicDescription>, ControllerError> {
+    match state.inner().as_ref() {
+        AppStateInner::Leader { topics, .. } => {
+            let topic = topics.create(&name, req.config).await.context(TopicSnafu)?;
+            Ok(Json(topic.description().clone()))
+        }
+        AppStateInner::Follower { leader_client, .. } => {
+            req.name = name;
+            leader_client
+                .create_topic(req)
+                .await
+                .with_context(|_| LeaderForwardingSnafu {
+                    leader: leader_client.base_url().to_string(),
+                })
+                .map(Json)
+        }
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
+
+/// Delete a topic on the leader, followers drop it once they see it is gone
+pub async fn delete_topic(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<TopicDescription>, ControllerError> {
+    match state.inner().as_ref() {
+        AppStateInner::Leader { topics, .. } => {
+            topics.delete(&name).await.context(TopicSnafu).map(Json)
+        }
+        AppStateInner::Follower { leader_client, .. } => leader_client
+            .delete_topic(&name)
+            .await
+            .with_context(|_| LeaderForwardingSnafu {
+                leader: leader_client.base_url().to_string(),
+            })
+            .map(Json),
+        AppStateInner::Electing { .. } => NoLeaderSnafu.fail(),
+    }
+}
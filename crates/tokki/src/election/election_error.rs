@@ -0,0 +1,19 @@
+use std::{io, path::PathBuf};
+
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum ElectionError {
+    #[snafu(display("Failed to read election state from {}: {source}", path.display()))]
+    ReadState { path: PathBuf, source: io::Error },
+    #[snafu(display("Failed to parse election state from {}: {source}", path.display()))]
+    ParseState {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[snafu(display("Failed to write election state to {}: {source}", path.display()))]
+    WriteState { path: PathBuf, source: io::Error },
+    #[snafu(display("This node does not take part in elections"))]
+    NotElected,
+}
@@ -0,0 +1,575 @@
+mod election_error;
+
+pub use election_error::ElectionError;
+pub(crate) use election_error::NotElectedSnafu;
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt as _;
+use tokio::{sync::Notify, task::JoinHandle, time::timeout};
+use tokki_api::{
+    TokkiClient,
+    cluster::ClusterMembers,
+    clustering::{
+        LeaderHeartbeatRequest, LeaderHeartbeatResponse, PartitionLog, VoteRequest, VoteResponse,
+    },
+};
+use tokki_common::Offset;
+use url::Url;
+
+use crate::{
+    app_state::{AppState, AppStateInner},
+    election::election_error::{ParseStateSnafu, ReadStateSnafu, WriteStateSnafu},
+    state_file::write_atomically,
+    timestamp_type::TimestampType,
+    topics::Topics,
+};
+
+/// File in the state directory holding the term and vote
+const STATE_FILE: &str = "election.json";
+
+/// How a node finds and talks to the others it holds elections with
+#[derive(Debug, Clone)]
+pub struct ElectionConfig {
+    advertised_url: Url,
+    peers: Vec<Url>,
+    election_timeout: Duration,
+    heartbeat_interval: Duration,
+    state_dir: Option<PathBuf>,
+}
+
+impl ElectionConfig {
+    /// Start out holding elections with `peers`, every other node in the
+    /// cluster. This node is known to them by `advertised_url`.
+    pub fn new(advertised_url: Url, peers: Vec<Url>) -> Self {
+        Self {
+            advertised_url,
+            peers,
+            election_timeout: Duration::from_millis(1500),
+            heartbeat_interval: Duration::from_millis(500),
+            state_dir: None,
+        }
+    }
+
+    /// Shortest time without hearing from a leader before starting an
+    /// election. Each wait is randomized up to twice this, so nodes rarely
+    /// start elections at the same time.
+    pub fn with_election_timeout(mut self, election_timeout: Duration) -> Self {
+        self.election_timeout = election_timeout;
+        self
+    }
+
+    /// How often the leader tells the other nodes it is still there. Well
+    /// under the election timeout, or followers start elections needlessly.
+    pub fn with_heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    /// Keep the term and vote in this directory, so a restarted node never
+    /// votes twice in the same term. Without one they are kept in memory.
+    pub fn with_state_dir(mut self, state_dir: impl Into<PathBuf>) -> Self {
+        self.state_dir = Some(state_dir.into());
+        self
+    }
+
+    /// Every node in the cluster, this one included
+    pub(crate) fn nodes(&self) -> Vec<Url> {
+        let mut nodes = self.peers.clone();
+        nodes.push(self.advertised_url.clone());
+        nodes
+    }
+
+    fn state_path(&self) -> Option<PathBuf> {
+        self.state_dir
+            .as_ref()
+            .map(|state_dir| state_dir.join(STATE_FILE))
+    }
+}
+
+/// The part of a node's election state that must survive a restart
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct TermState {
+    term: u64,
+    voted_for: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Role {
+    /// Following the leader of the current term, once it has been heard from
+    Follower {
+        leader: Option<Url>,
+    },
+    /// Asking the other nodes for votes
+    Candidate,
+    Leader,
+}
+
+struct ElectionState {
+    term: TermState,
+    role: Role,
+}
+
+/// Raft-style leader election among the cluster members. Every node starts
+/// out following no one. One that goes an election timeout without hearing
+/// from a leader starts an election for the next term, and leads once a
+/// majority of the members votes for it, and a majority of the members being
+/// changed to as well while a change is under way. Nodes vote once a term,
+/// and only for candidates whose logs are at least as up to date as their own
+/// on every partition, going by the leader epoch of each one's last record
+/// and then its length. Each change of role swaps out the node's
+/// `AppStateInner`.
+pub struct Election {
+    config: ElectionConfig,
+    token: String,
+    timestamp_type: TimestampType,
+    state: Mutex<ElectionState>,
+    /// Woken by heartbeats from the leader and by votes granted, either of
+    /// which puts off the next election
+    heard_from_leader: Notify,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Election {
+    pub(crate) fn open(
+        config: ElectionConfig,
+        token: String,
+        timestamp_type: TimestampType,
+    ) -> Result<Self, ElectionError> {
+        let term = match config.state_path() {
+            Some(path) => match fs::read(&path) {
+                Ok(buf) => serde_json::from_slice(&buf).context(ParseStateSnafu { path })?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => TermState::default(),
+                Err(source) => return Err(source).context(ReadStateSnafu { path }),
+            },
+            None => TermState::default(),
+        };
+        tracing::info!(term = term.term, "Opened election state");
+
+        Ok(Self {
+            config,
+            token,
+            timestamp_type,
+            state: Mutex::new(ElectionState {
+                term,
+                role: Role::Follower { leader: None },
+            }),
+            heard_from_leader: Notify::new(),
+            task: Mutex::new(None),
+        })
+    }
+
+    pub fn term(&self) -> u64 {
+        self.state.lock().expect("not poisoned").term.term
+    }
+
+    pub fn role(&self) -> Role {
+        self.state.lock().expect("not poisoned").role.clone()
+    }
+
+    pub(crate) fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn advertised_url(&self) -> &Url {
+        &self.config.advertised_url
+    }
+
+    /// Hold elections in the background, taking on whichever role they give
+    /// this node
+    pub(crate) fn start(self: &Arc<Self>, app_state: AppState) {
+        let task = tokio::task::spawn({
+            let election = self.clone();
+            async move { election.run(app_state).await }
+        });
+        *self.task.lock().expect("not poisoned") = Some(task);
+    }
+
+    /// Stop taking part in elections and following the leader, for shutting
+    /// the node down
+    pub fn stop(&self, app_state: &AppState) {
+        if let Some(task) = self.task.lock().expect("not poisoned").take() {
+            task.abort();
+        }
+        app_state.replace_inner(self.electing(app_state));
+    }
+
+    async fn run(&self, app_state: AppState) {
+        loop {
+            if self.role() == Role::Leader {
+                self.send_heartbeats(&app_state).await;
+                tokio::time::sleep(self.config.heartbeat_interval).await;
+                continue;
+            }
+
+            let wait = self
+                .config
+                .election_timeout
+                .mul_f64(1.0 + rand::random::<f64>());
+            tokio::select! {
+                () = self.heard_from_leader.notified() => {}
+                () = tokio::time::sleep(wait) => self.campaign(&app_state).await,
+            }
+        }
+    }
+
+    /// Stand for election in the next term, taking the lead if a majority votes for this node
+    async fn campaign(&self, app_state: &AppState) {
+        let members = app_state.membership().members();
+        let own_url = self.config.advertised_url.as_str();
+        if !members.all().contains(own_url) {
+            tracing::debug!("Not standing for election, no longer a member");
+            return;
+        }
+
+        let logs = partition_logs(app_state.topics()).await;
+        let req = {
+            let mut guard = self.state.lock().expect("not poisoned");
+            let mut term = guard.term.clone();
+            term.term += 1;
+            term.voted_for = Some(self.config.advertised_url.to_string());
+            if let Err(e) = self.persist(&term) {
+                tracing::error!("Failed to start an election: {}", e);
+                return;
+            }
+            guard.term = term;
+            guard.role = Role::Candidate;
+
+            VoteRequest::new(
+                guard.term.term,
+                self.config.advertised_url.to_string(),
+                logs,
+            )
+        };
+        let term = req.term;
+        tracing::info!(term, "Starting an election");
+        metrics::counter!("elections_started").increment(1);
+        app_state.replace_inner(self.electing(app_state));
+
+        let peers = self.peers(&members);
+        let responses = join_all(peers.iter().map(|peer| {
+            let client = TokkiClient::new(peer.clone());
+            let req = VoteRequest::new(req.term, req.candidate_url.clone(), req.logs.clone());
+            async move {
+                let res = timeout(
+                    self.config.election_timeout,
+                    client.request_vote(req, &self.token),
+                )
+                .await;
+                match res {
+                    Ok(Ok(res)) => res
+                        .into_verified(&self.token)
+                        .ok()
+                        .map(|res| (peer.to_string(), res)),
+                    Ok(Err(e)) => {
+                        tracing::debug!(peer = %peer, "Failed to request a vote: {}", e);
+                        None
+                    }
+                    Err(_) => None,
+                }
+            }
+        }))
+        .await;
+
+        let mut votes = BTreeSet::from([own_url.to_string()]);
+        for (peer, res) in responses.into_iter().flatten() {
+            if res.term > term {
+                self.step_down(app_state, res.term);
+                return;
+            }
+            if res.vote_granted {
+                votes.insert(peer);
+            }
+        }
+
+        if !won(&members, &votes) {
+            tracing::info!(term, ?votes, ?members, "Lost the election");
+            return;
+        }
+
+        {
+            let mut guard = self.state.lock().expect("not poisoned");
+            if guard.term.term != term || guard.role != Role::Candidate {
+                return;
+            }
+            guard.role = Role::Leader;
+        }
+        tracing::info!(term, ?votes, ?members, "Won the election");
+        metrics::counter!("elections_won").increment(1);
+        metrics::gauge!("election_term").set(term as f64);
+
+        self.lead(app_state, term).await;
+        self.send_heartbeats(app_state).await;
+    }
+
+    /// Take over replication of every partition and start taking puts in a
+    /// new leader epoch, numbered after the term
+    async fn lead(&self, app_state: &AppState, term: u64) {
+        for topic in app_state.topics().all() {
+            for partition in topic.partitions() {
+                let log_end = match partition.log_end().await {
+                    Ok(log_end) => log_end,
+                    Err(e) => {
+                        tracing::error!(topic = topic.name(), "Failed to read log end: {}", e);
+                        Offset(0)
+                    }
+                };
+                // Followers find out where their logs part from this one by
+                // where each epoch starts
+                if let Err(e) = partition.begin_epoch(term).await {
+                    tracing::error!(topic = topic.name(), "Failed to start leader epoch: {}", e);
+                }
+                partition
+                    .replication()
+                    .lock()
+                    .expect("not poisoned")
+                    .lead(log_end);
+            }
+        }
+
+        app_state.replace_inner(AppStateInner::Leader {
+            token: self.token.clone(),
+            topics: app_state.topics().clone(),
+            groups: app_state.groups().clone(),
+            timestamp_type: self.timestamp_type,
+            advertised_url: Some(self.config.advertised_url.clone()),
+            leader_epoch: term,
+        });
+    }
+
+    /// Tell every other member this node still leads, stepping down if one
+    /// of them has moved on to a later term
+    async fn send_heartbeats(&self, app_state: &AppState) {
+        let term = self.term();
+        let members = app_state.membership().members();
+        let responses = join_all(self.peers(&members).into_iter().map(|peer| {
+            let client = TokkiClient::new(peer);
+            let req = LeaderHeartbeatRequest::new(
+                term,
+                self.config.advertised_url.to_string(),
+                members.clone(),
+            );
+            async move {
+                let res = timeout(
+                    self.config.heartbeat_interval,
+                    client.send_leader_heartbeat(req, &self.token),
+                )
+                .await;
+                match res {
+                    Ok(Ok(res)) => res.into_verified(&self.token).ok(),
+                    _ => None,
+                }
+            }
+        }))
+        .await;
+
+        if let Some(later) = responses
+            .into_iter()
+            .flatten()
+            .map(|res| res.term)
+            .filter(|res_term| *res_term > term)
+            .max()
+        {
+            self.step_down(app_state, later);
+        }
+    }
+
+    /// Decide on a vote for a candidate. Seeing a later term moves this node
+    /// on to it, stepping down if it was leading or standing for election.
+    pub(crate) async fn vote(
+        &self,
+        app_state: &AppState,
+        req: VoteRequest,
+    ) -> Result<VoteResponse, ElectionError> {
+        let logs = partition_logs(app_state.topics()).await;
+
+        let mut guard = self.state.lock().expect("not poisoned");
+        let mut term = guard.term.clone();
+        let stepped_down = req.term > term.term && guard.role != Role::Follower { leader: None };
+        if req.term > term.term {
+            term.term = req.term;
+            term.voted_for = None;
+        }
+
+        // Partitions the candidate does not have count as empty
+        let up_to_date = logs.iter().all(|own| {
+            let empty = PartitionLog::new(own.topic.clone(), own.partition, None, Offset(0));
+            req.logs
+                .iter()
+                .find(|log| log.topic == own.topic && log.partition == own.partition)
+                .unwrap_or(&empty)
+                .up_to_date_with(own)
+        });
+        let vote_granted = req.term == term.term
+            && up_to_date
+            && term
+                .voted_for
+                .as_ref()
+                .is_none_or(|voted_for| *voted_for == req.candidate_url);
+        if vote_granted {
+            term.voted_for = Some(req.candidate_url.clone());
+        }
+
+        if term != guard.term {
+            self.persist(&term)?;
+            if term.term != guard.term.term {
+                guard.role = Role::Follower { leader: None };
+            }
+            guard.term = term;
+        }
+        let response = VoteResponse::new(guard.term.term, vote_granted);
+        drop(guard);
+
+        tracing::info!(
+            term = req.term,
+            candidate = req.candidate_url,
+            vote_granted,
+            "Voted"
+        );
+        if vote_granted {
+            self.heard_from_leader.notify_one();
+        }
+        if stepped_down {
+            app_state.replace_inner(self.electing(app_state));
+        }
+        Ok(response)
+    }
+
+    /// Follow the leader of a term at least as late as this node's, pointing
+    /// replication at it if it is new
+    pub(crate) fn heartbeat(
+        &self,
+        app_state: &AppState,
+        req: LeaderHeartbeatRequest,
+    ) -> Result<LeaderHeartbeatResponse, ElectionError> {
+        let Ok(leader) = Url::parse(&req.leader_url) else {
+            return Ok(LeaderHeartbeatResponse::new(self.term(), false));
+        };
+
+        let mut guard = self.state.lock().expect("not poisoned");
+        if req.term < guard.term.term {
+            return Ok(LeaderHeartbeatResponse::new(guard.term.term, false));
+        }
+        if req.term > guard.term.term {
+            let term = TermState {
+                term: req.term,
+                voted_for: None,
+            };
+            self.persist(&term)?;
+            guard.term = term;
+        }
+
+        let role = Role::Follower {
+            leader: Some(leader.clone()),
+        };
+        let changed = guard.role != role;
+        guard.role = role;
+        drop(guard);
+
+        self.heard_from_leader.notify_one();
+        if changed {
+            tracing::info!(term = req.term, leader = %leader, "Following a new leader");
+            app_state.replace_inner(AppStateInner::follower(
+                self.token.clone(),
+                app_state.topics().clone(),
+                app_state.groups().clone(),
+                leader,
+                self.config.advertised_url.to_string(),
+            ));
+        }
+        Ok(LeaderHeartbeatResponse::new(req.term, true))
+    }
+
+    fn step_down(&self, app_state: &AppState, later_term: u64) {
+        {
+            let mut guard = self.state.lock().expect("not poisoned");
+            if later_term <= guard.term.term {
+                return;
+            }
+            let term = TermState {
+                term: later_term,
+                voted_for: None,
+            };
+            if let Err(e) = self.persist(&term) {
+                tracing::error!("Failed to move on to term {}: {}", later_term, e);
+                return;
+            }
+            guard.term = term;
+            guard.role = Role::Follower { leader: None };
+        }
+        tracing::info!(term = later_term, "Stepped down for a later term");
+        app_state.replace_inner(self.electing(app_state));
+    }
+
+    /// Every other node that is a member, or is becoming one
+    fn peers(&self, members: &ClusterMembers) -> Vec<Url> {
+        members
+            .all()
+            .into_iter()
+            .filter(|url| *url != self.config.advertised_url.as_str())
+            .filter_map(|url| Url::parse(url).ok())
+            .collect()
+    }
+
+    fn electing(&self, app_state: &AppState) -> AppStateInner {
+        AppStateInner::Electing {
+            token: self.token.clone(),
+            topics: app_state.topics().clone(),
+            groups: app_state.groups().clone(),
+        }
+    }
+
+    fn persist(&self, term: &TermState) -> Result<(), ElectionError> {
+        let Some(path) = self.config.state_path() else {
+            return Ok(());
+        };
+
+        // Synced, as a vote forgotten in a crash could be cast again
+        let buf = serde_json::to_vec_pretty(term).expect("Election state serializes");
+        write_atomically(&path, &buf).context(WriteStateSnafu { path })
+    }
+}
+
+/// Whether `votes` are from a majority of the members, and of the members
+/// being changed to while a change is under way
+fn won(members: &ClusterMembers, votes: &BTreeSet<String>) -> bool {
+    let majority =
+        |voters: &BTreeSet<String>| voters.intersection(votes).count() * 2 > voters.len();
+    majority(&members.members) && members.joining.as_ref().is_none_or(majority)
+}
+
+/// How up to date this node's copy of every partition of every topic is
+async fn partition_logs(topics: &Topics) -> Vec<PartitionLog> {
+    let mut logs = Vec::new();
+    for topic in topics.all() {
+        for partition in topic.partitions() {
+            let log_end = match partition.log_end().await {
+                Ok(log_end) => log_end,
+                Err(e) => {
+                    tracing::error!(topic = topic.name(), "Failed to read log end: {}", e);
+                    continue;
+                }
+            };
+            let last_epoch = partition
+                .leader_epochs()
+                .lock()
+                .expect("not poisoned")
+                .last_record_epoch(log_end);
+            logs.push(PartitionLog::new(
+                topic.name(),
+                partition.index(),
+                last_epoch,
+                log_end,
+            ));
+        }
+    }
+    logs
+}
@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use tokki_api::groups::AssignmentStrategy;
+
+/// Split partitions `0..partitions` among `members`, given in id order. Every
+/// member gets a list, empty when there are more members than partitions, and
+/// no member gets more than one partition more than any other.
+pub fn assign(
+    strategy: AssignmentStrategy,
+    partitions: u32,
+    members: &[String],
+    previous: &BTreeMap<String, Vec<u32>>,
+) -> BTreeMap<String, Vec<u32>> {
+    if members.is_empty() {
+        return BTreeMap::new();
+    }
+
+    match strategy {
+        AssignmentStrategy::Range => assign_range(partitions, members),
+        AssignmentStrategy::RoundRobin => assign_round_robin(partitions, members),
+        AssignmentStrategy::Sticky => assign_sticky(partitions, members, previous),
+    }
+}
+
+fn assign_range(partitions: u32, members: &[String]) -> BTreeMap<String, Vec<u32>> {
+    let per_member = partitions / members.len() as u32;
+    let extra = partitions as usize % members.len();
+
+    let mut start = 0;
+    members
+        .iter()
+        .enumerate()
+        .map(|(idx, member)| {
+            let len = per_member + u32::from(idx < extra);
+            let range = (start..start + len).collect();
+            start += len;
+            (member.clone(), range)
+        })
+        .collect()
+}
+
+fn assign_round_robin(partitions: u32, members: &[String]) -> BTreeMap<String, Vec<u32>> {
+    let mut assignment: BTreeMap<_, _> = members
+        .iter()
+        .map(|member| (member.clone(), Vec::new()))
+        .collect();
+    for partition in 0..partitions {
+        let member = &members[partition as usize % members.len()];
+        assignment
+            .get_mut(member)
+            .expect("every member has a list")
+            .push(partition);
+    }
+    assignment
+}
+
+/// Members keep what they had, up to an even share. Members holding the most
+/// are the ones allowed the odd partitions left over from an even split, and
+/// whatever is left unowned goes to whoever has the fewest.
+fn assign_sticky(
+    partitions: u32,
+    members: &[String],
+    previous: &BTreeMap<String, Vec<u32>>,
+) -> BTreeMap<String, Vec<u32>> {
+    let mut unowned: BTreeSet<u32> = (0..partitions).collect();
+    let mut kept: Vec<(&String, Vec<u32>)> = members
+        .iter()
+        .map(|member| {
+            let owned = previous
+                .get(member)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|partition| unowned.remove(partition))
+                .collect();
+            (member, owned)
+        })
+        .collect();
+
+    let per_member = partitions as usize / members.len();
+    let extra = partitions as usize % members.len();
+    kept.sort_by(|(a, a_owned), (b, b_owned)| b_owned.len().cmp(&a_owned.len()).then(a.cmp(b)));
+    let mut assignment: Vec<(&String, Vec<u32>, usize)> = kept
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (member, mut owned))| {
+            let quota = per_member + usize::from(idx < extra);
+            owned.sort_unstable();
+            for partition in owned.drain(quota.min(owned.len())..) {
+                unowned.insert(partition);
+            }
+            (member, owned, quota)
+        })
+        .collect();
+
+    for partition in unowned {
+        let (_, owned, _) = assignment
+            .iter_mut()
+            .filter(|(_, owned, quota)| owned.len() < *quota)
+            .min_by(|(a, a_owned, _), (b, b_owned, _)| {
+                a_owned.len().cmp(&b_owned.len()).then(a.cmp(b))
+            })
+            .expect("quotas add up to the partition count");
+        owned.push(partition);
+    }
+
+    assignment
+        .into_iter()
+        .map(|(member, mut owned, _)| {
+            owned.sort_unstable();
+            (member.clone(), owned)
+        })
+        .collect()
+}
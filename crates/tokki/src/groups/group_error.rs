@@ -0,0 +1,39 @@
+use snafu::Snafu;
+use tokki_api::groups::AssignmentStrategy;
+
+use crate::{storage::StorageError, topics::TopicError};
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum GroupError {
+    #[snafu(display(
+        "Invalid group name {name:?}, names are up to 249 ASCII letters, digits, '.', '_' or '-'"
+    ))]
+    InvalidName { name: String },
+    #[snafu(display(
+        "Invalid member id {member_id:?}, ids are up to 249 ASCII letters, digits, '.', '_' or '-'"
+    ))]
+    InvalidMemberId { member_id: String },
+    #[snafu(display(
+        "Invalid session timeout of {session_timeout_ms}ms, it must be from {min_ms}ms to {max_ms}ms"
+    ))]
+    InvalidSessionTimeout {
+        session_timeout_ms: u64,
+        min_ms: u64,
+        max_ms: u64,
+    },
+    #[snafu(display("Group {group} has no members"))]
+    UnknownGroup { group: String },
+    #[snafu(display("Member {member_id} is not in group {group}, it has to join again"))]
+    UnknownMember { group: String, member_id: String },
+    #[snafu(display("Group {group} consumes {topic} with the {strategy:?} strategy"))]
+    Mismatch {
+        group: String,
+        topic: String,
+        strategy: AssignmentStrategy,
+    },
+    #[snafu(display("{source}"))]
+    Topic { source: TopicError },
+    #[snafu(display("Failed to read committed offsets: {source}"))]
+    Storage { source: StorageError },
+}
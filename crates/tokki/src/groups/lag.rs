@@ -0,0 +1,86 @@
+use std::{sync::Arc, time::Duration};
+
+use snafu::ResultExt as _;
+use tokio::task::JoinHandle;
+use tokki_api::groups::PartitionLag;
+use tokki_common::Offset;
+
+use crate::{
+    groups::{ConsumerGroups, GroupError, group_error::StorageSnafu},
+    topics::{Partition, Topic},
+};
+
+/// How far `group` is behind on every partition of `topic`, given the offsets
+/// it has committed there
+pub(crate) async fn topic_lag(
+    groups: &ConsumerGroups,
+    group: &str,
+    topic: &Topic,
+) -> Result<Vec<PartitionLag>, GroupError> {
+    let committed = groups.committed(group, topic).await?;
+
+    let mut lag = Vec::with_capacity(topic.partitions().len());
+    for partition in topic.partitions() {
+        let committed_offset = committed
+            .iter()
+            .find(|offset| offset.partition == partition.index())
+            .map(|offset| offset.offset);
+        lag.push(PartitionLag::new(
+            topic.name(),
+            partition.index(),
+            log_end_offset(partition).await?,
+            committed_offset,
+        ));
+    }
+    Ok(lag)
+}
+
+/// Offset the next record put on `partition` gets
+async fn log_end_offset(partition: &Partition) -> Result<Offset, GroupError> {
+    let storage = partition.storage();
+    match storage.max_offset().await.context(StorageSnafu)? {
+        Some(max_offset) => Ok(Offset(max_offset.0 + 1)),
+        None => storage.log_start_offset().await.context(StorageSnafu),
+    }
+}
+
+/// Periodically report every group's lag as the `consumer_lag` gauge, for each
+/// partition the group has committed an offset for
+pub fn spawn_lag_metrics(groups: Arc<ConsumerGroups>, interval: Duration) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let committed = match groups.committed_topics(None).await {
+                Ok(committed) => committed,
+                Err(e) => {
+                    tracing::error!("Failed to list committed offsets: {}", e);
+                    continue;
+                }
+            };
+            for (group, topic) in committed {
+                match topic_lag(&groups, &group, &topic).await {
+                    Ok(lag) => record(&group, &lag),
+                    Err(e) => {
+                        tracing::error!(group, topic = topic.name(), "Failed to get lag: {}", e);
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn record(group: &str, lag: &[PartitionLag]) {
+    for partition in lag {
+        if let Some(records) = partition.lag {
+            metrics::gauge!(
+                "consumer_lag",
+                "group" => group.to_string(),
+                "topic" => partition.topic.clone(),
+                "partition" => partition.partition.to_string()
+            )
+            .set(records as f64);
+        }
+    }
+}
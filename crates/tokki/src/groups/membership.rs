@@ -0,0 +1,157 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use tokio::time::Instant;
+use tokki_api::groups::{Assignment, AssignmentStrategy, GroupDescription, MemberAssignment};
+
+use crate::{groups::assignment::assign, topics::Topic};
+
+struct Member {
+    session_timeout: Duration,
+    last_heartbeat: Instant,
+}
+
+/// The members of one group and the partitions each is assigned. Only the
+/// leader keeps this, members join again if they lose it.
+pub(crate) struct Membership {
+    topic: String,
+    topic_id: u64,
+    partitions: u32,
+    strategy: AssignmentStrategy,
+    generation: u64,
+    members: BTreeMap<String, Member>,
+    assignment: BTreeMap<String, Vec<u32>>,
+}
+
+impl Membership {
+    pub(crate) fn new(topic: &Topic, strategy: AssignmentStrategy) -> Self {
+        Self {
+            topic: topic.name().to_string(),
+            topic_id: topic.description().id,
+            partitions: topic.partitions().len() as u32,
+            strategy,
+            generation: 0,
+            members: BTreeMap::new(),
+            assignment: BTreeMap::new(),
+        }
+    }
+
+    /// Point an empty group at a topic and strategy, possibly different ones,
+    /// carrying on from its generation
+    pub(crate) fn reset(&mut self, topic: &Topic, strategy: AssignmentStrategy) {
+        debug_assert!(self.is_empty());
+        *self = Self {
+            generation: self.generation,
+            ..Self::new(topic, strategy)
+        };
+    }
+
+    pub(crate) fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub(crate) fn strategy(&self) -> AssignmentStrategy {
+        self.strategy
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Pick up a topic that was recreated under the same name since the group
+    /// formed, returning whether its partitions need handing out again
+    pub(crate) fn refresh_topic(&mut self, topic: &Topic) -> bool {
+        let topic_id = topic.description().id;
+        if self.topic_id == topic_id {
+            return false;
+        }
+
+        self.topic_id = topic_id;
+        self.partitions = topic.partitions().len() as u32;
+        true
+    }
+
+    /// Add a member, or refresh the session of one already in the group,
+    /// returning whether it is new
+    pub(crate) fn join(
+        &mut self,
+        member_id: &str,
+        session_timeout: Duration,
+        now: Instant,
+    ) -> bool {
+        let member = Member {
+            session_timeout,
+            last_heartbeat: now,
+        };
+        self.members.insert(member_id.to_string(), member).is_none()
+    }
+
+    /// Refresh a member's session, returning false if it is not in the group
+    pub(crate) fn heartbeat(&mut self, member_id: &str, now: Instant) -> bool {
+        match self.members.get_mut(member_id) {
+            Some(member) => {
+                member.last_heartbeat = now;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn leave(&mut self, member_id: &str) -> bool {
+        self.members.remove(member_id).is_some()
+    }
+
+    /// Remove members that missed their session timeout, returning whether there were any
+    pub(crate) fn expire(&mut self, now: Instant) -> bool {
+        let before = self.members.len();
+        self.members.retain(|member_id, member| {
+            let alive = now.duration_since(member.last_heartbeat) <= member.session_timeout;
+            if !alive {
+                tracing::info!(member_id, "Member's session timed out");
+            }
+            alive
+        });
+        self.members.len() != before
+    }
+
+    /// Hand out the partitions again among the current members
+    pub(crate) fn rebalance(&mut self) {
+        let members: Vec<_> = self.members.keys().cloned().collect();
+        self.assignment = assign(self.strategy, self.partitions, &members, &self.assignment);
+        self.generation += 1;
+        tracing::info!(
+            topic = self.topic,
+            generation = self.generation,
+            assignment = ?self.assignment,
+            "Rebalanced group"
+        );
+    }
+
+    pub(crate) fn assignment(&self, member_id: &str) -> Assignment {
+        Assignment {
+            member_id: member_id.to_string(),
+            generation: self.generation,
+            partitions: self.assignment.get(member_id).cloned().unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn describe(&self, group: &str) -> GroupDescription {
+        GroupDescription {
+            group: group.to_string(),
+            topic: self.topic.clone(),
+            strategy: self.strategy,
+            generation: self.generation,
+            members: self
+                .members
+                .keys()
+                .map(|member_id| MemberAssignment {
+                    member_id: member_id.clone(),
+                    partitions: self.assignment.get(member_id).cloned().unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,382 @@
+mod assignment;
+mod group_error;
+mod lag;
+mod membership;
+
+pub use assignment::assign;
+pub use group_error::GroupError;
+pub use lag::spawn_lag_metrics;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt as _, ResultExt as _, ensure};
+use tokio::time::Instant;
+use tokki_api::{
+    groups::{Assignment, AssignmentStrategy, GroupDescription, PartitionLag, PartitionOffset},
+    topics::TopicConfig,
+};
+use tokki_common::{Offset, Record};
+
+use crate::{
+    groups::{
+        group_error::{
+            InvalidMemberIdSnafu, InvalidNameSnafu, InvalidSessionTimeoutSnafu, MismatchSnafu,
+            StorageSnafu, TopicSnafu, UnknownGroupSnafu, UnknownMemberSnafu,
+        },
+        lag::topic_lag,
+        membership::Membership,
+    },
+    topics::{Topic, Topics, is_valid_name},
+};
+
+/// Internal topic committed offsets are kept on. Followers copy it like any
+/// other topic, so commits survive losing the leader.
+pub const CONSUMER_OFFSETS_TOPIC: &str = "__consumer_offsets";
+
+/// Records read at a time when catching up with the offsets topic
+const CATCH_UP_RECORDS: usize = 1024;
+const MIN_SESSION_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_SESSION_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Value of a commit record, keyed by group, topic and partition
+#[derive(Debug, Serialize, Deserialize)]
+struct CommittedOffset {
+    /// Id of the topic committed for, so a topic recreated under the same
+    /// name starts out without the old topic's offsets
+    topic_id: u64,
+    offset: Offset,
+}
+
+/// Every group's latest commits, built up from the offsets topic
+#[derive(Default)]
+struct CommittedView {
+    /// Id of the offsets topic this was built from
+    topic_id: Option<u64>,
+    /// Truncations of the offsets topic when this started being built, any
+    /// since may have removed commits it holds
+    truncations: u64,
+    next_offset: Offset,
+    committed: HashMap<Vec<u8>, CommittedOffset>,
+}
+
+/// Consumer groups, their members and the offsets they commit. Commits are
+/// records on the offsets topic, and the latest commit for each partition is
+/// looked up from a view built by reading that topic. Membership is
+/// coordinated by the leader and kept in memory.
+pub struct ConsumerGroups {
+    topics: Arc<Topics>,
+    view: tokio::sync::Mutex<CommittedView>,
+    memberships: Mutex<HashMap<String, Membership>>,
+}
+
+impl ConsumerGroups {
+    pub fn new(topics: Arc<Topics>) -> Self {
+        Self {
+            topics,
+            view: Default::default(),
+            memberships: Default::default(),
+        }
+    }
+
+    /// Add a member to `group`, or refresh the session of one rejoining,
+    /// rebalancing the group if it changed. Members without an id are given
+    /// one. Every member of a group consumes the same topic with the same
+    /// strategy, until the group empties.
+    pub fn join(
+        &self,
+        group: &str,
+        topic: &Topic,
+        member_id: Option<&str>,
+        strategy: AssignmentStrategy,
+        session_timeout: Duration,
+    ) -> Result<Assignment, GroupError> {
+        ensure!(is_valid_name(group), InvalidNameSnafu { name: group });
+        ensure!(
+            (MIN_SESSION_TIMEOUT..=MAX_SESSION_TIMEOUT).contains(&session_timeout),
+            InvalidSessionTimeoutSnafu {
+                session_timeout_ms: session_timeout.as_millis() as u64,
+                min_ms: MIN_SESSION_TIMEOUT.as_millis() as u64,
+                max_ms: MAX_SESSION_TIMEOUT.as_millis() as u64,
+            }
+        );
+        let member_id = match member_id {
+            Some(member_id) => {
+                ensure!(is_valid_name(member_id), InvalidMemberIdSnafu { member_id });
+                member_id.to_string()
+            }
+            None => format!("{:016x}", rand::random::<u64>()),
+        };
+
+        let now = Instant::now();
+        let mut guard = self.memberships.lock().expect("not poisoned");
+        let membership = guard
+            .entry(group.to_string())
+            .or_insert_with(|| Membership::new(topic, strategy));
+
+        let mut changed = self.refresh(membership, now);
+        if membership.is_empty() {
+            membership.reset(topic, strategy);
+        }
+        ensure!(
+            membership.topic() == topic.name() && membership.strategy() == strategy,
+            MismatchSnafu {
+                group,
+                topic: membership.topic(),
+                strategy: membership.strategy(),
+            }
+        );
+        changed |= membership.refresh_topic(topic);
+        changed |= membership.join(&member_id, session_timeout, now);
+
+        if changed {
+            rebalance(group, membership);
+        }
+        Ok(membership.assignment(&member_id))
+    }
+
+    /// Keep a member in its group, returning its assignment. Members learn of
+    /// rebalances from the assignment changing.
+    pub fn heartbeat(&self, group: &str, member_id: &str) -> Result<Assignment, GroupError> {
+        let now = Instant::now();
+        let mut guard = self.memberships.lock().expect("not poisoned");
+        let membership = guard.get_mut(group).context(UnknownGroupSnafu { group })?;
+
+        if self.refresh(membership, now) {
+            rebalance(group, membership);
+        }
+        ensure!(
+            membership.heartbeat(member_id, now),
+            UnknownMemberSnafu { group, member_id }
+        );
+        Ok(membership.assignment(member_id))
+    }
+
+    /// Remove a member from its group, handing its partitions to the rest
+    pub fn leave(&self, group: &str, member_id: &str) -> Result<GroupDescription, GroupError> {
+        let now = Instant::now();
+        let mut guard = self.memberships.lock().expect("not poisoned");
+        let membership = guard.get_mut(group).context(UnknownGroupSnafu { group })?;
+
+        let left = membership.leave(member_id);
+        if self.refresh(membership, now) || left {
+            rebalance(group, membership);
+        }
+        ensure!(left, UnknownMemberSnafu { group, member_id });
+        Ok(membership.describe(group))
+    }
+
+    pub fn describe(&self, group: &str) -> Result<GroupDescription, GroupError> {
+        let now = Instant::now();
+        let mut guard = self.memberships.lock().expect("not poisoned");
+        let membership = guard.get_mut(group).context(UnknownGroupSnafu { group })?;
+
+        if self.refresh(membership, now) {
+            rebalance(group, membership);
+        }
+        Ok(membership.describe(group))
+    }
+
+    /// Drop members whose sessions timed out and pick up changes to the
+    /// group's topic, returning whether the group needs a rebalance
+    fn refresh(&self, membership: &mut Membership, now: Instant) -> bool {
+        let mut changed = membership.expire(now);
+        if let Ok(topic) = self.topics.get(membership.topic()) {
+            changed |= membership.refresh_topic(&topic);
+        }
+        changed
+    }
+
+    /// The topic commits are put on, created by the leader on first use
+    pub async fn offsets_topic(&self) -> Result<Arc<Topic>, GroupError> {
+        // Compacted so only the latest commit for each partition is kept
+        let config = TopicConfig::new().with_partitions(1).with_compaction(true);
+        self.topics
+            .get_or_create_internal(CONSUMER_OFFSETS_TOPIC, config)
+            .await
+            .context(TopicSnafu)
+    }
+
+    /// Records committing `offsets` for `group`, to be put on the offsets topic
+    pub fn commit_records(
+        &self,
+        group: &str,
+        topic: &Topic,
+        offsets: &[PartitionOffset],
+    ) -> Result<Vec<Record>, GroupError> {
+        ensure!(is_valid_name(group), InvalidNameSnafu { name: group });
+
+        offsets
+            .iter()
+            .map(|offset| {
+                topic.partition(offset.partition).context(TopicSnafu)?;
+                let value = CommittedOffset {
+                    topic_id: topic.description().id,
+                    offset: offset.offset,
+                };
+                Ok(Record::new(
+                    commit_key(group, topic.name(), offset.partition),
+                    serde_json::to_vec(&value).expect("Committed offsets serialize"),
+                ))
+            })
+            .collect()
+    }
+
+    /// The offsets `group` has committed for `topic`, in partition order. On a
+    /// follower these are the commits replicated so far.
+    pub async fn committed(
+        &self,
+        group: &str,
+        topic: &Topic,
+    ) -> Result<Vec<PartitionOffset>, GroupError> {
+        ensure!(is_valid_name(group), InvalidNameSnafu { name: group });
+
+        let mut view = self.view.lock().await;
+        self.catch_up(&mut view).await?;
+
+        let topic_id = topic.description().id;
+        Ok(topic
+            .partitions()
+            .iter()
+            .filter_map(|partition| {
+                let key = commit_key(group, topic.name(), partition.index());
+                view.committed
+                    .get(&key)
+                    .filter(|committed| committed.topic_id == topic_id)
+                    .map(|committed| PartitionOffset::new(partition.index(), committed.offset))
+            })
+            .collect())
+    }
+
+    /// How far `group` is behind on every partition of `topic`, or of every
+    /// topic it has committed offsets for. Partitions are ordered by topic,
+    /// then partition.
+    pub async fn lag(
+        &self,
+        group: &str,
+        topic: Option<Arc<Topic>>,
+    ) -> Result<Vec<PartitionLag>, GroupError> {
+        ensure!(is_valid_name(group), InvalidNameSnafu { name: group });
+
+        let topics = match topic {
+            Some(topic) => vec![topic],
+            None => self
+                .committed_topics(Some(group))
+                .await?
+                .into_iter()
+                .map(|(_, topic)| topic)
+                .collect(),
+        };
+
+        let mut lag = Vec::new();
+        for topic in topics {
+            lag.extend(topic_lag(self, group, &topic).await?);
+        }
+        Ok(lag)
+    }
+
+    /// Every group with committed offsets, or just `group`, paired with each
+    /// topic it committed them for. Commits for deleted topics are left out.
+    pub async fn committed_topics(
+        &self,
+        group: Option<&str>,
+    ) -> Result<Vec<(String, Arc<Topic>)>, GroupError> {
+        let mut view = self.view.lock().await;
+        self.catch_up(&mut view).await?;
+
+        let committed: BTreeSet<_> = view
+            .committed
+            .iter()
+            .filter_map(|(key, committed)| {
+                let (key_group, topic, _) = parse_commit_key(key)?;
+                group
+                    .is_none_or(|group| group == key_group)
+                    .then(|| (key_group.to_string(), topic.to_string(), committed.topic_id))
+            })
+            .collect();
+        drop(view);
+
+        Ok(committed
+            .into_iter()
+            .filter_map(|(group, topic, topic_id)| {
+                let topic = self.topics.get(&topic).ok()?;
+                (topic.description().id == topic_id).then_some((group, topic))
+            })
+            .collect())
+    }
+
+    /// Apply every commit put on the offsets topic since the view was last
+    /// updated, starting over if the topic was recreated or truncated
+    async fn catch_up(&self, view: &mut CommittedView) -> Result<(), GroupError> {
+        let Ok(offsets_topic) = self.topics.get(CONSUMER_OFFSETS_TOPIC) else {
+            *view = CommittedView::default();
+            return Ok(());
+        };
+        let partition = offsets_topic.partition(0).context(TopicSnafu)?;
+        let storage = partition.storage();
+
+        let topic_id = offsets_topic.description().id;
+        let truncations = partition.truncations();
+        if view.topic_id != Some(topic_id) || view.truncations != truncations {
+            *view = CommittedView {
+                topic_id: Some(topic_id),
+                truncations,
+                next_offset: storage.log_start_offset().await.context(StorageSnafu)?,
+                committed: HashMap::new(),
+            };
+        }
+
+        loop {
+            // Commits whose put timed out may still be dropped, so only
+            // those that made it to enough replicas count
+            let (records, next_offset) = partition
+                .get_committed_records(view.next_offset, CATCH_UP_RECORDS)
+                .await
+                .context(StorageSnafu)?;
+            if next_offset == view.next_offset {
+                return Ok(());
+            }
+
+            for record in records {
+                if record.is_tombstone() {
+                    view.committed.remove(record.key());
+                    continue;
+                }
+
+                match serde_json::from_slice(record.value()) {
+                    Ok(committed) => {
+                        view.committed.insert(record.key().to_vec(), committed);
+                    }
+                    Err(e) => tracing::warn!("Skipping unreadable committed offset: {}", e),
+                }
+            }
+            view.next_offset = next_offset;
+        }
+    }
+}
+
+fn rebalance(group: &str, membership: &mut Membership) {
+    membership.rebalance();
+    metrics::counter!("group_rebalances", "group" => group.to_string()).increment(1);
+    metrics::gauge!("group_members", "group" => group.to_string()).set(membership.len() as f64);
+}
+
+/// Group and topic names cannot hold a '/', so keys never collide
+fn commit_key(group: &str, topic: &str, partition: u32) -> Vec<u8> {
+    format!("{group}/{topic}/{partition}").into_bytes()
+}
+
+/// The group, topic and partition of a key written by `commit_key`
+fn parse_commit_key(key: &[u8]) -> Option<(&str, &str, u32)> {
+    let key = std::str::from_utf8(key).ok()?;
+    let mut parts = key.split('/');
+    let (group, topic, partition) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((group, topic, partition.parse().ok()?))
+}
@@ -1,8 +1,14 @@
 pub mod app_state;
 pub mod cli;
+pub mod cluster;
 mod controller_error;
 pub mod controllers;
+pub mod election;
+pub mod groups;
 pub mod replication;
 pub mod server;
 pub mod server_error;
+mod state_file;
 pub mod storage;
+pub mod timestamp_type;
+pub mod topics;
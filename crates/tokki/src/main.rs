@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use clap::Parser as _;
 use metrics_exporter_prometheus::PrometheusBuilder;
@@ -7,9 +7,13 @@ use tracing_subscriber::EnvFilter;
 use tokki::{
     app_state::AppState,
     cli::{Cli, CliMode, CliStorageEngine},
+    cluster::{ClusterMembership, MembershipConfig},
+    election::ElectionConfig,
+    groups::spawn_lag_metrics,
     server::{create_router, listen},
     server_error::ServerError,
-    storage::{InMemoryChannelStorage, InMemoryLockFree, InMemoryStorage, Storage},
+    storage::{DiskStorageConfig, Keyring},
+    topics::{TopicStorage, Topics, TopicsConfig},
 };
 
 #[tokio::main]
@@ -27,32 +31,130 @@ async fn main() -> Result<(), ServerError> {
 
     let addr: SocketAddr = ([0, 0, 0, 0], cli.port).into();
 
-    let storage: Arc<dyn Storage> = match cli.storage {
-        CliStorageEngine::InMemoryMutex => Arc::new(InMemoryStorage::default()),
-        CliStorageEngine::InMemoryChannel => Arc::new(InMemoryChannelStorage::new().await.unwrap()),
-        CliStorageEngine::InMemoryLockFree => Arc::new(InMemoryLockFree::new()),
+    if cli.encryption_key_file.is_some() && !matches!(cli.storage, CliStorageEngine::Disk) {
+        return Err(ServerError::EncryptionNeedsDisk);
+    }
+    let storage = match cli.storage {
+        CliStorageEngine::InMemoryMutex => TopicStorage::InMemoryMutex,
+        CliStorageEngine::InMemoryChannel => TopicStorage::InMemoryChannel,
+        CliStorageEngine::InMemoryLockFree => TopicStorage::InMemoryLockFree {
+            corrupt_records: cli.corrupt_record_policy(),
+        },
+        CliStorageEngine::Disk => {
+            let data_dir = cli
+                .data_dir
+                .clone()
+                .expect("clap requires a data dir for disk storage");
+            let mut config = DiskStorageConfig::new(data_dir.join("topics"))
+                .with_durability(cli.durability_policy())
+                .with_corrupt_records(cli.corrupt_record_policy());
+            if let Some(key_file) = &cli.encryption_key_file {
+                config = config.with_keyring(
+                    Keyring::load(key_file).map_err(|source| ServerError::KeyFile { source })?,
+                );
+            }
+            TopicStorage::Disk(config)
+        }
     };
 
+    let mut topics_config = TopicsConfig::new(storage)
+        .with_default_config(cli.default_topic_config())
+        .with_in_sync(cli.in_sync_config())
+        .with_retention_check(Duration::from_secs(cli.retention_check_secs))
+        .with_compaction_check(Duration::from_secs(cli.compaction_check_secs));
+    if let Some(data_dir) = &cli.data_dir {
+        topics_config = topics_config.with_metadata_dir(data_dir);
+    }
+    if let CliMode::Leader {
+        required_replicas, ..
+    }
+    | CliMode::Elected {
+        required_replicas, ..
+    } = cli.mode
+    {
+        topics_config = topics_config.with_required_replicas(required_replicas);
+    }
+    let topics = Arc::new(
+        Topics::open(topics_config)
+            .await
+            .map_err(|source| ServerError::TopicsOpen { source })?,
+    );
+
+    let timestamp_type = cli.timestamp_type();
+    let advertised_url = cli.advertised_url();
     let token = cli.token;
 
+    let members = match &cli.mode {
+        CliMode::Leader { member, .. } => member.clone(),
+        CliMode::Follower { .. } => Vec::new(),
+        CliMode::Elected { peers, .. } => {
+            let mut members = peers.clone();
+            members.push(advertised_url.clone());
+            members
+        }
+    };
+    let mut membership_config = MembershipConfig::new(members);
+    if let Some(data_dir) = &cli.data_dir {
+        membership_config = membership_config.with_state_dir(data_dir);
+    }
+    if let Some(follower_timeout_secs) = cli.follower_timeout_secs {
+        membership_config =
+            membership_config.with_follower_timeout(Duration::from_secs(follower_timeout_secs));
+    }
+    let membership = Arc::new(
+        ClusterMembership::open(membership_config)
+            .map_err(|source| ServerError::MembershipOpen { source })?,
+    );
+
     let app_state = match cli.mode {
-        CliMode::Leader { required_replicas } => AppState::builder()
+        CliMode::Leader { .. } => AppState::builder()
             .leader()
             .with_profiling_enabled(cli.enable_profiling)
-            .with_storage(storage)
+            .with_topics(topics)
             .with_token(token)
-            .with_required_replicas(required_replicas)
+            .with_timestamp_type(timestamp_type)
+            .with_advertised_url(advertised_url)
+            .with_membership(membership)
             .build(),
         CliMode::Follower { leader } => AppState::builder()
             .follower()
             .with_profiling_enabled(cli.enable_profiling)
             .with_leader(leader)
             .with_socket_addr(addr)
-            .with_storage(storage)
+            .with_advertised_url(advertised_url)
+            .with_topics(topics)
             .with_token(token)
             .build(),
+        CliMode::Elected {
+            peers,
+            election_timeout_ms,
+            heartbeat_interval_ms,
+            ..
+        } => {
+            let mut election = ElectionConfig::new(advertised_url, peers)
+                .with_election_timeout(Duration::from_millis(election_timeout_ms))
+                .with_heartbeat_interval(Duration::from_millis(heartbeat_interval_ms));
+            if let Some(data_dir) = &cli.data_dir {
+                election = election.with_state_dir(data_dir);
+            }
+            AppState::builder()
+                .elected()
+                .with_profiling_enabled(cli.enable_profiling)
+                .with_topics(topics)
+                .with_token(token)
+                .with_timestamp_type(timestamp_type)
+                .with_election(election)
+                .with_membership(membership)
+                .build()
+                .map_err(|source| ServerError::ElectionOpen { source })?
+        }
     };
 
+    let _lag_metrics = spawn_lag_metrics(
+        app_state.groups().clone(),
+        Duration::from_secs(cli.lag_metrics_secs),
+    );
+
     let app = create_router(app_state);
 
     listen(app, addr).await?;
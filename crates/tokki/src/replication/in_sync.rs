@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+/// How long members can go without catching up before they are out of sync
+const DEFAULT_MAX_LAG: Duration = Duration::from_secs(3);
+
+/// When members count as in sync with the leader. Records are acknowledged
+/// once enough in-sync members have them, so members that fall behind stop
+/// holding puts up once they drop out.
+#[derive(Debug, Clone, Copy)]
+pub struct InSyncConfig {
+    max_lag: Duration,
+    max_lag_records: Option<usize>,
+    min_insync_replicas: Option<usize>,
+}
+
+impl Default for InSyncConfig {
+    fn default() -> Self {
+        Self {
+            max_lag: DEFAULT_MAX_LAG,
+            max_lag_records: None,
+            min_insync_replicas: None,
+        }
+    }
+}
+
+impl InSyncConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop members that go this long without copying everything the leader
+    /// had when they last polled, including members that stop polling
+    pub fn with_max_lag(mut self, max_lag: Duration) -> Self {
+        self.max_lag = max_lag;
+        self
+    }
+
+    /// Drop members that poll while more than this many records behind
+    pub fn with_max_lag_records(mut self, max_lag_records: usize) -> Self {
+        self.max_lag_records = Some(max_lag_records);
+        self
+    }
+
+    /// Fail puts, rather than acknowledge them on fewer copies, while fewer
+    /// members than this are in sync. Defaults to the required replicas.
+    pub fn with_min_insync_replicas(mut self, min_insync_replicas: usize) -> Self {
+        self.min_insync_replicas = Some(min_insync_replicas);
+        self
+    }
+
+    pub(crate) fn max_lag(&self) -> Duration {
+        self.max_lag
+    }
+
+    pub(crate) fn max_lag_records(&self) -> Option<usize> {
+        self.max_lag_records
+    }
+
+    pub(crate) fn min_insync_replicas(&self, required_replicas: usize) -> usize {
+        self.min_insync_replicas.unwrap_or(required_replicas)
+    }
+}
+
+/// A member joining or leaving a partition's in-sync replicas
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InSyncChange {
+    /// Caught up with the committed records
+    Joined { follower: String },
+    /// Polled while further behind than allowed
+    Lagged { follower: String, records: usize },
+    /// Went too long without catching up, or polling
+    TimedOut { follower: String },
+}
+
+impl InSyncChange {
+    /// Log and count `changes` to a partition that has `in_sync` members left
+    /// in sync afterwards
+    pub(crate) fn record(changes: &[InSyncChange], topic: &str, partition: u32, in_sync: usize) {
+        for change in changes {
+            let kind = match change {
+                InSyncChange::Joined { follower } => {
+                    tracing::info!(follower, topic, partition, in_sync, "Follower is in sync");
+                    "joined"
+                }
+                InSyncChange::Lagged { follower, records } => {
+                    tracing::warn!(
+                        follower,
+                        topic,
+                        partition,
+                        in_sync,
+                        "Follower is out of sync, {} records behind",
+                        records
+                    );
+                    "lagged"
+                }
+                InSyncChange::TimedOut { follower } => {
+                    tracing::warn!(
+                        follower,
+                        topic,
+                        partition,
+                        in_sync,
+                        "Follower is out of sync, it has not caught up in time"
+                    );
+                    "timed_out"
+                }
+            };
+            metrics::counter!("in_sync_replica_changes", "topic" => topic.to_string(), "change" => kind)
+                .increment(1);
+        }
+        if !changes.is_empty() {
+            metrics::gauge!(
+                "in_sync_replicas",
+                "topic" => topic.to_string(),
+                "partition" => partition.to_string()
+            )
+            .set(in_sync as f64);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{fs, io, path::PathBuf};
+
+use tokki_api::clustering::LeaderEpoch;
+use tokki_common::Offset;
+
+use crate::state_file::write_atomically;
+
+/// Which leader epoch each run of a partition's log was written in.
+///
+/// Every leader starts a new epoch, so when two logs have records from the
+/// same epoch at an offset they agree on everything up to there. Entries
+/// are kept oldest first, and written to `path` whenever they change if the
+/// partition is kept on disk.
+pub struct LeaderEpochs {
+    path: Option<PathBuf>,
+    entries: Vec<LeaderEpoch>,
+}
+
+impl LeaderEpochs {
+    /// Load the epochs stored at `path`, dropping any that start past
+    /// `log_end` as the records they were for never made it to the log
+    pub fn open(path: Option<PathBuf>, log_end: Offset) -> io::Result<Self> {
+        let entries = match &path {
+            Some(path) => match fs::read(path) {
+                Ok(buf) => serde_json::from_slice(&buf)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e),
+            },
+            None => Vec::new(),
+        };
+
+        let mut epochs = Self { path, entries };
+        epochs.truncate(log_end)?;
+        Ok(epochs)
+    }
+
+    /// The epoch of the last record in the log
+    pub fn latest(&self) -> Option<LeaderEpoch> {
+        self.entries.last().copied()
+    }
+
+    /// The epoch of the last record in a log ending at `log_end`, passing
+    /// over any epoch a leader started that has no records yet
+    pub fn last_record_epoch(&self, log_end: Offset) -> Option<u64> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.start_offset < log_end)
+            .map(|entry| entry.epoch)
+    }
+
+    /// Note that records from `start_offset` on belong to `epoch`, unless the
+    /// log already has records from it or a later one
+    pub fn assign(&mut self, epoch: u64, start_offset: Offset) -> io::Result<()> {
+        if self.latest().is_some_and(|latest| latest.epoch >= epoch) {
+            return Ok(());
+        }
+
+        tracing::info!(epoch, start_offset = start_offset.0, "New leader epoch");
+        self.entries.push(LeaderEpoch::new(epoch, start_offset));
+        self.persist()
+    }
+
+    /// Find where `epoch` ends on this log, along with the latest epoch at or
+    /// before it. That is where the first later epoch starts, or `log_end`
+    /// when there is none. An epoch this log never had ends where the log
+    /// moved on to a later one.
+    pub fn end_offset_for(&self, epoch: Option<u64>, log_end: Offset) -> (Option<u64>, Offset) {
+        let later = self
+            .entries
+            .partition_point(|entry| Some(entry.epoch) <= epoch);
+        let end_offset = self
+            .entries
+            .get(later)
+            .map_or(log_end, |entry| entry.start_offset);
+        let found = later.checked_sub(1).map(|idx| self.entries[idx].epoch);
+
+        (found, end_offset)
+    }
+
+    /// The epochs of the records from `start` up to `end`, starting with the
+    /// one the record at `start` belongs to
+    pub fn between(&self, start: Offset, end: Offset) -> Vec<LeaderEpoch> {
+        let first = self
+            .entries
+            .partition_point(|entry| entry.start_offset <= start)
+            .saturating_sub(1);
+
+        self.entries[first..]
+            .iter()
+            .take_while(|entry| entry.start_offset < end)
+            .copied()
+            .collect()
+    }
+
+    /// Forget epochs that start at or after `log_end`, once the log has been
+    /// truncated to end there
+    pub fn truncate(&mut self, log_end: Offset) -> io::Result<()> {
+        let keep = self
+            .entries
+            .partition_point(|entry| entry.start_offset < log_end);
+        if keep == self.entries.len() {
+            return Ok(());
+        }
+
+        self.entries.truncate(keep);
+        self.persist()
+    }
+
+    fn persist(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        // Synced, as followers would truncate to the wrong offset going by
+        // epochs lost in a crash
+        write_atomically(path, &serde_json::to_vec(&self.entries)?)
+    }
+}
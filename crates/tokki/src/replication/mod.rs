@@ -1,11 +1,17 @@
+mod in_sync;
+mod leader_epochs;
 mod waiting_request;
 
+pub use in_sync::{InSyncChange, InSyncConfig};
+pub use leader_epochs::LeaderEpochs;
+
 use std::{
     cmp::Reverse,
-    collections::{BinaryHeap, HashMap},
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap},
 };
 
-use tokio::sync::oneshot;
+use tokio::{sync::oneshot, time::Instant};
+use tokki_api::cluster::{ClusterMembers, ReplicaStatus};
 use tokki_common::Offset;
 
 use crate::replication::waiting_request::WaitingRequest;
@@ -13,45 +19,326 @@ use crate::replication::waiting_request::WaitingRequest;
 pub struct Replication {
     waiting_requests: BinaryHeap<Reverse<WaitingRequest>>,
     required_replicas: usize,
-    follower_max_offsets: HashMap<String, Offset>,
+    in_sync_config: InSyncConfig,
+    /// Followers whose copies count towards `required_replicas`
+    members: ClusterMembers,
+    /// Members whose copies are counted, along with when each last caught up
+    in_sync: BTreeMap<String, Instant>,
+    /// How far each follower polling the leader has got, members or not
+    followers: HashMap<String, Follower>,
+    /// Offset after the last record the leader has made durable
+    log_end: Offset,
+    /// Offset after the last committed record, one that reached as many
+    /// followers as are required. Consumers read up to here by default.
+    high_watermark: Offset,
+}
+
+struct Follower {
+    /// Last record the follower has, `None` while it holds nothing
+    max_offset: Option<Offset>,
+    /// The leader's log end when the follower last polled
+    polled_log_end: Offset,
+}
+
+impl Follower {
+    /// Offset after the last record the follower has
+    fn end(&self) -> Offset {
+        self.max_offset.map_or(Offset(0), |offset| offset + 1)
+    }
 }
 
 impl Replication {
-    pub fn new(required_replicas: usize) -> Self {
+    /// Track replication of a log ending at `log_end`. Without followers to
+    /// wait for the whole log is committed. Otherwise nothing is known to be
+    /// committed until followers report how far they have got.
+    pub fn new(required_replicas: usize, log_end: Offset) -> Self {
+        let high_watermark = if required_replicas == 0 {
+            log_end
+        } else {
+            Offset(0)
+        };
         Self {
             required_replicas,
+            in_sync_config: InSyncConfig::default(),
             waiting_requests: Default::default(),
-            follower_max_offsets: Default::default(),
+            members: Default::default(),
+            in_sync: Default::default(),
+            followers: Default::default(),
+            log_end,
+            high_watermark,
         }
     }
 
+    /// Decide which members are in sync by `in_sync_config`
+    pub fn with_in_sync(mut self, in_sync_config: InSyncConfig) -> Self {
+        self.in_sync_config = in_sync_config;
+        self
+    }
+
+    pub fn high_watermark(&self) -> Offset {
+        self.high_watermark
+    }
+
+    pub fn log_end(&self) -> Offset {
+        self.log_end
+    }
+
+    /// Wake `wake_tx` once the record at `waiting_for` is committed
     pub fn register_wait(&mut self, waiting_for: Offset, wake_tx: oneshot::Sender<()>) {
         let waiting_request = WaitingRequest {
             waiting_for,
             wake_tx,
         };
+        if waiting_for < self.high_watermark {
+            waiting_request.wake();
+            return;
+        }
         self.waiting_requests.push(Reverse(waiting_request));
         tracing::info!("Registered wait for {:?}", waiting_for);
     }
 
-    pub fn update_follower_max_offset(&mut self, follower: String, offset: Option<Offset>) {
-        if let Some(offset) = offset {
-            self.follower_max_offsets.insert(follower, offset);
+    /// Note that the leader's records before `log_end` are durable
+    pub fn update_log_end(&mut self, log_end: Offset) {
+        self.log_end = self.log_end.max(log_end);
+        self.advance_high_watermark();
+    }
+
+    /// Note how far `follower` has got as it polls for more. Members that
+    /// have copied the committed records join the in-sync replicas, and ones
+    /// too far behind leave them.
+    pub fn update_follower_max_offset(
+        &mut self,
+        follower: String,
+        max_offset: Option<Offset>,
+    ) -> Vec<InSyncChange> {
+        let polled = Follower {
+            max_offset,
+            polled_log_end: self.log_end,
+        };
+        let end = polled.end();
+        let previous = self.followers.insert(follower.clone(), polled);
 
-            while let Some(next_req) = self.waiting_requests.peek() {
-                let followers_beyond = self
-                    .follower_max_offsets
-                    .values()
-                    .filter(|o| **o >= next_req.0.waiting_for)
-                    .count();
+        let mut changes = Vec::new();
+        if self.members.all().contains(follower.as_str()) {
+            let behind = self.log_end.0.saturating_sub(end.0);
+            let lagging = self
+                .in_sync_config
+                .max_lag_records()
+                .is_some_and(|max_lag_records| behind > max_lag_records);
+            // Everything the leader had when the follower last asked
+            let caught_up =
+                end >= previous.map_or(self.log_end, |previous| previous.polled_log_end);
 
-                if followers_beyond >= self.required_replicas {
-                    let next_req = self.waiting_requests.pop().expect("Some waiting request");
-                    next_req.0.wake();
-                } else {
-                    break;
+            match self.in_sync.get_mut(&follower) {
+                Some(_) if lagging => {
+                    self.in_sync.remove(&follower);
+                    changes.push(InSyncChange::Lagged {
+                        follower,
+                        records: behind,
+                    });
+                }
+                Some(caught_up_at) if caught_up => *caught_up_at = Instant::now(),
+                Some(_) => {}
+                None if !lagging && end >= self.high_watermark => {
+                    self.in_sync.insert(follower.clone(), Instant::now());
+                    changes.push(InSyncChange::Joined { follower });
                 }
+                None => {}
+            }
+        }
+
+        self.advance_high_watermark();
+        changes
+    }
+
+    /// Drop the members that have not caught up within the maximum lag by
+    /// `now` from the in-sync replicas
+    pub fn check_in_sync(&mut self, now: Instant) -> Vec<InSyncChange> {
+        let max_lag = self.in_sync_config.max_lag();
+        let timed_out: Vec<_> = self
+            .in_sync
+            .iter()
+            .filter(|(_, caught_up_at)| now.saturating_duration_since(**caught_up_at) > max_lag)
+            .map(|(follower, _)| follower.clone())
+            .collect();
+        if timed_out.is_empty() {
+            return Vec::new();
+        }
+
+        for follower in &timed_out {
+            self.in_sync.remove(follower);
+        }
+        self.advance_high_watermark();
+        timed_out
+            .into_iter()
+            .map(|follower| InSyncChange::TimedOut { follower })
+            .collect()
+    }
+
+    /// Count copies on `members` from now on. While they are changing,
+    /// records need enough copies among both the old and new members.
+    /// Members that are new to the partition are given the maximum lag to
+    /// show they are in sync, members only joining have to catch up first.
+    pub fn set_members(&mut self, members: ClusterMembers) {
+        let previous: BTreeSet<_> = self.members.all().into_iter().map(String::from).collect();
+        let now = Instant::now();
+        for member in &members.members {
+            if !previous.contains(member) {
+                self.in_sync.insert(member.clone(), now);
+            }
+        }
+        let all = members.all();
+        self.in_sync
+            .retain(|member, _| all.contains(member.as_str()));
+
+        self.members = members;
+        self.advance_high_watermark();
+    }
+
+    /// Stop tracking a follower that no longer polls
+    pub fn forget_follower(&mut self, follower: &str) {
+        self.followers.remove(follower);
+    }
+
+    /// Whether the members being changed to have enough copies of every
+    /// committed record to be counted on their own
+    pub fn joining_caught_up(&self) -> bool {
+        let Some(joining) = &self.members.joining else {
+            return true;
+        };
+        self.required_replicas == 0
+            || self
+                .replicated(joining)
+                .is_some_and(|replicated| replicated >= self.high_watermark)
+    }
+
+    /// Members in sync right now
+    pub fn in_sync(&self) -> impl Iterator<Item = &str> {
+        self.in_sync.keys().map(String::as_str)
+    }
+
+    /// Fewest members that have to be in sync for puts to be acknowledged
+    pub fn min_insync_replicas(&self) -> usize {
+        self.in_sync_config
+            .min_insync_replicas(self.required_replicas)
+    }
+
+    /// Whether enough members are in sync for puts to be acknowledged
+    pub fn enough_in_sync(&self) -> bool {
+        self.required_replicas == 0
+            || self.in_sync_among(&self.members.members) >= self.min_insync_replicas()
+    }
+
+    /// Every member and follower, and how far each has got
+    pub fn replicas(&self) -> Vec<ReplicaStatus> {
+        let all = self.members.all();
+        let mut urls: BTreeSet<&str> = all.clone();
+        urls.extend(self.followers.keys().map(String::as_str));
+        urls.into_iter()
+            .map(|url| ReplicaStatus {
+                url: url.to_string(),
+                member: all.contains(url),
+                in_sync: self.in_sync.contains_key(url),
+                max_offset: self
+                    .followers
+                    .get(url)
+                    .and_then(|follower| follower.max_offset),
+            })
+            .collect()
+    }
+
+    /// Take over as leader of a log ending at `log_end`. Followers report
+    /// their progress afresh, each member given the maximum lag to do so,
+    /// and puts waiting on the previous leadership fail.
+    pub fn lead(&mut self, log_end: Offset) {
+        self.waiting_requests.clear();
+        self.followers.clear();
+        let now = Instant::now();
+        self.in_sync = self
+            .members
+            .members
+            .iter()
+            .map(|member| (member.clone(), now))
+            .collect();
+        self.log_end = log_end;
+        self.advance_high_watermark();
+    }
+
+    /// Hand over leadership. Puts still waiting fail, as their records may
+    /// never be committed.
+    pub fn stop_leading(&mut self) {
+        self.waiting_requests.clear();
+    }
+
+    /// Take the leader's high watermark, as a follower. Followers never
+    /// commit anything themselves, so this replaces whatever they had.
+    pub fn set_high_watermark(&mut self, high_watermark: Offset) {
+        self.high_watermark = high_watermark;
+    }
+
+    /// Move the high watermark up to the furthest offset durable on the
+    /// leader and on as many in-sync members as are required, waking the
+    /// puts waiting on records it passed. Puts waiting while too few members
+    /// are in sync fail instead.
+    fn advance_high_watermark(&mut self) {
+        if !self.enough_in_sync() {
+            self.waiting_requests.clear();
+        }
+
+        let committed = if self.required_replicas == 0 {
+            self.log_end
+        } else {
+            // `None` sorts first, so either set of members without enough
+            // copies holds the high watermark back
+            let replicated = [Some(&self.members.members), self.members.joining.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(|members| self.replicated(members))
+                .min()
+                .flatten();
+            match replicated {
+                Some(replicated) => replicated.min(self.log_end),
+                None => return,
+            }
+        };
+        self.high_watermark = self.high_watermark.max(committed);
+
+        while let Some(next_req) = self.waiting_requests.peek() {
+            if next_req.0.waiting_for < self.high_watermark {
+                let next_req = self.waiting_requests.pop().expect("Some waiting request");
+                next_req.0.wake();
+            } else {
+                break;
             }
         }
     }
+
+    /// Offset after the last record as many of the in-sync `members` as are
+    /// required have copied. With fewer in sync than required every one of
+    /// them is needed, and nothing is known while fewer than the minimum are.
+    fn replicated(&self, members: &BTreeSet<String>) -> Option<Offset> {
+        let in_sync = self.in_sync_among(members);
+        if in_sync < self.min_insync_replicas() {
+            return None;
+        }
+        let required = self.required_replicas.min(in_sync);
+        if required == 0 {
+            return Some(self.log_end);
+        }
+
+        let mut replicated: Vec<_> = members
+            .iter()
+            .filter(|member| self.in_sync.contains_key(*member))
+            .map(|member| self.followers.get(member).map_or(Offset(0), Follower::end))
+            .collect();
+        replicated.sort_unstable_by(|a, b| b.cmp(a));
+        replicated.get(required - 1).copied()
+    }
+
+    fn in_sync_among(&self, members: &BTreeSet<String>) -> usize {
+        members
+            .iter()
+            .filter(|member| self.in_sync.contains_key(*member))
+            .count()
+    }
 }
@@ -2,15 +2,18 @@ use std::net::SocketAddr;
 
 use axum::{
     Router,
-    routing::{get, put},
+    routing::{delete, get, put},
 };
 use snafu::ResultExt as _;
 
 use crate::{
     app_state::AppState,
     controllers::{
-        get_healthcheck, get_records, get_records_for_replication, get_shards, put_records,
-        start_profiling,
+        add_member, commit_offsets, create_topic, delete_topic, describe_group,
+        get_committed_offsets, get_group_lag, get_healthcheck, get_in_sync_replicas,
+        get_offset_for_timestamp, get_record_frames, get_records, get_records_for_replication,
+        get_shards, heartbeat, join_group, leader_heartbeat, leave_group, list_members,
+        list_topics, put_records, remove_member, request_vote, start_profiling,
     },
     server_error::{PortBindSnafu, ServeSnafu, ServerError},
 };
@@ -19,9 +22,31 @@ pub fn create_router(app_state: AppState) -> Router {
     Router::new()
         .route("/healthcheck", get(get_healthcheck))
         .route("/shards", get(get_shards))
-        .route("/records", get(get_records))
-        .route("/records", put(put_records))
+        .route("/topics", get(list_topics))
+        .route("/topics/{name}", put(create_topic).delete(delete_topic))
+        .route("/topics/{name}/records", get(get_records).put(put_records))
+        .route("/topics/{name}/records/frames", get(get_record_frames))
+        .route("/topics/{name}/offsets", get(get_offset_for_timestamp))
+        .route(
+            "/groups/{group}/offsets",
+            get(get_committed_offsets).put(commit_offsets),
+        )
+        .route("/groups/{group}", get(describe_group))
+        .route("/groups/{group}/lag", get(get_group_lag))
+        .route("/groups/{group}/members", put(join_group))
+        .route("/groups/{group}/members/{member_id}", delete(leave_group))
+        .route(
+            "/groups/{group}/members/{member_id}/heartbeat",
+            put(heartbeat),
+        )
         .route("/replication", get(get_records_for_replication))
+        .route("/election/vote", put(request_vote))
+        .route("/election/heartbeat", put(leader_heartbeat))
+        .route(
+            "/cluster/members",
+            get(list_members).put(add_member).delete(remove_member),
+        )
+        .route("/cluster/in-sync", get(get_in_sync_replicas))
         .route("/profiling/start", get(start_profiling))
         .layer(axum_metrics::MetricLayer::default())
         .with_state(app_state)
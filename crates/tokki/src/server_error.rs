@@ -1,5 +1,9 @@
 use snafu::Snafu;
 
+use crate::{
+    cluster::ClusterError, election::ElectionError, storage::KeyringError, topics::TopicError,
+};
+
 /// Errors relating to starting the server
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(crate)))]
@@ -8,4 +12,14 @@ pub enum ServerError {
     PortBind { port: u16, source: std::io::Error },
     #[snafu(display("Failure to serve: {source}"))]
     Serve { source: std::io::Error },
+    #[snafu(display("Failed to open topics: {source}"))]
+    TopicsOpen { source: TopicError },
+    #[snafu(display("Failed to load encryption keys: {source}"))]
+    KeyFile { source: KeyringError },
+    #[snafu(display("Only the disk storage engine can encrypt records"))]
+    EncryptionNeedsDisk,
+    #[snafu(display("Failed to open election state: {source}"))]
+    ElectionOpen { source: ElectionError },
+    #[snafu(display("Failed to open cluster members: {source}"))]
+    MembershipOpen { source: ClusterError },
 }
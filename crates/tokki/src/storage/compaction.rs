@@ -0,0 +1,40 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::task::JoinHandle;
+
+use crate::storage::Storage;
+
+/// Keep only the latest record for each key in the parts of the log that are
+/// no longer being written to. Offsets of the surviving records are preserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPolicy {
+    /// How long a tombstone, a record with an empty value, is kept after it
+    /// was written so consumers have a chance to see the key was deleted
+    pub tombstone_retention: Duration,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self {
+            tombstone_retention: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Periodically compact `storage` in the background
+pub fn spawn_compaction(
+    storage: Arc<dyn Storage>,
+    policy: CompactionPolicy,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = storage.compact(&policy).await {
+                tracing::error!("Failed to compact log: {}", e);
+            }
+        }
+    })
+}
@@ -0,0 +1,25 @@
+use tokki_common::{DecodeError, Offset};
+
+/// What reads do when they come across a record that fails to decode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CorruptRecordPolicy {
+    /// Fail the read with `StorageError::CorruptRecord`
+    #[default]
+    Fail,
+    /// Step over the corrupt record and carry on reading, logging and
+    /// counting the offsets that were skipped
+    Skip,
+}
+
+impl CorruptRecordPolicy {
+    /// Note that the records in `from..to` were skipped because they could not be decoded
+    pub(crate) fn record_skipped(from: Offset, to: Offset, source: &DecodeError) {
+        tracing::warn!(
+            from = from.0,
+            to = to.0,
+            "Skipping corrupt records: {}",
+            source
+        );
+        metrics::counter!("corrupt_records_skipped").increment((to - from).0 as u64);
+    }
+}
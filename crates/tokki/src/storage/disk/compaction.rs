@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufWriter, Write as _},
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime,
+};
+
+use tokki_common::{Compression, Offset, RecordBatch};
+
+use crate::storage::{
+    CompactionPolicy, StorageError,
+    disk::{
+        DiskStorageInner,
+        index::{IndexEntry, SparseIndex},
+        segment::{Segment, SegmentSnapshot},
+        time_index::{TimeIndex, TimeIndexEntry},
+    },
+};
+
+/// Compacted segment files while they are being written
+const CLEANED_SUFFIX: &str = ".cleaned";
+/// Truncated segment files while they are being written
+pub const TRUNCATED_SUFFIX: &str = ".truncated";
+/// Compacted or truncated segment files that are complete and waiting to
+/// replace the originals
+const SWAP_SUFFIX: &str = ".swap";
+
+/// Rewrite every sealed segment keeping only the latest record for each key.
+/// Records with an empty key are left alone.
+///
+/// Segments are scanned and rewritten from snapshots without holding the
+/// lock, which is only taken to swap the finished files in. Compacted
+/// segments get an index entry for every record as offsets are no longer
+/// contiguous once records have been removed. Compressed batches are
+/// rewritten as individual records, since only some of a batch may survive.
+pub fn compact(
+    inner: &RwLock<DiskStorageInner>,
+    policy: &CompactionPolicy,
+) -> Result<(), StorageError> {
+    let (data_dir, truncations, sealed, active) = {
+        let guard = inner.read().expect("No panics");
+        let (active, sealed) = guard.segments.split_last().expect("At least one segment");
+
+        (
+            guard.config.data_dir.clone(),
+            guard.truncations,
+            sealed
+                .iter()
+                .map(Segment::snapshot)
+                .collect::<io::Result<Vec<_>>>()?,
+            active.snapshot()?,
+        )
+    };
+
+    if sealed.is_empty() {
+        return Ok(());
+    }
+
+    // Records without a key never supersede each other
+    let mut latest = HashMap::new();
+    for snapshot in sealed.iter().chain([&active]) {
+        snapshot.for_each_record(|offset, record| {
+            if !record.key().is_empty() {
+                latest.insert(record.key().to_vec(), offset);
+            }
+            Ok(())
+        })?;
+    }
+
+    let now = SystemTime::now();
+
+    for snapshot in sealed {
+        let tombstones_expired = now
+            .duration_since(snapshot.last_modified)
+            .is_ok_and(|age| age > policy.tombstone_retention);
+
+        let removed = write_cleaned(&data_dir, &snapshot, CLEANED_SUFFIX, |offset, record| {
+            if record.key().is_empty() {
+                return false;
+            }
+            let superseded = latest.get(record.key()) != Some(&offset);
+            superseded || (record.is_tombstone() && tombstones_expired)
+        })?;
+
+        let base_offset = snapshot.base_offset;
+        if removed == 0 {
+            remove_cleaned(&data_dir, base_offset)?;
+            continue;
+        }
+
+        let mut guard = inner.write().expect("No panics");
+
+        // Truncation may have rewritten the segment, and any compacted since
+        // would bring back records it removed
+        if guard.truncations != truncations {
+            remove_cleaned(&data_dir, base_offset)?;
+            tracing::info!("Log was truncated while compacting, trying again later");
+            return Ok(());
+        }
+
+        // Retention may have deleted the segment in the meantime
+        let Some(idx) = guard
+            .segments
+            .iter()
+            .position(|segment| segment.base_offset() == base_offset)
+        else {
+            remove_cleaned(&data_dir, base_offset)?;
+            continue;
+        };
+
+        swap_in(&data_dir, base_offset, CLEANED_SUFFIX)?;
+        guard.segments[idx] = Segment::open_sealed(
+            &data_dir,
+            base_offset,
+            snapshot.next_offset,
+            snapshot.keyring.clone(),
+        )?;
+
+        tracing::info!(base_offset = base_offset.0, removed, "Compacted segment");
+    }
+
+    Ok(())
+}
+
+/// Write out the records of `snapshot` that `remove` rejects, to files named
+/// with `suffix`, returning how many were removed
+pub fn write_cleaned(
+    data_dir: &Path,
+    snapshot: &SegmentSnapshot,
+    suffix: &str,
+    mut remove: impl FnMut(Offset, &tokki_common::Record) -> bool,
+) -> Result<usize, StorageError> {
+    let base_offset = snapshot.base_offset;
+    let log_path = with_suffix(&Segment::log_path(data_dir, base_offset), suffix);
+    let index_path = with_suffix(&Segment::index_path(data_dir, base_offset), suffix);
+    let time_index_path = with_suffix(&Segment::time_index_path(data_dir, base_offset), suffix);
+
+    let log = File::create(&log_path)?;
+    let mut writer = BufWriter::new(&log);
+    let mut index = SparseIndex::create(&index_path, base_offset)?;
+    let mut time_index = TimeIndex::create(&time_index_path, base_offset)?;
+    // Every record gets an entry, so they are written out together at the end
+    let mut index_entries = Vec::new();
+    let mut time_index_entries: Vec<TimeIndexEntry> = Vec::new();
+    let mut position = 0;
+    let mut removed = 0;
+    let mut buf = Vec::new();
+
+    snapshot.for_each_record(|offset, record| {
+        if remove(offset, &record) {
+            removed += 1;
+            return Ok(());
+        }
+
+        let len = match &snapshot.keyring {
+            // Rewritten with the current key, so compaction also rotates old segments
+            Some(keyring) => {
+                let batch = RecordBatch::new(std::slice::from_ref(&record), Compression::None)?;
+                let encrypted = keyring.encrypt(base_offset, offset, &batch);
+                buf.resize(encrypted.serialized_len(), 0);
+                encrypted.to_bytes(&mut buf)?
+            }
+            None => {
+                buf.resize(record.serialized_len(), 0);
+                record.to_bytes(&mut buf)?
+            }
+        };
+        writer.write_all(&buf[..len])?;
+
+        index_entries.push(IndexEntry { offset, position });
+        if time_index_entries
+            .last()
+            .is_none_or(|last| record.timestamp() > last.timestamp)
+        {
+            time_index_entries.push(TimeIndexEntry {
+                timestamp: record.timestamp(),
+                offset,
+            });
+        }
+        position += len as u64;
+        Ok(())
+    })?;
+
+    writer.flush()?;
+    drop(writer);
+    index.append_all(&index_entries)?;
+    time_index.append_all(&time_index_entries)?;
+    // Retention and tombstone expiry go by when the records were written, not
+    // when they were last compacted
+    log.set_modified(snapshot.last_modified)?;
+    log.sync_all()?;
+    index.sync()?;
+    time_index.sync()?;
+
+    Ok(removed)
+}
+
+/// Replace a segment's files with the versions named with `suffix`.
+///
+/// Renaming the log to its swap name is the commit point, if the node stops
+/// after that `recover_swaps` finishes the job, and before it the new files
+/// are thrown away.
+pub fn swap_in(data_dir: &Path, base_offset: Offset, suffix: &str) -> io::Result<()> {
+    for index_path in index_paths(data_dir, base_offset) {
+        fs::rename(
+            with_suffix(&index_path, suffix),
+            with_suffix(&index_path, SWAP_SUFFIX),
+        )?;
+    }
+
+    let log_path = Segment::log_path(data_dir, base_offset);
+    fs::rename(
+        with_suffix(&log_path, suffix),
+        with_suffix(&log_path, SWAP_SUFFIX),
+    )?;
+    sync_dir(data_dir)?;
+
+    complete_swap(data_dir, base_offset)
+}
+
+fn complete_swap(data_dir: &Path, base_offset: Offset) -> io::Result<()> {
+    for index_path in index_paths(data_dir, base_offset) {
+        let index_swap = with_suffix(&index_path, SWAP_SUFFIX);
+        if index_swap.exists() {
+            fs::rename(index_swap, &index_path)?;
+        }
+    }
+
+    let log_path = Segment::log_path(data_dir, base_offset);
+    fs::rename(with_suffix(&log_path, SWAP_SUFFIX), &log_path)?;
+
+    sync_dir(data_dir)
+}
+
+fn index_paths(data_dir: &Path, base_offset: Offset) -> [PathBuf; 2] {
+    [
+        Segment::index_path(data_dir, base_offset),
+        Segment::time_index_path(data_dir, base_offset),
+    ]
+}
+
+/// Finish or roll back any compaction or truncation that was interrupted by
+/// the node stopping
+pub fn recover_swaps(data_dir: &Path) -> io::Result<()> {
+    let paths = fs::read_dir(data_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let log_swap_suffix = format!(".log{SWAP_SUFFIX}");
+    for path in &paths {
+        let Some(base_offset) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(&log_swap_suffix))
+            .and_then(|base_offset| base_offset.parse().ok())
+        else {
+            continue;
+        };
+
+        tracing::info!(base_offset, "Completing interrupted compaction");
+        complete_swap(data_dir, Offset(base_offset))?;
+    }
+
+    // Anything left over never reached the commit point
+    for path in &paths {
+        let name = path.file_name().and_then(|name| name.to_str());
+        if name.is_some_and(|name| {
+            [CLEANED_SUFFIX, TRUNCATED_SUFFIX, SWAP_SUFFIX]
+                .iter()
+                .any(|suffix| name.ends_with(suffix))
+        }) && path.exists()
+        {
+            tracing::info!(?path, "Removing incomplete segment rewrite");
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_cleaned(data_dir: &Path, base_offset: Offset) -> io::Result<()> {
+    let log_path = Segment::log_path(data_dir, base_offset);
+
+    for path in [log_path]
+        .into_iter()
+        .chain(index_paths(data_dir, base_offset))
+    {
+        fs::remove_file(with_suffix(&path, CLEANED_SUFFIX))?;
+    }
+
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
@@ -0,0 +1,173 @@
+use std::{
+    io,
+    sync::{
+        Arc, RwLock, Weak,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::Duration,
+};
+
+use tokio::sync::watch;
+use tokki_common::Offset;
+
+use crate::storage::disk::{DiskStorageInner, SyncFn, segment::Segment};
+
+/// The next offset that is not yet durable, or why an fsync failed
+type Synced = Result<Offset, Arc<io::Error>>;
+
+/// Background fsync shared by every writer under the group commit policy.
+///
+/// A dedicated thread syncs the active segment whenever the delay elapses or
+/// enough bytes are pending, then publishes the next offset that is not yet
+/// durable so waiting writers can acknowledge.
+///
+/// A failed fsync may have thrown away the writes it was meant to flush, and
+/// a later one succeeding says nothing about them, so the first failure is
+/// published to every waiter and the thread stops. Nothing written after it
+/// is acknowledged until the log is reopened and recovered.
+pub struct GroupCommit {
+    max_bytes: u64,
+    pending_bytes: AtomicU64,
+    /// Bumped when the log is truncated, as a sync that started before then
+    /// covered records that are gone, not the ones that replace them
+    truncations: AtomicU64,
+    wake_tx: mpsc::Sender<()>,
+    synced_tx: watch::Sender<Synced>,
+}
+
+impl GroupCommit {
+    pub fn start(
+        inner: Weak<RwLock<DiskStorageInner>>,
+        max_delay: Duration,
+        max_bytes: u64,
+        synced: Offset,
+        sync: SyncFn,
+    ) -> Arc<Self> {
+        let (wake_tx, wake_rx) = mpsc::channel();
+        let (synced_tx, _) = watch::channel(Ok(synced));
+
+        let group_commit = Arc::new(Self {
+            max_bytes,
+            pending_bytes: AtomicU64::new(0),
+            truncations: AtomicU64::new(0),
+            wake_tx,
+            synced_tx,
+        });
+
+        std::thread::Builder::new()
+            .name("tokki-group-commit".to_string())
+            .spawn({
+                let group_commit = group_commit.clone();
+                move || group_commit.run(inner, wake_rx, max_delay, sync)
+            })
+            .expect("Spawn group commit thread");
+
+        group_commit
+    }
+
+    /// Note that `bytes` more have been written, waking the flusher early if enough are pending
+    pub fn appended(&self, bytes: u64) {
+        let pending = self.pending_bytes.fetch_add(bytes, Ordering::AcqRel) + bytes;
+        if pending >= self.max_bytes {
+            _ = self.wake_tx.send(());
+        }
+    }
+
+    /// Note that the log was truncated to end at `next_offset`, while holding
+    /// the storage's write lock. Records put from there on are not durable
+    /// until the next sync.
+    pub fn truncated(&self, next_offset: Offset) {
+        self.truncations.fetch_add(1, Ordering::AcqRel);
+        self.synced_tx.send_if_modified(|synced| match synced {
+            Ok(synced) => {
+                let truncated = *synced > next_offset;
+                *synced = (*synced).min(next_offset);
+                truncated
+            }
+            Err(_) => false,
+        });
+    }
+
+    /// Wait until the record at `offset` has been covered by an fsync,
+    /// failing if an fsync failed before it was
+    pub async fn wait_for(&self, offset: Offset) -> io::Result<()> {
+        let mut synced_rx = self.synced_tx.subscribe();
+        let synced = synced_rx
+            .wait_for(|synced| synced.as_ref().map_or(true, |synced| *synced > offset))
+            .await
+            .map_err(|_| io::Error::other("Group commit stopped"))?;
+
+        match &*synced {
+            Ok(_) => Ok(()),
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!("Group commit fsync failed: {e}"),
+            )),
+        }
+    }
+
+    fn run(
+        &self,
+        inner: Weak<RwLock<DiskStorageInner>>,
+        wake_rx: mpsc::Receiver<()>,
+        max_delay: Duration,
+        sync: SyncFn,
+    ) {
+        loop {
+            if let Err(RecvTimeoutError::Disconnected) = wake_rx.recv_timeout(max_delay) {
+                break;
+            }
+
+            // The storage has been dropped, nothing left to flush
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+
+            let (log, next_offset, truncations) = {
+                let guard = inner.read().expect("No panics");
+                let active = guard.segments.last().expect("At least one segment");
+                (
+                    active.try_clone_log(),
+                    active.next_offset(),
+                    self.truncations.load(Ordering::Acquire),
+                )
+            };
+            drop(inner);
+
+            if self
+                .synced_tx
+                .borrow()
+                .as_ref()
+                .is_ok_and(|synced| next_offset <= *synced)
+            {
+                continue;
+            }
+
+            self.pending_bytes.store(0, Ordering::Release);
+
+            match log.and_then(|log| Segment::sync_log(&log, sync)) {
+                Ok(()) => {
+                    // Checked under the watch's lock, which `truncated` also takes
+                    self.synced_tx.send_if_modified(|synced| {
+                        let current = truncations == self.truncations.load(Ordering::Acquire);
+                        if current {
+                            *synced = Ok(next_offset);
+                        }
+                        current
+                    });
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Group commit fsync failed, no more writes will be acknowledged: {}",
+                        e
+                    );
+                    _ = self.synced_tx.send_replace(Err(Arc::new(e)));
+                    break;
+                }
+            }
+        }
+
+        tracing::debug!("Group commit stopped");
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read as _, Write as _},
+    path::Path,
+};
+
+use tokki_common::Offset;
+
+const ENTRY_SIZE: usize = 2 * size_of::<u64>();
+
+/// A point in a segment's log file where a given offset can be found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub offset: Offset,
+    pub position: u64,
+}
+
+/// Sparse mapping of offsets to their byte position within a segment.
+///
+/// Only every so often is a record indexed, so a lookup gives the closest
+/// entry at or before the requested offset and the reader scans forward
+/// from there. Entries are stored relative to the segment's base offset as
+/// pairs of little-endian `u64`s.
+pub struct SparseIndex {
+    file: File,
+    base_offset: Offset,
+    entries: Vec<IndexEntry>,
+}
+
+impl SparseIndex {
+    pub fn open(path: &Path, base_offset: Offset) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        // Only entries written whole are kept, and later appends follow them
+        let torn_bytes = bytes.len() % ENTRY_SIZE;
+        if torn_bytes > 0 {
+            tracing::warn!(
+                ?path,
+                torn_bytes,
+                "Dropping partial entry from offset index"
+            );
+            file.set_len((bytes.len() - torn_bytes) as u64)?;
+        }
+
+        let entries = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| {
+                let (relative_offset, position) = entry.split_at(size_of::<u64>());
+                let relative_offset =
+                    u64::from_le_bytes(relative_offset.try_into().expect("8 bytes"));
+                let position = u64::from_le_bytes(position.try_into().expect("8 bytes"));
+
+                IndexEntry {
+                    offset: base_offset + relative_offset as usize,
+                    position,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            file,
+            base_offset,
+            entries,
+        })
+    }
+
+    /// Start a fresh, empty index, discarding any existing entries
+    pub fn create(path: &Path, base_offset: Offset) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        file.set_len(0)?;
+
+        Ok(Self {
+            file,
+            base_offset,
+            entries: Vec::new(),
+        })
+    }
+
+    pub fn append(&mut self, entry: IndexEntry) -> io::Result<()> {
+        self.file.write_all(&self.encode(entry))?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Append `entries` in one go, buffering the writes
+    pub fn append_all(&mut self, entries: &[IndexEntry]) -> io::Result<()> {
+        let mut writer = BufWriter::new(&self.file);
+        for entry in entries {
+            writer.write_all(&self.encode(*entry))?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn encode(&self, entry: IndexEntry) -> [u8; ENTRY_SIZE] {
+        let relative_offset = (entry.offset - self.base_offset).0 as u64;
+
+        let mut bytes = [0u8; ENTRY_SIZE];
+        bytes[..size_of::<u64>()].copy_from_slice(&relative_offset.to_le_bytes());
+        bytes[size_of::<u64>()..].copy_from_slice(&entry.position.to_le_bytes());
+        bytes
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Find the closest indexed position at or before `offset`, falling back
+    /// to the start of the segment, along with every entry that follows it.
+    pub fn lookup(&self, offset: Offset) -> (IndexEntry, &[IndexEntry]) {
+        let idx = self.entries.partition_point(|entry| entry.offset <= offset);
+
+        match idx.checked_sub(1) {
+            Some(idx) => (self.entries[idx], &self.entries[idx + 1..]),
+            None => (
+                IndexEntry {
+                    offset: self.base_offset,
+                    position: 0,
+                },
+                &self.entries,
+            ),
+        }
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+}
@@ -0,0 +1,738 @@
+mod compaction;
+mod group_commit;
+mod index;
+mod segment;
+mod time_index;
+
+use std::{
+    fs::{self, File},
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use tokki_common::{Compression, Offset, Record, RecordBatch, Timestamp};
+
+use crate::storage::{
+    CompactionPolicy, CorruptRecordPolicy, DurabilityPolicy, Keyring, RecordFrames,
+    RetentionPolicy, Storage, StorageError,
+    disk::{group_commit::GroupCommit, segment::Segment},
+};
+
+const DEFAULT_SEGMENT_BYTES: u64 = 1024 * 1024 * 1024;
+const DEFAULT_INDEX_INTERVAL_BYTES: u64 = 4096;
+
+/// Flushes a segment's log file to stable storage
+pub type SyncFn = fn(&File) -> io::Result<()>;
+
+#[derive(Debug, Clone)]
+pub struct DiskStorageConfig {
+    data_dir: PathBuf,
+    segment_bytes: u64,
+    index_interval_bytes: u64,
+    durability: DurabilityPolicy,
+    corrupt_records: CorruptRecordPolicy,
+    keyring: Option<Arc<Keyring>>,
+    sync: SyncFn,
+}
+
+impl DiskStorageConfig {
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+            index_interval_bytes: DEFAULT_INDEX_INTERVAL_BYTES,
+            durability: DurabilityPolicy::default(),
+            corrupt_records: CorruptRecordPolicy::default(),
+            keyring: None,
+            sync: File::sync_data,
+        }
+    }
+
+    /// The same settings for a log kept in `data_dir`
+    pub fn with_data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Roll over to a new segment once the active one reaches this size
+    pub fn with_segment_bytes(mut self, segment_bytes: u64) -> Self {
+        self.segment_bytes = segment_bytes;
+        self
+    }
+
+    /// Add an entry to a segment's offset index every time this many bytes are appended
+    pub fn with_index_interval_bytes(mut self, index_interval_bytes: u64) -> Self {
+        self.index_interval_bytes = index_interval_bytes;
+        self
+    }
+
+    pub fn with_durability(mut self, durability: DurabilityPolicy) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn with_corrupt_records(mut self, corrupt_records: CorruptRecordPolicy) -> Self {
+        self.corrupt_records = corrupt_records;
+        self
+    }
+
+    /// Flush log files with `sync` instead of `File::sync_data`, so tests can
+    /// stand in for a disk that fails
+    pub fn with_sync(mut self, sync: SyncFn) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Encrypt everything written from now on with the keyring's current key.
+    /// Segments written before encryption was turned on stay readable.
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(Arc::new(keyring));
+        self
+    }
+}
+
+/// Durable log made of rolling segment files in a data directory.
+///
+/// Each segment is named after the offset of its first record and holds
+/// `Record::to_bytes` and `RecordBatch::to_bytes` frames back to back,
+/// alongside sparse offset and time indexes so reads can seek close to the
+/// requested offset or timestamp rather than scanning the whole file.
+/// Batches stay compressed on disk and take up one offset per record.
+/// Offsets may have gaps, left by compaction or copied from a leader's
+/// compacted log, which are recorded in the offset index.
+/// Segments are read through memory maps, so `get_frames` can serve frames
+/// straight out of the page cache.
+#[derive(Clone)]
+pub struct DiskStorage {
+    inner: Arc<RwLock<DiskStorageInner>>,
+    group_commit: Option<Arc<GroupCommit>>,
+}
+
+pub struct DiskStorageInner {
+    config: DiskStorageConfig,
+    /// Ordered by base offset, the last segment is the one being appended to
+    segments: Vec<Segment>,
+    /// Bumped every time the log is truncated, so compaction can tell the
+    /// segments it started from have been rewritten
+    truncations: u64,
+}
+
+impl DiskStorage {
+    pub fn open(config: DiskStorageConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.data_dir)?;
+
+        compaction::recover_swaps(&config.data_dir)?;
+
+        let mut base_offsets = Vec::new();
+        for entry in fs::read_dir(&config.data_dir)? {
+            if let Some(base_offset) = Segment::parse_log_path(&entry?.path()) {
+                base_offsets.push(base_offset);
+            }
+        }
+        base_offsets.sort();
+
+        if base_offsets.is_empty() {
+            base_offsets.push(Offset(0));
+        }
+
+        let active_base_offset = base_offsets.pop().expect("At least one segment");
+
+        let mut segments = base_offsets
+            .iter()
+            .zip(base_offsets.iter().skip(1).chain([&active_base_offset]))
+            .map(|(base_offset, next_offset)| {
+                Segment::open_sealed(
+                    &config.data_dir,
+                    *base_offset,
+                    *next_offset,
+                    config.keyring.clone(),
+                )
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // Only the segment being appended to can hold a partial write
+        segments.push(Segment::recover(
+            &config.data_dir,
+            active_base_offset,
+            config.index_interval_bytes,
+            config.keyring.clone(),
+        )?);
+
+        let next_offset = segments.last().expect("At least one segment").next_offset();
+
+        tracing::info!(
+            data_dir = ?config.data_dir,
+            segments = segments.len(),
+            next_offset = next_offset.0,
+            durability = ?config.durability,
+            keyring = ?config.keyring,
+            "Opened disk storage"
+        );
+
+        let durability = config.durability;
+        let sync = config.sync;
+        let inner = Arc::new(RwLock::new(DiskStorageInner {
+            config,
+            segments,
+            truncations: 0,
+        }));
+
+        let group_commit = match durability {
+            DurabilityPolicy::GroupCommit {
+                max_delay,
+                max_bytes,
+            } => Some(GroupCommit::start(
+                Arc::downgrade(&inner),
+                max_delay,
+                max_bytes,
+                next_offset,
+                sync,
+            )),
+            DurabilityPolicy::Always | DurabilityPolicy::Os => None,
+        };
+
+        Ok(Self {
+            inner,
+            group_commit,
+        })
+    }
+
+    /// Run `read` against the log off the async executor, as it may block on
+    /// a write in progress or on the disk
+    async fn read<T: Send + 'static>(
+        &self,
+        read: impl FnOnce(&DiskStorageInner) -> Result<T, StorageError> + Send + 'static,
+    ) -> Result<T, StorageError> {
+        let inner = self.inner.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let guard = inner.read().expect("No panics");
+            read(&guard)
+        })
+        .await
+        .map_err(io::Error::from)?
+    }
+
+    /// Append `batch` as a single frame, starting at `offset` if set and
+    /// leaving a gap before it if the log ends earlier
+    async fn append_batch(
+        &self,
+        offset: Option<Offset>,
+        batch: RecordBatch,
+    ) -> Result<Range<Offset>, StorageError> {
+        let inner = self.inner.clone();
+        let batch_len = batch.serialized_len() as u64;
+
+        let offsets = tokio::task::spawn_blocking(move || -> Result<_, StorageError> {
+            let mut guard = inner.write().expect("No panics");
+            let log_end = guard.next_offset();
+            if let Some(offset) = offset
+                && offset < log_end
+            {
+                return Err(StorageError::NotAtLogEnd { offset, log_end });
+            }
+            if batch.is_empty() {
+                return Ok(log_end..log_end);
+            }
+
+            let index_interval_bytes = guard.config.index_interval_bytes;
+            let durability = guard.config.durability;
+            let sync = guard.config.sync;
+
+            let active = guard.active_segment()?;
+            if let Some(offset) = offset {
+                active.skip_to(offset, durability != DurabilityPolicy::Os)?;
+            }
+            let offsets = active.append_batch(&batch, index_interval_bytes)?;
+            if durability == DurabilityPolicy::Always {
+                active.sync(sync)?;
+            }
+
+            tracing::debug!(
+                compression = batch.compression().name(),
+                "Put batch at {}..{}",
+                offsets.start.0,
+                offsets.end.0
+            );
+            Ok(offsets)
+        })
+        .await
+        .map_err(io::Error::from)??;
+
+        if let Some(group_commit) = &self.group_commit {
+            group_commit.appended(batch_len);
+        }
+
+        Ok(offsets)
+    }
+}
+
+impl DiskStorageInner {
+    fn active_segment(&mut self) -> io::Result<&mut Segment> {
+        let active = self.segments.last_mut().expect("At least one segment");
+
+        if active.size() >= self.config.segment_bytes {
+            let base_offset = active.next_offset();
+            tracing::info!(base_offset = base_offset.0, "Rolling new segment");
+
+            // Fsyncs only ever target the active segment, so make sure the
+            // old one is complete on disk before moving on
+            active.seal(
+                (self.config.durability != DurabilityPolicy::Os).then_some(self.config.sync),
+            )?;
+
+            let segment = Segment::create(
+                &self.config.data_dir,
+                base_offset,
+                self.config.keyring.clone(),
+            )?;
+            self.segments.push(segment);
+        }
+
+        Ok(self.segments.last_mut().expect("At least one segment"))
+    }
+
+    fn next_offset(&self) -> Offset {
+        self.segments
+            .last()
+            .expect("At least one segment")
+            .next_offset()
+    }
+
+    fn log_start_offset(&self) -> Offset {
+        self.segments
+            .first()
+            .expect("At least one segment")
+            .base_offset()
+    }
+
+    /// Read segment by segment from `offset` until `read` has had
+    /// `max_records`, returning the offset to continue from. `read` is given
+    /// each segment along with the offset to start at and the records still
+    /// wanted, and returns the offset to continue from and the records it
+    /// read. Segments that offset is past, such as ones compaction emptied
+    /// out the end of, are skipped.
+    fn read_from(
+        &self,
+        offset: Offset,
+        max_records: usize,
+        mut read: impl FnMut(&Segment, Offset, usize) -> Result<(Offset, usize), StorageError>,
+    ) -> Result<Offset, StorageError> {
+        let log_start_offset = self.log_start_offset();
+        if offset < log_start_offset {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+            });
+        }
+
+        let first = self
+            .segments
+            .partition_point(|segment| segment.base_offset() <= offset)
+            .saturating_sub(1);
+
+        let mut current = offset;
+        let mut remaining = max_records;
+        for segment in &self.segments[first..] {
+            if remaining == 0 {
+                break;
+            }
+            if current >= segment.next_offset() {
+                continue;
+            }
+
+            let (next_offset, read) = read(segment, current, remaining)?;
+            current = next_offset;
+            remaining = remaining.saturating_sub(read);
+        }
+
+        Ok(current)
+    }
+
+    /// Read up to `max_records` from `offset`, returning them along with the offset to continue from
+    fn get_records(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
+        let mut records = Vec::new();
+        let next_offset = self.read_from(offset, max_records, |segment, offset, max_records| {
+            let (read, next_offset) =
+                segment.read(offset, max_records, self.config.corrupt_records)?;
+            let count = read.len();
+            records.extend(read);
+            Ok((next_offset, count))
+        })?;
+
+        Ok((records, next_offset))
+    }
+
+    /// Read batches as stored from `offset`, see `Storage::get_batches`
+    fn get_batches(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<(Offset, RecordBatch)>, Offset), StorageError> {
+        let mut batches = Vec::new();
+        let next_offset = self.read_from(offset, max_records, |segment, offset, max_records| {
+            let (read, next_offset) = segment.read_batches(offset, max_records)?;
+            let count = read.iter().map(|(_, batch)| batch.record_count()).sum();
+            batches.extend(read);
+            Ok((next_offset, count))
+        })?;
+
+        Ok((batches, next_offset))
+    }
+
+    /// Read frames as stored from `offset`, see `Storage::get_frames`
+    fn get_frames(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(RecordFrames, Offset), StorageError> {
+        let mut frames = RecordFrames::new();
+        let next_offset = self.read_from(offset, max_records, |segment, offset, max_records| {
+            let before = frames.record_count();
+            let next_offset = segment.read_frames(
+                offset,
+                max_records,
+                self.config.corrupt_records,
+                &mut frames,
+            )?;
+            Ok((next_offset, frames.record_count() - before))
+        })?;
+
+        Ok((frames, next_offset))
+    }
+
+    /// Find the first offset holding a record at or after `timestamp`
+    fn offset_for_timestamp(&self, timestamp: Timestamp) -> Result<Option<Offset>, StorageError> {
+        for segment in &self.segments {
+            if let Some(offset) =
+                segment.offset_for_timestamp(timestamp, self.config.corrupt_records)?
+            {
+                return Ok(Some(offset));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Remove every record from `end` onwards.
+    ///
+    /// Segments that start at or after `end` are deleted, and the one it
+    /// falls in is rewritten without the records past it, the same way
+    /// compaction rewrites segments. Files readers may have mapped are only
+    /// ever unlinked or replaced, never cut short. The rewritten segment is
+    /// sealed and a new one is started at `end`, so appends never go to a
+    /// file that was swapped in.
+    fn truncate(&mut self, end: Offset) -> Result<(), StorageError> {
+        let end = end.max(self.log_start_offset());
+        let next_offset = self.next_offset();
+        if end >= next_offset {
+            return Ok(());
+        }
+
+        self.truncations += 1;
+        let data_dir = self.config.data_dir.clone();
+
+        // Newest first, so a crash part way leaves a log that is only shorter
+        while self
+            .segments
+            .last()
+            .is_some_and(|segment| segment.base_offset() >= end)
+        {
+            let segment = self.segments.pop().expect("Checked there is one");
+            segment.delete(&data_dir)?;
+        }
+
+        if let Some(last) = self.segments.last_mut()
+            && last.next_offset() > end
+        {
+            let snapshot = last.snapshot()?;
+            compaction::write_cleaned(
+                &data_dir,
+                &snapshot,
+                compaction::TRUNCATED_SUFFIX,
+                |offset, _| offset >= end,
+            )?;
+            compaction::swap_in(
+                &data_dir,
+                snapshot.base_offset,
+                compaction::TRUNCATED_SUFFIX,
+            )?;
+            *last = Segment::open_sealed(&data_dir, snapshot.base_offset, end, snapshot.keyring)?;
+        }
+
+        self.segments.push(Segment::create(
+            &data_dir,
+            end,
+            self.config.keyring.clone(),
+        )?);
+
+        tracing::warn!(
+            data_dir = ?data_dir,
+            from = next_offset.0,
+            to = end.0,
+            "Truncated log"
+        );
+        Ok(())
+    }
+
+    /// Delete every segment and start a new one at `offset`
+    fn restart_at(&mut self, offset: Offset) -> io::Result<()> {
+        self.truncations += 1;
+        let data_dir = self.config.data_dir.clone();
+        let next_offset = self.next_offset();
+
+        // Newest first, a crash part way leaves a shorter log, or an empty
+        // one that starts over from the beginning
+        while let Some(segment) = self.segments.pop() {
+            segment.delete(&data_dir)?;
+        }
+        self.segments.push(Segment::create(
+            &data_dir,
+            offset,
+            self.config.keyring.clone(),
+        )?);
+
+        tracing::warn!(
+            data_dir = ?data_dir,
+            from = next_offset.0,
+            to = offset.0,
+            "Restarted log"
+        );
+        Ok(())
+    }
+
+    /// Delete whole segments from the start of the log while it breaks
+    /// `policy`. The active segment is never deleted, so the log can exceed
+    /// the limits by up to one segment. `max_bytes` is a floor as much as a
+    /// limit, a segment is only deleted for size if the log still holds at
+    /// least that many bytes without it.
+    fn enforce_retention(&mut self, policy: &RetentionPolicy) -> io::Result<Offset> {
+        let now = SystemTime::now();
+        let next_offset = self.next_offset();
+        let mut bytes: u64 = self.segments.iter().map(Segment::size).sum();
+
+        while self.segments.len() > 1 {
+            let oldest = &self.segments[0];
+
+            let expired = match policy.max_age {
+                Some(max_age) => now
+                    .duration_since(oldest.last_modified()?)
+                    .is_ok_and(|age| age > max_age),
+                None => false,
+            };
+            let too_many = policy
+                .max_records
+                .is_some_and(|max_records| (next_offset - oldest.base_offset()).0 > max_records);
+            let too_big = policy
+                .max_bytes
+                .is_some_and(|max_bytes| bytes > max_bytes && bytes - oldest.size() >= max_bytes);
+
+            if !(expired || too_many || too_big) {
+                break;
+            }
+
+            let oldest = self.segments.remove(0);
+            tracing::info!(
+                base_offset = oldest.base_offset().0,
+                next_offset = oldest.next_offset().0,
+                expired,
+                too_many,
+                too_big,
+                "Deleting segment for retention"
+            );
+            bytes -= oldest.size();
+            oldest.delete(&self.config.data_dir)?;
+        }
+
+        Ok(self.log_start_offset())
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for DiskStorage {
+    async fn max_offset(&self) -> Result<Option<Offset>, StorageError> {
+        let next_offset = self.read(|inner| Ok(inner.next_offset())).await?;
+        Ok(next_offset.0.checked_sub(1).map(Offset))
+    }
+
+    async fn log_start_offset(&self) -> Result<Offset, StorageError> {
+        self.read(|inner| Ok(inner.log_start_offset())).await
+    }
+
+    async fn put_records(&self, records: Vec<Record>) -> Result<Range<Offset>, StorageError> {
+        // Encrypting records one at a time would cost a nonce and tag for each
+        let encrypted = self
+            .inner
+            .read()
+            .expect("No panics")
+            .config
+            .keyring
+            .is_some();
+        if encrypted && !records.is_empty() {
+            let batch = RecordBatch::new(&records, Compression::None)?;
+            return self.put_batch(batch).await;
+        }
+
+        let inner = self.inner.clone();
+        let records_len: u64 = records
+            .iter()
+            .map(|record| record.serialized_len() as u64)
+            .sum();
+
+        let offsets = tokio::task::spawn_blocking(move || -> io::Result<Range<Offset>> {
+            let mut guard = inner.write().expect("No panics");
+            let index_interval_bytes = guard.config.index_interval_bytes;
+            let durability = guard.config.durability;
+            let sync = guard.config.sync;
+            let start = guard.next_offset();
+
+            for record in &records {
+                guard
+                    .active_segment()?
+                    .append(record, index_interval_bytes)?;
+            }
+
+            // Rolling seals the previous segment, so only the active one can be behind
+            if durability == DurabilityPolicy::Always && !records.is_empty() {
+                guard.active_segment()?.sync(sync)?;
+            }
+
+            let end = guard.next_offset();
+            tracing::debug!("Put records at {}..{}", start.0, end.0);
+            Ok(start..end)
+        })
+        .await
+        .map_err(io::Error::from)??;
+
+        if let Some(group_commit) = &self.group_commit {
+            group_commit.appended(records_len);
+        }
+
+        Ok(offsets)
+    }
+
+    async fn put_batch(&self, batch: RecordBatch) -> Result<Range<Offset>, StorageError> {
+        self.append_batch(None, batch).await
+    }
+
+    async fn put_batch_at(
+        &self,
+        offset: Offset,
+        batch: RecordBatch,
+    ) -> Result<Range<Offset>, StorageError> {
+        self.append_batch(Some(offset), batch).await
+    }
+
+    async fn wait_durable(&self, offset: Offset) -> Result<(), StorageError> {
+        match &self.group_commit {
+            Some(group_commit) => Ok(group_commit.wait_for(offset).await?),
+            None => Ok(()),
+        }
+    }
+
+    async fn get_records(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
+        self.read(move |inner| inner.get_records(offset, max_records))
+            .await
+    }
+
+    async fn get_batches(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<(Offset, RecordBatch)>, Offset), StorageError> {
+        self.read(move |inner| inner.get_batches(offset, max_records))
+            .await
+    }
+
+    async fn get_frames(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(RecordFrames, Offset), StorageError> {
+        self.read(move |inner| inner.get_frames(offset, max_records))
+            .await
+    }
+
+    async fn offset_for_timestamp(
+        &self,
+        timestamp: Timestamp,
+    ) -> Result<Option<Offset>, StorageError> {
+        self.read(move |inner| inner.offset_for_timestamp(timestamp))
+            .await
+    }
+
+    async fn truncate_after(&self, offset: Option<Offset>) -> Result<(), StorageError> {
+        let inner = self.inner.clone();
+        let group_commit = self.group_commit.clone();
+        let end = offset.map_or(Offset(0), |offset| offset + 1);
+
+        tokio::task::spawn_blocking(move || {
+            let mut guard = inner.write().expect("No panics");
+            guard.truncate(end)?;
+
+            if let Some(group_commit) = group_commit {
+                group_commit.truncated(guard.next_offset());
+            }
+            Ok(())
+        })
+        .await
+        .map_err(io::Error::from)?
+    }
+
+    async fn restart_at(&self, offset: Offset) -> Result<(), StorageError> {
+        let inner = self.inner.clone();
+        let group_commit = self.group_commit.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut guard = inner.write().expect("No panics");
+            guard.restart_at(offset)?;
+
+            if let Some(group_commit) = group_commit {
+                group_commit.truncated(guard.next_offset());
+            }
+            Ok(())
+        })
+        .await
+        .map_err(io::Error::from)?
+    }
+
+    async fn enforce_retention(&self, policy: &RetentionPolicy) -> Result<Offset, StorageError> {
+        let inner = self.inner.clone();
+        let policy = *policy;
+
+        let log_start_offset = tokio::task::spawn_blocking(move || {
+            let mut guard = inner.write().expect("No panics");
+            guard.enforce_retention(&policy)
+        })
+        .await
+        .map_err(io::Error::from)??;
+
+        Ok(log_start_offset)
+    }
+
+    async fn compact(&self, policy: &CompactionPolicy) -> Result<(), StorageError> {
+        let inner = self.inner.clone();
+        let policy = *policy;
+
+        tokio::task::spawn_blocking(move || compaction::compact(&inner, &policy))
+            .await
+            .map_err(io::Error::from)??;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1049 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write as _},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use memmap2::MmapOptions;
+use tokki_common::{
+    Compression, DecodeError, EncryptedBatch, Frame, FrameInfo, FrameKind, Offset, Record,
+    RecordBatch, Timestamp,
+};
+
+use crate::storage::{
+    CorruptRecordPolicy, Keyring, KeyringError, RecordFrames, StorageError,
+    disk::{
+        SyncFn,
+        index::{IndexEntry, SparseIndex},
+        time_index::{TimeIndex, TimeIndexEntry},
+    },
+};
+
+const LOG_EXTENSION: &str = "log";
+const INDEX_EXTENSION: &str = "index";
+const TIME_INDEX_EXTENSION: &str = "timeindex";
+
+/// A contiguous run of the log stored in a single file, starting at `base_offset`.
+pub struct Segment {
+    base_offset: Offset,
+    next_offset: Offset,
+    log: File,
+    log_len: u64,
+    /// The log file mapped into memory for reading, which appends outgrow
+    map: Mutex<Bytes>,
+    index: SparseIndex,
+    time_index: TimeIndex,
+    /// The newest record in the segment, which may not be indexed yet
+    max_timestamp: Option<TimeIndexEntry>,
+    bytes_since_index: u64,
+    /// Encrypts everything appended, and decrypts whatever was encrypted
+    keyring: Option<Arc<Keyring>>,
+}
+
+impl Segment {
+    pub fn log_path(data_dir: &Path, base_offset: Offset) -> PathBuf {
+        data_dir.join(format!("{:020}.{LOG_EXTENSION}", base_offset.0))
+    }
+
+    pub fn index_path(data_dir: &Path, base_offset: Offset) -> PathBuf {
+        data_dir.join(format!("{:020}.{INDEX_EXTENSION}", base_offset.0))
+    }
+
+    pub fn time_index_path(data_dir: &Path, base_offset: Offset) -> PathBuf {
+        data_dir.join(format!("{:020}.{TIME_INDEX_EXTENSION}", base_offset.0))
+    }
+
+    /// Parse the base offset out of a segment log file name, ignoring any other files
+    pub fn parse_log_path(path: &Path) -> Option<Offset> {
+        if path.extension()? != LOG_EXTENSION {
+            return None;
+        }
+
+        let stem = path.file_stem()?.to_str()?;
+        stem.parse().ok().map(Offset)
+    }
+
+    /// Create a new, empty segment to append to
+    pub fn create(
+        data_dir: &Path,
+        base_offset: Offset,
+        keyring: Option<Arc<Keyring>>,
+    ) -> io::Result<Self> {
+        let log = Self::open_log(data_dir, base_offset)?;
+        let index = SparseIndex::create(&Self::index_path(data_dir, base_offset), base_offset)?;
+        let time_index =
+            TimeIndex::create(&Self::time_index_path(data_dir, base_offset), base_offset)?;
+
+        Ok(Self {
+            base_offset,
+            next_offset: base_offset,
+            log,
+            log_len: 0,
+            map: Mutex::default(),
+            index,
+            time_index,
+            max_timestamp: None,
+            bytes_since_index: 0,
+            keyring,
+        })
+    }
+
+    /// Open a segment that is no longer appended to. Its records end where
+    /// the following segment begins at `next_offset`.
+    pub fn open_sealed(
+        data_dir: &Path,
+        base_offset: Offset,
+        next_offset: Offset,
+        keyring: Option<Arc<Keyring>>,
+    ) -> io::Result<Self> {
+        let log = Self::open_log(data_dir, base_offset)?;
+        let log_len = log.metadata()?.len();
+        let index = SparseIndex::open(&Self::index_path(data_dir, base_offset), base_offset)?;
+        // Sealing indexes the newest record, so it is the last entry
+        let time_index =
+            TimeIndex::open(&Self::time_index_path(data_dir, base_offset), base_offset)?;
+        let max_timestamp = time_index.last();
+
+        let segment = Self {
+            base_offset,
+            next_offset,
+            log,
+            log_len,
+            map: Mutex::default(),
+            index,
+            time_index,
+            max_timestamp,
+            bytes_since_index: 0,
+            keyring,
+        };
+        segment.check_index()?;
+        Ok(segment)
+    }
+
+    /// Check the offset index agrees with the log. Offsets after a gap can
+    /// only be found through the index, so a sealed segment is not opened
+    /// with entries that are out of order, do not point at the start of a
+    /// frame, or leave too few offsets for the records in it.
+    fn check_index(&self) -> io::Result<()> {
+        let entries = self.index.entries();
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let log = if self.log_len > 0 {
+            self.mapped()?
+        } else {
+            Bytes::new()
+        };
+        let mut min_offset = self.base_offset;
+        let mut min_position = 0;
+
+        for entry in entries {
+            let invalid = |problem: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Index entry for offset {} at position {} of segment {} {problem}",
+                        entry.offset.0, entry.position, self.base_offset.0
+                    ),
+                )
+            };
+
+            if entry.offset < min_offset || entry.position < min_position {
+                return Err(invalid("is out of order"));
+            }
+            let info = log
+                .get(entry.position as usize..)
+                .filter(|buf| !buf.is_empty())
+                .and_then(|buf| Frame::inspect(buf).ok())
+                .ok_or_else(|| invalid("does not point at a frame"))?;
+
+            min_offset = entry.offset + info.record_count;
+            min_position = entry.position + info.len as u64;
+            if min_offset > self.next_offset {
+                return Err(invalid("runs past the end of the segment"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Open the segment that was being appended to when the node last stopped.
+    ///
+    /// The whole log file is scanned and every frame verified against its
+    /// checksum. Anything after the last good frame is a partial or corrupt
+    /// write from a crash, so it is truncated away and the index is rebuilt
+    /// from the frames that survived.
+    pub fn recover(
+        data_dir: &Path,
+        base_offset: Offset,
+        index_interval_bytes: u64,
+        keyring: Option<Arc<Keyring>>,
+    ) -> io::Result<Self> {
+        let log = Self::open_log(data_dir, base_offset)?;
+        let log_len = log.metadata()?.len();
+
+        // Gaps left by followers copying a compacted log are only recorded in
+        // the index, which is otherwise rebuilt from scratch
+        let index_path = Self::index_path(data_dir, base_offset);
+        let gaps = SparseIndex::open(&index_path, base_offset)?
+            .entries()
+            .to_vec();
+        let index = SparseIndex::create(&index_path, base_offset)?;
+        let time_index =
+            TimeIndex::create(&Self::time_index_path(data_dir, base_offset), base_offset)?;
+
+        let mut segment = Self {
+            base_offset,
+            next_offset: base_offset,
+            log,
+            log_len,
+            map: Mutex::default(),
+            index,
+            time_index,
+            max_timestamp: None,
+            bytes_since_index: 0,
+            keyring,
+        };
+
+        let log = map_log(&segment.log, log_len)?;
+        let mut reader = SegmentReader::new(&log, 0);
+        let mut valid_len = 0;
+        let mut gaps = gaps.as_slice();
+
+        loop {
+            while let Some((gap, rest)) = gaps.split_first()
+                && gap.position <= valid_len
+            {
+                if gap.position == valid_len {
+                    segment.gap_at(valid_len, gap.offset, false)?;
+                }
+                gaps = rest;
+            }
+
+            match reader.next_frame(segment.next_offset) {
+                Ok(Some(frame)) => {
+                    let len = reader.position() - valid_len;
+                    segment.frame_appended(valid_len, len, &frame, index_interval_bytes)?;
+                    valid_len = reader.position();
+                }
+                Ok(None) => break,
+                Err(StorageError::CorruptRecord { offset, source }) => {
+                    tracing::warn!(
+                        base_offset = base_offset.0,
+                        offset = offset.0,
+                        position = valid_len,
+                        "Found torn write in segment: {}",
+                        source
+                    );
+                    break;
+                }
+                Err(StorageError::Io { source }) => return Err(source),
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+
+        // Nothing past the new end of the file may be mapped once it is truncated
+        drop(log);
+
+        if valid_len < log_len {
+            tracing::warn!(
+                base_offset = base_offset.0,
+                discarded_bytes = log_len - valid_len,
+                next_offset = segment.next_offset.0,
+                "Truncating segment to last complete record"
+            );
+            segment.log.set_len(valid_len)?;
+            segment.log.sync_all()?;
+            segment.log_len = valid_len;
+        }
+
+        Ok(segment)
+    }
+
+    fn open_log(data_dir: &Path, base_offset: Offset) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(Self::log_path(data_dir, base_offset))
+    }
+
+    pub fn base_offset(&self) -> Offset {
+        self.base_offset
+    }
+
+    pub fn next_offset(&self) -> Offset {
+        self.next_offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.log_len
+    }
+
+    /// Everything appended to the log so far, mapped into memory. The file is
+    /// mapped again whenever appends have taken it past the current mapping.
+    fn mapped(&self) -> io::Result<Bytes> {
+        let mut map = self.map.lock().expect("No panics");
+        if (map.len() as u64) < self.log_len {
+            *map = map_log(&self.log, self.log_len)?;
+        }
+
+        Ok(map.slice(..self.log_len as usize))
+    }
+
+    /// Append a record to the end of the segment, returning its offset
+    pub fn append(&mut self, record: &Record, index_interval_bytes: u64) -> io::Result<Offset> {
+        // Only batches can be encrypted
+        if self.keyring.is_some() {
+            let batch = RecordBatch::new(std::slice::from_ref(record), Compression::None)?;
+            return Ok(self.append_batch(&batch, index_interval_bytes)?.start);
+        }
+
+        let mut buf = vec![0u8; record.serialized_len()];
+        let len = record.to_bytes(&mut buf)?;
+        self.log.write_all(&buf[..len])?;
+
+        let offset = self.next_offset;
+        self.record_appended(
+            self.log_len,
+            len as u64,
+            1,
+            record.timestamp(),
+            index_interval_bytes,
+        )?;
+        self.log_len += len as u64;
+
+        Ok(offset)
+    }
+
+    /// Leave a gap in the offsets so the next frame appended starts at
+    /// `offset`, if it is past the end of the segment. The gap is only
+    /// recorded in the index, which is flushed before the frame is written
+    /// if `sync` is set so the gap is never lost when the frame is not.
+    pub fn skip_to(&mut self, offset: Offset, sync: bool) -> io::Result<()> {
+        self.gap_at(self.log_len, offset, sync)
+    }
+
+    /// Leave a gap so the frame at `position` starts at `offset`
+    fn gap_at(&mut self, position: u64, offset: Offset, sync: bool) -> io::Result<()> {
+        if offset <= self.next_offset {
+            return Ok(());
+        }
+
+        self.index.append(IndexEntry { offset, position })?;
+        if sync {
+            self.index.sync()?;
+        }
+        self.next_offset = offset;
+        self.bytes_since_index = 0;
+        Ok(())
+    }
+
+    /// Append a batch to the end of the segment as a single frame, encrypted
+    /// if the segment has a keyring, returning the offsets of its records
+    pub fn append_batch(
+        &mut self,
+        batch: &RecordBatch,
+        index_interval_bytes: u64,
+    ) -> io::Result<Range<Offset>> {
+        let start = self.next_offset;
+        let buf = match &self.keyring {
+            Some(keyring) => encrypted_frame(keyring, self.base_offset, start, batch)?,
+            None => {
+                let mut buf = vec![0u8; batch.serialized_len()];
+                let len = batch.to_bytes(&mut buf)?;
+                buf.truncate(len);
+                buf
+            }
+        };
+        let len = buf.len();
+        self.log.write_all(&buf)?;
+
+        self.record_appended(
+            self.log_len,
+            len as u64,
+            batch.record_count(),
+            batch.max_timestamp(),
+            index_interval_bytes,
+        )?;
+        self.log_len += len as u64;
+
+        Ok(start..self.next_offset)
+    }
+
+    fn frame_appended(
+        &mut self,
+        position: u64,
+        len: u64,
+        frame: &Frame,
+        index_interval_bytes: u64,
+    ) -> io::Result<()> {
+        self.record_appended(
+            position,
+            len,
+            frame.record_count(),
+            frame.max_timestamp(),
+            index_interval_bytes,
+        )
+    }
+
+    /// Account for a frame of `len` bytes holding `record_count` records
+    /// written at `position`, indexing it if due
+    fn record_appended(
+        &mut self,
+        position: u64,
+        len: u64,
+        record_count: usize,
+        timestamp: Timestamp,
+        index_interval_bytes: u64,
+    ) -> io::Result<()> {
+        // A gap may have indexed this position already
+        if self.bytes_since_index >= index_interval_bytes
+            && self
+                .index
+                .entries()
+                .last()
+                .is_none_or(|last| last.position < position)
+        {
+            self.index.append(IndexEntry {
+                offset: self.next_offset,
+                position,
+            })?;
+            self.index_max_timestamp()?;
+            self.bytes_since_index = 0;
+        }
+
+        // Every record up to the last one in the frame is at most this new
+        if self
+            .max_timestamp
+            .is_none_or(|max| timestamp > max.timestamp)
+        {
+            self.max_timestamp = Some(TimeIndexEntry {
+                timestamp,
+                offset: self.next_offset + record_count.saturating_sub(1),
+            });
+        }
+
+        self.bytes_since_index += len;
+        self.next_offset += record_count;
+
+        // A reader that cannot decode a batch has no way of telling how many
+        // offsets it held, so the frame after it is always indexed
+        if record_count > 1 {
+            self.bytes_since_index = self.bytes_since_index.max(index_interval_bytes);
+        }
+
+        Ok(())
+    }
+
+    /// Add the newest record so far to the time index, unless it is already there
+    fn index_max_timestamp(&mut self) -> io::Result<()> {
+        let Some(max) = self.max_timestamp else {
+            return Ok(());
+        };
+
+        if self
+            .time_index
+            .last()
+            .is_none_or(|last| max.timestamp > last.timestamp)
+        {
+            self.time_index.append(max)?;
+        }
+
+        Ok(())
+    }
+
+    /// When the segment was last appended to, all of its records are at least this old
+    pub fn last_modified(&self) -> io::Result<SystemTime> {
+        self.log.metadata()?.modified()
+    }
+
+    /// Remove the segment's files from the data directory
+    pub fn delete(self, data_dir: &Path) -> io::Result<()> {
+        let base_offset = self.base_offset;
+        drop(self);
+
+        fs::remove_file(Self::log_path(data_dir, base_offset))?;
+        fs::remove_file(Self::index_path(data_dir, base_offset))?;
+        fs::remove_file(Self::time_index_path(data_dir, base_offset))?;
+        Ok(())
+    }
+
+    /// Flush appended records to stable storage
+    pub fn sync(&self, sync: SyncFn) -> io::Result<()> {
+        Self::sync_log(&self.log, sync)
+    }
+
+    /// Stop appending to the segment. Its newest record is indexed so it can
+    /// be found again on reopening, and everything is flushed with `sync` if set.
+    pub fn seal(&mut self, sync: Option<SyncFn>) -> io::Result<()> {
+        self.index_max_timestamp()?;
+
+        if let Some(sync) = sync {
+            self.sync(sync)?;
+            self.index.sync()?;
+            self.time_index.sync()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn try_clone_log(&self) -> io::Result<File> {
+        self.log.try_clone()
+    }
+
+    pub fn sync_log(log: &File, sync: SyncFn) -> io::Result<()> {
+        let start = Instant::now();
+        sync(log)?;
+        metrics::histogram!("fsync").record(start.elapsed());
+        Ok(())
+    }
+
+    /// Read up to `max_records` records starting at `offset`, which must be
+    /// within this segment. Also returns the offset to continue reading from.
+    pub fn read(
+        &self,
+        offset: Offset,
+        max_records: usize,
+        corrupt_records: CorruptRecordPolicy,
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
+        let mut records = Vec::new();
+        if max_records == 0 {
+            return Ok((records, offset));
+        }
+
+        let next_offset = self.scan(offset, corrupt_records, |_, record| {
+            records.push(record);
+            records.len() < max_records
+        })?;
+
+        Ok((records, next_offset))
+    }
+
+    /// Read whole frames from `offset` until they hold at least `max_records`
+    /// records, as batches ready to be replicated along with the offsets of
+    /// their first records. A batch starting before `offset` is cut down and
+    /// compressed again, and runs of single records with consecutive offsets
+    /// are gathered into uncompressed batches. Corrupt records are never
+    /// skipped, as the copy of the log would have no record of the gap.
+    pub fn read_batches(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<(Offset, RecordBatch)>, Offset), StorageError> {
+        let mut batches = Vec::new();
+        // Consecutive single records waiting to be batched, from `run_start`
+        let mut records = Vec::new();
+        let mut run_start = offset;
+        let mut record_count = 0;
+
+        let log = self.mapped()?;
+        let (start, entries) = self.index.lookup(offset);
+        let mut reader = OffsetReader::new(&log, start, entries, self.next_offset)
+            .with_keyring(self.keyring.as_deref(), self.base_offset);
+
+        while record_count < max_records {
+            let Some((frame_offset, frame)) = reader.next_frame()? else {
+                break;
+            };
+            if reader.next_offset() <= offset {
+                continue;
+            }
+
+            let batch = match frame {
+                Frame::Record(record) => {
+                    if run_start + records.len() != frame_offset && !records.is_empty() {
+                        batches.push((run_start, RecordBatch::new(&records, Compression::None)?));
+                        records.clear();
+                    }
+                    if records.is_empty() {
+                        run_start = frame_offset;
+                    }
+                    records.push(record);
+                    record_count += 1;
+                    continue;
+                }
+                Frame::Batch(batch) => batch,
+                // Followers keep their own keys, so batches are shipped decrypted
+                Frame::Encrypted(batch) => reader.decrypt(frame_offset, &batch)?,
+            };
+            let (base_offset, batch) = if frame_offset >= offset {
+                (frame_offset, batch)
+            } else {
+                let tail = batch
+                    .records()
+                    .map_err(|source| StorageError::CorruptRecord {
+                        offset: frame_offset,
+                        source,
+                    })?
+                    .split_off((offset - frame_offset).0);
+                (offset, RecordBatch::new(&tail, batch.compression())?)
+            };
+
+            if !records.is_empty() {
+                batches.push((run_start, RecordBatch::new(&records, Compression::None)?));
+                records.clear();
+            }
+            record_count += batch.record_count();
+            batches.push((base_offset, batch));
+        }
+
+        if !records.is_empty() {
+            batches.push((run_start, RecordBatch::new(&records, Compression::None)?));
+        }
+
+        Ok((batches, reader.next_offset().max(offset)))
+    }
+
+    /// Read whole frames from `offset` until they hold at least `max_records`
+    /// records, adding them to `frames` as slices of the mapped log. Frames
+    /// next to each other share a slice. Only a batch starting before
+    /// `offset`, which is cut down, or an encrypted batch, which is shipped
+    /// decrypted, has to be copied. Returns the offset to continue from.
+    pub fn read_frames(
+        &self,
+        offset: Offset,
+        max_records: usize,
+        corrupt_records: CorruptRecordPolicy,
+        frames: &mut RecordFrames,
+    ) -> Result<Offset, StorageError> {
+        let log = self.mapped()?;
+        let (start, entries) = self.index.lookup(offset);
+        let mut reader = OffsetReader::new(&log, start, entries, self.next_offset)
+            .with_keyring(self.keyring.as_deref(), self.base_offset);
+
+        // Frames not yet added, from the log between these positions
+        let mut run = 0..0;
+        let mut run_records = 0;
+        let mut record_count = 0;
+
+        while record_count < max_records {
+            let (frame_offset, position, info) = match reader.next_raw_frame() {
+                Ok(Some(next)) => next,
+                Ok(None) => break,
+                Err(StorageError::CorruptRecord {
+                    offset: from,
+                    source,
+                }) if corrupt_records == CorruptRecordPolicy::Skip => {
+                    let to = reader.skip_corrupt(&source);
+                    CorruptRecordPolicy::record_skipped(from, to, &source);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if reader.next_offset() <= offset {
+                continue;
+            }
+
+            let stored = match info.kind {
+                FrameKind::Record => true,
+                FrameKind::Batch => frame_offset >= offset,
+                FrameKind::Encrypted => false,
+            };
+            if stored {
+                if run.end != position {
+                    frames.push(log.slice(run.clone()), run_records);
+                    run = position..position;
+                    run_records = 0;
+                }
+                run.end = position + info.len;
+                run_records += info.record_count;
+                record_count += info.record_count;
+                continue;
+            }
+
+            let (frame, _) =
+                Frame::from_bytes(&log[position..position + info.len]).map_err(|source| {
+                    StorageError::CorruptRecord {
+                        offset: frame_offset,
+                        source,
+                    }
+                })?;
+            let batch = match frame {
+                Frame::Batch(batch) => batch,
+                Frame::Encrypted(batch) => reader.decrypt(frame_offset, &batch)?,
+                Frame::Record(_) => unreachable!("Records are always stored"),
+            };
+            let batch = if frame_offset >= offset {
+                batch
+            } else {
+                let records = match batch.records() {
+                    Ok(records) => records,
+                    Err(source) if corrupt_records == CorruptRecordPolicy::Skip => {
+                        CorruptRecordPolicy::record_skipped(
+                            frame_offset,
+                            reader.next_offset(),
+                            &source,
+                        );
+                        continue;
+                    }
+                    Err(source) => {
+                        return Err(StorageError::CorruptRecord {
+                            offset: frame_offset,
+                            source,
+                        });
+                    }
+                };
+                let tail = &records[(offset - frame_offset).0..];
+                RecordBatch::new(tail, batch.compression())?
+            };
+
+            frames.push(log.slice(run.clone()), run_records);
+            run = 0..0;
+            run_records = 0;
+            record_count += batch.record_count();
+            frames.push_batch(&batch)?;
+        }
+
+        frames.push(log.slice(run), run_records);
+
+        Ok(reader.next_offset().max(offset))
+    }
+
+    /// Find the first record, in offset order, at or after `timestamp`
+    pub fn offset_for_timestamp(
+        &self,
+        timestamp: Timestamp,
+        corrupt_records: CorruptRecordPolicy,
+    ) -> Result<Option<Offset>, StorageError> {
+        if self
+            .max_timestamp
+            .is_none_or(|max| max.timestamp < timestamp)
+        {
+            return Ok(None);
+        }
+
+        let mut found = None;
+        let start = self.time_index.lookup(timestamp);
+        self.scan(start, corrupt_records, |offset, record| {
+            if record.timestamp() >= timestamp {
+                found = Some(offset);
+            }
+            found.is_none()
+        })?;
+
+        Ok(found)
+    }
+
+    /// Visit records from `offset` onwards until `visit` returns false,
+    /// returning the offset to continue from.
+    fn scan(
+        &self,
+        offset: Offset,
+        corrupt_records: CorruptRecordPolicy,
+        mut visit: impl FnMut(Offset, Record) -> bool,
+    ) -> Result<Offset, StorageError> {
+        let log = self.mapped()?;
+        let (start, entries) = self.index.lookup(offset);
+        let mut reader = OffsetReader::new(&log, start, entries, self.next_offset)
+            .with_keyring(self.keyring.as_deref(), self.base_offset);
+
+        loop {
+            let (record_offset, record) = match reader.next_record() {
+                Ok(Some(next)) => next,
+                Ok(None) => return Ok(self.next_offset.max(offset)),
+                Err(StorageError::CorruptRecord {
+                    offset: from,
+                    source,
+                }) if corrupt_records == CorruptRecordPolicy::Skip => {
+                    let to = reader.skip_corrupt(&source);
+                    CorruptRecordPolicy::record_skipped(from, to, &source);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if record_offset >= offset && !visit(record_offset, record) {
+                return Ok(reader.next_offset());
+            }
+        }
+    }
+
+    /// Capture the segment's current contents so they can be scanned without holding a lock
+    pub fn snapshot(&self) -> io::Result<SegmentSnapshot> {
+        Ok(SegmentSnapshot {
+            base_offset: self.base_offset,
+            next_offset: self.next_offset,
+            log: self.mapped()?,
+            index: self.index.entries().to_vec(),
+            last_modified: self.last_modified()?,
+            keyring: self.keyring.clone(),
+        })
+    }
+}
+
+/// A point in time view of a segment, the records in it never change once
+/// it is sealed so this can be read while the log carries on being written.
+pub struct SegmentSnapshot {
+    pub base_offset: Offset,
+    pub next_offset: Offset,
+    pub last_modified: SystemTime,
+    pub keyring: Option<Arc<Keyring>>,
+    log: Bytes,
+    index: Vec<IndexEntry>,
+}
+
+impl SegmentSnapshot {
+    /// Visit every record in the segment along with its offset
+    pub fn for_each_record(
+        &self,
+        mut f: impl FnMut(Offset, Record) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        let start = IndexEntry {
+            offset: self.base_offset,
+            position: 0,
+        };
+        let mut reader = OffsetReader::new(&self.log, start, &self.index, self.next_offset)
+            .with_keyring(self.keyring.as_deref(), self.base_offset);
+
+        while let Some((offset, record)) = reader.next_record()? {
+            f(offset, record)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads records along with their offsets. Offsets normally go up by one per
+/// record, but compaction leaves gaps, so wherever the index has an entry for
+/// a frame its offset is taken from there instead.
+struct OffsetReader<'a> {
+    reader: SegmentReader<'a>,
+    entries: &'a [IndexEntry],
+    next_offset: Offset,
+    /// Offset following the last record in the segment
+    end_offset: Offset,
+    /// Records left over from the last batch that was read
+    pending: VecDeque<(Offset, Record)>,
+    /// The last batch was read in full but its records could not be decoded
+    batch_failed: bool,
+    keyring: Option<&'a Keyring>,
+    /// Where the segment starts, which batches are encrypted for
+    segment_base: Offset,
+}
+
+impl<'a> OffsetReader<'a> {
+    fn new(
+        log: &'a [u8],
+        start: IndexEntry,
+        entries: &'a [IndexEntry],
+        end_offset: Offset,
+    ) -> Self {
+        Self {
+            reader: SegmentReader::new(log, start.position),
+            entries,
+            next_offset: start.offset,
+            end_offset,
+            pending: VecDeque::new(),
+            batch_failed: false,
+            keyring: None,
+            segment_base: Offset(0),
+        }
+    }
+
+    /// Decrypt batches with `keyring`, as written to the segment starting at `segment_base`
+    fn with_keyring(mut self, keyring: Option<&'a Keyring>, segment_base: Offset) -> Self {
+        self.keyring = keyring;
+        self.segment_base = segment_base;
+        self
+    }
+
+    /// Decrypt a batch starting at `offset`, which fails if there is no keyring
+    fn decrypt(&self, offset: Offset, batch: &EncryptedBatch) -> Result<RecordBatch, StorageError> {
+        let decrypted = match self.keyring {
+            Some(keyring) => keyring.decrypt(self.segment_base, offset, batch),
+            None => Err(KeyringError::UnknownKey {
+                key_id: batch.key_id(),
+            }),
+        };
+
+        decrypted.map_err(|source| StorageError::Encryption { offset, source })
+    }
+
+    fn next_offset(&self) -> Offset {
+        match self.pending.front() {
+            Some((offset, _)) => *offset,
+            None => self.next_offset,
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<(Offset, Record)>, StorageError> {
+        loop {
+            if let Some(next) = self.pending.pop_front() {
+                return Ok(Some(next));
+            }
+
+            match self.next_frame()? {
+                None => return Ok(None),
+                Some((offset, Frame::Record(record))) => return Ok(Some((offset, record))),
+                Some((offset, frame @ (Frame::Batch(_) | Frame::Encrypted(_)))) => {
+                    let batch = match frame {
+                        Frame::Encrypted(batch) => self.decrypt(offset, &batch)?,
+                        Frame::Batch(batch) => batch,
+                        Frame::Record(_) => unreachable!("Matched a batch"),
+                    };
+                    let records = batch.records().map_err(|source| {
+                        self.batch_failed = true;
+                        StorageError::CorruptRecord { offset, source }
+                    })?;
+                    self.pending.extend((0..).map(|i| offset + i).zip(records));
+                }
+            }
+        }
+    }
+
+    /// Read the next whole frame along with the offset of its first record
+    fn next_frame(&mut self) -> Result<Option<(Offset, Frame)>, StorageError> {
+        self.follow_index();
+
+        let Some(frame) = self.reader.next_frame(self.next_offset)? else {
+            return Ok(None);
+        };
+
+        let offset = self.next_offset;
+        self.next_offset += frame.record_count();
+        Ok(Some((offset, frame)))
+    }
+
+    /// Check the next frame without decoding it, returning the offset of its
+    /// first record and where it starts in the log
+    fn next_raw_frame(&mut self) -> Result<Option<(Offset, usize, FrameInfo)>, StorageError> {
+        self.follow_index();
+
+        let position = self.reader.position;
+        let Some(info) = self.reader.next_raw_frame(self.next_offset)? else {
+            return Ok(None);
+        };
+
+        let offset = self.next_offset;
+        self.next_offset += info.record_count;
+        Ok(Some((offset, position, info)))
+    }
+
+    /// Take the offset of the next frame from the index if it has an entry for it
+    fn follow_index(&mut self) {
+        let position = self.reader.position();
+        while let Some((entry, rest)) = self.entries.split_first() {
+            if entry.position > position {
+                break;
+            }
+            if entry.position == position {
+                self.next_offset = entry.offset;
+            }
+            self.entries = rest;
+        }
+    }
+
+    /// Move past the frame that just failed to decode, returning the offset
+    /// reading resumes from. A frame with a bad checksum was read in full so
+    /// only that record is lost, but when the framing itself is broken there
+    /// is no telling where the next frame starts, so everything up to the
+    /// next index entry is skipped. A batch whose records could not be
+    /// decoded was already stepped over, along with all of its offsets.
+    fn skip_corrupt(&mut self, source: &DecodeError) -> Offset {
+        if std::mem::take(&mut self.batch_failed) {
+            return self.next_offset;
+        }
+
+        match (source, self.entries.first()) {
+            (DecodeError::ChecksumMismatch { frame_len, .. }, _) => {
+                self.reader.seek(self.reader.position() + *frame_len as u64);
+                self.next_offset += 1;
+            }
+            (_, Some(entry)) => {
+                self.reader.seek(entry.position);
+                self.next_offset = entry.offset;
+            }
+            (_, None) => {
+                self.reader.seek(self.reader.log.len() as u64);
+                self.next_offset = self.end_offset;
+            }
+        }
+
+        self.next_offset
+    }
+}
+
+/// Decodes consecutive frames out of a segment's mapped log
+struct SegmentReader<'a> {
+    log: &'a [u8],
+    /// Position in the log of the next frame to be decoded
+    position: usize,
+}
+
+impl<'a> SegmentReader<'a> {
+    fn new(log: &'a [u8], position: u64) -> Self {
+        Self {
+            log,
+            position: position as usize,
+        }
+    }
+
+    fn position(&self) -> u64 {
+        self.position as u64
+    }
+
+    /// Decode the next frame, which starts with the record at `offset`
+    fn next_frame(&mut self, offset: Offset) -> Result<Option<Frame>, StorageError> {
+        let Some(buf) = self.remaining() else {
+            return Ok(None);
+        };
+
+        let (frame, len) = Frame::from_bytes(buf)
+            .map_err(|source| StorageError::CorruptRecord { offset, source })?;
+        self.position += len;
+        Ok(Some(frame))
+    }
+
+    /// Check the next frame in place, which starts with the record at `offset`
+    fn next_raw_frame(&mut self, offset: Offset) -> Result<Option<FrameInfo>, StorageError> {
+        let Some(buf) = self.remaining() else {
+            return Ok(None);
+        };
+
+        let info =
+            Frame::inspect(buf).map_err(|source| StorageError::CorruptRecord { offset, source })?;
+        self.position += info.len;
+        Ok(Some(info))
+    }
+
+    fn remaining(&self) -> Option<&'a [u8]> {
+        self.log.get(self.position..).filter(|buf| !buf.is_empty())
+    }
+
+    /// Carry on decoding from `position` in the log
+    fn seek(&mut self, position: u64) {
+        self.position = position as usize;
+    }
+}
+
+/// Map the first `len` bytes of a log file into memory.
+fn map_log(log: &File, len: u64) -> io::Result<Bytes> {
+    // SAFETY: Log files are only appended to while they are open, and only
+    // the part written so far is mapped. Recovery drops its mapping before
+    // truncating a torn write, while compaction and retention rename or
+    // unlink whole files, which leaves existing mappings of them intact.
+    let map = unsafe { MmapOptions::new().len(len as usize).map(log)? };
+    Ok(Bytes::from_owner(map))
+}
+
+/// Encrypt `batch` for `base_offset` in the segment starting at
+/// `segment_base` and encode it as a frame
+fn encrypted_frame(
+    keyring: &Keyring,
+    segment_base: Offset,
+    base_offset: Offset,
+    batch: &RecordBatch,
+) -> io::Result<Vec<u8>> {
+    let encrypted = keyring.encrypt(segment_base, base_offset, batch);
+    let mut buf = vec![0u8; encrypted.serialized_len()];
+    let len = encrypted.to_bytes(&mut buf)?;
+    buf.truncate(len);
+    Ok(buf)
+}
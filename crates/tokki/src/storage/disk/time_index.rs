@@ -0,0 +1,129 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read as _, Write as _},
+    path::Path,
+};
+
+use tokki_common::{Offset, Timestamp};
+
+const ENTRY_SIZE: usize = 2 * size_of::<u64>();
+
+/// The record at `offset` carries `timestamp`, the newest seen in the segment up to that point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeIndexEntry {
+    pub timestamp: Timestamp,
+    pub offset: Offset,
+}
+
+/// Sparse mapping of timestamps to offsets within a segment.
+///
+/// Record timestamps need not increase with their offsets, so an entry is
+/// only added when a record is newer than every one before it. That keeps
+/// the entries sorted by both timestamp and offset, and means every record
+/// up to an entry's offset is no newer than its timestamp. Entries are
+/// stored as pairs of little-endian `u64`s, the timestamp followed by the
+/// offset relative to the segment's base offset.
+pub struct TimeIndex {
+    file: File,
+    base_offset: Offset,
+    entries: Vec<TimeIndexEntry>,
+}
+
+impl TimeIndex {
+    pub fn open(path: &Path, base_offset: Offset) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let entries = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| {
+                let (timestamp, relative_offset) = entry.split_at(size_of::<u64>());
+                let timestamp = u64::from_le_bytes(timestamp.try_into().expect("8 bytes"));
+                let relative_offset =
+                    u64::from_le_bytes(relative_offset.try_into().expect("8 bytes"));
+
+                TimeIndexEntry {
+                    timestamp: Timestamp(timestamp),
+                    offset: base_offset + relative_offset as usize,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            file,
+            base_offset,
+            entries,
+        })
+    }
+
+    /// Start a fresh, empty index, discarding any existing entries
+    pub fn create(path: &Path, base_offset: Offset) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        file.set_len(0)?;
+
+        Ok(Self {
+            file,
+            base_offset,
+            entries: Vec::new(),
+        })
+    }
+
+    pub fn append(&mut self, entry: TimeIndexEntry) -> io::Result<()> {
+        self.file.write_all(&self.encode(entry))?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Append `entries` in one go, buffering the writes
+    pub fn append_all(&mut self, entries: &[TimeIndexEntry]) -> io::Result<()> {
+        let mut writer = BufWriter::new(&self.file);
+        for entry in entries {
+            writer.write_all(&self.encode(*entry))?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn encode(&self, entry: TimeIndexEntry) -> [u8; ENTRY_SIZE] {
+        let relative_offset = (entry.offset - self.base_offset).0 as u64;
+
+        let mut bytes = [0u8; ENTRY_SIZE];
+        bytes[..size_of::<u64>()].copy_from_slice(&entry.timestamp.0.to_le_bytes());
+        bytes[size_of::<u64>()..].copy_from_slice(&relative_offset.to_le_bytes());
+        bytes
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Find the first offset that could hold a record at or after
+    /// `timestamp`. Everything before it is known to be older.
+    pub fn lookup(&self, timestamp: Timestamp) -> Offset {
+        let idx = self
+            .entries
+            .partition_point(|entry| entry.timestamp < timestamp);
+
+        match idx.checked_sub(1) {
+            Some(idx) => self.entries[idx].offset + 1,
+            None => self.base_offset,
+        }
+    }
+
+    pub fn last(&self) -> Option<TimeIndexEntry> {
+        self.entries.last().copied()
+    }
+}
@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+/// When appended records are forced to stable storage before being acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DurabilityPolicy {
+    /// Fsync once per put, covering every record or batch it wrote, before
+    /// the put returns
+    Always,
+    /// Fsync in the background once `max_delay` has passed or `max_bytes` are
+    /// pending, so many writes share the cost of one fsync. Writers wait for
+    /// the fsync that covers them before acknowledging.
+    GroupCommit { max_delay: Duration, max_bytes: u64 },
+    /// Never fsync explicitly and leave flushing to the OS page cache
+    Os,
+}
+
+impl Default for DurabilityPolicy {
+    fn default() -> Self {
+        Self::GroupCommit {
+            max_delay: Duration::from_millis(10),
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
@@ -1,11 +1,14 @@
 use std::{
-    io,
+    collections::VecDeque,
+    ops::Range,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
-use tokki_common::{Offset, Record};
+use snafu::ResultExt as _;
+use tokki_common::{Offset, Record, RecordBatch};
 
-use crate::storage::Storage;
+use crate::storage::{RetentionPolicy, Storage, StorageError, storage_error::CorruptBatchSnafu};
 
 #[derive(Default, Clone)]
 pub struct InMemoryStorage {
@@ -14,7 +17,10 @@ pub struct InMemoryStorage {
 
 #[derive(Default)]
 pub struct InMemoryStorageInner {
-    records: Vec<StoredRecord>,
+    /// Records paired with when they were appended, the front is at `log_start_offset`
+    records: VecDeque<(Instant, StoredRecord)>,
+    log_start_offset: usize,
+    bytes: u64,
 }
 
 #[derive(Default, Clone)]
@@ -22,27 +28,82 @@ enum StoredRecord {
     #[default]
     Empty,
     Uncommitted(Record),
+    /// The record at `index` in a batch kept compressed, shared by every offset in the batch
+    Batch {
+        batch: Arc<RecordBatch>,
+        index: usize,
+    },
     // Committed(Record),
     // Aborted,
 }
 
+impl StoredRecord {
+    fn serialized_len(&self) -> u64 {
+        match self {
+            StoredRecord::Empty => 0,
+            StoredRecord::Uncommitted(record) => record.serialized_len() as u64,
+            // The whole batch is counted against its first offset
+            StoredRecord::Batch { batch, index: 0 } => batch.serialized_len() as u64,
+            StoredRecord::Batch { .. } => 0,
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl Storage for InMemoryStorage {
-    async fn max_offset(&self) -> io::Result<Option<Offset>> {
+    async fn max_offset(&self) -> Result<Option<Offset>, StorageError> {
         let guard = self.inner.lock().expect("No panics");
-        if guard.records.is_empty() {
+        let next_offset = guard.log_start_offset + guard.records.len();
+        if next_offset == 0 {
             Ok(None)
         } else {
-            Ok(Some(Offset(guard.records.len() - 1)))
+            Ok(Some(Offset(next_offset - 1)))
+        }
+    }
+
+    async fn log_start_offset(&self) -> Result<Offset, StorageError> {
+        let guard = self.inner.lock().expect("No panics");
+        Ok(Offset(guard.log_start_offset))
+    }
+
+    async fn put_records(&self, records: Vec<Record>) -> Result<Range<Offset>, StorageError> {
+        let mut guard = self.inner.lock().expect("No panics");
+        let now = Instant::now();
+        let start = Offset(guard.log_start_offset + guard.records.len());
+
+        for record in records {
+            let record = StoredRecord::Uncommitted(record);
+            guard.bytes += record.serialized_len();
+            guard.records.push_back((now, record));
         }
+
+        let end = Offset(guard.log_start_offset + guard.records.len());
+        tracing::debug!("Put records at {}..{}", start.0, end.0);
+        Ok(start..end)
     }
 
-    async fn put_record(&self, record: Record) -> io::Result<Offset> {
+    async fn put_batch(&self, batch: RecordBatch) -> Result<Range<Offset>, StorageError> {
+        // Batches are kept as they are, so one whose count is wrong would
+        // take offsets that cannot be read
+        batch.records().context(CorruptBatchSnafu)?;
+
         let mut guard = self.inner.lock().expect("No panics");
-        guard.records.push(StoredRecord::Uncommitted(record));
-        let offset = guard.records.len() - 1;
-        tracing::debug!("Put record at {}", offset);
-        Ok(Offset(offset))
+        let now = Instant::now();
+        let start = Offset(guard.log_start_offset + guard.records.len());
+
+        let batch = Arc::new(batch);
+        for index in 0..batch.record_count() {
+            let record = StoredRecord::Batch {
+                batch: batch.clone(),
+                index,
+            };
+            guard.bytes += record.serialized_len();
+            guard.records.push_back((now, record));
+        }
+
+        let end = Offset(guard.log_start_offset + guard.records.len());
+        tracing::debug!("Put batch at {}..{}", start.0, end.0);
+        Ok(start..end)
     }
 
     /// Get some number of records from an offset. Returns a list of the records and the offset of the next record
@@ -50,29 +111,111 @@ impl Storage for InMemoryStorage {
         &self,
         offset: Offset,
         max_records: usize,
-    ) -> io::Result<(Vec<Record>, Offset)> {
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
         let guard = self.inner.lock().expect("No panics");
+
+        if offset.0 < guard.log_start_offset {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset: Offset(guard.log_start_offset),
+            });
+        }
+
         let mut records = Vec::new();
-        let mut last_offset = offset.0;
+        let mut next_offset = offset.0;
+        // Consecutive offsets usually come from the same batch, so it is only decompressed once
+        let mut decompressed: Option<(&Arc<RecordBatch>, Vec<Record>)> = None;
 
-        for (index, record_opt) in guard
+        for (index, (_, record_opt)) in guard
             .records
             .iter()
             .enumerate()
-            .skip(offset.0)
+            .skip(offset.0 - guard.log_start_offset)
             .take(max_records)
         {
             // TODO add distinction between uncommited and commited
-            if let StoredRecord::Uncommitted(record) = record_opt {
-                records.push(record.clone());
-                last_offset = index;
-            } else {
+            match record_opt {
+                StoredRecord::Uncommitted(record) => records.push(record.clone()),
+                StoredRecord::Batch {
+                    batch,
+                    index: batch_index,
+                } => {
+                    let batch_records = match &decompressed {
+                        Some((decompressed_batch, batch_records))
+                            if Arc::ptr_eq(decompressed_batch, batch) =>
+                        {
+                            batch_records
+                        }
+                        _ => {
+                            let batch_records =
+                                batch
+                                    .records()
+                                    .map_err(|source| StorageError::CorruptRecord {
+                                        offset: Offset(guard.log_start_offset + index),
+                                        source,
+                                    })?;
+                            &decompressed.insert((batch, batch_records)).1
+                        }
+                    };
+                    records.push(batch_records[*batch_index].clone());
+                }
+                StoredRecord::Empty => break,
+            }
+            next_offset = guard.log_start_offset + index + 1;
+        }
+
+        Ok((records, Offset(next_offset)))
+    }
+
+    async fn truncate_after(&self, offset: Option<Offset>) -> Result<(), StorageError> {
+        let mut guard = self.inner.lock().expect("No panics");
+        let end = offset.map_or(0, |offset| offset.0 + 1);
+        let len = end.saturating_sub(guard.log_start_offset);
+
+        while guard.records.len() > len {
+            let (_, record) = guard.records.pop_back().expect("Longer than len");
+            guard.bytes -= record.serialized_len();
+        }
+
+        tracing::debug!("Truncated log to {}", guard.log_start_offset + len);
+        Ok(())
+    }
+
+    async fn restart_at(&self, offset: Offset) -> Result<(), StorageError> {
+        let mut guard = self.inner.lock().expect("No panics");
+        guard.records.clear();
+        guard.bytes = 0;
+        guard.log_start_offset = offset.0;
+
+        tracing::debug!("Restarted log at {}", offset.0);
+        Ok(())
+    }
+
+    async fn enforce_retention(&self, policy: &RetentionPolicy) -> Result<Offset, StorageError> {
+        let mut guard = self.inner.lock().expect("No panics");
+        let now = Instant::now();
+
+        while let Some((appended_at, record)) = guard.records.front() {
+            let expired = policy
+                .max_age
+                .is_some_and(|max_age| now.duration_since(*appended_at) > max_age);
+            let too_many = policy
+                .max_records
+                .is_some_and(|max_records| guard.records.len() > max_records);
+            let too_big = policy
+                .max_bytes
+                .is_some_and(|max_bytes| guard.bytes > max_bytes);
+
+            if !(expired || too_many || too_big) {
                 break;
             }
+
+            guard.bytes -= record.serialized_len();
+            guard.records.pop_front();
+            guard.log_start_offset += 1;
         }
 
-        let next_record_offset = last_offset + 1;
-        Ok((records, Offset(next_record_offset)))
+        Ok(Offset(guard.log_start_offset))
     }
 
     //     /// Write an uncomitted record to an exact location
@@ -1,10 +1,4 @@
-use std::{
-    io,
-    sync::{
-        Arc,
-        atomic::{AtomicUsize, Ordering},
-    },
-};
+use std::{collections::VecDeque, io, ops::Range, time::Instant};
 
 use tokio::sync::{
     mpsc::{Receiver, Sender, channel},
@@ -12,16 +6,26 @@ use tokio::sync::{
 };
 use tokki_common::{Offset, Record};
 
-use crate::storage::Storage;
+use crate::storage::{RetentionPolicy, Storage, StorageError};
 
 enum LogFileRequest {
-    Put(Record),
+    MaxOffset,
+    LogStartOffset,
+    Put(Vec<Record>),
     Get((Offset, usize)),
+    Truncate(Option<Offset>),
+    RestartAt(Offset),
+    EnforceRetention(RetentionPolicy),
 }
 
 enum LogFileResponse {
-    Put(io::Result<Offset>),
-    Get(io::Result<(Vec<Record>, Offset)>),
+    MaxOffset(Option<Offset>),
+    LogStartOffset(Offset),
+    Put(io::Result<Range<Offset>>),
+    Get(Result<(Vec<Record>, Offset), StorageError>),
+    Truncate,
+    RestartAt,
+    EnforceRetention(Offset),
 }
 #[derive(Default, Clone)]
 enum StoredRecord {
@@ -31,9 +35,22 @@ enum StoredRecord {
     // Committed(Record),
     // Aborted,
 }
+
+impl StoredRecord {
+    fn serialized_len(&self) -> u64 {
+        match self {
+            StoredRecord::Empty => 0,
+            StoredRecord::Uncommitted(record) => record.serialized_len() as u64,
+        }
+    }
+}
+
 struct LogFile {
     cmd_rx: Receiver<(LogFileRequest, oneshot::Sender<LogFileResponse>)>,
-    records: Vec<StoredRecord>,
+    /// Records paired with when they were appended, the front is at `log_start_offset`
+    records: VecDeque<(Instant, StoredRecord)>,
+    log_start_offset: usize,
+    bytes: u64,
 }
 
 impl LogFile {
@@ -41,44 +58,118 @@ impl LogFile {
         Self {
             cmd_rx,
             records: Default::default(),
+            log_start_offset: 0,
+            bytes: 0,
+        }
+    }
+
+    fn next_offset(&self) -> usize {
+        self.log_start_offset + self.records.len()
+    }
+
+    fn get(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
+        if offset.0 < self.log_start_offset {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset: Offset(self.log_start_offset),
+            });
         }
+
+        let mut records = Vec::new();
+        for (_, record) in self
+            .records
+            .iter()
+            .skip(offset.0 - self.log_start_offset)
+            .take(max_records)
+        {
+            match record {
+                StoredRecord::Uncommitted(record) => {
+                    records.push(record.clone());
+                }
+                StoredRecord::Empty => {
+                    break;
+                }
+            }
+        }
+
+        let next_offset = Offset::new(offset.0 + records.len());
+        Ok((records, next_offset))
+    }
+
+    fn enforce_retention(&mut self, policy: &RetentionPolicy) -> Offset {
+        let now = Instant::now();
+
+        while let Some((appended_at, record)) = self.records.front() {
+            let expired = policy
+                .max_age
+                .is_some_and(|max_age| now.duration_since(*appended_at) > max_age);
+            let too_many = policy
+                .max_records
+                .is_some_and(|max_records| self.records.len() > max_records);
+            let too_big = policy
+                .max_bytes
+                .is_some_and(|max_bytes| self.bytes > max_bytes);
+
+            if !(expired || too_many || too_big) {
+                break;
+            }
+
+            self.bytes -= record.serialized_len();
+            self.records.pop_front();
+            self.log_start_offset += 1;
+        }
+
+        Offset(self.log_start_offset)
     }
 
     async fn run(&mut self) {
-        loop {
-            match self.cmd_rx.recv().await {
-                Some((request, res_tx)) => match request {
-                    LogFileRequest::Put(record) => {
-                        let offset = Offset::new(self.records.len());
-                        self.records.push(StoredRecord::Uncommitted(record));
-                        let _ = res_tx.send(LogFileResponse::Put(Ok(offset)));
+        while let Some((request, res_tx)) = self.cmd_rx.recv().await {
+            match request {
+                LogFileRequest::MaxOffset => {
+                    let max_offset = self.next_offset().checked_sub(1).map(Offset::new);
+                    let _ = res_tx.send(LogFileResponse::MaxOffset(max_offset));
+                }
+                LogFileRequest::LogStartOffset => {
+                    let log_start_offset = Offset::new(self.log_start_offset);
+                    let _ = res_tx.send(LogFileResponse::LogStartOffset(log_start_offset));
+                }
+                LogFileRequest::Put(records) => {
+                    let now = Instant::now();
+                    let start = Offset::new(self.next_offset());
+                    for record in records {
+                        let record = StoredRecord::Uncommitted(record);
+                        self.bytes += record.serialized_len();
+                        self.records.push_back((now, record));
                     }
-                    LogFileRequest::Get((offset, max_records)) => {
-                        let start = offset.0;
-                        let end = (start + max_records).min(self.records.len());
-
-                        let mut records = Vec::new();
-                        for i in start..end {
-                            match &self.records[i] {
-                                StoredRecord::Uncommitted(record) => {
-                                    records.push(record.clone());
-                                }
-                                StoredRecord::Empty => {
-                                    break;
-                                }
-                            }
-                        }
-
-                        let next_offset = if end < self.records.len() {
-                            Offset::new(end)
-                        } else {
-                            Offset::new(self.records.len())
-                        };
-
-                        let _ = res_tx.send(LogFileResponse::Get(Ok((records, next_offset))));
+                    let end = Offset::new(self.next_offset());
+                    let _ = res_tx.send(LogFileResponse::Put(Ok(start..end)));
+                }
+                LogFileRequest::Get((offset, max_records)) => {
+                    let _ = res_tx.send(LogFileResponse::Get(self.get(offset, max_records)));
+                }
+                LogFileRequest::Truncate(offset) => {
+                    let end = offset.map_or(0, |offset| offset.0 + 1);
+                    let len = end.saturating_sub(self.log_start_offset);
+                    while self.records.len() > len {
+                        let (_, record) = self.records.pop_back().expect("Longer than len");
+                        self.bytes -= record.serialized_len();
                     }
-                },
-                None => break,
+                    let _ = res_tx.send(LogFileResponse::Truncate);
+                }
+                LogFileRequest::RestartAt(offset) => {
+                    self.records.clear();
+                    self.bytes = 0;
+                    self.log_start_offset = offset.0;
+                    let _ = res_tx.send(LogFileResponse::RestartAt);
+                }
+                LogFileRequest::EnforceRetention(policy) => {
+                    let log_start_offset = self.enforce_retention(&policy);
+                    let _ = res_tx.send(LogFileResponse::EnforceRetention(log_start_offset));
+                }
             }
         }
     }
@@ -86,7 +177,6 @@ impl LogFile {
 
 #[derive(Clone)]
 pub struct InMemoryChannelStorage {
-    max_offset: Arc<AtomicUsize>,
     cmd_tx: Sender<(LogFileRequest, oneshot::Sender<LogFileResponse>)>,
 }
 
@@ -99,29 +189,42 @@ impl InMemoryChannelStorage {
             logger.run().await;
         });
 
-        Ok(Self {
-            max_offset: Arc::new(AtomicUsize::default()),
-            cmd_tx,
-        })
+        Ok(Self { cmd_tx })
     }
 }
 
 #[async_trait::async_trait]
 impl Storage for InMemoryChannelStorage {
-    async fn max_offset(&self) -> io::Result<Option<Offset>> {
-        let max_offset = self.max_offset.load(Ordering::Relaxed);
+    async fn max_offset(&self) -> Result<Option<Offset>, StorageError> {
+        let (res_tx, res_rx) = oneshot::channel();
+        self.cmd_tx
+            .send((LogFileRequest::MaxOffset, res_tx))
+            .await
+            .unwrap();
 
-        if max_offset == 0 {
-            Ok(None)
-        } else {
-            Ok(Some(Offset::new(max_offset - 1)))
+        match res_rx.await.unwrap() {
+            LogFileResponse::MaxOffset(max_offset) => Ok(max_offset),
+            _ => unreachable!(),
         }
     }
 
-    async fn put_record(&self, record: Record) -> io::Result<Offset> {
+    async fn log_start_offset(&self) -> Result<Offset, StorageError> {
         let (res_tx, res_rx) = oneshot::channel();
         self.cmd_tx
-            .send((LogFileRequest::Put(record), res_tx))
+            .send((LogFileRequest::LogStartOffset, res_tx))
+            .await
+            .unwrap();
+
+        match res_rx.await.unwrap() {
+            LogFileResponse::LogStartOffset(log_start_offset) => Ok(log_start_offset),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn put_records(&self, records: Vec<Record>) -> Result<Range<Offset>, StorageError> {
+        let (res_tx, res_rx) = oneshot::channel();
+        self.cmd_tx
+            .send((LogFileRequest::Put(records), res_tx))
             .await
             .unwrap();
 
@@ -137,7 +240,7 @@ impl Storage for InMemoryChannelStorage {
         &self,
         offset: Offset,
         max_records: usize,
-    ) -> io::Result<(Vec<Record>, Offset)> {
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
         let (res_tx, res_rx) = oneshot::channel();
 
         self.cmd_tx
@@ -152,4 +255,45 @@ impl Storage for InMemoryChannelStorage {
 
         Ok(res)
     }
+
+    async fn truncate_after(&self, offset: Option<Offset>) -> Result<(), StorageError> {
+        let (res_tx, res_rx) = oneshot::channel();
+
+        self.cmd_tx
+            .send((LogFileRequest::Truncate(offset), res_tx))
+            .await
+            .unwrap();
+
+        match res_rx.await.unwrap() {
+            LogFileResponse::Truncate => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+    async fn restart_at(&self, offset: Offset) -> Result<(), StorageError> {
+        let (res_tx, res_rx) = oneshot::channel();
+
+        self.cmd_tx
+            .send((LogFileRequest::RestartAt(offset), res_tx))
+            .await
+            .unwrap();
+
+        match res_rx.await.unwrap() {
+            LogFileResponse::RestartAt => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn enforce_retention(&self, policy: &RetentionPolicy) -> Result<Offset, StorageError> {
+        let (res_tx, res_rx) = oneshot::channel();
+
+        self.cmd_tx
+            .send((LogFileRequest::EnforceRetention(*policy), res_tx))
+            .await
+            .unwrap();
+
+        match res_rx.await.unwrap() {
+            LogFileResponse::EnforceRetention(log_start_offset) => Ok(log_start_offset),
+            _ => unreachable!(),
+        }
+    }
 }
@@ -1,162 +1,711 @@
-use std::{
-    io,
-    sync::{
-        Arc,
-        atomic::{AtomicUsize, Ordering},
-    },
+use std::{io, ops::Range, ptr, sync::Arc, time::Instant};
+
+use bytes::Bytes;
+#[cfg(tokki_loom)]
+use loom::sync::{
+    Mutex,
+    atomic::{AtomicPtr, AtomicUsize, Ordering, fence},
+};
+#[cfg(not(tokki_loom))]
+use std::sync::{
+    Mutex,
+    atomic::{AtomicPtr, AtomicUsize, Ordering, fence},
 };
+use tokki_common::{Frame, Offset, Record};
+
+use crate::storage::{CorruptRecordPolicy, RecordFrames, RetentionPolicy, Storage, StorageError};
 
-use tokki_common::{Offset, Record};
+/// Offsets in each chunk of the index
+#[cfg(not(tokki_loom))]
+const CHUNK_LEN: usize = 64 * 1024;
+/// Small enough for model tests to fill several chunks
+#[cfg(tokki_loom)]
+const CHUNK_LEN: usize = 2;
 
-use crate::storage::Storage;
+/// Upper bound on the number of chunks, the log holds up to
+/// `CHUNK_LEN * MAX_CHUNKS` records
+#[cfg(not(tokki_loom))]
+const MAX_CHUNKS: usize = 64 * 1024;
+#[cfg(tokki_loom)]
+const MAX_CHUNKS: usize = 4;
 
-const OFFSETS_SIZE: usize = 1024 * 1024 * 1024;
-const SIZE: usize = 4 * 1024 * 1024 * 1024;
+/// Held in `reserved` while the log is being truncated, keeping writers out
+const TRUNCATING: usize = usize::MAX;
 
+/// Lock-free log held in memory.
+///
+/// Every put encodes its records into a buffer of their own, which is never
+/// written to again once it has been handed to the index, so readers can
+/// never see a partially written record and can serve frames straight out
+/// of it. The index maps offsets to where their records sit in those
+/// buffers. It is made of fixed size chunks allocated as the log reaches
+/// them, so memory grows with the log rather than being claimed up front.
+///
+/// Writers wait for the put before them to commit, reserve offsets with a
+/// compare and swap, fill in their entries, then commit them by advancing
+/// `committed` with release ordering. Nothing is awaited between reserving
+/// and committing, so a put that is dropped while it waits leaves no
+/// offsets reserved behind it. Readers load `committed` with acquire
+/// ordering and only ever look at entries below it, which are then fully
+/// written.
+///
+/// Truncating waits for the put in flight the same way, then lowers
+/// `committed` and lets writers reuse the entries above it, which readers
+/// that loaded the old `committed` may still be looking at. So readers
+/// check `truncations` did not change while they read, starting over if it
+/// did.
+///
+/// Retention removes whole puts from the start of the log by raising
+/// `log_start`, which readers check after loading `committed`. Entries
+/// below it are never written to again, so readers still looking at them
+/// only need their buffers kept.
+///
+/// Buffers that only removed records were in are retired in the current
+/// epoch. Readers count themselves in the slot of `readers` for the epoch
+/// they started in, before loading `committed` or `log_start`. The epoch
+/// only moves on once the slot of the one before it is empty, so by the
+/// time it has moved on twice every reader that could still reach a buffer
+/// retired in it is done, and the buffer is freed. Reads that keep
+/// overlapping each other hold back only what was retired before they
+/// started.
 #[derive(Clone)]
 pub struct InMemoryLockFree {
     inner: Arc<InMemoryLockFreeInner>,
+    corrupt_records: CorruptRecordPolicy,
+}
+
+struct InMemoryLockFreeInner {
+    /// Index chunks, null until first written to
+    chunks: Box<[AtomicPtr<Chunk>]>,
+    /// Offsets handed out to writers
+    reserved: AtomicUsize,
+    /// Offsets below this are fully written and can be read
+    committed: AtomicUsize,
+    /// Offsets below this have been removed
+    log_start: AtomicUsize,
+    /// Bumped every time the log is truncated or restarted
+    truncations: AtomicUsize,
+    /// Size of the buffers from `log_start` on, including puts in flight
+    bytes: AtomicUsize,
+    /// Moved on once every read started two epochs ago is done
+    epoch: AtomicUsize,
+    /// Reads running, by the parity of the epoch they started in
+    readers: [AtomicUsize; 2],
+    /// Buffers that only removed records were in, along with the epoch they
+    /// were retired in. Held while removing records so only one removal runs
+    /// at a time, and while the epoch moves on and buffers are freed.
+    retired: Mutex<Vec<RetiredBuf>>,
+}
+
+/// The encoded records of one put, shared by every entry it covers
+struct PutBuf {
+    bytes: Bytes,
+    put_at: Instant,
+}
+
+/// A buffer no longer referenced from between `log_start` and `committed`
+struct RetiredBuf {
+    buf: *mut PutBuf,
+    epoch: usize,
+}
+
+// SAFETY: The buffer is only ever read through, and freed once no reader can reach it
+unsafe impl Send for RetiredBuf {}
+
+/// Counts a read in its slot of `readers` for as long as it runs
+struct ReadGuard<'a> {
+    inner: &'a InMemoryLockFreeInner,
+    slot: usize,
 }
 
-pub struct InMemoryLockFreeInner {
-    offsets: Vec<usize>,
-    commited_offset_head: AtomicUsize,
-    offset_head: AtomicUsize,
-    data: Vec<u8>,
-    committed_data_head: AtomicUsize,
-    data_head: AtomicUsize,
+/// A run of index entries
+struct Chunk {
+    entries: Box<[Entry]>,
+}
+
+/// Where the record at an offset is stored
+struct Entry {
+    /// Buffer holding the record, shared by everything put alongside it.
+    /// The entry at position zero owns it.
+    buf: AtomicPtr<PutBuf>,
+    /// Where the record's frame starts in `buf`
+    position: AtomicUsize,
+}
+
+impl Default for InMemoryLockFree {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InMemoryLockFree {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(InMemoryLockFreeInner {
-                offsets: vec![0; OFFSETS_SIZE],
-                commited_offset_head: AtomicUsize::new(0),
-                offset_head: AtomicUsize::new(0),
-                data: vec![0; SIZE],
-                committed_data_head: AtomicUsize::new(0),
-                data_head: AtomicUsize::new(0),
+                chunks: (0..MAX_CHUNKS)
+                    .map(|_| AtomicPtr::new(ptr::null_mut()))
+                    .collect(),
+                reserved: AtomicUsize::new(0),
+                committed: AtomicUsize::new(0),
+                log_start: AtomicUsize::new(0),
+                truncations: AtomicUsize::new(0),
+                bytes: AtomicUsize::new(0),
+                epoch: AtomicUsize::new(0),
+                readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+                retired: Mutex::default(),
             }),
+            corrupt_records: CorruptRecordPolicy::default(),
         }
     }
-}
 
-#[async_trait::async_trait]
-impl Storage for InMemoryLockFree {
-    async fn max_offset(&self) -> io::Result<Option<Offset>> {
-        let current_head = self.inner.commited_offset_head.load(Ordering::SeqCst);
-        if current_head == 0 {
-            Ok(None)
-        } else {
-            Ok(Some(Offset(current_head - 1)))
+    pub fn with_corrupt_records(mut self, corrupt_records: CorruptRecordPolicy) -> Self {
+        self.corrupt_records = corrupt_records;
+        self
+    }
+
+    /// Read records below `committed`, see `Storage::get_records`
+    fn read_records(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
+        let inner = self.inner.as_ref();
+        let start_offset = offset.0;
+        let committed = inner.committed.load(Ordering::Acquire);
+        inner.check_log_start(offset)?;
+
+        if start_offset >= committed {
+            return Ok((Vec::new(), offset));
         }
+
+        let mut records = Vec::new();
+
+        // Determine how many records we can read
+        let available_records = committed - start_offset;
+        let records_to_read = available_records.min(max_records);
+
+        for current_offset in start_offset..start_offset + records_to_read {
+            let (put, position) = inner.entry(current_offset);
+
+            let offset = Offset(current_offset);
+            match Record::from_bytes(put.bytes.get(position..).unwrap_or_default()) {
+                Ok((record, _)) => records.push(record),
+                Err(source) if self.corrupt_records == CorruptRecordPolicy::Skip => {
+                    CorruptRecordPolicy::record_skipped(offset, offset + 1, &source);
+                }
+                Err(source) => return Err(StorageError::CorruptRecord { offset, source }),
+            }
+        }
+
+        Ok((records, Offset(start_offset + records_to_read)))
     }
 
-    async fn put_record(&self, record: Record) -> io::Result<Offset> {
+    /// Read frames below `committed`, see `Storage::get_frames`
+    fn read_frames(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(RecordFrames, Offset), StorageError> {
         let inner = self.inner.as_ref();
-        let serializd_len = record.serialized_len();
-
-        // Reserve offset and data
-        // Offset and data are not necessarily acquired in-order. E.g.
-        // offset -> data start
-        // 0 -> 0
-        // 1 -> 200
-        // 2 -> 100
-        let offset_idx = inner.offset_head.fetch_add(1, Ordering::AcqRel);
-        let offset_end = offset_idx + 1;
-        if offset_idx >= OFFSETS_SIZE {
-            return Err(io::Error::new(
-                io::ErrorKind::OutOfMemory,
-                "Offset buffer capacity exceeded",
-            ));
+        let start_offset = offset.0;
+        let committed = inner.committed.load(Ordering::Acquire);
+        inner.check_log_start(offset)?;
+
+        let mut frames = RecordFrames::new();
+        if start_offset >= committed {
+            return Ok((frames, offset));
+        }
+
+        let end_offset = committed.min(start_offset.saturating_add(max_records));
+
+        // Records put together sit next to each other in their buffer, so
+        // they are served as one slice of it
+        let mut run: Option<(&Bytes, Range<usize>)> = None;
+        let mut run_records = 0;
+
+        for current_offset in start_offset..end_offset {
+            let (put, position) = inner.entry(current_offset);
+            let buf = &put.bytes;
+
+            let offset = Offset(current_offset);
+            let len = match Frame::inspect(buf.get(position..).unwrap_or_default()) {
+                Ok(info) => info.len,
+                Err(source) if self.corrupt_records == CorruptRecordPolicy::Skip => {
+                    CorruptRecordPolicy::record_skipped(offset, offset + 1, &source);
+                    continue;
+                }
+                Err(source) => return Err(StorageError::CorruptRecord { offset, source }),
+            };
+
+            match &mut run {
+                Some((run_buf, range)) if ptr::eq(*run_buf, buf) && range.end == position => {
+                    range.end = position + len;
+                }
+                _ => {
+                    if let Some((run_buf, range)) = run.take() {
+                        frames.push(run_buf.slice(range), run_records);
+                    }
+                    run = Some((buf, position..position + len));
+                    run_records = 0;
+                }
+            }
+            run_records += 1;
+        }
+
+        if let Some((run_buf, range)) = run {
+            frames.push(run_buf.slice(range), run_records);
+        }
+
+        Ok((frames, Offset(end_offset)))
+    }
+}
+
+impl InMemoryLockFreeInner {
+    /// Reserve `len` offsets once no other put is in flight, failing without
+    /// reserving anything if the index is full
+    async fn reserve(&self, len: usize) -> io::Result<usize> {
+        loop {
+            // Committing is what publishes entries, acquiring here only orders
+            // writes to reused entries after the truncation that freed them up
+            let start = self.reserved.load(Ordering::Relaxed);
+            if start == TRUNCATING || start != self.committed.load(Ordering::Relaxed) {
+                backoff().await;
+                continue;
+            }
+
+            let end = start + len;
+            if end > CHUNK_LEN * MAX_CHUNKS {
+                return Err(io::Error::new(
+                    io::ErrorKind::OutOfMemory,
+                    "Offset index capacity exceeded",
+                ));
+            }
+
+            if self
+                .reserved
+                .compare_exchange(start, end, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Ok(start);
+            }
         }
+    }
+
+    /// Make `start..end` readable. Every change to `committed` is a
+    /// read-modify-write, so a reader acquiring `end` also sees the entries
+    /// of every writer that committed before.
+    fn commit(&self, start: usize, end: usize) {
+        let previous = self.committed.swap(end, Ordering::Release);
+        debug_assert_eq!(previous, start, "Only one put is in flight");
+    }
 
-        let data_start = inner.data_head.fetch_add(serializd_len, Ordering::AcqRel);
-        let data_end = data_start + serializd_len;
-        if data_start >= OFFSETS_SIZE {
+    /// Wait for the put in flight, if any, then keep writers out until
+    /// `reserved` is stored again. Returns where the log ends.
+    async fn stop_writers(&self) -> usize {
+        loop {
+            let committed = self.committed.load(Ordering::Acquire);
+            if self
+                .reserved
+                .compare_exchange(committed, TRUNCATING, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return committed;
+            }
+            backoff().await;
+        }
+    }
+
+    /// Fail reads from before the start of the log. Anything that lowers
+    /// `committed` or moves it past `log_start` stores `log_start` first, so
+    /// this is checked after loading `committed`.
+    fn check_log_start(&self, offset: Offset) -> Result<(), StorageError> {
+        let log_start = self.log_start.load(Ordering::Acquire);
+        if offset.0 < log_start {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset: Offset(log_start),
+            });
+        }
+        Ok(())
+    }
+
+    /// Drop every offset from `end` onwards, once no put is in flight.
+    /// Records already removed from the start stay removed.
+    async fn truncate(&self, end: usize) {
+        if self.committed.load(Ordering::Acquire) <= end {
+            return;
+        }
+        let committed = self.stop_writers().await;
+        let mut retired = self.retired.lock().expect("No panics");
+        let end = end
+            .max(self.log_start.load(Ordering::Relaxed))
+            .min(committed);
+
+        // A reader that sees the new count also sees the lower `committed`.
+        // Writers only get to reuse the entries once `reserved` is released
+        // below, so a reader that sees a reused entry also sees the count.
+        if end < committed {
+            self.committed.store(end, Ordering::Release);
+            self.truncations.fetch_add(1, Ordering::Release);
+        }
+
+        let epoch = self.epoch.load(Ordering::Relaxed);
+        for offset in end..committed {
+            let (put, position) = self.entry(offset);
+            // Buffers starting before `end` are still owned by their first entry
+            if position == 0 {
+                self.bytes.fetch_sub(put.bytes.len(), Ordering::Relaxed);
+                retired.push(RetiredBuf::new(put, epoch));
+            }
+        }
+        self.reclaim(&mut retired);
+        drop(retired);
+
+        self.reserved.store(end, Ordering::Release);
+    }
+
+    /// Drop the whole log, once no put is in flight, and carry on from `offset`
+    async fn restart_at(&self, offset: usize) -> io::Result<()> {
+        if offset > CHUNK_LEN * MAX_CHUNKS {
             return Err(io::Error::new(
                 io::ErrorKind::OutOfMemory,
-                "Data buffer capacity exceeded",
+                "Offset index capacity exceeded",
             ));
         }
 
-        // Write the data
-        let buf = unsafe {
-            let base_ptr = inner.data.as_ptr();
-            let start_ptr = base_ptr.add(data_start) as *mut u8;
-            tracing::trace!(
-                ?data_start,
-                ?base_ptr,
-                ?start_ptr,
-                ?serializd_len,
-                "Writing data"
-            );
-            std::slice::from_raw_parts_mut(start_ptr, serializd_len)
-        };
-        record.to_bytes(buf)?;
-
-        unsafe {
-            let ptr = inner.offsets.as_ptr().add(offset_idx) as *mut usize;
-            *ptr = data_start;
-        }
-
-        // Advance the committed heads
-        // Make sure the data is committed before the offset is
-        loop {
-            if let Ok(_) = inner.commited_offset_head.compare_exchange(
-                offset_idx,
-                offset_end,
-                Ordering::Relaxed, // TODO fix
-                Ordering::Relaxed, // TODO fix
-            ) {
+        let committed = self.stop_writers().await;
+        let mut retired = self.retired.lock().expect("No panics");
+
+        // Entries between the old and new end were never written, so
+        // readers must see the new start before they can see the new end
+        let log_start = self.log_start.swap(offset, Ordering::Release);
+        self.committed.store(offset, Ordering::Release);
+        self.truncations.fetch_add(1, Ordering::Release);
+
+        let epoch = self.epoch.load(Ordering::Relaxed);
+        for offset in log_start..committed {
+            let (put, position) = self.entry(offset);
+            if position == 0 {
+                retired.push(RetiredBuf::new(put, epoch));
+            }
+        }
+        self.bytes.store(0, Ordering::Relaxed);
+        self.reclaim(&mut retired);
+        drop(retired);
+
+        self.reserved.store(offset, Ordering::Release);
+        Ok(())
+    }
+
+    /// Remove whole puts from the start of the log while it breaks `policy`,
+    /// returning the new log start. The latest put is never removed, so the
+    /// log can exceed the limits by up to one put.
+    fn enforce_retention(&self, policy: &RetentionPolicy) -> usize {
+        let mut retired = self.retired.lock().expect("No panics");
+        let now = Instant::now();
+        let committed = self.committed.load(Ordering::Acquire);
+        let mut log_start = self.log_start.load(Ordering::Relaxed);
+
+        while log_start < committed {
+            let (put, _) = self.entry(log_start);
+            let mut end = log_start + 1;
+            while end < committed && ptr::eq(self.entry(end).0, put) {
+                end += 1;
+            }
+            if end == committed {
+                break;
+            }
+
+            let expired = policy
+                .max_age
+                .is_some_and(|max_age| now.duration_since(put.put_at) > max_age);
+            let too_many = policy
+                .max_records
+                .is_some_and(|max_records| committed - log_start > max_records);
+            let too_big = policy
+                .max_bytes
+                .is_some_and(|max_bytes| self.bytes.load(Ordering::Relaxed) as u64 > max_bytes);
+
+            if !(expired || too_many || too_big) {
+                break;
+            }
+
+            self.bytes.fetch_sub(put.bytes.len(), Ordering::Relaxed);
+            retired.push(RetiredBuf::new(put, self.epoch.load(Ordering::Relaxed)));
+            log_start = end;
+        }
+
+        self.log_start.store(log_start, Ordering::Release);
+        self.reclaim(&mut retired);
+        log_start
+    }
+
+    /// Move the epoch on as far as the readers allow, freeing the buffers
+    /// retired two epochs ago or more. Removals retire buffers only once they
+    /// can no longer be reached from `committed` or `log_start`, which readers
+    /// load after counting themselves, so either this sees a reader that
+    /// could still reach them or that reader cannot.
+    fn reclaim(&self, retired: &mut Vec<RetiredBuf>) {
+        fence(Ordering::SeqCst);
+        let mut epoch = self.epoch.load(Ordering::Relaxed);
+        for _ in 0..2 {
+            // Readers that started in the epoch before this one
+            if self.readers[(epoch + 1) % 2].load(Ordering::Acquire) != 0 {
                 break;
             }
+            epoch += 1;
+            self.epoch.store(epoch, Ordering::Release);
+            fence(Ordering::SeqCst);
         }
 
+        retired.retain(|retired| {
+            if retired.epoch + 2 > epoch {
+                return true;
+            }
+            // SAFETY: Every reader that started before it was retired is
+            // done, and later ones cannot reach it
+            drop(unsafe { Box::from_raw(retired.buf) });
+            false
+        });
+    }
+
+    /// Run `read` until no truncation happens while it does. A read that
+    /// raced a truncation may have seen entries half reused, so whatever it
+    /// returned is thrown away, errors included.
+    fn read_consistent<T>(&self, mut read: impl FnMut() -> T) -> T {
+        let _guard = ReadGuard::new(self);
         loop {
-            if let Ok(_) = inner.committed_data_head.compare_exchange(
-                data_start,
-                data_end,
-                Ordering::Relaxed, // TODO fix
-                Ordering::Relaxed, // TODO fix
+            let truncations = self.truncations.load(Ordering::Acquire);
+            let result = read();
+            fence(Ordering::Acquire);
+            if self.truncations.load(Ordering::Relaxed) == truncations {
+                return result;
+            }
+        }
+    }
+
+    /// The entry for `offset`, allocating its chunk if this is the first write to it
+    fn entry_for_write(&self, offset: usize) -> &Entry {
+        let (chunk_idx, idx) = (offset / CHUNK_LEN, offset % CHUNK_LEN);
+        let slot = &self.chunks[chunk_idx];
+
+        let mut chunk = slot.load(Ordering::Acquire);
+        if chunk.is_null() {
+            let new = Box::into_raw(Box::new(Chunk::new()));
+            chunk = match slot.compare_exchange(
+                ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
             ) {
-                break;
+                Ok(_) => new,
+                Err(current) => {
+                    // Another writer got there first
+                    // SAFETY: `new` was never shared
+                    drop(unsafe { Box::from_raw(new) });
+                    current
+                }
+            };
+        }
+
+        // SAFETY: Chunks are only freed when the whole log is dropped
+        unsafe { &(*chunk).entries[idx] }
+    }
+
+    /// The buffer and position of a committed record
+    fn entry(&self, offset: usize) -> (&PutBuf, usize) {
+        let chunk = self.chunks[offset / CHUNK_LEN].load(Ordering::Acquire);
+        // SAFETY: Committed offsets have been written, so their chunk exists,
+        // and chunks are only freed when the whole log is dropped
+        let entry = unsafe { &(*chunk).entries[offset % CHUNK_LEN] };
+
+        let buf = entry.buf.load(Ordering::Acquire);
+        let position = entry.position.load(Ordering::Relaxed);
+        // SAFETY: Buffers are never written to once they are in the index.
+        // Only readers counted in `readers` and removals holding `retired`
+        // look up entries, and neither lets the buffer be freed meanwhile.
+        (unsafe { &*buf }, position)
+    }
+}
+
+impl RetiredBuf {
+    fn new(put: &PutBuf, epoch: usize) -> Self {
+        Self {
+            buf: ptr::from_ref(put).cast_mut(),
+            epoch,
+        }
+    }
+}
+
+impl<'a> ReadGuard<'a> {
+    /// Count a read in the slot of the current epoch, before it loads
+    /// `committed` or `log_start`
+    fn new(inner: &'a InMemoryLockFreeInner) -> Self {
+        loop {
+            let epoch = inner.epoch.load(Ordering::Acquire);
+            let slot = epoch % 2;
+            inner.readers[slot].fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            // The epoch moved on in between, so the slot may already have
+            // been found empty
+            if inner.epoch.load(Ordering::Acquire) == epoch {
+                return Self { inner, slot };
             }
+            inner.readers[slot].fetch_sub(1, Ordering::Release);
         }
+    }
+}
 
-        Ok(Offset(offset_idx))
+impl Drop for ReadGuard<'_> {
+    /// Free what was retired while the reads of its epoch ran, if this was
+    /// the last of them
+    fn drop(&mut self) {
+        if self.inner.readers[self.slot].fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // Otherwise whatever is left is freed by a later read or removal
+        if let Ok(mut retired) = self.inner.retired.try_lock()
+            && !retired.is_empty()
+        {
+            self.inner.reclaim(&mut retired);
+        }
     }
+}
 
-    async fn get_records(
-        &self,
-        offset: Offset,
-        max_records: usize,
-    ) -> io::Result<(Vec<Record>, Offset)> {
-        let inner = self.inner.as_ref();
-        let start_offset = offset.0;
-        let committed_head = inner.commited_offset_head.load(Ordering::SeqCst);
+impl Chunk {
+    fn new() -> Self {
+        Self {
+            entries: (0..CHUNK_LEN)
+                .map(|_| Entry {
+                    buf: AtomicPtr::new(ptr::null_mut()),
+                    position: AtomicUsize::new(0),
+                })
+                .collect(),
+        }
+    }
+}
 
-        if start_offset >= committed_head {
-            return Ok((Vec::new(), offset));
+impl Drop for InMemoryLockFreeInner {
+    fn drop(&mut self) {
+        let committed = self.committed.load(Ordering::Relaxed);
+        let log_start = self.log_start.load(Ordering::Relaxed);
+        for retired in self.retired.get_mut().expect("No panics").drain(..) {
+            // SAFETY: Nothing else can hold a reference to the log any more,
+            // and nothing from `log_start` to `committed` points at a retired buffer
+            drop(unsafe { Box::from_raw(retired.buf) });
         }
 
-        let mut records = Vec::new();
+        for (chunk_idx, slot) in self.chunks.iter_mut().enumerate() {
+            let chunk = slot.load(Ordering::Relaxed);
+            if chunk.is_null() {
+                continue;
+            }
 
-        // Determine how many records we can read
-        let available_records = committed_head - start_offset;
-        let records_to_read = available_records.min(max_records);
+            // SAFETY: Nothing else can hold a reference to the log any more
+            let chunk = unsafe { Box::from_raw(chunk) };
+            // Entries outside `log_start..committed` were removed, their
+            // buffers are retired
+            let first = chunk_idx * CHUNK_LEN;
+            let live = log_start.saturating_sub(first).min(CHUNK_LEN)
+                ..committed.saturating_sub(first).min(CHUNK_LEN);
+            for entry in &chunk.entries[live] {
+                let buf = entry.buf.load(Ordering::Relaxed);
+                if !buf.is_null() && entry.position.load(Ordering::Relaxed) == 0 {
+                    // SAFETY: Each buffer has exactly one entry at position zero
+                    drop(unsafe { Box::from_raw(buf) });
+                }
+            }
+        }
+    }
+}
 
-        for current_offset in start_offset..start_offset + records_to_read {
-            let data_pos = inner.offsets[current_offset];
+#[async_trait::async_trait]
+impl Storage for InMemoryLockFree {
+    async fn max_offset(&self) -> Result<Option<Offset>, StorageError> {
+        let committed = self.inner.committed.load(Ordering::Acquire);
+        Ok(committed.checked_sub(1).map(Offset))
+    }
+
+    async fn log_start_offset(&self) -> Result<Offset, StorageError> {
+        Ok(Offset(self.inner.log_start.load(Ordering::Acquire)))
+    }
+
+    async fn put_records(&self, records: Vec<Record>) -> Result<Range<Offset>, StorageError> {
+        let inner = self.inner.as_ref();
 
-            let buf = &inner.data[data_pos..];
+        // Encode everything before reserving offsets, as the offsets cannot
+        // be handed back once they are reserved
+        let mut buf = vec![0; records.iter().map(Record::serialized_len).sum()];
+        let mut positions = Vec::with_capacity(records.len());
+        let mut position = 0;
+        for record in &records {
+            positions.push(position);
+            position += record.to_bytes(&mut buf[position..])?;
+        }
 
-            let (record, _) = Record::from_bytes(buf)?;
+        let start = inner.reserve(records.len()).await?;
+        let end = start + records.len();
 
-            records.push(record);
+        if !records.is_empty() {
+            // Counted before committing, so truncation never takes it off first
+            inner.bytes.fetch_add(buf.len(), Ordering::Relaxed);
+            let buf = Box::into_raw(Box::new(PutBuf {
+                bytes: Bytes::from(buf),
+                put_at: Instant::now(),
+            }));
+            for (offset, position) in (start..end).zip(positions) {
+                let entry = inner.entry_for_write(offset);
+                // Both release, so a reader that raced a truncation and saw
+                // either of them written again knows to start over
+                entry.position.store(position, Ordering::Release);
+                entry.buf.store(buf, Ordering::Release);
+            }
         }
 
-        Ok((records, Offset(start_offset + records_to_read + 1)))
+        inner.commit(start, end);
+
+        Ok(Offset(start)..Offset(end))
+    }
+
+    async fn get_records(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
+        self.inner
+            .read_consistent(|| self.read_records(offset, max_records))
     }
+
+    async fn get_frames(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(RecordFrames, Offset), StorageError> {
+        self.inner
+            .read_consistent(|| self.read_frames(offset, max_records))
+    }
+
+    async fn truncate_after(&self, offset: Option<Offset>) -> Result<(), StorageError> {
+        self.inner
+            .truncate(offset.map_or(0, |offset| offset.0 + 1))
+            .await;
+        Ok(())
+    }
+
+    async fn restart_at(&self, offset: Offset) -> Result<(), StorageError> {
+        self.inner.restart_at(offset.0).await?;
+        Ok(())
+    }
+
+    async fn enforce_retention(&self, policy: &RetentionPolicy) -> Result<Offset, StorageError> {
+        Ok(Offset(self.inner.enforce_retention(policy)))
+    }
+}
+
+/// Let the put in flight or the truncation in progress get on while waiting for it
+async fn backoff() {
+    #[cfg(tokki_loom)]
+    loom::thread::yield_now();
+    #[cfg(not(tokki_loom))]
+    tokio::task::yield_now().await;
 }
@@ -0,0 +1,172 @@
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit as _, Nonce,
+    aead::{Aead as _, Payload},
+};
+use rand::RngCore as _;
+use snafu::{OptionExt as _, ResultExt as _, ensure};
+use tokki_common::{EncryptedBatch, Offset, RecordBatch, encrypted_batch::NONCE_LEN};
+
+use crate::storage::keyring_error::{
+    DecryptSnafu, DuplicateKeySnafu, InvalidKeySnafu, KeyFileLineSnafu, KeyringError, NoKeysSnafu,
+    ReadKeyFileSnafu, UnknownKeySnafu,
+};
+
+/// AES-256-GCM keys for encrypting the log at rest, by id.
+///
+/// New data is always encrypted with the current key, the one with the
+/// highest id. Rotating adds a key with a higher id, and older keys are kept
+/// so data written before the rotation can still be read.
+pub struct Keyring {
+    keys: BTreeMap<u32, Aes256Gcm>,
+}
+
+impl Keyring {
+    /// Read keys from a file with one key per line, as a numeric id and 32
+    /// hex encoded bytes separated by whitespace. Blank lines and lines
+    /// starting with `#` are ignored.
+    pub fn load(path: &Path) -> Result<Self, KeyringError> {
+        let contents = fs::read_to_string(path).context(ReadKeyFileSnafu { path })?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, KeyringError> {
+        let mut keys = BTreeMap::new();
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line_number = idx + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (Some(key_id), Some(key), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                return KeyFileLineSnafu { line: line_number }.fail();
+            };
+            let key_id: u32 = key_id
+                .parse()
+                .ok()
+                .context(KeyFileLineSnafu { line: line_number })?;
+            let key: [u8; 32] = hex::decode(key)
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .context(InvalidKeySnafu {
+                    key_id,
+                    line: line_number,
+                })?;
+
+            ensure!(
+                keys.insert(key_id, key).is_none(),
+                DuplicateKeySnafu { key_id }
+            );
+        }
+
+        Self::new(keys)
+    }
+
+    pub fn new(keys: impl IntoIterator<Item = (u32, [u8; 32])>) -> Result<Self, KeyringError> {
+        let keys: BTreeMap<_, _> = keys
+            .into_iter()
+            .map(|(key_id, key)| (key_id, Aes256Gcm::new(&key.into())))
+            .collect();
+        ensure!(!keys.is_empty(), NoKeysSnafu);
+
+        Ok(Self { keys })
+    }
+
+    /// The id of the key new data is encrypted with
+    pub fn current_key_id(&self) -> u32 {
+        *self.keys.keys().next_back().expect("At least one key")
+    }
+
+    /// Encrypt the batch's data with the current key and a random nonce, for
+    /// writing at `base_offset` in the segment starting at `segment_base`
+    pub fn encrypt(
+        &self,
+        segment_base: Offset,
+        base_offset: Offset,
+        batch: &RecordBatch,
+    ) -> EncryptedBatch {
+        let (&key_id, cipher) = self.keys.last_key_value().expect("At least one key");
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rng().fill_bytes(&mut nonce);
+
+        let aad = EncryptedBatch::associated_data(
+            segment_base,
+            base_offset,
+            batch.compression(),
+            batch.record_count(),
+            batch.max_timestamp(),
+            key_id,
+        );
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: batch.data(),
+                    aad: &aad,
+                },
+            )
+            .expect("Batches are far smaller than AES-GCM's limit");
+
+        EncryptedBatch::new(
+            batch.compression(),
+            batch.record_count(),
+            batch.max_timestamp(),
+            key_id,
+            nonce,
+            ciphertext,
+        )
+    }
+
+    /// Decrypt a batch with whichever key it was encrypted with, which fails
+    /// unless it was encrypted for the same offsets
+    pub fn decrypt(
+        &self,
+        segment_base: Offset,
+        base_offset: Offset,
+        batch: &EncryptedBatch,
+    ) -> Result<RecordBatch, KeyringError> {
+        let key_id = batch.key_id();
+        let cipher = self.keys.get(&key_id).context(UnknownKeySnafu { key_id })?;
+
+        let aad = EncryptedBatch::associated_data(
+            segment_base,
+            base_offset,
+            batch.compression(),
+            batch.record_count(),
+            batch.max_timestamp(),
+            key_id,
+        );
+        let data = cipher
+            .decrypt(
+                Nonce::from_slice(batch.nonce()),
+                Payload {
+                    msg: batch.ciphertext(),
+                    aad: &aad,
+                },
+            )
+            .ok()
+            .context(DecryptSnafu { key_id })?;
+
+        Ok(RecordBatch::from_parts(
+            batch.compression(),
+            batch.record_count(),
+            batch.max_timestamp(),
+            data,
+        ))
+    }
+}
+
+/// Only the key ids, the keys themselves must never end up in logs
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
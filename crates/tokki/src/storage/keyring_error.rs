@@ -0,0 +1,22 @@
+use std::{io, path::PathBuf};
+
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum KeyringError {
+    #[snafu(display("Failed to read key file {}: {source}", path.display()))]
+    ReadKeyFile { path: PathBuf, source: io::Error },
+    #[snafu(display("Key file line {line} should be a key id followed by a hex key"))]
+    KeyFileLine { line: usize },
+    #[snafu(display("Key {key_id} on line {line} is not 32 hex encoded bytes"))]
+    InvalidKey { key_id: u32, line: usize },
+    #[snafu(display("Key {key_id} appears more than once"))]
+    DuplicateKey { key_id: u32 },
+    #[snafu(display("Key file has no keys"))]
+    NoKeys,
+    #[snafu(display("Data was encrypted with key {key_id} which is not in the key file"))]
+    UnknownKey { key_id: u32 },
+    #[snafu(display("Data encrypted with key {key_id} failed authentication"))]
+    Decrypt { key_id: u32 },
+}
@@ -1,27 +1,187 @@
-use std::io;
-
+pub use compaction::{CompactionPolicy, spawn_compaction};
+pub use corrupt_records::CorruptRecordPolicy;
+pub use disk::{DiskStorage, DiskStorageConfig};
+pub use durability::DurabilityPolicy;
 pub use in_memory::InMemoryStorage;
 pub use in_memory_channel::InMemoryChannelStorage;
 pub use in_memory_lockfree::InMemoryLockFree;
-use tokki_common::{Offset, Record};
+pub use keyring::Keyring;
+pub use keyring_error::KeyringError;
+pub use record_frames::RecordFrames;
+pub use retention::{RetentionPolicy, spawn_retention};
+use std::ops::Range;
+pub use storage_error::StorageError;
+
+use snafu::{ResultExt as _, ensure};
+use tokki_common::{Compression, Offset, Record, RecordBatch, Timestamp};
 
-mod disk_future;
+use crate::storage::storage_error::{CorruptBatchSnafu, NotAtLogEndSnafu};
+
+mod compaction;
+mod corrupt_records;
+mod disk;
+mod durability;
 mod in_memory;
 mod in_memory_channel;
 mod in_memory_lockfree;
+mod keyring;
+mod keyring_error;
+mod record_frames;
+mod retention;
+mod storage_error;
+
+/// Records read at a time when scanning the log for a timestamp
+const TIMESTAMP_SCAN_RECORDS: usize = 1024;
 
 #[async_trait::async_trait]
 pub trait Storage: Send + Sync {
     /// Get the current maximum offset
-    async fn max_offset(&self) -> io::Result<Option<Offset>>;
+    async fn max_offset(&self) -> Result<Option<Offset>, StorageError>;
+
+    /// Get the offset of the first record still in the log. Everything before
+    /// it has been removed by retention.
+    async fn log_start_offset(&self) -> Result<Offset, StorageError> {
+        Ok(Offset(0))
+    }
 
     /// Put a record on the log, returning it's offset.
-    async fn put_record(&self, record: Record) -> io::Result<Offset>;
+    async fn put_record(&self, record: Record) -> Result<Offset, StorageError> {
+        let offsets = self.put_records(vec![record]).await?;
+        Ok(offsets.start)
+    }
+
+    /// Put a batch of records on the log, returning the offsets they were
+    /// given. The range is reserved atomically so concurrent batches never
+    /// interleave, and is empty when `records` is.
+    async fn put_records(&self, records: Vec<Record>) -> Result<Range<Offset>, StorageError>;
+
+    /// Put a batch of records on the log as a single unit, returning the
+    /// offsets its records were given. Engines that keep batches compressed
+    /// store it as it is, others decompress it and put its records.
+    async fn put_batch(&self, batch: RecordBatch) -> Result<Range<Offset>, StorageError> {
+        let records = batch.records().context(CorruptBatchSnafu)?;
+        self.put_records(records).await
+    }
 
-    /// Get `max_records` number off `Records` from the provided `offset`.
+    /// Put a batch whose first record belongs at `offset`, returning the
+    /// offsets its records were given. Followers use this to keep the
+    /// leader's offsets, which compaction leaves gaps in. Engines that never
+    /// have gaps fail unless the log ends at `offset`, and every engine fails
+    /// if the log already holds it.
+    async fn put_batch_at(
+        &self,
+        offset: Offset,
+        batch: RecordBatch,
+    ) -> Result<Range<Offset>, StorageError> {
+        let log_end = self.max_offset().await?.map_or(Offset(0), |max| max + 1);
+        ensure!(offset == log_end, NotAtLogEndSnafu { offset, log_end });
+        self.put_batch(batch).await
+    }
+
+    /// Wait until the record at `offset`, and everything before it, has been
+    /// flushed as required by the storage's durability policy. Writes must not
+    /// be acknowledged before this returns.
+    async fn wait_durable(&self, _offset: Offset) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Get `max_records` number off `Records` from the provided `offset`,
+    /// along with the offset to continue from. That is the offset straight
+    /// after the last record read or skipped, or `offset` itself when there
+    /// is nothing to read.
+    ///
+    /// Fails with `StorageError::OffsetOutOfRange` if `offset` is before the
+    /// start of the log, and with `StorageError::CorruptRecord` if a record
+    /// cannot be decoded unless the engine is set to skip corrupt records.
     async fn get_records(
         &self,
         offset: Offset,
         max_records: usize,
-    ) -> io::Result<(Vec<Record>, Offset)>;
+    ) -> Result<(Vec<Record>, Offset), StorageError>;
+
+    /// Get batches holding the records from `offset` onwards, as they are
+    /// stored, each with the offset of its first record, along with the
+    /// offset to continue from. Whole batches are returned so there may be
+    /// more than `max_records` records, except for a batch that starts before
+    /// `offset` which is cut down to size. A batch's records have consecutive
+    /// offsets, but there may be gaps between batches.
+    async fn get_batches(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<(Offset, RecordBatch)>, Offset), StorageError> {
+        let (records, next_offset) = self.get_records(offset, max_records).await?;
+        if records.is_empty() {
+            return Ok((Vec::new(), next_offset));
+        }
+
+        // Offsets are contiguous in engines that do not override this
+        let batch = RecordBatch::new(&records, Compression::None)?;
+        Ok((vec![(offset, batch)], next_offset))
+    }
+
+    /// Get the records from `offset` onwards as encoded frames, along with the
+    /// offset to continue from. Engines that keep their log encoded serve the
+    /// frames as they are stored, without copying them, others encode what
+    /// `get_records` returns. As with `get_batches`, whole batches are
+    /// returned so there may be more than `max_records` records.
+    async fn get_frames(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(RecordFrames, Offset), StorageError> {
+        let (records, next_offset) = self.get_records(offset, max_records).await?;
+        Ok((RecordFrames::encode(&records)?, next_offset))
+    }
+
+    /// Find the first offset, in log order, whose record has a timestamp at or
+    /// after `timestamp`, or `None` if every record is older.
+    async fn offset_for_timestamp(
+        &self,
+        timestamp: Timestamp,
+    ) -> Result<Option<Offset>, StorageError> {
+        // Engines without a time index scan the whole log, relying on their
+        // offsets being contiguous
+        let mut offset = self.log_start_offset().await?;
+        loop {
+            let (records, _) = self.get_records(offset, TIMESTAMP_SCAN_RECORDS).await?;
+            if records.is_empty() {
+                return Ok(None);
+            }
+
+            if let Some(idx) = records
+                .iter()
+                .position(|record| record.timestamp() >= timestamp)
+            {
+                return Ok(Some(offset + idx));
+            }
+            offset += records.len();
+        }
+    }
+
+    /// Remove every record after `offset`, or the whole log when it is
+    /// `None`, so the next put carries on straight after it. Followers use
+    /// this to drop records a new leader never had. Records already removed
+    /// from the start of the log stay removed, so the log never ends before
+    /// its start offset.
+    async fn truncate_after(&self, offset: Option<Offset>) -> Result<(), StorageError>;
+
+    /// Remove the whole log and carry on from `offset`, which becomes the log
+    /// start offset. Followers use this when the leader has already removed
+    /// the records they would copy next.
+    async fn restart_at(&self, offset: Offset) -> Result<(), StorageError>;
+
+    /// Remove records from the start of the log that fall outside `policy`,
+    /// returning the new log start offset. Engines that cannot reclaim space
+    /// keep everything.
+    async fn enforce_retention(&self, _policy: &RetentionPolicy) -> Result<Offset, StorageError> {
+        self.log_start_offset().await
+    }
+
+    /// Remove records that have been superseded by a later record with the
+    /// same key, and tombstones older than the policy allows. Engines that
+    /// cannot reclaim space keep everything.
+    async fn compact(&self, _policy: &CompactionPolicy) -> Result<(), StorageError> {
+        Ok(())
+    }
 }
@@ -0,0 +1,80 @@
+use std::io;
+
+use bytes::Bytes;
+use tokki_common::{Record, RecordBatch};
+
+/// Encoded frames on their way to a reader, as chunks to be written out back
+/// to back. Engines that keep their log encoded hand out slices of it, so
+/// serving frames costs no copies or allocations per record.
+///
+/// Batches are never encrypted, but may be compressed.
+#[derive(Debug, Clone, Default)]
+pub struct RecordFrames {
+    chunks: Vec<Bytes>,
+    record_count: usize,
+}
+
+impl RecordFrames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encode `records` into a single chunk, for engines that keep them decoded
+    pub fn encode(records: &[Record]) -> io::Result<Self> {
+        let mut buf = vec![0; records.iter().map(Record::serialized_len).sum()];
+        let mut position = 0;
+        for record in records {
+            position += record.to_bytes(&mut buf[position..])?;
+        }
+        buf.truncate(position);
+
+        let mut frames = Self::new();
+        frames.push(buf.into(), records.len());
+        Ok(frames)
+    }
+
+    /// Add a chunk of whole frames holding `record_count` records between them
+    pub fn push(&mut self, chunk: Bytes, record_count: usize) {
+        if !chunk.is_empty() {
+            self.chunks.push(chunk);
+        }
+        self.record_count += record_count;
+    }
+
+    /// Encode `batch` as a frame of its own and add it
+    pub fn push_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        let mut buf = vec![0; batch.serialized_len()];
+        let len = batch.to_bytes(&mut buf)?;
+        buf.truncate(len);
+
+        self.push(buf.into(), batch.record_count());
+        Ok(())
+    }
+
+    /// Add everything in `other` after the frames already here
+    pub fn extend(&mut self, other: RecordFrames) {
+        self.chunks.extend(other.chunks);
+        self.record_count += other.record_count;
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.record_count
+    }
+
+    /// Total length of the frames in bytes
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(Bytes::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn chunks(&self) -> &[Bytes] {
+        &self.chunks
+    }
+
+    pub fn into_chunks(self) -> Vec<Bytes> {
+        self.chunks
+    }
+}
@@ -0,0 +1,50 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::task::JoinHandle;
+
+use crate::storage::Storage;
+
+/// Limits on how much of the log is kept, anything beyond them is removed
+/// from the start of the log. Unset limits are not enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<u64>,
+    pub max_records: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn is_unbounded(&self) -> bool {
+        self.max_age.is_none() && self.max_bytes.is_none() && self.max_records.is_none()
+    }
+}
+
+/// Periodically enforce `policy` on the storage of a partition of `topic` in the background
+pub fn spawn_retention(
+    topic: String,
+    partition: u32,
+    storage: Arc<dyn Storage>,
+    policy: RetentionPolicy,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            match storage.enforce_retention(&policy).await {
+                Ok(log_start_offset) => {
+                    metrics::gauge!(
+                        "log_start_offset",
+                        "topic" => topic.clone(),
+                        "partition" => partition.to_string()
+                    )
+                    .set(log_start_offset.0 as f64);
+                }
+                Err(e) => {
+                    tracing::error!(topic, partition, "Failed to enforce retention: {}", e);
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,37 @@
+use std::io;
+
+use snafu::Snafu;
+use tokki_common::{DecodeError, Offset};
+
+use crate::storage::KeyringError;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum StorageError {
+    #[snafu(context(false), display("I/O error: {source}"))]
+    Io { source: io::Error },
+    #[snafu(display(
+        "Offset {} is before the start of the log at {}",
+        offset.0,
+        log_start_offset.0
+    ))]
+    OffsetOutOfRange {
+        offset: Offset,
+        log_start_offset: Offset,
+    },
+    #[snafu(display("Record at offset {} is corrupt: {source}", offset.0))]
+    CorruptRecord { offset: Offset, source: DecodeError },
+    #[snafu(display("Records from offset {} could not be decrypted: {source}", offset.0))]
+    Encryption {
+        offset: Offset,
+        source: KeyringError,
+    },
+    #[snafu(display(
+        "Cannot put records at offset {} as the log ends at {}",
+        offset.0,
+        log_end.0
+    ))]
+    NotAtLogEnd { offset: Offset, log_end: Offset },
+    #[snafu(display("Record batch is corrupt: {source}"))]
+    CorruptBatch { source: DecodeError },
+}
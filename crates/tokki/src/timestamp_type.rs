@@ -0,0 +1,23 @@
+use tokki_common::{Record, Timestamp};
+
+/// Which time the leader keeps in the timestamp of records it appends
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampType {
+    /// Keep the timestamp the producer set, only filling in records without one
+    #[default]
+    CreateTime,
+    /// Overwrite every timestamp with the time the leader appended the record
+    LogAppendTime,
+}
+
+impl TimestampType {
+    /// Stamp a batch of records about to be appended at `now`
+    pub fn apply(&self, records: &mut [Record], now: Timestamp) {
+        for record in records {
+            let keep = *self == TimestampType::CreateTime && record.timestamp().is_set();
+            if !keep {
+                record.set_timestamp(now);
+            }
+        }
+    }
+}
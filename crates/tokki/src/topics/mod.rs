@@ -0,0 +1,518 @@
+mod partition;
+mod topic_error;
+mod topic_storage;
+
+pub use partition::Partition;
+pub use topic_error::TopicError;
+pub use topic_storage::TopicStorage;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use snafu::{OptionExt as _, ResultExt as _, ensure};
+use tokio::task::JoinHandle;
+use tokki_api::{
+    cluster::ClusterMembers,
+    topics::{TopicConfig, TopicDescription},
+};
+use tokki_common::{Offset, Record};
+
+use crate::{
+    replication::{InSyncConfig, LeaderEpochs},
+    state_file::write_atomically,
+    storage::{CompactionPolicy, RetentionPolicy, spawn_compaction, spawn_retention},
+    topics::topic_error::{
+        AlreadyExistsSnafu, InvalidNameSnafu, InvalidPartitionCountSnafu, NotFoundSnafu,
+        OpenLeaderEpochsSnafu, OpenStorageSnafu, ParseMetadataSnafu, PartitionNotFoundSnafu,
+        ReadMetadataSnafu, ReadStorageSnafu, RemoveDeletedSnafu, RemoveStorageSnafu,
+        ReservedNameSnafu, WriteMetadataSnafu,
+    },
+};
+
+/// File in the metadata directory listing every topic and its config
+const METADATA_FILE: &str = "topics.json";
+const MAX_NAME_LEN: usize = 249;
+const MAX_PARTITIONS: u32 = 1024;
+/// Start of the names of topics the server keeps for itself
+const INTERNAL_PREFIX: &str = "__";
+
+#[derive(Debug, Clone)]
+pub struct TopicsConfig {
+    storage: TopicStorage,
+    metadata_dir: Option<PathBuf>,
+    default_config: TopicConfig,
+    required_replicas: usize,
+    in_sync: InSyncConfig,
+    retention_check: Duration,
+    compaction_check: Duration,
+}
+
+impl TopicsConfig {
+    pub fn new(storage: TopicStorage) -> Self {
+        Self {
+            storage,
+            metadata_dir: None,
+            default_config: TopicConfig::default(),
+            required_replicas: 0,
+            in_sync: InSyncConfig::default(),
+            retention_check: Duration::from_secs(60),
+            compaction_check: Duration::from_secs(60),
+        }
+    }
+
+    /// Keep the list of topics in this directory so they survive a restart.
+    /// Without one, topics only last as long as the process.
+    pub fn with_metadata_dir(mut self, metadata_dir: impl Into<PathBuf>) -> Self {
+        self.metadata_dir = Some(metadata_dir.into());
+        self
+    }
+
+    /// Settings for topics created without their own
+    pub fn with_default_config(mut self, default_config: TopicConfig) -> Self {
+        self.default_config = default_config;
+        self
+    }
+
+    /// Followers that must have a record before a put on any partition is acknowledged
+    pub fn with_required_replicas(mut self, required_replicas: usize) -> Self {
+        self.required_replicas = required_replicas;
+        self
+    }
+
+    /// Which followers count towards the required replicas on each partition
+    pub fn with_in_sync(mut self, in_sync: InSyncConfig) -> Self {
+        self.in_sync = in_sync;
+        self
+    }
+
+    /// How often each topic's retention policy is enforced
+    pub fn with_retention_check(mut self, retention_check: Duration) -> Self {
+        self.retention_check = retention_check;
+        self
+    }
+
+    /// How often topics with compaction turned on are compacted
+    pub fn with_compaction_check(mut self, compaction_check: Duration) -> Self {
+        self.compaction_check = compaction_check;
+        self
+    }
+}
+
+/// A named set of partitions along with everything kept for them
+pub struct Topic {
+    description: TopicDescription,
+    partitions: Vec<Partition>,
+    /// Retention and compaction running in the background, stopped with the topic
+    tasks: Vec<JoinHandle<()>>,
+    /// Where the data of a deleted topic was moved, removed once the last
+    /// request using the topic is done with it
+    tombstone: Mutex<Option<PathBuf>>,
+}
+
+impl Topic {
+    async fn open(
+        description: TopicDescription,
+        config: &TopicsConfig,
+    ) -> Result<Self, TopicError> {
+        let name = &description.name;
+        let retention = RetentionPolicy::from(&description.config);
+        let compaction = compaction_policy(&description.config);
+
+        let mut partitions = Vec::new();
+        let mut tasks = Vec::new();
+        for partition in 0..description.config.partition_count() {
+            let storage = config
+                .storage
+                .open(name, partition)
+                .await
+                .context(OpenStorageSnafu { name, partition })?;
+
+            if !retention.is_unbounded() {
+                tasks.push(spawn_retention(
+                    name.clone(),
+                    partition,
+                    storage.clone(),
+                    retention,
+                    config.retention_check,
+                ));
+            }
+            if let Some(compaction) = compaction {
+                tasks.push(spawn_compaction(
+                    storage.clone(),
+                    compaction,
+                    config.compaction_check,
+                ));
+            }
+
+            let log_end = storage
+                .max_offset()
+                .await
+                .context(ReadStorageSnafu { name, partition })?
+                .map_or(Offset(0), |max_offset| max_offset + 1);
+            let leader_epochs =
+                LeaderEpochs::open(config.storage.leader_epochs_path(name, partition), log_end)
+                    .context(OpenLeaderEpochsSnafu { name, partition })?;
+            partitions.push(Partition::new(
+                partition,
+                storage,
+                leader_epochs,
+                config.required_replicas,
+                config.in_sync,
+                log_end,
+            ));
+        }
+
+        tracing::info!(topic = name, id = description.id, config = ?description.config, "Opened topic");
+
+        Ok(Self {
+            description,
+            partitions,
+            tasks,
+            tombstone: Mutex::new(None),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.description.name
+    }
+
+    pub fn description(&self) -> &TopicDescription {
+        &self.description
+    }
+
+    pub fn partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+
+    fn set_members(&self, members: &ClusterMembers) {
+        for partition in &self.partitions {
+            partition
+                .replication()
+                .lock()
+                .expect("not poisoned")
+                .set_members(members.clone());
+        }
+    }
+
+    pub fn partition(&self, partition: u32) -> Result<&Partition, TopicError> {
+        self.partitions
+            .get(partition as usize)
+            .context(PartitionNotFoundSnafu {
+                name: self.name(),
+                partition,
+            })
+    }
+
+    /// The partition `record` goes on when the producer leaves it to the key,
+    /// so every record with the same key lands on the same partition
+    pub fn partition_for(&self, record: &Record) -> u32 {
+        (record.hash_key() % self.partitions.len() as u64) as u32
+    }
+
+    /// Split `records` up by the partition each goes on, in partition order,
+    /// keeping the order they came in within each partition. Every record goes
+    /// on `partition` when one is given.
+    pub fn route(
+        &self,
+        records: Vec<Record>,
+        partition: Option<u32>,
+    ) -> Result<Vec<(&Partition, Vec<Record>)>, TopicError> {
+        if let Some(partition) = partition {
+            return Ok(vec![(self.partition(partition)?, records)]);
+        }
+
+        let mut routed: BTreeMap<u32, Vec<Record>> = BTreeMap::new();
+        for record in records {
+            routed
+                .entry(self.partition_for(&record))
+                .or_default()
+                .push(record);
+        }
+        routed
+            .into_iter()
+            .map(|(partition, records)| Ok((self.partition(partition)?, records)))
+            .collect()
+    }
+}
+
+impl Drop for Topic {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+
+        let Some(tombstone) = self.tombstone.get_mut().expect("not poisoned").take() else {
+            return;
+        };
+        let remove = move || {
+            if let Err(e) = fs::remove_dir_all(&tombstone) {
+                tracing::warn!(path = %tombstone.display(), "Failed to remove deleted topic: {e}");
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => _ = handle.spawn_blocking(remove),
+            Err(_) => remove(),
+        }
+    }
+}
+
+/// Every topic on this node, by name
+pub struct Topics {
+    config: TopicsConfig,
+    topics: RwLock<HashMap<String, Arc<Topic>>>,
+    /// Followers counted towards the required replicas of every partition
+    members: RwLock<ClusterMembers>,
+    /// Held while creating or deleting topics, so each change is persisted
+    /// before the next one starts
+    admin: tokio::sync::Mutex<()>,
+}
+
+impl Topics {
+    /// Open every topic listed in the metadata directory
+    pub async fn open(config: TopicsConfig) -> Result<Self, TopicError> {
+        if let Some(metadata_dir) = &config.metadata_dir {
+            fs::create_dir_all(metadata_dir).context(WriteMetadataSnafu {
+                path: metadata_dir.clone(),
+            })?;
+        }
+
+        config
+            .storage
+            .remove_tombstones()
+            .context(RemoveDeletedSnafu)?;
+
+        let mut topics = HashMap::new();
+        if let Some(path) = config.metadata_path() {
+            let descriptions: Vec<TopicDescription> = match fs::read(&path) {
+                Ok(buf) => serde_json::from_slice(&buf).context(ParseMetadataSnafu { path })?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(source) => return Err(source).context(ReadMetadataSnafu { path }),
+            };
+
+            for description in descriptions {
+                let topic = Topic::open(description, &config).await?;
+                topics.insert(topic.name().to_string(), Arc::new(topic));
+            }
+        }
+
+        Ok(Self {
+            config,
+            topics: RwLock::new(topics),
+            members: Default::default(),
+            admin: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<Topic>, TopicError> {
+        let guard = self.topics.read().expect("not poisoned");
+        guard.get(name).cloned().context(NotFoundSnafu { name })
+    }
+
+    /// Every topic, ordered by name
+    pub fn all(&self) -> Vec<Arc<Topic>> {
+        let guard = self.topics.read().expect("not poisoned");
+        let mut topics: Vec<_> = guard.values().cloned().collect();
+        topics.sort_by(|a, b| a.name().cmp(b.name()));
+        topics
+    }
+
+    pub fn list(&self) -> Vec<TopicDescription> {
+        self.all()
+            .iter()
+            .map(|topic| topic.description().clone())
+            .collect()
+    }
+
+    pub fn required_replicas(&self) -> usize {
+        self.config.required_replicas
+    }
+
+    pub fn members(&self) -> ClusterMembers {
+        self.members.read().expect("not poisoned").clone()
+    }
+
+    /// Count copies on `members` for every partition, including those of
+    /// topics created later
+    pub fn set_members(&self, members: ClusterMembers) {
+        // Held throughout so a topic being created cannot miss the change
+        let mut guard = self.members.write().expect("not poisoned");
+        for topic in self.all() {
+            topic.set_members(&members);
+        }
+        *guard = members;
+    }
+
+    /// Create a new, empty topic, filling in any settings `config` leaves
+    /// unset from the defaults
+    pub async fn create(&self, name: &str, config: TopicConfig) -> Result<Arc<Topic>, TopicError> {
+        ensure!(!is_internal(name), ReservedNameSnafu { name });
+        let config = config.with_defaults(&self.config.default_config);
+        validate(name, &config)?;
+
+        let _admin = self.admin.lock().await;
+        ensure!(self.get(name).is_err(), AlreadyExistsSnafu { name });
+
+        let description = TopicDescription::new(name, rand::random(), config);
+        self.insert(description).await
+    }
+
+    /// Get one of the topics the server keeps for itself, creating it with
+    /// `config` if it does not exist yet
+    pub(crate) async fn get_or_create_internal(
+        &self,
+        name: &str,
+        config: TopicConfig,
+    ) -> Result<Arc<Topic>, TopicError> {
+        debug_assert!(is_internal(name));
+        validate(name, &config)?;
+
+        let _admin = self.admin.lock().await;
+        if let Ok(topic) = self.get(name) {
+            return Ok(topic);
+        }
+
+        let description = TopicDescription::new(name, rand::random(), config);
+        self.insert(description).await
+    }
+
+    /// Make sure this node has the topic as described, replacing a topic of
+    /// the same name that was created separately. Followers use this to
+    /// mirror the leader's topics.
+    pub async fn ensure(&self, description: TopicDescription) -> Result<Arc<Topic>, TopicError> {
+        let name = &description.name;
+        validate(name, &description.config)?;
+
+        let _admin = self.admin.lock().await;
+        if let Ok(topic) = self.get(name) {
+            if topic.description().id == description.id {
+                return Ok(topic);
+            }
+            self.remove(name)?;
+        }
+
+        self.insert(description).await
+    }
+
+    /// Delete a topic and everything stored for it
+    pub async fn delete(&self, name: &str) -> Result<TopicDescription, TopicError> {
+        ensure!(!is_internal(name), ReservedNameSnafu { name });
+        self.delete_any(name).await
+    }
+
+    /// Delete a topic, internal or not. Followers use this to drop topics the
+    /// leader no longer has.
+    pub(crate) async fn delete_any(&self, name: &str) -> Result<TopicDescription, TopicError> {
+        let _admin = self.admin.lock().await;
+        let topic = self.remove(name)?;
+        self.persist()?;
+        Ok(topic.description().clone())
+    }
+
+    async fn insert(&self, description: TopicDescription) -> Result<Arc<Topic>, TopicError> {
+        let topic = Arc::new(Topic::open(description, &self.config).await?);
+        {
+            let members = self.members.read().expect("not poisoned");
+            topic.set_members(&members);
+            let mut guard = self.topics.write().expect("not poisoned");
+            guard.insert(topic.name().to_string(), topic.clone());
+        }
+        self.persist()?;
+
+        tracing::info!(topic = topic.name(), "Created topic");
+        Ok(topic)
+    }
+
+    /// Take a topic out of the list. Its data is moved aside straight away but
+    /// only removed once requests still holding the topic are done with it.
+    fn remove(&self, name: &str) -> Result<Arc<Topic>, TopicError> {
+        let topic = self.get(name)?;
+        let tombstone = self
+            .config
+            .storage
+            .tombstone(name, topic.description().id)
+            .context(RemoveStorageSnafu { name })?;
+        *topic.tombstone.lock().expect("not poisoned") = tombstone;
+        self.topics.write().expect("not poisoned").remove(name);
+
+        tracing::info!(topic = name, "Deleted topic");
+        Ok(topic)
+    }
+
+    /// Write out the list of topics, replacing the old one in a single rename
+    fn persist(&self) -> Result<(), TopicError> {
+        let Some(path) = self.config.metadata_path() else {
+            return Ok(());
+        };
+
+        let buf = serde_json::to_vec_pretty(&self.list()).expect("Topics serialize");
+        write_atomically(&path, &buf).context(WriteMetadataSnafu { path })
+    }
+}
+
+impl TopicsConfig {
+    fn metadata_path(&self) -> Option<PathBuf> {
+        self.metadata_dir
+            .as_ref()
+            .map(|metadata_dir| metadata_dir.join(METADATA_FILE))
+    }
+}
+
+fn validate(name: &str, config: &TopicConfig) -> Result<(), TopicError> {
+    ensure!(is_valid_name(name), InvalidNameSnafu { name });
+
+    let partitions = config.partition_count();
+    ensure!(
+        (1..=MAX_PARTITIONS).contains(&partitions),
+        InvalidPartitionCountSnafu {
+            partitions,
+            max: MAX_PARTITIONS
+        }
+    );
+
+    Ok(())
+}
+
+/// Topics the server keeps for itself, which cannot be created or deleted through the API
+fn is_internal(name: &str) -> bool {
+    name.starts_with(INTERNAL_PREFIX)
+}
+
+/// Names end up in URL paths and directory names, so only allow characters
+/// that are safe in both
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name != "."
+        && name != ".."
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+impl From<&TopicConfig> for RetentionPolicy {
+    fn from(config: &TopicConfig) -> Self {
+        RetentionPolicy {
+            max_age: config.retention_max_age_secs.map(Duration::from_secs),
+            max_bytes: config.retention_max_bytes,
+            max_records: config.retention_max_records,
+        }
+    }
+}
+
+/// How the topic is compacted, if it is at all
+fn compaction_policy(config: &TopicConfig) -> Option<CompactionPolicy> {
+    if config.compaction != Some(true) {
+        return None;
+    }
+
+    let mut policy = CompactionPolicy::default();
+    if let Some(secs) = config.tombstone_retention_secs {
+        policy.tombstone_retention = Duration::from_secs(secs);
+    }
+    Some(policy)
+}
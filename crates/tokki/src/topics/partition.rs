@@ -0,0 +1,191 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+use tokki_common::{Offset, Record};
+
+use crate::{
+    replication::{InSyncConfig, LeaderEpochs, Replication},
+    storage::{RecordFrames, Storage, StorageError},
+};
+
+/// One of a topic's logs. Each partition has offsets of its own, and records
+/// are only ordered relative to others on the same partition.
+pub struct Partition {
+    index: u32,
+    storage: Arc<dyn Storage>,
+    leader_epochs: Mutex<LeaderEpochs>,
+    replication: Mutex<Replication>,
+    /// Bumped every time records are removed from the end of the log
+    truncations: AtomicU64,
+}
+
+impl Partition {
+    pub(crate) fn new(
+        index: u32,
+        storage: Arc<dyn Storage>,
+        leader_epochs: LeaderEpochs,
+        required_replicas: usize,
+        in_sync: InSyncConfig,
+        log_end: Offset,
+    ) -> Self {
+        Self {
+            index,
+            storage,
+            leader_epochs: Mutex::new(leader_epochs),
+            replication: Mutex::new(
+                Replication::new(required_replicas, log_end).with_in_sync(in_sync),
+            ),
+            truncations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+
+    /// Offset after the last record in the log, committed or not
+    pub async fn log_end(&self) -> Result<Offset, StorageError> {
+        let max_offset = self.storage.max_offset().await?;
+        Ok(max_offset.map_or(Offset(0), |max_offset| max_offset + 1))
+    }
+
+    /// Which leader epoch each run of the log was written in
+    pub fn leader_epochs(&self) -> &Mutex<LeaderEpochs> {
+        &self.leader_epochs
+    }
+
+    /// Start `epoch` at the end of the log, if the log is not already in it.
+    /// Leaders do this before putting records in a new epoch.
+    pub async fn begin_epoch(&self, epoch: u64) -> Result<(), StorageError> {
+        let latest = self.leader_epochs.lock().expect("not poisoned").latest();
+        if latest.is_some_and(|latest| latest.epoch >= epoch) {
+            return Ok(());
+        }
+
+        let log_end = self.log_end().await?;
+        self.leader_epochs
+            .lock()
+            .expect("not poisoned")
+            .assign(epoch, log_end)?;
+        Ok(())
+    }
+
+    /// Remove every record from `end` onwards, along with their epochs. The
+    /// high watermark is pulled back too, though on a follower it only ever
+    /// has to be when the log lost records it was told were committed.
+    pub async fn truncate(&self, end: Offset) -> Result<(), StorageError> {
+        self.storage
+            .truncate_after(end.0.checked_sub(1).map(Offset))
+            .await?;
+        self.truncations.fetch_add(1, Ordering::Release);
+        self.leader_epochs
+            .lock()
+            .expect("not poisoned")
+            .truncate(end)?;
+
+        let mut replication = self.replication.lock().expect("not poisoned");
+        let high_watermark = replication.high_watermark().min(end);
+        replication.set_high_watermark(high_watermark);
+        Ok(())
+    }
+
+    /// Remove the whole log along with its epochs, and carry on from
+    /// `offset`. Followers do this when the leader has already removed the
+    /// records they would copy next. The high watermark moves to `offset`
+    /// too, as nothing before it is left to read.
+    pub async fn restart_at(&self, offset: Offset) -> Result<(), StorageError> {
+        self.storage.restart_at(offset).await?;
+        self.truncations.fetch_add(1, Ordering::Release);
+        self.replication
+            .lock()
+            .expect("not poisoned")
+            .set_high_watermark(offset);
+        self.leader_epochs
+            .lock()
+            .expect("not poisoned")
+            .truncate(Offset(0))?;
+        Ok(())
+    }
+
+    /// How many times the log has been truncated or restarted. Anything built
+    /// from the log while this stayed the same only holds records still in it,
+    /// as it is bumped once they are gone.
+    pub fn truncations(&self) -> u64 {
+        self.truncations.load(Ordering::Acquire)
+    }
+
+    /// Followers' progress through this partition, and puts waiting on them
+    pub fn replication(&self) -> &Mutex<Replication> {
+        &self.replication
+    }
+
+    /// Offset after the last committed record
+    pub fn high_watermark(&self) -> Offset {
+        self.replication
+            .lock()
+            .expect("not poisoned")
+            .high_watermark()
+    }
+
+    /// Get up to `max_records` committed records from `offset`, along with
+    /// the offset to continue from. Records past the high watermark are left
+    /// for a later read, once they are committed.
+    pub async fn get_committed_records(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(Vec<Record>, Offset), StorageError> {
+        let high_watermark = self.high_watermark();
+        if offset >= high_watermark {
+            return Ok((Vec::new(), offset));
+        }
+
+        let max_records = max_records.min((high_watermark - offset).0);
+        let (records, next_offset) = self.storage.get_records(offset, max_records).await?;
+        if next_offset <= high_watermark {
+            return Ok((records, next_offset));
+        }
+
+        // Compaction left gaps in the offsets, so as many records ran past the
+        // high watermark. Read them one at a time to stop where it is.
+        let mut records = Vec::with_capacity(max_records);
+        let mut next_offset = offset;
+        while records.len() < max_records {
+            let (read, after) = self.storage.get_records(next_offset, 1).await?;
+            if after > high_watermark || after == next_offset {
+                break;
+            }
+            records.extend(read);
+            next_offset = after;
+        }
+        Ok((records, next_offset))
+    }
+
+    /// Get committed records from `offset` as frames, along with the offset to
+    /// continue from. Frames are served as they are stored unless they run
+    /// past the high watermark, in which case the committed records are encoded.
+    pub async fn get_committed_frames(
+        &self,
+        offset: Offset,
+        max_records: usize,
+    ) -> Result<(RecordFrames, Offset), StorageError> {
+        let high_watermark = self.high_watermark();
+        if offset >= high_watermark {
+            return Ok((RecordFrames::new(), offset));
+        }
+
+        let (frames, next_offset) = self.storage.get_frames(offset, max_records).await?;
+        if next_offset <= high_watermark {
+            return Ok((frames, next_offset));
+        }
+
+        let (records, next_offset) = self.get_committed_records(offset, max_records).await?;
+        Ok((RecordFrames::encode(&records)?, next_offset))
+    }
+}
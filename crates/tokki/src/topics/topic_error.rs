@@ -0,0 +1,59 @@
+use std::{io, path::PathBuf};
+
+use snafu::Snafu;
+
+use crate::storage::StorageError;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum TopicError {
+    #[snafu(display(
+        "Invalid topic name {name:?}, names are up to 249 ASCII letters, digits, '.', '_' or '-'"
+    ))]
+    InvalidName { name: String },
+    #[snafu(display(
+        "Topic name {name:?} is reserved, names starting with \"__\" are kept for internal topics"
+    ))]
+    ReservedName { name: String },
+    #[snafu(display("Invalid partition count {partitions}, topics have 1 to {max} partitions"))]
+    InvalidPartitionCount { partitions: u32, max: u32 },
+    #[snafu(display("Topic {name} already exists"))]
+    AlreadyExists { name: String },
+    #[snafu(display("Topic {name} does not exist"))]
+    NotFound { name: String },
+    #[snafu(display("Topic {name} has no partition {partition}"))]
+    PartitionNotFound { name: String, partition: u32 },
+    #[snafu(display("Failed to open storage for partition {partition} of topic {name}: {source}"))]
+    OpenStorage {
+        name: String,
+        partition: u32,
+        source: io::Error,
+    },
+    #[snafu(display("Failed to read storage for partition {partition} of topic {name}: {source}"))]
+    ReadStorage {
+        name: String,
+        partition: u32,
+        source: StorageError,
+    },
+    #[snafu(display(
+        "Failed to open leader epochs for partition {partition} of topic {name}: {source}"
+    ))]
+    OpenLeaderEpochs {
+        name: String,
+        partition: u32,
+        source: io::Error,
+    },
+    #[snafu(display("Failed to remove storage for topic {name}: {source}"))]
+    RemoveStorage { name: String, source: io::Error },
+    #[snafu(display("Failed to remove storage of deleted topics: {source}"))]
+    RemoveDeleted { source: io::Error },
+    #[snafu(display("Failed to read topic metadata from {}: {source}", path.display()))]
+    ReadMetadata { path: PathBuf, source: io::Error },
+    #[snafu(display("Failed to parse topic metadata from {}: {source}", path.display()))]
+    ParseMetadata {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[snafu(display("Failed to write topic metadata to {}: {source}", path.display()))]
+    WriteMetadata { path: PathBuf, source: io::Error },
+}
@@ -0,0 +1,108 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::storage::{
+    CorruptRecordPolicy, DiskStorage, DiskStorageConfig, InMemoryChannelStorage, InMemoryLockFree,
+    InMemoryStorage, Storage,
+};
+
+const LEADER_EPOCHS_FILE: &str = "leader-epochs.json";
+/// Ends the names of directories of deleted topics that are still to be
+/// removed. They also hold a `~`, which topic names cannot.
+const TOMBSTONE_EXTENSION: &str = "deleted";
+
+/// The engine topics keep their logs in, every topic gets a log of its own
+#[derive(Debug, Clone)]
+pub enum TopicStorage {
+    InMemoryMutex,
+    InMemoryChannel,
+    InMemoryLockFree {
+        corrupt_records: CorruptRecordPolicy,
+    },
+    /// Each topic is kept in a directory named after it, inside the config's
+    /// data directory, with each partition's log in a directory of its own
+    Disk(DiskStorageConfig),
+}
+
+impl TopicStorage {
+    /// Open the log for a partition of `topic`, picking up anything already stored for it
+    pub(crate) async fn open(&self, topic: &str, partition: u32) -> io::Result<Arc<dyn Storage>> {
+        Ok(match self {
+            TopicStorage::InMemoryMutex => Arc::new(InMemoryStorage::default()),
+            TopicStorage::InMemoryChannel => Arc::new(InMemoryChannelStorage::new().await?),
+            TopicStorage::InMemoryLockFree { corrupt_records } => {
+                Arc::new(InMemoryLockFree::new().with_corrupt_records(*corrupt_records))
+            }
+            TopicStorage::Disk(config) => {
+                let data_dir = config.data_dir().join(topic).join(partition.to_string());
+                let config = config.clone().with_data_dir(data_dir);
+                Arc::new(DiskStorage::open(config)?)
+            }
+        })
+    }
+
+    /// Where the leader epochs of a partition of `topic` are kept, alongside
+    /// its log. Epochs of logs held in memory are lost with them.
+    pub(crate) fn leader_epochs_path(&self, topic: &str, partition: u32) -> Option<PathBuf> {
+        match self {
+            TopicStorage::Disk(config) => Some(
+                config
+                    .data_dir()
+                    .join(topic)
+                    .join(partition.to_string())
+                    .join(LEADER_EPOCHS_FILE),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Move everything stored for `topic` aside, so a topic created under the
+    /// same name starts out empty while the old data is still in use. Returns
+    /// the directory it was moved to, if there was anything stored.
+    pub(crate) fn tombstone(&self, topic: &str, id: u64) -> io::Result<Option<PathBuf>> {
+        let TopicStorage::Disk(config) = self else {
+            return Ok(None);
+        };
+
+        let tombstone = config
+            .data_dir()
+            .join(format!("{topic}~{id}.{TOMBSTONE_EXTENSION}"));
+        match fs::rename(config.data_dir().join(topic), &tombstone) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            result => result?,
+        }
+        // Synced, as the topic would come back with its data after a crash otherwise
+        fs::File::open(config.data_dir())?.sync_all()?;
+        Ok(Some(tombstone))
+    }
+
+    /// Remove what is left of topics deleted before a crash
+    pub(crate) fn remove_tombstones(&self) -> io::Result<()> {
+        let TopicStorage::Disk(config) = self else {
+            return Ok(());
+        };
+
+        let entries = match fs::read_dir(config.data_dir()) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            result => result?,
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if is_tombstone(&path) {
+                fs::remove_dir_all(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_tombstone(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == TOMBSTONE_EXTENSION)
+        && path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().contains('~'))
+}
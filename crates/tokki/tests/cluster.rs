@@ -0,0 +1,966 @@
+mod common;
+
+use std::{
+    fs::File,
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::net::TcpListener;
+use tokki::{
+    app_state::AppState,
+    cluster::{ClusterMembership, MembershipConfig},
+    replication::InSyncConfig,
+    storage::{CompactionPolicy, DurabilityPolicy, RetentionPolicy, Storage},
+    topics::{TopicStorage, Topics, TopicsConfig},
+};
+use tokki_api::{
+    ClientError, TokkiClient,
+    cluster::{ClusterMembers, MemberRequest},
+    clustering::LeaderEpoch,
+    get_records::{GetRecordsRequest, IsolationLevel},
+    group_member::GroupMember,
+    groups::{
+        Assignment, AssignmentStrategy, CommitOffsetsRequest, GetCommittedOffsetsRequest,
+        GetGroupLagRequest, JoinGroupRequest, PartitionOffset,
+    },
+    put_record::PutRecordsRequest,
+    topics::{CreateTopicRequest, TopicConfig},
+};
+use tokki_common::{Offset, Record, hmac::HmacForm};
+use url::Url;
+
+use crate::common::{TOKEN, bind, disk_config, eventually, records, serve, topics, url};
+
+/// Members counting the followers reached on `followers`
+fn membership(followers: &[SocketAddr]) -> Arc<ClusterMembership> {
+    let members = followers.iter().copied().map(url).collect();
+    Arc::new(ClusterMembership::open(MembershipConfig::new(members)).unwrap())
+}
+
+/// Start a leader that waits for one follower, and that follower
+async fn start_cluster() -> (TokkiClient, TokkiClient) {
+    start_cluster_requiring(1).await
+}
+
+/// Start a leader that waits for `required_replicas` followers, and one follower
+async fn start_cluster_requiring(required_replicas: usize) -> (TokkiClient, TokkiClient) {
+    let (listener, addr) = bind().await;
+    let (follower_listener, follower_addr) = bind().await;
+    let leader_state = AppState::builder()
+        .leader()
+        .with_token(TOKEN)
+        .with_topics(topics(required_replicas).await)
+        .with_advertised_url(url(addr))
+        .with_membership(membership(&[follower_addr]))
+        .build();
+    let leader = serve(listener, leader_state).0;
+
+    let follower = start_follower(follower_listener, &leader).await;
+
+    (leader, follower)
+}
+
+/// Start a follower of `leader`, known to it by the address it listens on
+async fn start_follower(listener: TcpListener, leader: &TokkiClient) -> TokkiClient {
+    let follower_state = AppState::builder()
+        .follower()
+        .with_socket_addr(listener.local_addr().unwrap())
+        .with_token(TOKEN)
+        .with_topics(topics(0).await)
+        .with_leader(leader.base_url().clone())
+        .build();
+    serve(listener, follower_state).0
+}
+
+/// Wait for the member's next rebalance
+async fn changed(member: &mut GroupMember) -> Assignment {
+    tokio::time::timeout(Duration::from_secs(5), member.changed())
+        .await
+        .expect("Rebalanced in time")
+}
+
+#[tokio::test]
+async fn followers_mirror_the_leaders_topics() {
+    let (leader, follower) = start_cluster().await;
+
+    // Created through the follower, which forwards to the leader
+    let created = follower
+        .create_topic(CreateTopicRequest::new("events"))
+        .await
+        .unwrap();
+    assert!(matches!(
+        leader.create_topic(CreateTopicRequest::new("events")).await,
+        Err(ClientError::Conflict { .. })
+    ));
+
+    // Acknowledged once the follower has the record
+    let records = vec![Record::new("key", "1"), Record::new("key", "2")];
+    leader
+        .put_record(PutRecordsRequest::new("events", records))
+        .await
+        .unwrap();
+
+    let listed = follower.list_topics().await.unwrap();
+    assert_eq!(listed.topics, [created]);
+    // Readable on the follower once it hears the records are committed
+    let read = eventually(|| async {
+        let read = follower
+            .get_records(GetRecordsRequest::new("events", Offset(0), 10))
+            .await
+            .unwrap();
+        (read.records().len() == 2).then_some(read)
+    })
+    .await;
+    let values: Vec<_> = read.records().iter().map(Record::value).collect();
+    assert_eq!(values, [b"1", b"2"]);
+
+    leader.delete_topic("events").await.unwrap();
+    eventually(|| async {
+        let listed = follower.list_topics().await.unwrap();
+        listed.topics.is_empty().then_some(())
+    })
+    .await;
+    assert!(matches!(
+        follower
+            .get_records(GetRecordsRequest::new("events", Offset(0), 10))
+            .await,
+        Err(ClientError::NotFound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn puts_spread_over_partitions_by_key() {
+    let (leader, follower) = start_cluster().await;
+    let config = TopicConfig::new().with_partitions(3);
+    leader
+        .create_topic(CreateTopicRequest::new("events").with_config(config))
+        .await
+        .unwrap();
+
+    let records: Vec<_> = (0..30u32)
+        .map(|i| Record::new(format!("key-{}", i % 6), i.to_be_bytes()))
+        .collect();
+    let put = follower
+        .put_record(PutRecordsRequest::new("events", records))
+        .await
+        .unwrap();
+    assert_eq!(put.len(), 30);
+
+    let explicit = leader
+        .put_record(
+            PutRecordsRequest::single("events", Record::new("key-0", "x")).with_partition(2),
+        )
+        .await
+        .unwrap();
+    assert_eq!(explicit.partitions[0].partition, 2);
+
+    // Both nodes know the layout, and point at the leader for every partition
+    for node in [&leader, &follower] {
+        let shards = node.get_shards().await.unwrap();
+        assert_eq!(shards.topics.len(), 1);
+        let partitions = &shards.topics[0].partitions;
+        assert_eq!(partitions.len(), 3);
+        for (index, partition) in partitions.iter().enumerate() {
+            assert_eq!(partition.partition, index as u32);
+            assert_eq!(partition.leader, Some(leader.base_url().to_string()));
+        }
+    }
+
+    // Every key is read back from a single partition, in the order it was put
+    let mut read = 0;
+    let mut keys = std::collections::HashSet::new();
+    for partition in 0..3 {
+        let leader_end = leader
+            .get_records(GetRecordsRequest::new("events", Offset(0), 100).with_partition(partition))
+            .await
+            .unwrap()
+            .next_offset();
+        let res = eventually(|| async {
+            let res = follower
+                .get_records(
+                    GetRecordsRequest::new("events", Offset(0), 100).with_partition(partition),
+                )
+                .await
+                .unwrap();
+            (res.next_offset() == leader_end).then_some(res)
+        })
+        .await;
+        let mut values = std::collections::HashMap::new();
+        for record in res.records().iter().filter(|record| record.value() != b"x") {
+            assert!(keys.insert(record.key().to_vec()) || values.contains_key(record.key()));
+            let previous = values.insert(record.key().to_vec(), record.value().to_vec());
+            assert!(previous < Some(record.value().to_vec()));
+            read += 1;
+        }
+    }
+    assert_eq!(read, 30);
+
+    assert!(matches!(
+        follower
+            .get_records(GetRecordsRequest::new("events", Offset(0), 10).with_partition(3))
+            .await,
+        Err(ClientError::NotFound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn committed_offsets_reach_every_node() {
+    let (leader, follower) = start_cluster().await;
+    let config = TopicConfig::new().with_partitions(2);
+    leader
+        .create_topic(CreateTopicRequest::new("events").with_config(config))
+        .await
+        .unwrap();
+
+    let get = || GetCommittedOffsetsRequest::new("billing", "events");
+    assert!(
+        leader
+            .get_committed_offsets(get())
+            .await
+            .unwrap()
+            .offsets
+            .is_empty()
+    );
+
+    // Committed through the follower, which forwards to the leader
+    let offsets = vec![
+        PartitionOffset::new(0, Offset(10)),
+        PartitionOffset::new(1, Offset(20)),
+    ];
+    let committed = follower
+        .commit_offsets(CommitOffsetsRequest::new(
+            "billing",
+            "events",
+            offsets.clone(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(committed.offsets, offsets);
+
+    // Acknowledged once replicated, so the follower has them too
+    for node in [&leader, &follower] {
+        let committed = node.get_committed_offsets(get()).await.unwrap();
+        assert_eq!(committed.offset(0), Some(Offset(10)));
+        assert_eq!(committed.offset(1), Some(Offset(20)));
+    }
+
+    assert!(matches!(
+        leader
+            .commit_offsets(CommitOffsetsRequest::new(
+                "billing",
+                "events",
+                vec![PartitionOffset::new(2, Offset(1))]
+            ))
+            .await,
+        Err(ClientError::NotFound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn group_members_are_told_of_rebalances() {
+    let (leader, follower) = start_cluster().await;
+    let config = TopicConfig::new().with_partitions(4);
+    leader
+        .create_topic(CreateTopicRequest::new("events").with_config(config))
+        .await
+        .unwrap();
+
+    let join = || {
+        JoinGroupRequest::new("billing", "events")
+            .with_strategy(AssignmentStrategy::Sticky)
+            .with_session_timeout(Duration::from_millis(300))
+    };
+    // Membership goes through the follower to the leader
+    let mut first = GroupMember::join(follower.clone(), join()).await.unwrap();
+    assert_eq!(first.assignment().partitions, [0, 1, 2, 3]);
+
+    let second = GroupMember::join(leader.clone(), join()).await.unwrap();
+    let first_share = changed(&mut first).await;
+    let second_share = second.assignment();
+    assert_eq!(first_share.generation, second_share.generation);
+    assert_eq!(first_share.partitions.len(), 2);
+    assert!(
+        first_share
+            .partitions
+            .iter()
+            .all(|partition| !second_share.partitions.contains(partition))
+    );
+
+    let group = follower.describe_group("billing").await.unwrap();
+    assert_eq!(group.topic, "events");
+    assert_eq!(group.members.len(), 2);
+
+    // A member that stops heartbeating has its partitions handed back
+    drop(second);
+    assert_eq!(changed(&mut first).await.partitions, [0, 1, 2, 3]);
+
+    first.leave().await.unwrap();
+    assert!(
+        leader
+            .describe_group("billing")
+            .await
+            .unwrap()
+            .members
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn lag_is_reported_by_every_node() {
+    let (leader, follower) = start_cluster().await;
+    let config = TopicConfig::new().with_partitions(2);
+    leader
+        .create_topic(CreateTopicRequest::new("events").with_config(config))
+        .await
+        .unwrap();
+    for partition in 0..2 {
+        let records = vec![Record::new("key", "value"); 5];
+        leader
+            .put_record(PutRecordsRequest::new("events", records).with_partition(partition))
+            .await
+            .unwrap();
+    }
+    leader
+        .commit_offsets(CommitOffsetsRequest::new(
+            "billing",
+            "events",
+            vec![PartitionOffset::new(0, Offset(2))],
+        ))
+        .await
+        .unwrap();
+
+    for node in [&leader, &follower] {
+        let lag = node
+            .get_group_lag(GetGroupLagRequest::new("billing"))
+            .await
+            .unwrap();
+        let partitions: Vec<_> = lag
+            .partitions
+            .iter()
+            .map(|partition| (partition.partition, partition.log_end_offset, partition.lag))
+            .collect();
+        assert_eq!(partitions, [(0, Offset(5), Some(3)), (1, Offset(5), None)]);
+        assert_eq!(lag.total_lag(), 3);
+    }
+
+    assert!(matches!(
+        leader
+            .get_group_lag(GetGroupLagRequest::new("billing").with_topic("missing"))
+            .await,
+        Err(ClientError::NotFound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn uncommitted_records_are_hidden_from_consumers() {
+    // The leader waits for two followers but only has one, so nothing commits
+    let (leader, follower) = start_cluster_requiring(2).await;
+    leader
+        .create_topic(CreateTopicRequest::new("events"))
+        .await
+        .unwrap();
+    let put = tokio::spawn({
+        let leader = leader.clone();
+        async move {
+            let req = PutRecordsRequest::single("events", Record::new("key", "value"));
+            leader.put_record(req).await
+        }
+    });
+
+    let uncommitted = || {
+        GetRecordsRequest::new("events", Offset(0), 10)
+            .with_isolation(IsolationLevel::ReadUncommitted)
+    };
+    for node in [&leader, &follower] {
+        eventually(|| async {
+            let read = node.get_records(uncommitted()).await.ok()?;
+            (read.records().len() == 1).then_some(())
+        })
+        .await;
+
+        let read = node
+            .get_records(GetRecordsRequest::new("events", Offset(0), 10))
+            .await
+            .unwrap();
+        assert!(read.records().is_empty());
+        assert_eq!(read.next_offset(), Offset(0));
+    }
+
+    // The producer is told its put failed
+    assert!(put.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn diverged_followers_are_truncated_to_match_the_leader() {
+    let leader_records = vec![
+        Record::new("a", "0"),
+        Record::new("a", "1"),
+        Record::new("a", "2"),
+    ];
+    let stale_records = vec![Record::new("stale", "1"), Record::new("stale", "2")];
+
+    // The leader took over in epoch 2 without seeing what the follower wrote
+    // as the leader of epoch 1
+    let leader_topics = topics(1).await;
+    let topic = leader_topics
+        .create("events", TopicConfig::new())
+        .await
+        .unwrap();
+    let partition = topic.partition(0).unwrap();
+    partition
+        .storage()
+        .put_records(leader_records.clone())
+        .await
+        .unwrap();
+    {
+        let mut epochs = partition.leader_epochs().lock().unwrap();
+        epochs.assign(0, Offset(0)).unwrap();
+        epochs.assign(2, Offset(1)).unwrap();
+    }
+
+    let follower_topics = topics(0).await;
+    let follower_topic = follower_topics
+        .ensure(topic.description().clone())
+        .await
+        .unwrap();
+    let follower_partition = follower_topic.partition(0).unwrap();
+    let storage = follower_partition.storage();
+    storage.put_record(leader_records[0].clone()).await.unwrap();
+    storage.put_records(stale_records).await.unwrap();
+    {
+        let mut epochs = follower_partition.leader_epochs().lock().unwrap();
+        epochs.assign(0, Offset(0)).unwrap();
+        epochs.assign(1, Offset(1)).unwrap();
+    }
+
+    let (listener, addr) = bind().await;
+    let (follower_listener, follower_addr) = bind().await;
+    let leader_state = AppState::builder()
+        .leader()
+        .with_token(TOKEN)
+        .with_topics(leader_topics)
+        .with_advertised_url(url(addr))
+        .with_membership(membership(&[follower_addr]))
+        .build();
+    let leader = serve(listener, leader_state).0;
+
+    let (listener, addr) = (follower_listener, follower_addr);
+    let follower_state = AppState::builder()
+        .follower()
+        .with_socket_addr(addr)
+        .with_token(TOKEN)
+        .with_topics(follower_topics.clone())
+        .with_leader(leader.base_url().clone())
+        .build();
+    serve(listener, follower_state);
+
+    // Epoch 1 is dropped, and epoch 2 copied over in its place
+    let read = eventually(|| async {
+        let (read, _) = storage.get_records(Offset(0), 10).await.unwrap();
+        (read == leader_records).then_some(read)
+    })
+    .await;
+    assert_eq!(read, leader_records);
+
+    let latest = follower_partition.leader_epochs().lock().unwrap().latest();
+    assert_eq!(latest, Some(LeaderEpoch::new(2, Offset(1))));
+}
+
+/// Topics kept on disk in `data_dir`, in segments of a few records each
+async fn disk_topics(data_dir: &tempfile::TempDir, required_replicas: usize) -> Arc<Topics> {
+    let storage = disk_config(data_dir).with_segment_bytes(100);
+    let config = TopicsConfig::new(TopicStorage::Disk(storage))
+        .with_metadata_dir(data_dir.path())
+        .with_required_replicas(required_replicas);
+    Arc::new(Topics::open(config).await.unwrap())
+}
+
+/// Every record in `storage` along with its offset
+async fn offset_records(storage: &dyn Storage) -> Vec<(Offset, Record)> {
+    let mut out = Vec::new();
+    let mut offset = storage.log_start_offset().await.unwrap();
+    loop {
+        let (mut read, next_offset) = storage.get_records(offset, 1).await.unwrap();
+        let Some(record) = read.pop() else {
+            return out;
+        };
+        out.push((next_offset - 1, record));
+        offset = next_offset;
+    }
+}
+
+#[tokio::test]
+async fn followers_keep_the_offsets_of_compacted_logs() {
+    let leader_dir = tempfile::tempdir().unwrap();
+    let leader_topics = disk_topics(&leader_dir, 1).await;
+    let topic = leader_topics
+        .create("events", TopicConfig::new())
+        .await
+        .unwrap();
+    let storage = topic.partition(0).unwrap().storage().clone();
+    for round in 0..10 {
+        for key in ["a", "b", "c"] {
+            storage
+                .put_record(Record::new(key, format!("{key}-{round}")))
+                .await
+                .unwrap();
+        }
+    }
+    storage.compact(&CompactionPolicy::default()).await.unwrap();
+    let compacted = offset_records(storage.as_ref()).await;
+    assert!(compacted.len() < 30);
+
+    let (listener, addr) = bind().await;
+    let (follower_listener, follower_addr) = bind().await;
+    let leader_state = AppState::builder()
+        .leader()
+        .with_token(TOKEN)
+        .with_topics(leader_topics)
+        .with_advertised_url(url(addr))
+        .with_membership(membership(&[follower_addr]))
+        .build();
+    let leader = serve(listener, leader_state).0;
+
+    let follower_dir = tempfile::tempdir().unwrap();
+    let follower_topics = disk_topics(&follower_dir, 0).await;
+    let follower_state = AppState::builder()
+        .follower()
+        .with_socket_addr(follower_addr)
+        .with_token(TOKEN)
+        .with_topics(follower_topics.clone())
+        .with_leader(leader.base_url().clone())
+        .build();
+    serve(follower_listener, follower_state);
+
+    let follower_storage = eventually(|| async {
+        let topic = follower_topics.get("events").ok()?;
+        Some(topic.partition(0).unwrap().storage().clone())
+    })
+    .await;
+    eventually(|| async {
+        let copied = offset_records(follower_storage.as_ref()).await;
+        (copied.len() >= compacted.len()).then_some(())
+    })
+    .await;
+
+    // Survivors are copied once, at the offsets they have on the leader
+    let copied = offset_records(follower_storage.as_ref()).await;
+    assert_eq!(copied, compacted);
+
+    // New records carry on from the same offset on both
+    leader
+        .put_record(PutRecordsRequest::new(
+            "events",
+            vec![Record::new("d", "d-0")],
+        ))
+        .await
+        .unwrap();
+    let copied = eventually(|| async {
+        let copied = offset_records(follower_storage.as_ref()).await;
+        (copied.len() > compacted.len()).then_some(copied)
+    })
+    .await;
+    assert_eq!(copied, offset_records(storage.as_ref()).await);
+    assert_eq!(copied.last().unwrap().0, Offset(30));
+}
+
+#[tokio::test]
+async fn followers_start_over_from_the_leaders_log_start() {
+    let leader_topics = topics(1).await;
+    let topic = leader_topics
+        .create("events", TopicConfig::new())
+        .await
+        .unwrap();
+    let storage = topic.partition(0).unwrap().storage().clone();
+    for record in records("record", 10) {
+        storage.put_record(record).await.unwrap();
+    }
+    let retention = RetentionPolicy {
+        max_records: Some(3),
+        ..RetentionPolicy::default()
+    };
+    let log_start = storage.enforce_retention(&retention).await.unwrap();
+    assert_eq!(log_start, Offset(7));
+
+    let (listener, addr) = bind().await;
+    let (follower_listener, follower_addr) = bind().await;
+    let leader_state = AppState::builder()
+        .leader()
+        .with_token(TOKEN)
+        .with_topics(leader_topics)
+        .with_advertised_url(url(addr))
+        .with_membership(membership(&[follower_addr]))
+        .build();
+    let leader = serve(listener, leader_state).0;
+
+    // The follower has to give up the records it has, as the leader no
+    // longer has the ones that come after them
+    let follower_topics = topics(0).await;
+    let follower_topic = follower_topics
+        .ensure(topic.description().clone())
+        .await
+        .unwrap();
+    let follower_storage = follower_topic.partition(0).unwrap().storage().clone();
+    follower_storage
+        .put_records(records("record", 2))
+        .await
+        .unwrap();
+    let follower_state = AppState::builder()
+        .follower()
+        .with_socket_addr(follower_addr)
+        .with_token(TOKEN)
+        .with_topics(follower_topics)
+        .with_leader(leader.base_url().clone())
+        .build();
+    serve(follower_listener, follower_state);
+
+    eventually(|| async {
+        let max_offset = follower_storage.max_offset().await.unwrap();
+        (max_offset == Some(Offset(9))).then_some(())
+    })
+    .await;
+    assert_eq!(
+        follower_storage.log_start_offset().await.unwrap(),
+        log_start
+    );
+    assert_eq!(
+        offset_records(follower_storage.as_ref()).await,
+        offset_records(storage.as_ref()).await
+    );
+
+    // Puts are acknowledged once the follower has carried on from there
+    let put = leader
+        .put_record(PutRecordsRequest::new(
+            "events",
+            vec![Record::new("key", "value")],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(put.partitions[0].offset, Offset(10));
+    assert_eq!(
+        follower_storage.max_offset().await.unwrap(),
+        Some(Offset(10))
+    );
+}
+
+static FLAKY_SYNCS: AtomicUsize = AtomicUsize::new(0);
+
+/// Fails the first few fsyncs, as a disk that briefly ran out of space would
+fn flaky_sync(file: &File) -> io::Result<()> {
+    if FLAKY_SYNCS.fetch_add(1, Ordering::SeqCst) < 3 {
+        return Err(io::Error::other("No space left on device"));
+    }
+    file.sync_data()
+}
+
+#[tokio::test]
+async fn followers_carry_on_after_storage_errors() {
+    let (listener, addr) = bind().await;
+    let (follower_listener, follower_addr) = bind().await;
+    let leader_state = AppState::builder()
+        .leader()
+        .with_token(TOKEN)
+        .with_topics(topics(1).await)
+        .with_advertised_url(url(addr))
+        .with_membership(membership(&[follower_addr]))
+        .build();
+    let leader = serve(listener, leader_state).0;
+
+    let follower_dir = tempfile::tempdir().unwrap();
+    let storage = disk_config(&follower_dir)
+        .with_durability(DurabilityPolicy::Always)
+        .with_sync(flaky_sync);
+    let config = TopicsConfig::new(TopicStorage::Disk(storage))
+        .with_metadata_dir(follower_dir.path())
+        .with_required_replicas(0);
+    let follower_state = AppState::builder()
+        .follower()
+        .with_socket_addr(follower_addr)
+        .with_token(TOKEN)
+        .with_topics(Arc::new(Topics::open(config).await.unwrap()))
+        .with_leader(leader.base_url().clone())
+        .build();
+    let follower = serve(follower_listener, follower_state).0;
+
+    leader
+        .create_topic(CreateTopicRequest::new("events"))
+        .await
+        .unwrap();
+    for value in ["1", "2", "3", "4"] {
+        leader
+            .put_record(PutRecordsRequest::new(
+                "events",
+                vec![Record::new("key", value)],
+            ))
+            .await
+            .unwrap();
+    }
+    assert!(FLAKY_SYNCS.load(Ordering::SeqCst) > 3);
+
+    let read = eventually(|| async {
+        let read = follower
+            .get_records(GetRecordsRequest::new("events", Offset(0), 10))
+            .await
+            .unwrap();
+        (read.records().len() == 4).then_some(read)
+    })
+    .await;
+    let values: Vec<_> = read.records().iter().map(Record::value).collect();
+    assert_eq!(values, [b"1", b"2", b"3", b"4"]);
+}
+
+#[tokio::test]
+async fn members_change_once_the_new_ones_catch_up() {
+    let (leader, follower) = start_cluster().await;
+    let (listener, learner_addr) = bind().await;
+    let learner = start_follower(listener, &leader).await;
+    leader
+        .create_topic(CreateTopicRequest::new("events"))
+        .await
+        .unwrap();
+    let records = vec![Record::new("key", "1"), Record::new("key", "2")];
+    leader
+        .put_record(PutRecordsRequest::new("events", records))
+        .await
+        .unwrap();
+
+    // Added through a follower, which forwards to the leader
+    let follower_url = follower.base_url().to_string();
+    let learner_url = learner.base_url().to_string();
+    let members = follower
+        .add_member(MemberRequest::new(learner_url.clone()), TOKEN)
+        .await
+        .unwrap();
+    assert_eq!(members.members, [follower_url.clone()].into());
+    assert!(matches!(
+        leader
+            .remove_member(MemberRequest::new(follower_url.clone()), TOKEN)
+            .await,
+        Err(ClientError::Conflict { .. })
+    ));
+
+    let both = ClusterMembers::new([follower_url.clone(), learner_url.clone()]);
+    eventually(|| async {
+        let members = leader.list_members().await.unwrap();
+        (members == both).then_some(())
+    })
+    .await;
+    assert!(matches!(
+        leader
+            .add_member(MemberRequest::new(url(learner_addr)), TOKEN)
+            .await,
+        Err(ClientError::Conflict { .. })
+    ));
+
+    // The leader is left waiting on the learner alone
+    leader
+        .remove_member(MemberRequest::new(follower_url.clone()), TOKEN)
+        .await
+        .unwrap();
+    let learner_only = ClusterMembers::new([learner_url.clone()]);
+    eventually(|| async {
+        let members = leader.list_members().await.unwrap();
+        (members == learner_only).then_some(())
+    })
+    .await;
+    assert!(matches!(
+        leader
+            .remove_member(MemberRequest::new(follower_url), TOKEN)
+            .await,
+        Err(ClientError::NotFound { .. })
+    ));
+    assert!(matches!(
+        leader
+            .remove_member(MemberRequest::new(learner_url), TOKEN)
+            .await,
+        Err(ClientError::Conflict { .. })
+    ));
+    leader
+        .put_record(PutRecordsRequest::single("events", Record::new("key", "3")))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn member_changes_must_be_signed_with_the_token() {
+    let (leader, follower) = start_cluster().await;
+    let members = leader.list_members().await.unwrap();
+    let req = MemberRequest::new("http://127.0.0.1:9");
+
+    let http = reqwest::Client::new();
+    let bodies = [
+        serde_json::to_value(HmacForm::new(req.clone(), "wrong-token")).unwrap(),
+        serde_json::json!({ "hmac": "", "data": req }),
+    ];
+    for node in [&leader, &follower] {
+        let url = node.base_url().join("cluster/members").unwrap();
+        for body in &bodies {
+            for method in [reqwest::Method::PUT, reqwest::Method::DELETE] {
+                let res = http
+                    .request(method, url.clone())
+                    .json(body)
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+            }
+        }
+        // Not even in the form of a signed request
+        let res = http.put(url).json(&req).send().await.unwrap();
+        assert!(res.status().is_client_error());
+    }
+    assert_eq!(leader.list_members().await.unwrap(), members);
+}
+
+#[tokio::test]
+async fn members_that_stop_polling_are_removed() {
+    let (listener, addr) = bind().await;
+    let (follower_listener, follower_addr) = bind().await;
+    // Never started
+    let (_, quiet_addr) = bind().await;
+    let membership = ClusterMembership::open(
+        MembershipConfig::new(vec![url(follower_addr), url(quiet_addr)])
+            .with_follower_timeout(Duration::from_secs(2)),
+    )
+    .unwrap();
+    let leader_state = AppState::builder()
+        .leader()
+        .with_token(TOKEN)
+        .with_topics(topics(1).await)
+        .with_advertised_url(url(addr))
+        .with_membership(Arc::new(membership))
+        .build();
+    let leader = serve(listener, leader_state).0;
+    leader
+        .create_topic(CreateTopicRequest::new("events"))
+        .await
+        .unwrap();
+    start_follower(follower_listener, &leader).await;
+
+    let follower_only = ClusterMembers::new([url(follower_addr)]);
+    eventually(|| async {
+        let members = leader.list_members().await.unwrap();
+        (members == follower_only).then_some(())
+    })
+    .await;
+
+    // Records commit on the member that is left
+    leader
+        .put_record(PutRecordsRequest::single("events", Record::new("key", "1")))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn member_changes_survive_restarts() {
+    let state_dir = tempfile::tempdir().unwrap();
+    let config = || {
+        MembershipConfig::new(vec![Url::parse("http://a:9999").unwrap()])
+            .with_state_dir(state_dir.path())
+    };
+    let topics = topics(1).await;
+
+    let membership = ClusterMembership::open(config()).unwrap();
+    assert_eq!(
+        membership.members(),
+        ClusterMembers::new(["http://a:9999/"])
+    );
+    let members = membership.add(&topics, "http://b:9999").unwrap();
+    assert_eq!(
+        members.joining,
+        Some(["http://a:9999/".to_string(), "http://b:9999/".to_string()].into())
+    );
+
+    // Picked up again rather than the configured members
+    let reopened = ClusterMembership::open(config()).unwrap();
+    assert_eq!(reopened.members(), members);
+}
+
+/// Start a leader waiting on `required_replicas` of the members reached on
+/// `followers`, dropping them from the in-sync replicas after 2s
+async fn start_leader(
+    required_replicas: usize,
+    min_insync_replicas: usize,
+    followers: &[SocketAddr],
+) -> TokkiClient {
+    let in_sync = InSyncConfig::new()
+        .with_max_lag(Duration::from_secs(2))
+        .with_min_insync_replicas(min_insync_replicas);
+    let config = TopicsConfig::new(TopicStorage::InMemoryMutex)
+        .with_required_replicas(required_replicas)
+        .with_in_sync(in_sync);
+    let (listener, addr) = bind().await;
+    let leader_state = AppState::builder()
+        .leader()
+        .with_token(TOKEN)
+        .with_topics(Arc::new(Topics::open(config).await.unwrap()))
+        .with_advertised_url(url(addr))
+        .with_membership(membership(followers))
+        .build();
+    serve(listener, leader_state).0
+}
+
+#[tokio::test]
+async fn dead_members_stop_holding_up_puts() {
+    let (follower_listener, follower_addr) = bind().await;
+    // Never started
+    let (_, dead_addr) = bind().await;
+    let leader = start_leader(2, 1, &[follower_addr, dead_addr]).await;
+    leader
+        .create_topic(CreateTopicRequest::new("events"))
+        .await
+        .unwrap();
+    let follower = start_follower(follower_listener, &leader).await;
+
+    // Acknowledged by the follower alone once the dead member is out of sync
+    let put = leader
+        .put_record(PutRecordsRequest::single("events", Record::new("key", "1")))
+        .await
+        .unwrap();
+    assert_eq!(put.partitions[0].offset, Offset(0));
+
+    let res = follower.get_in_sync_replicas().await.unwrap();
+    let [partition] = &res.partitions[..] else {
+        panic!("One partition");
+    };
+    assert_eq!(partition.topic, "events");
+    assert_eq!(partition.high_watermark, Offset(1));
+    assert_eq!(partition.min_insync_replicas, 1);
+    let statuses: Vec<_> = partition
+        .replicas
+        .iter()
+        .map(|replica| (replica.url.clone(), replica.in_sync))
+        .collect();
+    let mut expected = vec![
+        (url(follower_addr).to_string(), true),
+        (url(dead_addr).to_string(), false),
+    ];
+    expected.sort();
+    assert_eq!(statuses, expected);
+}
+
+#[tokio::test]
+async fn puts_fail_while_too_few_members_are_in_sync() {
+    // Never started
+    let (_, dead_addr) = bind().await;
+    let leader = start_leader(1, 1, &[dead_addr]).await;
+    leader
+        .create_topic(CreateTopicRequest::new("events"))
+        .await
+        .unwrap();
+
+    // Failed as soon as the dead member is out of sync
+    let put = || leader.put_record(PutRecordsRequest::single("events", Record::new("key", "1")));
+    assert!(matches!(put().await, Err(ClientError::BadResponse { .. })));
+
+    // Straight away, rather than after waiting on the dead member
+    let started = std::time::Instant::now();
+    assert!(matches!(put().await, Err(ClientError::BadResponse { .. })));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
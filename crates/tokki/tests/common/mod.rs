@@ -0,0 +1,83 @@
+//! Fixtures shared by the integration tests. Each test binary only uses some
+//! of them.
+#![allow(dead_code)]
+
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokki::{
+    app_state::AppState,
+    server::create_router,
+    storage::{DiskStorage, DiskStorageConfig, RecordFrames, Storage},
+    topics::{TopicStorage, Topics, TopicsConfig},
+};
+use tokki_api::{TokkiClient, get_records::GetRecordsResponse};
+use tokki_common::{Offset, Record};
+use url::Url;
+
+pub const TOKEN: &str = "test-token";
+
+/// `len` records keyed `{prefix}-{i}`, each with its index as the value
+pub fn records(prefix: &str, len: usize) -> Vec<Record> {
+    (0..len)
+        .map(|i| Record::new(format!("{prefix}-{i}"), i.to_string()))
+        .collect()
+}
+
+/// Settings for a disk log kept in `data_dir`
+pub fn disk_config(data_dir: &tempfile::TempDir) -> DiskStorageConfig {
+    DiskStorageConfig::new(data_dir.path())
+}
+
+/// Put `records` one at a time into a log opened with `config`, closing it again afterwards
+pub async fn written_log(config: DiskStorageConfig, records: &[Record]) {
+    let storage = DiskStorage::open(config).unwrap();
+    for record in records {
+        storage.put_record(record.clone()).await.unwrap();
+    }
+}
+
+/// The records held by `frames`, as a consumer would decode them
+pub fn decode(frames: &RecordFrames, next_offset: Offset) -> Vec<Record> {
+    let response = GetRecordsResponse::from_frames(&frames.chunks().concat(), next_offset).unwrap();
+    assert_eq!(response.records().len(), frames.record_count());
+    response.records().to_vec()
+}
+
+pub async fn bind() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+pub fn url(addr: SocketAddr) -> Url {
+    Url::parse(&format!("http://{addr}")).unwrap()
+}
+
+/// Serve `state` on `listener` in the background, returning a client of the
+/// node along with the task serving it
+pub fn serve(listener: TcpListener, state: AppState) -> (TokkiClient, JoinHandle<()>) {
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        axum::serve(listener, create_router(state)).await.unwrap();
+    });
+    (TokkiClient::new(url(addr)), server)
+}
+
+/// In memory topics whose puts wait for `required_replicas` followers
+pub async fn topics(required_replicas: usize) -> Arc<Topics> {
+    let config =
+        TopicsConfig::new(TopicStorage::InMemoryMutex).with_required_replicas(required_replicas);
+    Arc::new(Topics::open(config).await.unwrap())
+}
+
+/// Poll `check` until it returns a value, failing the test after ten seconds
+pub async fn eventually<T, F: Future<Output = Option<T>>>(mut check: impl FnMut() -> F) -> T {
+    for _ in 0..200 {
+        if let Some(value) = check().await {
+            return value;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Condition never held");
+}
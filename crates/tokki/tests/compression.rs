@@ -0,0 +1,234 @@
+mod common;
+
+use std::{fs, sync::Arc};
+
+use tokki::storage::{CorruptRecordPolicy, DiskStorage, InMemoryStorage, Storage, StorageError};
+use tokki_common::{Compression, Offset, Record, RecordBatch, Timestamp};
+
+use crate::common::disk_config;
+
+/// Records with similar JSON values, which compress well together
+fn json_records(prefix: &str, len: u64) -> Vec<Record> {
+    (0..len)
+        .map(|i| {
+            Record::new(
+                format!("{prefix}-{i}"),
+                format!(r#"{{"id":{i},"kind":"{prefix}","payload":"aaaaaaaaaaaaaaaa"}}"#),
+            )
+            .with_timestamp(Timestamp(1_000 + i))
+        })
+        .collect()
+}
+
+/// A record, a compressed batch, then another record, returning everything
+/// that was written in offset order
+async fn write_log(storage: &dyn Storage, compression: Compression) -> Vec<Record> {
+    let before = Record::new("before", "1");
+    let batch = json_records("batch", 20);
+    let after = Record::new("after", "2");
+
+    assert_eq!(storage.put_record(before.clone()).await.unwrap(), Offset(0));
+    let offsets = storage
+        .put_batch(RecordBatch::new(&batch, compression).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(offsets, Offset(1)..Offset(21));
+    assert_eq!(storage.put_record(after.clone()).await.unwrap(), Offset(21));
+
+    [vec![before], batch, vec![after]].concat()
+}
+
+async fn assert_reads_records(storage: &dyn Storage, written: &[Record]) {
+    let (read, next_offset) = storage.get_records(Offset(0), 100).await.unwrap();
+    assert_eq!(read, written);
+    assert_eq!(next_offset, Offset(written.len()));
+
+    // Reads can start and stop in the middle of a batch
+    let (read, next_offset) = storage.get_records(Offset(5), 3).await.unwrap();
+    assert_eq!(read, written[5..8]);
+    assert_eq!(next_offset, Offset(8));
+}
+
+#[tokio::test]
+async fn disk_reads_records_out_of_batches() {
+    for compression in [Compression::Lz4, Compression::Zstd] {
+        let data_dir = tempfile::tempdir().unwrap();
+        let storage = DiskStorage::open(disk_config(&data_dir)).unwrap();
+        let written = write_log(&storage, compression).await;
+
+        assert_reads_records(&storage, &written).await;
+        assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(21)));
+
+        // Batches are recovered whole when the log is reopened
+        drop(storage);
+        let storage = DiskStorage::open(disk_config(&data_dir)).unwrap();
+        assert_reads_records(&storage, &written).await;
+        assert_eq!(
+            storage.put_record(Record::new("k", "v")).await.unwrap(),
+            Offset(22)
+        );
+    }
+}
+
+#[tokio::test]
+async fn disk_stores_batches_compressed() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(disk_config(&data_dir)).unwrap();
+    let records = json_records("batch", 200);
+    let uncompressed: usize = records.iter().map(Record::serialized_len).sum();
+
+    storage
+        .put_batch(RecordBatch::new(&records, Compression::Zstd).unwrap())
+        .await
+        .unwrap();
+
+    let log_len = fs::metadata(data_dir.path().join(format!("{:020}.log", 0)))
+        .unwrap()
+        .len() as usize;
+    assert!(
+        log_len * 4 < uncompressed,
+        "{log_len} of {uncompressed} bytes"
+    );
+}
+
+#[tokio::test]
+async fn disk_ships_batches_as_stored() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(disk_config(&data_dir)).unwrap();
+    let written = write_log(&storage, Compression::Zstd).await;
+
+    let (batches, next_offset) = storage.get_batches(Offset(0), 100).await.unwrap();
+    assert_eq!(next_offset, Offset(22));
+    let compressions: Vec<_> = batches
+        .iter()
+        .map(|(_, batch)| batch.compression())
+        .collect();
+    assert_eq!(
+        compressions,
+        [Compression::None, Compression::Zstd, Compression::None]
+    );
+    let base_offsets: Vec<_> = batches.iter().map(|(offset, _)| *offset).collect();
+    assert_eq!(base_offsets, [Offset(0), Offset(1), Offset(21)]);
+
+    // A batch that starts before the requested offset is cut down
+    let (batches, next_offset) = storage.get_batches(Offset(15), 1).await.unwrap();
+    assert_eq!(next_offset, Offset(21));
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].0, Offset(15));
+    assert_eq!(batches[0].1.compression(), Compression::Zstd);
+    assert_eq!(batches[0].1.records().unwrap(), written[15..21]);
+}
+
+#[tokio::test]
+async fn disk_finds_timestamps_inside_batches() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(
+        disk_config(&data_dir)
+            .with_segment_bytes(1)
+            .with_index_interval_bytes(1),
+    )
+    .unwrap();
+
+    for chunk in json_records("batch", 40).chunks(10) {
+        storage
+            .put_batch(RecordBatch::new(chunk, Compression::Lz4).unwrap())
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(
+        storage
+            .offset_for_timestamp(Timestamp(1_025))
+            .await
+            .unwrap(),
+        Some(Offset(25))
+    );
+    assert_eq!(
+        storage
+            .offset_for_timestamp(Timestamp(2_000))
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn disk_skips_corrupt_batch_whole() {
+    let before_len = Record::new("before", "1").serialized_len();
+    let batch_len = RecordBatch::new(&json_records("batch", 20), Compression::Lz4)
+        .unwrap()
+        .serialized_len();
+    let after_len = Record::new("after", "2").serialized_len();
+    let data_dir = tempfile::tempdir().unwrap();
+    // The whole of `write_log` fits in the first segment, sealed by the next write
+    let config = |corrupt_records| {
+        disk_config(&data_dir)
+            .with_segment_bytes((before_len + batch_len + after_len) as u64)
+            .with_index_interval_bytes(1024 * 1024)
+            .with_corrupt_records(corrupt_records)
+    };
+
+    let storage = DiskStorage::open(config(CorruptRecordPolicy::Fail)).unwrap();
+    let written = write_log(&storage, Compression::Lz4).await;
+    storage.put_record(Record::new("next", "3")).await.unwrap();
+    drop(storage);
+
+    // Flip a byte of the batch's compressed data
+    let path = data_dir.path().join(format!("{:020}.log", 0));
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[before_len + 40] ^= 0xff;
+    fs::write(&path, bytes).unwrap();
+
+    let storage = DiskStorage::open(config(CorruptRecordPolicy::Fail)).unwrap();
+    let err = storage.get_records(Offset(0), 100).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::CorruptRecord {
+            offset: Offset(1),
+            ..
+        }
+    ));
+    drop(storage);
+
+    // Every offset in the batch is skipped, and the record after it keeps its offset
+    let storage = DiskStorage::open(config(CorruptRecordPolicy::Skip)).unwrap();
+    let (read, next_offset) = storage.get_records(Offset(0), 100).await.unwrap();
+    assert_eq!(
+        read,
+        [
+            written[0].clone(),
+            written[21].clone(),
+            Record::new("next", "3")
+        ]
+    );
+    assert_eq!(next_offset, Offset(23));
+
+    let (read, _) = storage.get_records(Offset(21), 1).await.unwrap();
+    assert_eq!(read, written[21..]);
+}
+
+#[tokio::test]
+async fn in_memory_reads_records_out_of_batches() {
+    let storage = Arc::new(InMemoryStorage::default());
+    let written = write_log(storage.as_ref(), Compression::Zstd).await;
+
+    assert_reads_records(storage.as_ref(), &written).await;
+}
+
+#[tokio::test]
+async fn in_memory_rejects_batches_miscounting_their_records() {
+    let storage = InMemoryStorage::default();
+    let batch = RecordBatch::new(&json_records("batch", 3), Compression::Zstd).unwrap();
+    let miscounted = RecordBatch::from_parts(
+        batch.compression(),
+        5,
+        batch.max_timestamp(),
+        batch.data().to_vec(),
+    );
+
+    assert!(matches!(
+        storage.put_batch(miscounted).await,
+        Err(StorageError::CorruptBatch { .. })
+    ));
+    assert_eq!(storage.max_offset().await.unwrap(), None);
+}
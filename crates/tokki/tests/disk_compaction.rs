@@ -0,0 +1,239 @@
+mod common;
+
+use std::{fs, time::Duration};
+
+use tokki::storage::{CompactionPolicy, DiskStorage, DiskStorageConfig, Storage, StorageError};
+use tokki_common::{Compression, Offset, Record, RecordBatch};
+
+use crate::common::disk_config;
+
+fn config(data_dir: &tempfile::TempDir) -> DiskStorageConfig {
+    // Tiny segments of five records each, so nearly every record ends up in a sealed segment
+    let record_len = Record::new("a", "a-0").serialized_len() as u64;
+    disk_config(data_dir)
+        .with_segment_bytes(5 * record_len)
+        .with_index_interval_bytes(64)
+}
+
+async fn put(storage: &DiskStorage, key: &str, value: &str) -> Offset {
+    storage.put_record(Record::new(key, value)).await.unwrap()
+}
+
+/// Read the log one record at a time, the offset each read continues from
+/// tells us the offset of the record that was just read
+async fn read_all(storage: &DiskStorage, mut offset: Offset) -> Vec<(Offset, Record)> {
+    let mut out = Vec::new();
+    loop {
+        let (mut records, next_offset) = storage.get_records(offset, 1).await.unwrap();
+        let Some(record) = records.pop() else {
+            return out;
+        };
+        out.push((next_offset - 1, record));
+        offset = next_offset;
+    }
+}
+
+#[tokio::test]
+async fn keeps_latest_record_per_key_with_original_offsets() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+
+    for round in 0..10 {
+        for key in ["a", "b", "c"] {
+            put(&storage, key, &format!("{key}-{round}")).await;
+        }
+    }
+    let next_offset = storage.max_offset().await.unwrap().unwrap() + 1;
+
+    storage.compact(&CompactionPolicy::default()).await.unwrap();
+
+    // The active segment, holding offsets 25 onwards, is left alone
+    let records = read_all(&storage, Offset(0)).await;
+    assert_eq!(
+        records,
+        vec![
+            (Offset(25), Record::new("b", "b-8")),
+            (Offset(26), Record::new("c", "c-8")),
+            (Offset(27), Record::new("a", "a-9")),
+            (Offset(28), Record::new("b", "b-9")),
+            (Offset(29), Record::new("c", "c-9")),
+        ]
+    );
+    assert_eq!(storage.max_offset().await.unwrap(), Some(next_offset - 1));
+
+    // Consumers resuming from an offset that was compacted away skip ahead
+    let (read, _) = storage.get_records(Offset(5), 1).await.unwrap();
+    assert_eq!(read, vec![Record::new("b", "b-8")]);
+
+    // New writes carry on from the same offset, and survive reopening
+    assert_eq!(put(&storage, "a", "a-10").await, next_offset);
+    drop(storage);
+
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    let records = read_all(&storage, Offset(0)).await;
+    assert_eq!(records.len(), 6);
+    assert_eq!(records[5], (next_offset, Record::new("a", "a-10")));
+}
+
+#[tokio::test]
+async fn removes_tombstones_after_grace_period() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+
+    for round in 0..5 {
+        put(&storage, "deleted", &format!("value-{round}")).await;
+        put(&storage, "kept", &format!("value-{round}")).await;
+    }
+    let tombstone = put(&storage, "deleted", "").await;
+    // Push the tombstone out of the active segment
+    for round in 5..10 {
+        put(&storage, "kept", &format!("value-{round}")).await;
+    }
+
+    let policy = CompactionPolicy {
+        tombstone_retention: Duration::from_secs(60 * 60),
+    };
+    storage.compact(&policy).await.unwrap();
+
+    let records = read_all(&storage, Offset(0)).await;
+    assert_eq!(records[0], (tombstone, Record::new("deleted", "")));
+    assert!(records[0].1.is_tombstone());
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let policy = CompactionPolicy {
+        tombstone_retention: Duration::from_millis(10),
+    };
+    storage.compact(&policy).await.unwrap();
+
+    let records = read_all(&storage, Offset(0)).await;
+    assert!(records.iter().all(|(_, record)| record.key() == b"kept"));
+    assert_eq!(records.last().unwrap().1, Record::new("kept", "value-9"));
+}
+
+#[tokio::test]
+async fn keeps_every_record_without_a_key() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+
+    let mut unkeyed = Vec::new();
+    for round in 0..10 {
+        put(&storage, "a", &format!("a-{round}")).await;
+        let value = format!("x-{round}");
+        unkeyed.push((put(&storage, "", &value).await, Record::new("", value)));
+    }
+
+    storage.compact(&CompactionPolicy::default()).await.unwrap();
+
+    let records = read_all(&storage, Offset(0)).await;
+    let kept: Vec<_> = records
+        .iter()
+        .filter(|(_, record)| record.key().is_empty())
+        .cloned()
+        .collect();
+    assert_eq!(kept, unkeyed);
+    assert!(records.len() < 20);
+}
+
+#[tokio::test]
+async fn checks_the_index_of_compacted_segments() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    for round in 0..10 {
+        for key in ["a", "b", "c"] {
+            put(&storage, key, &format!("{key}-{round}")).await;
+        }
+    }
+    // Seal the segment starting at 25 so it is compacted, with gaps where a and b were
+    put(&storage, "d", "d-0").await;
+    for round in 10..12 {
+        put(&storage, "a", &format!("a-{round}")).await;
+    }
+    storage.compact(&CompactionPolicy::default()).await.unwrap();
+    let compacted = read_all(&storage, Offset(0)).await;
+    drop(storage);
+
+    // Part of an entry that never finished being written is dropped
+    let index = data_dir.path().join(format!("{:020}.index", 25));
+    let entries = fs::read(&index).unwrap();
+    assert!(entries.len() >= 32, "Expected several entries");
+    fs::write(&index, [entries.as_slice(), &[1, 2, 3]].concat()).unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    assert_eq!(read_all(&storage, Offset(0)).await, compacted);
+    drop(storage);
+    assert_eq!(fs::read(&index).unwrap(), entries);
+
+    // Entries that disagree with the log would give records the wrong offsets
+    let mut swapped = entries.clone();
+    swapped[..16].copy_from_slice(&entries[16..32]);
+    swapped[16..32].copy_from_slice(&entries[..16]);
+    fs::write(&index, swapped).unwrap();
+    let error = DiskStorage::open(config(&data_dir)).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    let mut shifted = entries.clone();
+    shifted[8] += 1;
+    fs::write(&index, shifted).unwrap();
+    let error = DiskStorage::open(config(&data_dir)).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn discards_interrupted_compaction() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    for round in 0..10 {
+        put(&storage, "a", &format!("value-{round}")).await;
+    }
+    drop(storage);
+
+    // A compaction that stopped before its commit point leaves these behind
+    let cleaned = data_dir.path().join(format!("{:020}.log.cleaned", 0));
+    let index_swap = data_dir.path().join(format!("{:020}.index.swap", 0));
+    fs::write(&cleaned, b"garbage").unwrap();
+    fs::write(&index_swap, b"garbage").unwrap();
+
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    assert!(!cleaned.exists());
+    assert!(!index_swap.exists());
+    assert_eq!(read_all(&storage, Offset(0)).await.len(), 10);
+}
+
+#[tokio::test]
+async fn copies_keep_the_gaps_in_compacted_offsets() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    for round in 0..10 {
+        for key in ["a", "b", "c"] {
+            put(&storage, key, &format!("{key}-{round}")).await;
+        }
+    }
+    storage.compact(&CompactionPolicy::default()).await.unwrap();
+    let compacted = read_all(&storage, Offset(0)).await;
+
+    // Copied the way followers copy the leader's log
+    let copy_dir = tempfile::tempdir().unwrap();
+    let copy = DiskStorage::open(disk_config(&copy_dir)).unwrap();
+    let (batches, next_offset) = storage.get_batches(Offset(0), 100).await.unwrap();
+    assert_eq!(next_offset, Offset(30));
+    for (base_offset, batch) in batches {
+        let offsets = copy.put_batch_at(base_offset, batch).await.unwrap();
+        assert_eq!(offsets.start, base_offset);
+    }
+    assert_eq!(read_all(&copy, Offset(0)).await, compacted);
+
+    // Offsets the copy already holds cannot be put again
+    let batch = RecordBatch::new(&[Record::new("a", "a-10")], Compression::None).unwrap();
+    assert!(matches!(
+        copy.put_batch_at(Offset(29), batch.clone()).await,
+        Err(StorageError::NotAtLogEnd { .. })
+    ));
+
+    // The gaps are only recorded in the index, which recovery picks them up from
+    copy.put_batch_at(Offset(40), batch).await.unwrap();
+    drop(copy);
+    let copy = DiskStorage::open(disk_config(&copy_dir)).unwrap();
+    let mut expected = compacted;
+    expected.push((Offset(40), Record::new("a", "a-10")));
+    assert_eq!(read_all(&copy, Offset(0)).await, expected);
+    assert_eq!(copy.max_offset().await.unwrap(), Some(Offset(40)));
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use std::fs;
+
+use tokki::storage::{CorruptRecordPolicy, DiskStorage, DiskStorageConfig, Storage, StorageError};
+use tokki_common::{DecodeError, Offset};
+
+use crate::common::{disk_config, records, written_log};
+
+const RECORDS: usize = 8;
+
+fn config(data_dir: &tempfile::TempDir, corrupt_records: CorruptRecordPolicy) -> DiskStorageConfig {
+    // Every record rolls a new segment
+    disk_config(data_dir)
+        .with_segment_bytes(1)
+        .with_index_interval_bytes(1)
+        .with_corrupt_records(corrupt_records)
+}
+
+/// Flip the last byte of the value in a segment holding one record, just
+/// before its checksum, so the checksum no longer matches
+fn flip_value(bytes: &mut [u8]) {
+    let value_end = bytes.len() - 9;
+    bytes[value_end] ^= 0xff;
+}
+
+fn corrupt_segment(data_dir: &tempfile::TempDir, base_offset: usize, f: impl FnOnce(&mut [u8])) {
+    let path = data_dir.path().join(format!("{base_offset:020}.log"));
+    let mut bytes = fs::read(&path).unwrap();
+    f(&mut bytes);
+    fs::write(path, bytes).unwrap();
+}
+
+#[tokio::test]
+async fn fails_reads_of_corrupt_records() {
+    let records = records("record", RECORDS);
+    let data_dir = tempfile::tempdir().unwrap();
+    written_log(config(&data_dir, CorruptRecordPolicy::Fail), &records).await;
+    corrupt_segment(&data_dir, 2, flip_value);
+
+    let storage = DiskStorage::open(config(&data_dir, CorruptRecordPolicy::Fail)).unwrap();
+
+    let (read, _) = storage.get_records(Offset(0), 2).await.unwrap();
+    assert_eq!(read, records[..2]);
+
+    let err = storage.get_records(Offset(0), 10).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::CorruptRecord {
+            offset: Offset(2),
+            source: DecodeError::ChecksumMismatch { .. },
+        }
+    ));
+}
+
+#[tokio::test]
+async fn skips_record_with_bad_checksum() {
+    let records = records("record", RECORDS);
+    let data_dir = tempfile::tempdir().unwrap();
+    written_log(config(&data_dir, CorruptRecordPolicy::Fail), &records).await;
+    corrupt_segment(&data_dir, 2, flip_value);
+
+    let storage = DiskStorage::open(config(&data_dir, CorruptRecordPolicy::Skip)).unwrap();
+
+    let (read, next_offset) = storage.get_records(Offset(0), 10).await.unwrap();
+    let expected: Vec<_> = records
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 2)
+        .map(|(_, record)| record.clone())
+        .collect();
+    assert_eq!(read, expected);
+    assert_eq!(next_offset, Offset(RECORDS));
+}
+
+#[tokio::test]
+async fn skips_broken_framing_to_end_of_segment() {
+    let records = records("record", RECORDS);
+    let data_dir = tempfile::tempdir().unwrap();
+    written_log(config(&data_dir, CorruptRecordPolicy::Fail), &records).await;
+    // A frame length far larger than the segment, so no later frame can be found in it
+    corrupt_segment(&data_dir, 3, |bytes| {
+        bytes[6..14].copy_from_slice(&(1u64 << 40).to_le_bytes())
+    });
+
+    let storage = DiskStorage::open(config(&data_dir, CorruptRecordPolicy::Fail)).unwrap();
+    let err = storage.get_records(Offset(3), 1).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::CorruptRecord {
+            offset: Offset(3),
+            source: DecodeError::Truncated { .. },
+        }
+    ));
+    drop(storage);
+
+    let storage = DiskStorage::open(config(&data_dir, CorruptRecordPolicy::Skip)).unwrap();
+    let (read, _) = storage.get_records(Offset(0), 10).await.unwrap();
+    let expected: Vec<_> = records
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .map(|(_, record)| record.clone())
+        .collect();
+    assert_eq!(read, expected);
+}
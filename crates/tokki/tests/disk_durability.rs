@@ -0,0 +1,159 @@
+mod common;
+
+use std::{
+    fs::File,
+    io,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokki::storage::{DiskStorage, DurabilityPolicy, Storage, StorageError};
+use tokki_common::{Offset, Record};
+
+use crate::common::{disk_config, records};
+
+// Each test counts its own fsyncs, as tests run side by side
+static ALWAYS_SYNCS: AtomicUsize = AtomicUsize::new(0);
+static GROUP_SYNCS: AtomicUsize = AtomicUsize::new(0);
+static OS_SYNCS: AtomicUsize = AtomicUsize::new(0);
+
+fn always_sync(file: &File) -> io::Result<()> {
+    ALWAYS_SYNCS.fetch_add(1, Ordering::SeqCst);
+    file.sync_data()
+}
+
+fn group_sync(file: &File) -> io::Result<()> {
+    GROUP_SYNCS.fetch_add(1, Ordering::SeqCst);
+    file.sync_data()
+}
+
+fn os_sync(file: &File) -> io::Result<()> {
+    OS_SYNCS.fetch_add(1, Ordering::SeqCst);
+    file.sync_data()
+}
+
+fn failing_sync(_: &File) -> io::Result<()> {
+    Err(io::Error::other("Disk on fire"))
+}
+
+#[tokio::test]
+async fn always_syncs_every_put() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = disk_config(&data_dir)
+        .with_durability(DurabilityPolicy::Always)
+        .with_sync(always_sync);
+    let storage = DiskStorage::open(config).unwrap();
+
+    for (i, record) in records("record", 5).into_iter().enumerate() {
+        let offset = storage.put_record(record).await.unwrap();
+        assert_eq!(ALWAYS_SYNCS.load(Ordering::SeqCst), i + 1);
+        storage.wait_durable(offset).await.unwrap();
+    }
+
+    storage.put_records(records("batch", 5)).await.unwrap();
+    assert_eq!(ALWAYS_SYNCS.load(Ordering::SeqCst), 6);
+}
+
+#[tokio::test]
+async fn group_commit_shares_fsyncs_between_writers() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = disk_config(&data_dir)
+        .with_durability(DurabilityPolicy::GroupCommit {
+            max_delay: Duration::from_millis(50),
+            max_bytes: u64::MAX,
+        })
+        .with_sync(group_sync);
+    let storage = Arc::new(DiskStorage::open(config).unwrap());
+
+    let writers: Vec<_> = records("record", 20)
+        .into_iter()
+        .map(|record| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let offset = storage.put_record(record).await.unwrap();
+                storage.wait_durable(offset).await.unwrap();
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    let syncs = GROUP_SYNCS.load(Ordering::SeqCst);
+    assert!(
+        (1..20).contains(&syncs),
+        "Expected writers to share fsyncs, got {syncs}"
+    );
+}
+
+#[tokio::test]
+async fn group_commit_syncs_once_enough_bytes_are_pending() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let record = Record::new("key", "value");
+    let config = disk_config(&data_dir).with_durability(DurabilityPolicy::GroupCommit {
+        max_delay: Duration::from_secs(3600),
+        max_bytes: record.serialized_len() as u64,
+    });
+    let storage = DiskStorage::open(config).unwrap();
+
+    let offset = storage.put_record(record).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), storage.wait_durable(offset))
+        .await
+        .expect("Synced without waiting for the delay")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn os_never_syncs() {
+    let data_dir = tempfile::tempdir().unwrap();
+    // Rolling segments does not sync either
+    let config = disk_config(&data_dir)
+        .with_segment_bytes(1)
+        .with_durability(DurabilityPolicy::Os)
+        .with_sync(os_sync);
+    let storage = DiskStorage::open(config).unwrap();
+
+    for record in records("record", 5) {
+        let offset = storage.put_record(record).await.unwrap();
+        storage.wait_durable(offset).await.unwrap();
+    }
+    assert_eq!(OS_SYNCS.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn failed_fsyncs_fail_writes() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = disk_config(&data_dir)
+        .with_durability(DurabilityPolicy::Always)
+        .with_sync(failing_sync);
+    let storage = DiskStorage::open(config).unwrap();
+    assert!(matches!(
+        storage.put_record(Record::new("key", "value")).await,
+        Err(StorageError::Io { .. })
+    ));
+
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = disk_config(&data_dir)
+        .with_durability(DurabilityPolicy::GroupCommit {
+            max_delay: Duration::from_millis(10),
+            max_bytes: u64::MAX,
+        })
+        .with_sync(failing_sync);
+    let storage = DiskStorage::open(config).unwrap();
+
+    // Every waiter hears of the failure, including those that come after it
+    for _ in 0..2 {
+        let offset = storage
+            .put_record(Record::new("key", "value"))
+            .await
+            .unwrap();
+        let durable = tokio::time::timeout(Duration::from_secs(5), storage.wait_durable(offset))
+            .await
+            .expect("Waiters are told of the failure");
+        assert!(matches!(durable, Err(StorageError::Io { .. })));
+    }
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(1)));
+}
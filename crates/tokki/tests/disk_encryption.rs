@@ -0,0 +1,245 @@
+mod common;
+
+use std::fs;
+
+use tokki::storage::{
+    CompactionPolicy, DiskStorage, DiskStorageConfig, Keyring, KeyringError, Storage, StorageError,
+};
+use tokki_common::{Compression, Offset, Record, RecordBatch};
+
+use crate::common::disk_config;
+
+const KEY_1: [u8; 32] = [1; 32];
+const KEY_2: [u8; 32] = [2; 32];
+
+fn keyring(keys: &[(u32, [u8; 32])]) -> Keyring {
+    Keyring::new(keys.iter().copied()).unwrap()
+}
+
+fn config(data_dir: &tempfile::TempDir) -> DiskStorageConfig {
+    // Every write rolls a new segment, so each can be read on its own
+    disk_config(data_dir).with_segment_bytes(1)
+}
+
+fn personal(i: usize) -> Record {
+    Record::new(format!("user-{i}"), format!("jane.doe.{i}@example.com"))
+}
+
+/// Everything in the data directory's log files
+fn log_bytes(data_dir: &tempfile::TempDir) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for entry in fs::read_dir(data_dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "log") {
+            bytes.extend(fs::read(path).unwrap());
+        }
+    }
+    bytes
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[tokio::test]
+async fn encrypts_records_and_batches_at_rest() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(1, KEY_1)]))).unwrap();
+
+    storage
+        .put_records(vec![personal(0), personal(1)])
+        .await
+        .unwrap();
+    storage.put_record(personal(2)).await.unwrap();
+    storage
+        .put_batch(RecordBatch::new(&[personal(3), personal(4)], Compression::Lz4).unwrap())
+        .await
+        .unwrap();
+
+    let bytes = log_bytes(&data_dir);
+    assert!(!contains(&bytes, b"example.com"));
+    assert!(!contains(&bytes, b"user-"));
+
+    let written: Vec<_> = (0..5).map(personal).collect();
+    let (read, next_offset) = storage.get_records(Offset(0), 10).await.unwrap();
+    assert_eq!(read, written);
+    assert_eq!(next_offset, Offset(5));
+
+    // Followers have their own keys, so replication ships plaintext
+    let (batches, _) = storage.get_batches(Offset(1), 10).await.unwrap();
+    let replicated: Vec<_> = batches
+        .iter()
+        .flat_map(|(_, batch)| batch.records().unwrap())
+        .collect();
+    assert_eq!(replicated, written[1..]);
+
+    // Records are recovered whole when the log is reopened
+    drop(storage);
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(1, KEY_1)]))).unwrap();
+    assert_eq!(storage.get_records(Offset(0), 10).await.unwrap().0, written);
+}
+
+#[tokio::test]
+async fn old_segments_stay_readable_after_rotation() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(1, KEY_1)]))).unwrap();
+    storage.put_record(personal(0)).await.unwrap();
+    drop(storage);
+
+    let rotated = keyring(&[(1, KEY_1), (2, KEY_2)]);
+    assert_eq!(rotated.current_key_id(), 2);
+    let storage = DiskStorage::open(config(&data_dir).with_keyring(rotated)).unwrap();
+    storage.put_record(personal(1)).await.unwrap();
+    assert_eq!(
+        storage.get_records(Offset(0), 10).await.unwrap().0,
+        [personal(0), personal(1)]
+    );
+    drop(storage);
+
+    // Once the old key is gone only data written after the rotation can be read
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(2, KEY_2)]))).unwrap();
+    assert_eq!(
+        storage.get_records(Offset(1), 10).await.unwrap().0,
+        [personal(1)]
+    );
+    assert!(matches!(
+        storage.get_records(Offset(0), 10).await,
+        Err(StorageError::Encryption {
+            offset: Offset(0),
+            source: KeyringError::UnknownKey { key_id: 1 },
+        })
+    ));
+}
+
+#[tokio::test]
+async fn compaction_rewrites_with_current_key() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(1, KEY_1)]))).unwrap();
+    storage
+        .put_records(vec![personal(0), personal(1)])
+        .await
+        .unwrap();
+    // Supersedes the first record
+    storage.put_record(personal(0)).await.unwrap();
+    drop(storage);
+
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(1, KEY_1), (2, KEY_2)])))
+            .unwrap();
+    storage.compact(&CompactionPolicy::default()).await.unwrap();
+    drop(storage);
+
+    // The compacted segment no longer needs the old key
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(2, KEY_2)]))).unwrap();
+    assert_eq!(
+        storage.get_records(Offset(0), 1).await.unwrap().0,
+        [personal(1)]
+    );
+}
+
+#[tokio::test]
+async fn wrong_key_fails_authentication() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(1, KEY_1)]))).unwrap();
+    storage.put_record(personal(0)).await.unwrap();
+    drop(storage);
+
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(1, KEY_2)]))).unwrap();
+    assert!(matches!(
+        storage.get_records(Offset(0), 10).await,
+        Err(StorageError::Encryption {
+            source: KeyringError::Decrypt { key_id: 1 },
+            ..
+        })
+    ));
+
+    // Without any keys the data cannot be read either
+    drop(storage);
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    assert!(matches!(
+        storage.get_records(Offset(0), 10).await,
+        Err(StorageError::Encryption {
+            source: KeyringError::UnknownKey { key_id: 1 },
+            ..
+        })
+    ));
+}
+
+#[test]
+fn batches_only_decrypt_where_they_were_written() {
+    let keyring = keyring(&[(1, KEY_1)]);
+    let batch = RecordBatch::new(&[personal(0)], Compression::None).unwrap();
+    let encrypted = keyring.encrypt(Offset(10), Offset(12), &batch);
+    assert_eq!(
+        keyring.decrypt(Offset(10), Offset(12), &encrypted).unwrap(),
+        batch
+    );
+
+    // Copied to another offset, or to the same offset in another segment
+    for (segment_base, base_offset) in [(Offset(10), Offset(13)), (Offset(0), Offset(12))] {
+        assert!(matches!(
+            keyring.decrypt(segment_base, base_offset, &encrypted),
+            Err(KeyringError::Decrypt { key_id: 1 })
+        ));
+    }
+}
+
+#[tokio::test]
+async fn plaintext_segments_stay_readable_once_encrypted() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    storage.put_record(personal(0)).await.unwrap();
+    drop(storage);
+
+    let storage =
+        DiskStorage::open(config(&data_dir).with_keyring(keyring(&[(1, KEY_1)]))).unwrap();
+    storage.put_record(personal(1)).await.unwrap();
+    assert_eq!(
+        storage.get_records(Offset(0), 10).await.unwrap().0,
+        [personal(0), personal(1)]
+    );
+}
+
+#[test]
+fn parses_key_files() {
+    let key_file = format!(
+        "# Rotated 2024-01-01\n1 {}\n\n  2\t{}  \n",
+        hex(&KEY_1),
+        hex(&KEY_2)
+    );
+    let keyring = Keyring::parse(&key_file).unwrap();
+    assert_eq!(keyring.current_key_id(), 2);
+    assert_eq!(format!("{keyring:?}"), "Keyring { key_ids: [1, 2] }");
+
+    let cases = [
+        ("", "no keys"),
+        ("1", "missing key"),
+        ("1 abc extra", "extra field"),
+        ("one 00", "bad id"),
+        ("1 00", "short key"),
+        ("1 zz", "bad hex"),
+    ];
+    for (key_file, case) in cases {
+        assert!(Keyring::parse(key_file).is_err(), "{case}");
+    }
+
+    let duplicate = format!("1 {}\n1 {}", hex(&KEY_1), hex(&KEY_2));
+    assert!(matches!(
+        Keyring::parse(&duplicate),
+        Err(KeyringError::DuplicateKey { key_id: 1 })
+    ));
+}
+
+fn hex(key: &[u8]) -> String {
+    key.iter().map(|byte| format!("{byte:02x}")).collect()
+}
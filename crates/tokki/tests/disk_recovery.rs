@@ -0,0 +1,140 @@
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use tokki::storage::{DiskStorage, DiskStorageConfig, Storage};
+use tokki_common::{Offset, Record};
+
+use crate::common::{disk_config, records, written_log};
+
+const INDEX_INTERVAL_BYTES: u64 = 64;
+
+fn config(data_dir: &tempfile::TempDir) -> DiskStorageConfig {
+    disk_config(data_dir).with_index_interval_bytes(INDEX_INTERVAL_BYTES)
+}
+
+fn log_path(data_dir: &tempfile::TempDir) -> PathBuf {
+    data_dir.path().join(format!("{:020}.log", 0))
+}
+
+/// Write every record to a fresh log and return the raw bytes of the segment
+async fn segment_bytes(records: &[Record]) -> Vec<u8> {
+    let data_dir = tempfile::tempdir().unwrap();
+    written_log(config(&data_dir), records).await;
+    fs::read(log_path(&data_dir)).unwrap()
+}
+
+async fn assert_recovered(data_dir: &tempfile::TempDir, expected: &[Record], expected_len: u64) {
+    let storage = DiskStorage::open(config(data_dir)).unwrap();
+
+    let max_offset = storage.max_offset().await.unwrap();
+    assert_eq!(max_offset, expected.len().checked_sub(1).map(Offset));
+
+    let (read, next_offset) = storage.get_records(Offset(0), 100).await.unwrap();
+    assert_eq!(read, expected);
+    assert_eq!(next_offset, Offset(expected.len()));
+
+    let log_len = fs::metadata(log_path(data_dir)).unwrap().len();
+    assert_eq!(log_len, expected_len);
+
+    // The log must be appendable again after recovery
+    let record = Record::new("after", "recovery");
+    let offset = storage.put_record(record.clone()).await.unwrap();
+    assert_eq!(offset, Offset(expected.len()));
+    drop(storage);
+
+    let storage = DiskStorage::open(config(data_dir)).unwrap();
+    let (read, _) = storage.get_records(offset, 1).await.unwrap();
+    assert_eq!(read, vec![record]);
+}
+
+#[tokio::test]
+async fn recovers_from_truncation_at_every_byte() {
+    let records = records("record", 8);
+    let bytes = segment_bytes(&records).await;
+
+    for cut in 0..=bytes.len() {
+        let data_dir = tempfile::tempdir().unwrap();
+        fs::write(log_path(&data_dir), &bytes[..cut]).unwrap();
+
+        // Only records whose whole frame made it to disk survive
+        let mut complete = 0;
+        let mut complete_len = 0;
+        for record in &records {
+            let len = record.serialized_len();
+            if complete_len + len > cut {
+                break;
+            }
+            complete += 1;
+            complete_len += len;
+        }
+
+        assert_recovered(&data_dir, &records[..complete], complete_len as u64).await;
+    }
+}
+
+#[tokio::test]
+async fn discards_corrupt_tail() {
+    let records = records("record", 8);
+    let mut bytes = segment_bytes(&records).await;
+
+    let last_start = bytes.len() - records.last().unwrap().serialized_len();
+    // Flip the last byte of the final record's value, just before its
+    // checksum, so the checksum no longer matches
+    let value_end = bytes.len() - 9;
+    bytes[value_end] ^= 0xff;
+
+    let data_dir = tempfile::tempdir().unwrap();
+    fs::write(log_path(&data_dir), &bytes).unwrap();
+
+    assert_recovered(&data_dir, &records[..records.len() - 1], last_start as u64).await;
+}
+
+#[tokio::test]
+async fn rebuilds_torn_index() {
+    let records = records("record", 8);
+    let bytes = segment_bytes(&records).await;
+
+    let data_dir = tempfile::tempdir().unwrap();
+    fs::write(log_path(&data_dir), &bytes).unwrap();
+    // Half an index entry pointing way past the end of the log
+    fs::write(data_dir.path().join(format!("{:020}.index", 0)), [0xff; 12]).unwrap();
+
+    assert_recovered(&data_dir, &records, bytes.len() as u64).await;
+}
+
+#[tokio::test]
+async fn truncation_survives_reopening() {
+    let records = records("record", 8);
+    let data_dir = tempfile::tempdir().unwrap();
+    // Small segments so the truncation drops some whole and cuts one short
+    let config = || config(&data_dir).with_segment_bytes(48);
+    let storage = DiskStorage::open(config()).unwrap();
+    storage.put_records(records.clone()).await.unwrap();
+
+    storage.truncate_after(Some(Offset(4))).await.unwrap();
+    let record = Record::new("after", "truncation");
+    let offset = storage.put_record(record.clone()).await.unwrap();
+    assert_eq!(offset, Offset(5));
+    drop(storage);
+
+    // A rewrite that never reached its commit point is thrown away
+    fs::write(
+        data_dir.path().join(format!("{:020}.log.truncated", 0)),
+        b"partial",
+    )
+    .unwrap();
+
+    let storage = DiskStorage::open(config()).unwrap();
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(5)));
+    let (read, next_offset) = storage.get_records(Offset(0), 100).await.unwrap();
+    assert_eq!(read[..5], records[..5]);
+    assert_eq!(read[5..], [record]);
+    assert_eq!(next_offset, Offset(6));
+    assert!(
+        !data_dir
+            .path()
+            .join(format!("{:020}.log.truncated", 0))
+            .exists()
+    );
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use std::fs;
+
+use tokki::storage::{DiskStorage, DiskStorageConfig, RetentionPolicy, Storage};
+use tokki_common::Offset;
+
+use crate::common::{disk_config, records};
+
+fn config(data_dir: &tempfile::TempDir) -> DiskStorageConfig {
+    // A few records to a segment, so the log rolls over many of them
+    disk_config(data_dir)
+        .with_segment_bytes(100)
+        .with_index_interval_bytes(1)
+}
+
+#[tokio::test]
+async fn reads_back_what_was_put() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    assert_eq!(storage.max_offset().await.unwrap(), None);
+
+    let written = records("record", 20);
+    for (i, record) in written.iter().enumerate() {
+        let offset = storage.put_record(record.clone()).await.unwrap();
+        assert_eq!(offset, Offset(i));
+    }
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(19)));
+
+    let (read, next_offset) = storage.get_records(Offset(0), 100).await.unwrap();
+    assert_eq!(read, written);
+    assert_eq!(next_offset, Offset(20));
+
+    // From any offset, across segment boundaries
+    for offset in 0..20 {
+        let (read, next_offset) = storage.get_records(Offset(offset), 3).await.unwrap();
+        let end = (offset + 3).min(20);
+        assert_eq!(read, written[offset..end]);
+        assert_eq!(next_offset, Offset(end));
+    }
+
+    let (read, next_offset) = storage.get_records(Offset(20), 10).await.unwrap();
+    assert!(read.is_empty());
+    assert_eq!(next_offset, Offset(20));
+}
+
+#[tokio::test]
+async fn records_survive_reopening() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let written = records("record", 30);
+
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    storage.put_records(written[..10].to_vec()).await.unwrap();
+    drop(storage);
+
+    let segments = std::fs::read_dir(data_dir.path())
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "log")
+        })
+        .count();
+    assert!(segments > 1, "Expected several segments, found {segments}");
+
+    // Appends carry on from where the log ended, every time it is reopened
+    for chunk in written[10..].chunks(10) {
+        let storage = DiskStorage::open(config(&data_dir)).unwrap();
+        let next_offset = storage.max_offset().await.unwrap().unwrap() + 1;
+        let offsets = storage.put_records(chunk.to_vec()).await.unwrap();
+        assert_eq!(offsets, next_offset..next_offset + chunk.len());
+    }
+
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(29)));
+    let (read, next_offset) = storage.get_records(Offset(0), 100).await.unwrap();
+    assert_eq!(read, written);
+    assert_eq!(next_offset, Offset(30));
+
+    let (read, _) = storage.get_records(Offset(17), 2).await.unwrap();
+    assert_eq!(read, written[17..19]);
+}
+
+#[tokio::test]
+async fn empty_log_survives_reopening() {
+    let data_dir = tempfile::tempdir().unwrap();
+    drop(DiskStorage::open(config(&data_dir)).unwrap());
+
+    let storage = DiskStorage::open(config(&data_dir)).unwrap();
+    assert_eq!(storage.max_offset().await.unwrap(), None);
+    assert_eq!(
+        storage.put_records(records("record", 2)).await.unwrap(),
+        Offset(0)..Offset(2)
+    );
+}
+
+#[tokio::test]
+async fn retention_keeps_at_least_max_bytes() {
+    let data_dir = tempfile::tempdir().unwrap();
+    // A segment to each record, all the same size
+    let storage = DiskStorage::open(disk_config(&data_dir).with_segment_bytes(1)).unwrap();
+    storage.put_records(records("record", 1)).await.unwrap();
+    let segment_bytes = fs::read_dir(data_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "log"))
+        .map(|path| fs::metadata(path).unwrap().len())
+        .unwrap();
+    for record in records("record", 5).into_iter().skip(1) {
+        storage.put_record(record).await.unwrap();
+    }
+
+    // Just over two segments keeps three, rather than dropping below the limit
+    let policy = |max_bytes| RetentionPolicy {
+        max_bytes: Some(max_bytes),
+        ..RetentionPolicy::default()
+    };
+    let log_start = storage
+        .enforce_retention(&policy(2 * segment_bytes + 1))
+        .await
+        .unwrap();
+    assert_eq!(log_start, Offset(2));
+
+    // Exactly two segments is within the limit
+    let log_start = storage
+        .enforce_retention(&policy(2 * segment_bytes))
+        .await
+        .unwrap();
+    assert_eq!(log_start, Offset(3));
+}
@@ -0,0 +1,377 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokki::{
+    app_state::AppState,
+    election::{ElectionConfig, Role},
+};
+use tokki_api::{
+    TokkiClient,
+    cluster::ClusterMembers,
+    clustering::{LeaderHeartbeatRequest, PartitionLog, VoteRequest},
+    get_records::GetRecordsRequest,
+    put_record::PutRecordsRequest,
+    topics::{CreateTopicRequest, TopicConfig},
+};
+use tokki_common::{Offset, Record};
+use url::Url;
+
+use crate::common::{TOKEN, bind, eventually, serve, topics, url};
+
+struct Node {
+    url: Url,
+    client: TokkiClient,
+    state: AppState,
+    server: JoinHandle<()>,
+}
+
+impl Node {
+    fn role(&self) -> Role {
+        self.state.election().unwrap().role()
+    }
+
+    fn term(&self) -> u64 {
+        self.state.election().unwrap().term()
+    }
+
+    /// Take the node down as if it crashed
+    fn stop(&self) {
+        self.server.abort();
+        self.state.election().unwrap().stop(&self.state);
+    }
+}
+
+/// Start `size` nodes electing a leader among themselves, each waiting for one follower
+async fn start_cluster(size: usize) -> Vec<Node> {
+    let mut listeners = Vec::new();
+    for _ in 0..size {
+        let (listener, addr) = bind().await;
+        listeners.push((listener, url(addr)));
+    }
+    let urls: Vec<_> = listeners.iter().map(|(_, url)| url.clone()).collect();
+
+    let mut nodes = Vec::new();
+    for (listener, url) in listeners {
+        let peers = urls.iter().filter(|peer| **peer != url).cloned().collect();
+        let election = ElectionConfig::new(url.clone(), peers)
+            .with_election_timeout(Duration::from_millis(300))
+            .with_heartbeat_interval(Duration::from_millis(50));
+        let state = AppState::builder()
+            .elected()
+            .with_token(TOKEN)
+            .with_topics(topics(1).await)
+            .with_election(election)
+            .build()
+            .unwrap();
+        let (client, server) = serve(listener, state.clone());
+        nodes.push(Node {
+            client,
+            url,
+            server,
+            state,
+        });
+    }
+    nodes
+}
+
+/// Wait for one of `nodes` to lead and the rest to follow it, returning the leader's index
+async fn settled(nodes: &[&Node]) -> usize {
+    eventually(|| async {
+        let leaders: Vec<_> = (0..nodes.len())
+            .filter(|index| nodes[*index].role() == Role::Leader)
+            .collect();
+        let [leader] = leaders[..] else {
+            return None;
+        };
+        let following = Role::Follower {
+            leader: Some(nodes[leader].url.clone()),
+        };
+        nodes
+            .iter()
+            .enumerate()
+            .all(|(index, node)| index == leader || node.role() == following)
+            .then_some(leader)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn a_new_leader_takes_over_when_the_leader_is_lost() {
+    let nodes = start_cluster(3).await;
+    let all: Vec<_> = nodes.iter().collect();
+    let leader = settled(&all).await;
+    let first_term = nodes[leader].term();
+    let follower = &nodes[(leader + 1) % 3];
+
+    // Every node reports the elected leader, and forwards to it
+    let shards = follower.client.get_shards().await;
+    assert!(shards.is_ok());
+    eventually(|| async {
+        follower
+            .client
+            .create_topic(CreateTopicRequest::new("events"))
+            .await
+            .ok()
+    })
+    .await;
+    follower
+        .client
+        .put_record(PutRecordsRequest::single(
+            "events",
+            Record::new("key", "before"),
+        ))
+        .await
+        .unwrap();
+
+    nodes[leader].stop();
+    let remaining: Vec<_> = nodes
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != leader)
+        .map(|(_, node)| node)
+        .collect();
+    let new_leader = remaining[settled(&remaining).await];
+    assert!(new_leader.term() > first_term);
+
+    // Committed records survive the old leader, and the new one takes puts
+    // once the remaining follower is copying from it
+    let put = eventually(|| async {
+        let req = PutRecordsRequest::single("events", Record::new("key", "after"));
+        remaining[0].client.put_record(req).await.ok()
+    })
+    .await;
+    assert_eq!(put.partitions[0].offset, Offset(1));
+
+    let read = new_leader
+        .client
+        .get_records(GetRecordsRequest::new("events", Offset(0), 10))
+        .await
+        .unwrap();
+    let values: Vec<_> = read.records().iter().map(Record::value).collect();
+    assert_eq!(values, [b"before".as_slice(), b"after"]);
+
+    let shards = remaining[0].client.get_shards().await.unwrap();
+    assert_eq!(
+        shards.topics[0].partitions[0].leader,
+        Some(new_leader.url.to_string())
+    );
+}
+
+#[tokio::test]
+async fn elections_are_held_among_the_members() {
+    let nodes = start_cluster(5).await;
+    let all: Vec<_> = nodes.iter().collect();
+    let leader = &nodes[settled(&all).await];
+
+    // Two nodes are lost and removed, leaving a majority of three that no
+    // longer counts them
+    let followers: Vec<_> = nodes.iter().filter(|node| node.url != leader.url).collect();
+    let (lost, remaining) = followers.split_at(2);
+    for node in lost {
+        node.stop();
+        let membership = leader.state.membership();
+        membership
+            .remove(leader.state.topics(), node.url.as_str(), Some(&leader.url))
+            .unwrap();
+        eventually(|| async { membership.members().joining.is_none().then_some(()) }).await;
+    }
+    let members = leader.state.membership().members();
+    for node in remaining {
+        eventually(|| async { (node.state.membership().members() == members).then_some(()) }).await;
+    }
+
+    leader.stop();
+    let new_leader = remaining[settled(remaining).await];
+    assert!(new_leader.term() > leader.term());
+}
+
+#[tokio::test]
+async fn puts_waiting_on_followers_fail_when_the_leader_steps_down() {
+    let nodes = start_cluster(3).await;
+    let all: Vec<_> = nodes.iter().collect();
+    let leader = &nodes[settled(&all).await];
+    leader
+        .client
+        .create_topic(CreateTopicRequest::new("events"))
+        .await
+        .unwrap();
+
+    // The followers stop copying, but stay in sync for a while yet
+    for node in nodes.iter().filter(|node| node.url != leader.url) {
+        node.stop();
+    }
+    let req = PutRecordsRequest::single("events", Record::new("key", "value"));
+    let put = tokio::spawn({
+        let client = leader.client.clone();
+        async move { client.put_record(req).await }
+    });
+    let topic = leader.state.topic("events").unwrap();
+    let replication = topic.partition(0).unwrap().replication();
+    eventually(|| async { (replication.lock().unwrap().log_end() == Offset(1)).then_some(()) })
+        .await;
+
+    // Straight away, rather than once the wait times out
+    let started = std::time::Instant::now();
+    leader.state.election().unwrap().stop(&leader.state);
+    let err = put.await.unwrap().unwrap_err();
+    assert!(err.to_string().contains("Stopped leading"), "{err}");
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn votes_go_once_a_term_to_candidates_that_are_up_to_date() {
+    let state_dir = tempfile::tempdir().unwrap();
+    let (listener, addr) = bind().await;
+    let node_url = url(addr);
+    // The only peer never answers and the timeout is long, so the node never
+    // stands for election itself
+    let election = || {
+        ElectionConfig::new(
+            node_url.clone(),
+            vec![Url::parse("http://127.0.0.1:9").unwrap()],
+        )
+        .with_election_timeout(Duration::from_secs(60))
+        .with_state_dir(state_dir.path())
+    };
+    let topics = topics(1).await;
+    let state = AppState::builder()
+        .elected()
+        .with_token(TOKEN)
+        .with_topics(topics.clone())
+        .with_election(election())
+        .build()
+        .unwrap();
+    let (client, _server) = serve(listener, state.clone());
+
+    let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+    let records = vec![Record::new("key", "value"); 5];
+    let storage = topic.partition(0).unwrap().storage();
+    storage.put_records(records).await.unwrap();
+
+    let vote = |term, candidate: &str, last_epoch, log_end| {
+        let log = PartitionLog::new("events", 0, last_epoch, Offset(log_end));
+        let req = VoteRequest::new(term, candidate.to_string(), vec![log]);
+        let client = client.clone();
+        async move {
+            let res = client.request_vote(req, TOKEN).await.unwrap();
+            res.into_verified(TOKEN).unwrap()
+        }
+    };
+
+    // Behind this node's log
+    let res = vote(1, "http://behind", None, 4).await;
+    assert!(!res.vote_granted);
+    assert_eq!(res.term, 1);
+
+    let res = vote(1, "http://first", None, 5).await;
+    assert!(res.vote_granted);
+    // Only one vote a term, though the same candidate may ask again
+    assert!(!vote(1, "http://second", None, 9).await.vote_granted);
+    assert!(vote(1, "http://first", None, 5).await.vote_granted);
+
+    // A later leader epoch wins out over a longer log
+    let res = vote(2, "http://second", Some(1), 1).await;
+    assert!(res.vote_granted);
+    assert_eq!(res.term, 2);
+
+    // Old leaders are turned away, current ones followed along with their members
+    let heartbeat = |term, leader: &str| {
+        let members = ClusterMembers::new([leader.to_string()]);
+        let req = LeaderHeartbeatRequest::new(term, leader.to_string(), members);
+        let client = client.clone();
+        async move {
+            let res = client.send_leader_heartbeat(req, TOKEN).await.unwrap();
+            res.into_verified(TOKEN).unwrap()
+        }
+    };
+    let members = state.membership().members();
+    assert!(!heartbeat(1, "http://first").await.accepted);
+    assert_eq!(state.membership().members(), members);
+    assert!(heartbeat(2, "http://second").await.accepted);
+    assert_eq!(
+        state.membership().members(),
+        ClusterMembers::new(["http://second"])
+    );
+    assert_eq!(
+        state.election().unwrap().role(),
+        Role::Follower {
+            leader: Some(Url::parse("http://second").unwrap())
+        }
+    );
+    assert_eq!(
+        state.leader_url(),
+        Some(Url::parse("http://second").unwrap())
+    );
+
+    // The term and vote survive a restart
+    state.election().unwrap().stop(&state);
+    let restarted = AppState::builder()
+        .elected()
+        .with_token(TOKEN)
+        .with_topics(topics)
+        .with_election(election())
+        .build()
+        .unwrap();
+    assert_eq!(restarted.election().unwrap().term(), 2);
+    assert_eq!(restarted.leader_url(), None);
+}
+
+#[tokio::test]
+async fn votes_need_candidates_up_to_date_on_every_partition() {
+    let (listener, addr) = bind().await;
+    let election = ElectionConfig::new(url(addr), vec![Url::parse("http://127.0.0.1:9").unwrap()])
+        .with_election_timeout(Duration::from_secs(60));
+    let topics = topics(1).await;
+    let state = AppState::builder()
+        .elected()
+        .with_token(TOKEN)
+        .with_topics(topics.clone())
+        .with_election(election)
+        .build()
+        .unwrap();
+    let (client, _server) = serve(listener, state.clone());
+
+    // This node last led partition 0 in epoch 1, and followed another leader
+    // on partition 1 in epoch 2
+    let config = TopicConfig::new().with_partitions(2);
+    let topic = topics.create("events", config).await.unwrap();
+    for (index, epoch, count) in [(0, 1, 5), (1, 2, 3)] {
+        let partition = topic.partition(index).unwrap();
+        partition.begin_epoch(epoch).await.unwrap();
+        let records = vec![Record::new("key", "value"); count];
+        partition.storage().put_records(records).await.unwrap();
+    }
+
+    let vote = |term, candidate: &str, logs: [(Option<u64>, usize); 2]| {
+        let logs = (0..)
+            .zip(logs)
+            .map(|(index, (last_epoch, log_end))| {
+                PartitionLog::new("events", index, last_epoch, Offset(log_end))
+            })
+            .collect();
+        let req = VoteRequest::new(term, candidate.to_string(), logs);
+        let client = client.clone();
+        async move {
+            let res = client.request_vote(req, TOKEN).await.unwrap();
+            res.into_verified(TOKEN).unwrap()
+        }
+    };
+
+    // Ahead on partition 0 and with more records all told, but missing the
+    // later epoch of partition 1
+    let res = vote(1, "http://lopsided", [(Some(1), 50), (Some(1), 10)]).await;
+    assert!(!res.vote_granted);
+    // A candidate without partition 1 at all is behind on it too
+    let req = VoteRequest::new(
+        2,
+        "http://partial".to_string(),
+        vec![PartitionLog::new("events", 0, Some(1), Offset(5))],
+    );
+    let res = client.request_vote(req, TOKEN).await.unwrap();
+    assert!(!res.into_verified(TOKEN).unwrap().vote_granted);
+
+    let res = vote(3, "http://caught-up", [(Some(1), 5), (Some(2), 3)]).await;
+    assert!(res.vote_granted);
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+use tokki::groups::assign;
+use tokki_api::groups::AssignmentStrategy;
+
+const STRATEGIES: [AssignmentStrategy; 3] = [
+    AssignmentStrategy::Range,
+    AssignmentStrategy::RoundRobin,
+    AssignmentStrategy::Sticky,
+];
+
+fn members(ids: impl IntoIterator<Item = usize>) -> Vec<String> {
+    ids.into_iter()
+        .map(|id| format!("member-{id:03}"))
+        .collect()
+}
+
+/// Every partition goes to exactly one member, and shares differ by at most one
+fn assert_balanced(partitions: u32, members: &[String], assignment: &BTreeMap<String, Vec<u32>>) {
+    assert_eq!(
+        assignment.keys().collect::<Vec<_>>(),
+        members.iter().collect::<Vec<_>>()
+    );
+
+    let mut assigned: Vec<u32> = assignment.values().flatten().copied().collect();
+    assigned.sort_unstable();
+    assert_eq!(assigned, (0..partitions).collect::<Vec<_>>());
+
+    let shares = assignment.values().map(Vec::len);
+    let (min, max) = (shares.clone().min().unwrap(), shares.max().unwrap());
+    assert!(max - min <= 1, "{assignment:?}");
+}
+
+#[test]
+fn range_hands_out_neighbouring_partitions() {
+    let assignment = assign(
+        AssignmentStrategy::Range,
+        7,
+        &members(0..3),
+        &BTreeMap::new(),
+    );
+    let shares: Vec<_> = assignment.into_values().collect();
+    assert_eq!(shares, [vec![0, 1, 2], vec![3, 4], vec![5, 6]]);
+}
+
+#[test]
+fn round_robin_deals_partitions_in_turn() {
+    let assignment = assign(
+        AssignmentStrategy::RoundRobin,
+        7,
+        &members(0..3),
+        &BTreeMap::new(),
+    );
+    let shares: Vec<_> = assignment.into_values().collect();
+    assert_eq!(shares, [vec![0, 3, 6], vec![1, 4], vec![2, 5]]);
+}
+
+#[test]
+fn members_beyond_the_partition_count_get_nothing() {
+    for strategy in STRATEGIES {
+        let assignment = assign(strategy, 2, &members(0..4), &BTreeMap::new());
+        assert_balanced(2, &members(0..4), &assignment);
+    }
+}
+
+proptest! {
+    #[test]
+    fn every_strategy_is_balanced(
+        partitions in 0u32..64,
+        member_count in 1usize..12,
+    ) {
+        for strategy in STRATEGIES {
+            let members = members(0..member_count);
+            let assignment = assign(strategy, partitions, &members, &BTreeMap::new());
+            assert_balanced(partitions, &members, &assignment);
+        }
+    }
+
+    #[test]
+    fn sticky_keeps_partitions_where_it_can(
+        partitions in 1u32..64,
+        before in proptest::collection::btree_set(0usize..16, 1..10),
+        after in proptest::collection::btree_set(0usize..16, 1..10),
+    ) {
+        let before = members(before);
+        let after = members(after);
+        let previous = assign(AssignmentStrategy::Sticky, partitions, &before, &BTreeMap::new());
+        let next = assign(AssignmentStrategy::Sticky, partitions, &after, &previous);
+        assert_balanced(partitions, &after, &next);
+
+        // A member staying on only gives up partitions beyond an even share
+        let share = partitions as usize / after.len();
+        for (member, owned) in &next {
+            let Some(had) = previous.get(member) else { continue };
+            let kept = had.iter().filter(|partition| owned.contains(partition)).count();
+            prop_assert!(kept >= had.len().min(share), "{previous:?} -> {next:?}");
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::{sync::Arc, time::Duration};
+
+use tokki::{
+    groups::{CONSUMER_OFFSETS_TOPIC, ConsumerGroups, GroupError},
+    storage::DiskStorageConfig,
+    topics::{Topic, TopicError, TopicStorage, Topics, TopicsConfig},
+};
+use tokki_api::{
+    groups::{AssignmentStrategy, PartitionLag, PartitionOffset},
+    topics::TopicConfig,
+};
+use tokki_common::{Offset, Record};
+
+async fn open_topics(data_dir: &tempfile::TempDir) -> Arc<Topics> {
+    let storage = DiskStorageConfig::new(data_dir.path().join("topics"));
+    let config = TopicsConfig::new(TopicStorage::Disk(storage)).with_metadata_dir(data_dir.path());
+    Arc::new(Topics::open(config).await.unwrap())
+}
+
+/// Commit the way the leader does, by putting the commit records on the
+/// offsets topic, which commits them as no replicas are required
+async fn commit(groups: &ConsumerGroups, group: &str, topic: &Topic, offsets: &[PartitionOffset]) {
+    let records = groups.commit_records(group, topic, offsets).unwrap();
+    let offsets_topic = groups.offsets_topic().await.unwrap();
+    let partition = offsets_topic.partition(0).unwrap();
+    let offsets = partition.storage().put_records(records).await.unwrap();
+    partition
+        .replication()
+        .lock()
+        .unwrap()
+        .update_log_end(offsets.end);
+}
+
+#[tokio::test]
+async fn latest_commit_wins_per_group_and_partition() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics = open_topics(&data_dir).await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let topic = topics
+        .create("events", TopicConfig::new().with_partitions(3))
+        .await
+        .unwrap();
+
+    // Nothing committed, not even an offsets topic yet
+    assert_eq!(groups.committed("billing", &topic).await.unwrap(), []);
+
+    commit(
+        &groups,
+        "billing",
+        &topic,
+        &[PartitionOffset::new(0, Offset(5))],
+    )
+    .await;
+    commit(
+        &groups,
+        "billing",
+        &topic,
+        &[
+            PartitionOffset::new(2, Offset(7)),
+            PartitionOffset::new(0, Offset(9)),
+        ],
+    )
+    .await;
+    commit(
+        &groups,
+        "audit",
+        &topic,
+        &[PartitionOffset::new(1, Offset(1))],
+    )
+    .await;
+
+    assert_eq!(
+        groups.committed("billing", &topic).await.unwrap(),
+        [
+            PartitionOffset::new(0, Offset(9)),
+            PartitionOffset::new(2, Offset(7)),
+        ]
+    );
+    assert_eq!(
+        groups.committed("audit", &topic).await.unwrap(),
+        [PartitionOffset::new(1, Offset(1))]
+    );
+}
+
+#[tokio::test]
+async fn commits_survive_a_restart() {
+    let data_dir = tempfile::tempdir().unwrap();
+    {
+        let topics = open_topics(&data_dir).await;
+        let groups = ConsumerGroups::new(topics.clone());
+        let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+        commit(
+            &groups,
+            "billing",
+            &topic,
+            &[PartitionOffset::new(0, Offset(3))],
+        )
+        .await;
+        commit(
+            &groups,
+            "billing",
+            &topic,
+            &[PartitionOffset::new(0, Offset(4))],
+        )
+        .await;
+    }
+
+    let topics = open_topics(&data_dir).await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let topic = topics.get("events").unwrap();
+    assert_eq!(
+        groups.committed("billing", &topic).await.unwrap(),
+        [PartitionOffset::new(0, Offset(4))]
+    );
+}
+
+#[tokio::test]
+async fn recreated_topics_start_without_commits() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics = open_topics(&data_dir).await;
+    let groups = ConsumerGroups::new(topics.clone());
+
+    let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+    commit(
+        &groups,
+        "billing",
+        &topic,
+        &[PartitionOffset::new(0, Offset(3))],
+    )
+    .await;
+    topics.delete("events").await.unwrap();
+
+    let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+    assert_eq!(groups.committed("billing", &topic).await.unwrap(), []);
+}
+
+#[tokio::test]
+async fn commits_count_once_they_are_committed() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics = open_topics(&data_dir).await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+
+    // Put on the leader but not yet on enough replicas, as when the put times out
+    let offsets = [PartitionOffset::new(0, Offset(5))];
+    let records = groups.commit_records("billing", &topic, &offsets).unwrap();
+    let offsets_topic = groups.offsets_topic().await.unwrap();
+    let partition = offsets_topic.partition(0).unwrap();
+    let put = partition.storage().put_records(records).await.unwrap();
+    assert_eq!(groups.committed("billing", &topic).await.unwrap(), []);
+
+    partition
+        .replication()
+        .lock()
+        .unwrap()
+        .update_log_end(put.end);
+    assert_eq!(groups.committed("billing", &topic).await.unwrap(), offsets);
+}
+
+#[tokio::test]
+async fn commits_truncated_away_are_forgotten() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics = open_topics(&data_dir).await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+
+    let offsets = [PartitionOffset::new(0, Offset(5))];
+    commit(&groups, "billing", &topic, &offsets).await;
+    assert_eq!(groups.committed("billing", &topic).await.unwrap(), offsets);
+
+    // As a follower does when the new leader never had the commit
+    let offsets_topic = groups.offsets_topic().await.unwrap();
+    let partition = offsets_topic.partition(0).unwrap();
+    partition.truncate(Offset(0)).await.unwrap();
+    assert_eq!(groups.committed("billing", &topic).await.unwrap(), []);
+
+    // The new leader's commits take the offsets the old ones had
+    let offsets = [PartitionOffset::new(0, Offset(3))];
+    commit(&groups, "billing", &topic, &offsets).await;
+    assert_eq!(groups.committed("billing", &topic).await.unwrap(), offsets);
+}
+
+#[tokio::test]
+async fn commits_are_checked() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics = open_topics(&data_dir).await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+
+    assert!(matches!(
+        groups.commit_records("a/b", &topic, &[PartitionOffset::new(0, Offset(1))]),
+        Err(GroupError::InvalidName { .. })
+    ));
+    assert!(matches!(
+        groups.commit_records("billing", &topic, &[PartitionOffset::new(1, Offset(1))]),
+        Err(GroupError::Topic {
+            source: TopicError::PartitionNotFound { .. }
+        })
+    ));
+
+    // The offsets topic belongs to the server
+    groups.offsets_topic().await.unwrap();
+    assert!(matches!(
+        topics.delete(CONSUMER_OFFSETS_TOPIC).await,
+        Err(TopicError::ReservedName { .. })
+    ));
+    assert!(matches!(
+        topics.create("__mine", TopicConfig::new()).await,
+        Err(TopicError::ReservedName { .. })
+    ));
+}
+
+async fn in_memory_topics() -> Arc<Topics> {
+    let config = TopicsConfig::new(TopicStorage::InMemoryMutex);
+    Arc::new(Topics::open(config).await.unwrap())
+}
+
+const SESSION: Duration = Duration::from_secs(10);
+
+#[tokio::test(start_paused = true)]
+async fn members_split_the_partitions() {
+    let topics = in_memory_topics().await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let topic = topics
+        .create("events", TopicConfig::new().with_partitions(4))
+        .await
+        .unwrap();
+
+    let first = groups
+        .join("billing", &topic, None, AssignmentStrategy::Range, SESSION)
+        .unwrap();
+    assert_eq!(first.partitions, [0, 1, 2, 3]);
+
+    let second = groups
+        .join(
+            "billing",
+            &topic,
+            Some("second"),
+            AssignmentStrategy::Range,
+            SESSION,
+        )
+        .unwrap();
+    assert_eq!(second.generation, first.generation + 1);
+    assert_eq!(second.member_id, "second");
+
+    // The first member learns of the rebalance from its next heartbeat
+    let first = groups.heartbeat("billing", &first.member_id).unwrap();
+    assert_eq!(first.generation, second.generation);
+    let mut assigned = [first.partitions.clone(), second.partitions.clone()].concat();
+    assigned.sort_unstable();
+    assert_eq!(assigned, [0, 1, 2, 3]);
+    assert_eq!(first.partitions.len(), 2);
+
+    // Rejoining under the same id changes nothing
+    let again = groups
+        .join(
+            "billing",
+            &topic,
+            Some("second"),
+            AssignmentStrategy::Range,
+            SESSION,
+        )
+        .unwrap();
+    assert_eq!(again, second);
+
+    let description = groups.leave("billing", "second").unwrap();
+    assert_eq!(description.members.len(), 1);
+    assert_eq!(description.members[0].partitions, [0, 1, 2, 3]);
+    assert_eq!(description.generation, second.generation + 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_members_time_out() {
+    let topics = in_memory_topics().await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let topic = topics
+        .create("events", TopicConfig::new().with_partitions(2))
+        .await
+        .unwrap();
+
+    let strategy = AssignmentStrategy::Sticky;
+    let alive = groups
+        .join("billing", &topic, Some("alive"), strategy, SESSION)
+        .unwrap();
+    groups
+        .join("billing", &topic, Some("silent"), strategy, SESSION)
+        .unwrap();
+    let alive = groups.heartbeat("billing", &alive.member_id).unwrap();
+    assert_eq!(alive.partitions.len(), 1);
+
+    for _ in 0..3 {
+        tokio::time::advance(SESSION / 2).await;
+        groups.heartbeat("billing", "alive").unwrap();
+    }
+
+    let alive = groups.heartbeat("billing", "alive").unwrap();
+    assert_eq!(alive.partitions, [0, 1]);
+    assert!(matches!(
+        groups.heartbeat("billing", "silent"),
+        Err(GroupError::UnknownMember { .. })
+    ));
+
+    // Once everyone has gone the group can move on to another topic
+    tokio::time::advance(SESSION * 2).await;
+    let other = topics.create("other", TopicConfig::new()).await.unwrap();
+    let joined = groups
+        .join("billing", &other, None, AssignmentStrategy::Range, SESSION)
+        .unwrap();
+    assert!(joined.generation > alive.generation);
+    assert_eq!(joined.partitions, [0]);
+}
+
+#[tokio::test]
+async fn joins_are_checked() {
+    let topics = in_memory_topics().await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+    let other = topics.create("other", TopicConfig::new()).await.unwrap();
+    let range = AssignmentStrategy::Range;
+
+    groups
+        .join("billing", &topic, None, range, SESSION)
+        .unwrap();
+    assert!(matches!(
+        groups.join("billing", &other, None, range, SESSION),
+        Err(GroupError::Mismatch { .. })
+    ));
+    assert!(matches!(
+        groups.join("billing", &topic, None, AssignmentStrategy::Sticky, SESSION),
+        Err(GroupError::Mismatch { .. })
+    ));
+    assert!(matches!(
+        groups.join("billing", &topic, None, range, Duration::from_millis(1)),
+        Err(GroupError::InvalidSessionTimeout { .. })
+    ));
+    assert!(matches!(
+        groups.join("billing", &topic, Some("a b"), range, SESSION),
+        Err(GroupError::InvalidMemberId { .. })
+    ));
+    assert!(matches!(
+        groups.heartbeat("nobody", "member"),
+        Err(GroupError::UnknownGroup { .. })
+    ));
+}
+
+#[tokio::test]
+async fn lag_is_measured_from_the_end_of_each_partition() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics = open_topics(&data_dir).await;
+    let groups = ConsumerGroups::new(topics.clone());
+    let events = topics
+        .create("events", TopicConfig::new().with_partitions(2))
+        .await
+        .unwrap();
+    let orders = topics.create("orders", TopicConfig::new()).await.unwrap();
+    for (partition, count) in [(0, 10), (1, 4)] {
+        let records = vec![Record::new("key", "value"); count];
+        let storage = events.partition(partition).unwrap().storage();
+        storage.put_records(records).await.unwrap();
+    }
+
+    // Nothing committed, so nothing to report without a topic
+    assert_eq!(groups.lag("billing", None).await.unwrap(), []);
+
+    commit(
+        &groups,
+        "billing",
+        &events,
+        &[PartitionOffset::new(0, Offset(6))],
+    )
+    .await;
+    commit(
+        &groups,
+        "billing",
+        &orders,
+        &[PartitionOffset::new(0, Offset(0))],
+    )
+    .await;
+
+    let expected_events = [
+        PartitionLag::new("events", 0, Offset(10), Some(Offset(6))),
+        PartitionLag::new("events", 1, Offset(4), None),
+    ];
+    let lag = groups.lag("billing", None).await.unwrap();
+    assert_eq!(lag[..2], expected_events);
+    assert_eq!(
+        lag[2],
+        PartitionLag::new("orders", 0, Offset(0), Some(Offset(0)))
+    );
+    assert_eq!(lag[0].lag, Some(4));
+    assert_eq!(lag[1].lag, None);
+    assert_eq!(lag[2].lag, Some(0));
+
+    let lag = groups.lag("billing", Some(events.clone())).await.unwrap();
+    assert_eq!(lag, expected_events);
+
+    let committed = groups.committed_topics(None).await.unwrap();
+    let names: Vec<_> = committed
+        .iter()
+        .map(|(group, topic)| (group.as_str(), topic.name()))
+        .collect();
+    assert_eq!(names, [("billing", "events"), ("billing", "orders")]);
+
+    // Commits for a deleted topic are no longer reported
+    topics.delete("orders").await.unwrap();
+    assert_eq!(groups.lag("billing", None).await.unwrap(), expected_events);
+    assert!(matches!(
+        groups.lag("bad/name", None).await,
+        Err(GroupError::InvalidName { .. })
+    ));
+}
@@ -0,0 +1,194 @@
+//! Model tests for the lock-free engine, checking every interleaving of its
+//! writers and readers.
+//!
+//! Run with `scripts/loom.sh`, which builds with `--cfg tokki_loom` and
+//! keeps the target features from `.cargo/config.toml` that `RUSTFLAGS`
+//! overrides.
+#![cfg(tokki_loom)]
+
+mod common;
+
+use loom::{future::block_on, thread};
+use tokki::storage::{InMemoryLockFree, RetentionPolicy, Storage, StorageError};
+use tokki_common::{Offset, Record};
+
+use crate::common::{decode, records};
+
+/// Every record read must be one that was written, in full, and records put
+/// together must be read together
+fn assert_whole_puts(read: &[Record], puts: &[Vec<Record>]) {
+    let mut read = read;
+    while !read.is_empty() {
+        let put = puts
+            .iter()
+            .find(|put| read.starts_with(put))
+            .unwrap_or_else(|| panic!("Read records that were never put: {read:?}"));
+        read = &read[put.len()..];
+    }
+}
+
+#[test]
+fn readers_never_see_partial_records() {
+    loom::model(|| {
+        let storage = InMemoryLockFree::new();
+        let puts = [records("writer-0", 2), records("writer-1", 2)];
+
+        // Each put spans a whole chunk
+        let writers: Vec<_> = puts
+            .iter()
+            .cloned()
+            .map(|put| {
+                let storage = storage.clone();
+                thread::spawn(move || block_on(storage.put_records(put)).unwrap())
+            })
+            .collect();
+
+        let (read, _) = block_on(storage.get_records(Offset(0), 10)).unwrap();
+        assert_whole_puts(&read, &puts);
+        let (frames, next_offset) = block_on(storage.get_frames(Offset(0), 10)).unwrap();
+        assert_eq!(next_offset, Offset(frames.record_count()));
+        assert_whole_puts(&decode(&frames, next_offset), &puts);
+
+        let mut ranges: Vec<_> = writers.into_iter().map(|w| w.join().unwrap()).collect();
+        ranges.sort_by_key(|range| range.start);
+        assert_eq!(ranges, [Offset(0)..Offset(2), Offset(2)..Offset(4)]);
+
+        let (read, _) = block_on(storage.get_records(Offset(0), 10)).unwrap();
+        assert_eq!(read.len(), 4);
+        assert_whole_puts(&read, &puts);
+    });
+}
+
+#[test]
+fn writers_racing_into_a_new_chunk_share_it() {
+    loom::model(|| {
+        let storage = InMemoryLockFree::new();
+        let puts = [records("writer-0", 1), records("writer-1", 1)];
+
+        // Both puts land in the first chunk, whichever of them allocates it
+        let writers: Vec<_> = puts
+            .iter()
+            .cloned()
+            .map(|put| {
+                let storage = storage.clone();
+                thread::spawn(move || block_on(storage.put_records(put)).unwrap())
+            })
+            .collect();
+
+        let (read, _) = block_on(storage.get_records(Offset(0), 10)).unwrap();
+        assert_whole_puts(&read, &puts);
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let (read, _) = block_on(storage.get_records(Offset(0), 10)).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_whole_puts(&read, &puts);
+    });
+}
+
+#[test]
+fn full_log_rejects_puts_without_reserving() {
+    loom::model(|| {
+        let storage = InMemoryLockFree::new();
+        let puts = [
+            records("writer-0", 6),
+            records("writer-1", 2),
+            records("writer-2", 1),
+        ];
+        block_on(storage.put_records(puts[0].clone())).unwrap();
+
+        // Only one of the two fits in what is left of the log
+        let writer = {
+            let storage = storage.clone();
+            let put = puts[1].clone();
+            thread::spawn(move || block_on(storage.put_records(put)))
+        };
+        let single = block_on(storage.put_records(puts[2].clone()));
+        let pair = writer.join().unwrap();
+        assert!(single.is_ok() != pair.is_ok());
+
+        let expected = if single.is_ok() { 7 } else { 8 };
+        assert_eq!(
+            block_on(storage.max_offset()).unwrap(),
+            Some(Offset(expected - 1))
+        );
+        let (read, _) = block_on(storage.get_records(Offset(0), 10)).unwrap();
+        assert_eq!(read.len(), expected);
+        assert_whole_puts(&read, &puts);
+    });
+}
+
+#[test]
+fn truncation_races_readers_and_writers() {
+    loom::model(|| {
+        let storage = InMemoryLockFree::new();
+        let first = records("writer-0", 2);
+        let second = records("writer-1", 1);
+        block_on(storage.put_records(first.clone())).unwrap();
+
+        let writer = {
+            let storage = storage.clone();
+            let put = second.clone();
+            thread::spawn(move || block_on(storage.put_records(put)).unwrap())
+        };
+        let truncater = {
+            let storage = storage.clone();
+            thread::spawn(move || block_on(storage.truncate_after(Some(Offset(0)))).unwrap())
+        };
+
+        // Either side of the truncation, but never a mix of the two
+        let (read, next_offset) = block_on(storage.get_records(Offset(0), 10)).unwrap();
+        assert_eq!(next_offset, Offset(read.len()));
+        let kept = if read.starts_with(&first) { 2 } else { 1 };
+        assert_eq!(read[..kept], first[..kept]);
+        assert!(read[kept..].is_empty() || read[kept..] == second);
+
+        let written = writer.join().unwrap();
+        truncater.join().unwrap();
+
+        // The put either lands after the truncation or is cut by it
+        let (read, _) = block_on(storage.get_records(Offset(0), 10)).unwrap();
+        if written.start == Offset(1) {
+            assert_eq!(read, [first[0].clone(), second[0].clone()]);
+        } else {
+            assert_eq!(written, Offset(2)..Offset(3));
+            assert_eq!(read, first[..1]);
+        }
+    });
+}
+
+#[test]
+fn retention_races_readers() {
+    loom::model(|| {
+        let storage = InMemoryLockFree::new();
+        let puts = [records("writer-0", 1), records("writer-1", 1)];
+        for put in &puts {
+            block_on(storage.put_records(put.clone())).unwrap();
+        }
+
+        let remover = {
+            let storage = storage.clone();
+            thread::spawn(move || {
+                let policy = RetentionPolicy {
+                    max_records: Some(1),
+                    ..RetentionPolicy::default()
+                };
+                block_on(storage.enforce_retention(&policy)).unwrap()
+            })
+        };
+
+        // The removed put is read whole or not at all, while its buffer is
+        // freed once no reader is left looking at it
+        match block_on(storage.get_records(Offset(0), 10)) {
+            Ok((read, _)) => assert_eq!(read, puts.concat()),
+            Err(StorageError::OffsetOutOfRange { .. }) => {}
+            Err(e) => panic!("Unexpected error: {e}"),
+        }
+        let (read, _) = block_on(storage.get_records(Offset(1), 10)).unwrap();
+        assert_eq!(read, puts[1]);
+
+        assert_eq!(remover.join().unwrap(), Offset(1));
+    });
+}
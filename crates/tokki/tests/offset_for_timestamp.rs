@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use tokki::{
+    storage::{DiskStorage, DiskStorageConfig, InMemoryStorage, Storage},
+    timestamp_type::TimestampType,
+};
+use tokki_common::{Offset, Record, Timestamp};
+
+/// Timestamps that mostly increase but with stragglers from slow producers
+const TIMESTAMPS: [u64; 12] = [100, 110, 105, 120, 130, 125, 90, 140, 150, 150, 145, 160];
+
+async fn write(storage: &dyn Storage) {
+    let records = TIMESTAMPS
+        .iter()
+        .map(|ts| Record::new("key", ts.to_string()).with_timestamp(Timestamp(*ts)))
+        .collect();
+    storage.put_records(records).await.unwrap();
+}
+
+/// The first offset in the log with a timestamp at or after `timestamp`
+fn expected(timestamp: u64) -> Option<Offset> {
+    TIMESTAMPS
+        .iter()
+        .position(|ts| *ts >= timestamp)
+        .map(Offset)
+}
+
+async fn assert_lookups(storage: &dyn Storage) {
+    for timestamp in 0..=170 {
+        assert_eq!(
+            storage
+                .offset_for_timestamp(Timestamp(timestamp))
+                .await
+                .unwrap(),
+            expected(timestamp),
+            "timestamp {timestamp}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn in_memory_finds_first_offset_at_or_after_timestamp() {
+    let storage = Arc::new(InMemoryStorage::default());
+    write(storage.as_ref()).await;
+    assert_lookups(storage.as_ref()).await;
+}
+
+#[tokio::test]
+async fn disk_finds_first_offset_at_or_after_timestamp() {
+    let data_dir = tempfile::tempdir().unwrap();
+    // A few records per segment, with most of them indexed
+    let config = DiskStorageConfig::new(data_dir.path())
+        .with_segment_bytes(150)
+        .with_index_interval_bytes(40);
+
+    let storage = DiskStorage::open(config.clone()).unwrap();
+    write(&storage).await;
+    assert_lookups(&storage).await;
+    drop(storage);
+
+    // Sealed segments rely on the time index written to disk, the active one is rebuilt
+    let storage = DiskStorage::open(config).unwrap();
+    assert_lookups(&storage).await;
+}
+
+#[test]
+fn create_time_keeps_producer_timestamps() {
+    let mut records = vec![
+        Record::new("a", "1").with_timestamp(Timestamp(5)),
+        Record::new("b", "2"),
+    ];
+
+    TimestampType::CreateTime.apply(&mut records, Timestamp(10));
+    assert_eq!(records[0].timestamp(), Timestamp(5));
+    assert_eq!(records[1].timestamp(), Timestamp(10));
+
+    TimestampType::LogAppendTime.apply(&mut records, Timestamp(20));
+    assert!(records.iter().all(|r| r.timestamp() == Timestamp(20)));
+    // Restamping keeps the stored checksum in step
+    assert_eq!(
+        records[0],
+        Record::new("a", "1").with_timestamp(Timestamp(20))
+    );
+}
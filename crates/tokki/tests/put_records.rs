@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use tokki::storage::{
+    DiskStorage, DiskStorageConfig, InMemoryChannelStorage, InMemoryStorage, Storage,
+};
+use tokki_common::{Offset, Record};
+
+const WRITERS: usize = 8;
+const BATCHES: usize = 20;
+const BATCH_LEN: usize = 5;
+
+/// Concurrent batches must each get a contiguous run of offsets holding exactly their records
+async fn assert_batches_are_contiguous(storage: Arc<dyn Storage>) {
+    let writers = (0..WRITERS).map(|writer| {
+        let storage = storage.clone();
+        tokio::spawn(async move {
+            let mut written = Vec::new();
+            for batch in 0..BATCHES {
+                let records: Vec<_> = (0..BATCH_LEN)
+                    .map(|i| Record::new(format!("{writer}-{batch}"), i.to_string()))
+                    .collect();
+                let offsets = storage.put_records(records.clone()).await.unwrap();
+                assert_eq!((offsets.end - offsets.start).0, BATCH_LEN);
+                written.push((offsets.start, records));
+            }
+            written
+        })
+    });
+
+    for writer in writers {
+        for (start, records) in writer.await.unwrap() {
+            let (read, _) = storage.get_records(start, BATCH_LEN).await.unwrap();
+            assert_eq!(read, records);
+        }
+    }
+
+    let total = WRITERS * BATCHES * BATCH_LEN;
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(total - 1)));
+
+    let empty = storage.put_records(Vec::new()).await.unwrap();
+    assert_eq!(empty, Offset(total)..Offset(total));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn in_memory_batches_are_contiguous() {
+    assert_batches_are_contiguous(Arc::new(InMemoryStorage::default())).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn in_memory_channel_batches_are_contiguous() {
+    assert_batches_are_contiguous(Arc::new(InMemoryChannelStorage::new().await.unwrap())).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn disk_batches_are_contiguous() {
+    let data_dir = tempfile::tempdir().unwrap();
+    // Small segments so batches straddle segment boundaries
+    let config = DiskStorageConfig::new(data_dir.path()).with_segment_bytes(256);
+    assert_batches_are_contiguous(Arc::new(DiskStorage::open(config).unwrap())).await;
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use std::fs;
+
+use tokki::storage::{
+    CorruptRecordPolicy, DiskStorage, InMemoryLockFree, InMemoryStorage, Keyring, RetentionPolicy,
+    Storage, StorageError,
+};
+use tokki_common::{Compression, Offset, Record, RecordBatch};
+
+use crate::common::{decode, disk_config, records};
+
+/// Records with a header, so their frames have a headers section
+fn traced_records(prefix: &str, len: usize) -> Vec<Record> {
+    records(prefix, len)
+        .into_iter()
+        .map(|record| record.with_header("trace", "1"))
+        .collect()
+}
+
+/// Frames must hold the same records `get_records` returns, from any offset
+async fn assert_frames_match_records(storage: &dyn Storage) {
+    let written = [
+        traced_records("single", 3),
+        traced_records("batch", 10),
+        traced_records("after", 2),
+    ]
+    .concat();
+    storage.put_records(written[..3].to_vec()).await.unwrap();
+    storage
+        .put_batch(RecordBatch::new(&written[3..13], Compression::Lz4).unwrap())
+        .await
+        .unwrap();
+    storage.put_records(written[13..].to_vec()).await.unwrap();
+
+    let (frames, next_offset) = storage.get_frames(Offset(0), 100).await.unwrap();
+    assert_eq!(decode(&frames, next_offset), written);
+    assert_eq!(
+        next_offset,
+        storage.get_records(Offset(0), 100).await.unwrap().1
+    );
+
+    // Starting in the middle of a batch only returns the rest of it
+    let (frames, next_offset) = storage.get_frames(Offset(5), 100).await.unwrap();
+    assert_eq!(decode(&frames, next_offset), written[5..]);
+
+    for offset in 0..written.len() {
+        let (read, _) = storage.get_records(Offset(offset), 1).await.unwrap();
+        let (frames, next_offset) = storage.get_frames(Offset(offset), 1).await.unwrap();
+        assert_eq!(decode(&frames, next_offset)[..1], read);
+    }
+
+    let (frames, _) = storage
+        .get_frames(Offset(written.len()), 100)
+        .await
+        .unwrap();
+    assert!(frames.is_empty());
+}
+
+#[tokio::test]
+async fn disk_frames_match_records() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(disk_config(&data_dir)).unwrap();
+    assert_frames_match_records(&storage).await;
+
+    // Across many segments, each with an index entry for every frame
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = disk_config(&data_dir)
+        .with_segment_bytes(128)
+        .with_index_interval_bytes(1);
+    let storage = DiskStorage::open(config).unwrap();
+    assert_frames_match_records(&storage).await;
+}
+
+#[tokio::test]
+async fn in_memory_frames_match_records() {
+    assert_frames_match_records(&InMemoryStorage::default()).await;
+}
+
+#[tokio::test]
+async fn lockfree_frames_match_records() {
+    assert_frames_match_records(&InMemoryLockFree::new()).await;
+}
+
+#[tokio::test]
+async fn lockfree_serves_each_put_as_one_chunk() {
+    let storage = InMemoryLockFree::new();
+    storage
+        .put_records(traced_records("first", 5))
+        .await
+        .unwrap();
+    storage
+        .put_records(traced_records("second", 5))
+        .await
+        .unwrap();
+
+    let (frames, next_offset) = storage.get_frames(Offset(2), 100).await.unwrap();
+    assert_eq!(frames.chunks().len(), 2);
+    assert_eq!(frames.record_count(), 8);
+    assert_eq!(next_offset, Offset(10));
+
+    let (read, _) = storage.get_records(Offset(2), 100).await.unwrap();
+    assert_eq!(decode(&frames, next_offset), read);
+}
+
+#[tokio::test]
+async fn disk_serves_frames_as_stored() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = DiskStorage::open(disk_config(&data_dir)).unwrap();
+    let written = traced_records("record", 10);
+    storage.put_records(written[..5].to_vec()).await.unwrap();
+    let batch = RecordBatch::new(&written[5..], Compression::Zstd).unwrap();
+    storage.put_batch(batch.clone()).await.unwrap();
+
+    // Frames next to each other in the log are served as a single slice of it
+    let (frames, _) = storage.get_frames(Offset(0), 100).await.unwrap();
+    assert_eq!(frames.chunks().len(), 1);
+    assert_eq!(frames.record_count(), written.len());
+
+    let log = fs::read(data_dir.path().join(format!("{:020}.log", 0))).unwrap();
+    assert_eq!(frames.chunks()[0], log);
+
+    // The batch is served still compressed
+    let mut frame = vec![0; batch.serialized_len()];
+    batch.to_bytes(&mut frame).unwrap();
+    let (frames, _) = storage.get_frames(Offset(5), 1).await.unwrap();
+    assert_eq!(frames.chunks(), [frame]);
+}
+
+#[tokio::test]
+async fn disk_frames_outlive_deleted_segments() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = disk_config(&data_dir).with_segment_bytes(1);
+    let storage = DiskStorage::open(config).unwrap();
+    let written = traced_records("record", 3);
+    for record in &written {
+        storage.put_record(record.clone()).await.unwrap();
+    }
+
+    let (frames, next_offset) = storage.get_frames(Offset(0), 2).await.unwrap();
+
+    let retention = RetentionPolicy {
+        max_records: Some(1),
+        ..RetentionPolicy::default()
+    };
+    assert_eq!(
+        storage.enforce_retention(&retention).await.unwrap(),
+        Offset(2)
+    );
+
+    assert_eq!(decode(&frames, next_offset), written[..2]);
+    assert!(matches!(
+        storage.get_frames(Offset(0), 2).await,
+        Err(StorageError::OffsetOutOfRange { .. })
+    ));
+}
+
+#[tokio::test]
+async fn disk_decrypts_frames() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let keyring = Keyring::new([(1, [7; 32])]).unwrap();
+    let config = disk_config(&data_dir).with_keyring(keyring);
+    let storage = DiskStorage::open(config).unwrap();
+    let written = traced_records("secret", 4);
+    storage.put_records(written.clone()).await.unwrap();
+
+    let (frames, next_offset) = storage.get_frames(Offset(1), 100).await.unwrap();
+    assert_eq!(decode(&frames, next_offset), written[1..]);
+}
+
+#[tokio::test]
+async fn disk_checks_frames_before_serving_them() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let written = traced_records("record", 3);
+    let record_len = written[0].serialized_len();
+    // The records fill the first segment, sealed by the next write
+    let config = |corrupt_records| {
+        disk_config(&data_dir)
+            .with_segment_bytes(3 * record_len as u64)
+            .with_corrupt_records(corrupt_records)
+    };
+
+    let storage = DiskStorage::open(config(CorruptRecordPolicy::Fail)).unwrap();
+    storage.put_records(written.clone()).await.unwrap();
+    storage.put_record(Record::new("next", "1")).await.unwrap();
+    drop(storage);
+
+    // Flip a byte of the middle record's value
+    let path = data_dir.path().join(format!("{:020}.log", 0));
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[2 * record_len - 18] ^= 0xff;
+    fs::write(&path, bytes).unwrap();
+
+    let storage = DiskStorage::open(config(CorruptRecordPolicy::Fail)).unwrap();
+    assert!(matches!(
+        storage.get_frames(Offset(0), 100).await,
+        Err(StorageError::CorruptRecord {
+            offset: Offset(1),
+            ..
+        })
+    ));
+    drop(storage);
+
+    let storage = DiskStorage::open(config(CorruptRecordPolicy::Skip)).unwrap();
+    let (frames, next_offset) = storage.get_frames(Offset(0), 100).await.unwrap();
+    assert_eq!(
+        decode(&frames, next_offset),
+        [
+            written[0].clone(),
+            written[2].clone(),
+            Record::new("next", "1")
+        ]
+    );
+    assert_eq!(next_offset, Offset(4));
+}
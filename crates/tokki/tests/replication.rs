@@ -0,0 +1,284 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::oneshot, time::Instant};
+use tokki::{
+    replication::{InSyncChange, InSyncConfig, Replication},
+    topics::{Topic, TopicStorage, Topics, TopicsConfig},
+};
+use tokki_api::{cluster::ClusterMembers, topics::TopicConfig};
+use tokki_common::{Offset, Record};
+
+async fn topic(required_replicas: usize) -> Arc<Topic> {
+    let config =
+        TopicsConfig::new(TopicStorage::InMemoryMutex).with_required_replicas(required_replicas);
+    let topics = Topics::open(config).await.unwrap();
+    topics.create("events", TopicConfig::new()).await.unwrap()
+}
+
+#[test]
+fn high_watermark_follows_the_required_replicas() {
+    let mut replication = Replication::new(2, Offset(0));
+    replication.set_members(ClusterMembers::new(["a", "b", "c"]));
+    replication.update_log_end(Offset(10));
+    assert_eq!(replication.high_watermark(), Offset(0));
+
+    let (wake_tx, mut wake_rx) = oneshot::channel();
+    replication.register_wait(Offset(5), wake_tx);
+
+    // One follower is not enough
+    replication.update_follower_max_offset("a".to_string(), Some(Offset(7)));
+    assert_eq!(replication.high_watermark(), Offset(0));
+    assert!(wake_rx.try_recv().is_err());
+
+    // The second furthest follower decides
+    replication.update_follower_max_offset("b".to_string(), Some(Offset(4)));
+    assert_eq!(replication.high_watermark(), Offset(5));
+    assert!(wake_rx.try_recv().is_err());
+    replication.update_follower_max_offset("c".to_string(), Some(Offset(20)));
+    assert_eq!(replication.high_watermark(), Offset(8));
+    assert!(wake_rx.try_recv().is_ok());
+
+    // Never past what the leader has, and never back
+    replication.update_follower_max_offset("a".to_string(), Some(Offset(30)));
+    assert_eq!(replication.high_watermark(), Offset(10));
+    replication.update_follower_max_offset("a".to_string(), Some(Offset(0)));
+    replication.update_follower_max_offset("c".to_string(), Some(Offset(0)));
+    assert_eq!(replication.high_watermark(), Offset(10));
+
+    // Waits on committed records return straight away
+    let (wake_tx, mut wake_rx) = oneshot::channel();
+    replication.register_wait(Offset(9), wake_tx);
+    assert!(wake_rx.try_recv().is_ok());
+}
+
+#[test]
+fn only_members_are_counted() {
+    let mut replication = Replication::new(1, Offset(10));
+    replication.set_members(ClusterMembers::new(["a"]));
+
+    // Learners copy the log without being waited for
+    replication.update_follower_max_offset("learner".to_string(), Some(Offset(9)));
+    assert_eq!(replication.high_watermark(), Offset(0));
+    replication.update_follower_max_offset("a".to_string(), Some(Offset(3)));
+    assert_eq!(replication.high_watermark(), Offset(4));
+
+    // Forgotten followers are no longer counted once members change
+    replication.forget_follower("a");
+    replication.set_members(ClusterMembers::new(["learner"]));
+    assert_eq!(replication.high_watermark(), Offset(10));
+}
+
+#[test]
+fn member_changes_need_copies_among_old_and_new_members() {
+    let mut replication = Replication::new(1, Offset(10));
+    replication.set_members(ClusterMembers::new(["a"]));
+    replication.update_follower_max_offset("a".to_string(), Some(Offset(3)));
+    assert_eq!(replication.high_watermark(), Offset(4));
+
+    // Replacing a with b
+    let mut members = ClusterMembers::new(["a"]);
+    members.joining = Some(["b".to_string()].into());
+    replication.set_members(members);
+    assert!(!replication.joining_caught_up());
+
+    // The old members alone no longer commit anything
+    replication.update_follower_max_offset("a".to_string(), Some(Offset(7)));
+    assert_eq!(replication.high_watermark(), Offset(4));
+
+    // Until b catches up, the old members' copies are still required
+    replication.update_follower_max_offset("b".to_string(), Some(Offset(2)));
+    assert_eq!(replication.high_watermark(), Offset(4));
+    assert!(!replication.joining_caught_up());
+    replication.update_follower_max_offset("b".to_string(), Some(Offset(9)));
+    assert_eq!(replication.high_watermark(), Offset(8));
+    assert!(replication.joining_caught_up());
+
+    replication.set_members(ClusterMembers::new(["b"]));
+    assert_eq!(replication.high_watermark(), Offset(10));
+}
+
+#[tokio::test(start_paused = true)]
+async fn members_that_stop_catching_up_leave_the_in_sync_replicas() {
+    let in_sync = InSyncConfig::new()
+        .with_max_lag(Duration::from_secs(1))
+        .with_min_insync_replicas(1);
+    let mut replication = Replication::new(2, Offset(10)).with_in_sync(in_sync);
+    replication.set_members(ClusterMembers::new(["a", "b"]));
+    replication.update_follower_max_offset("a".to_string(), Some(Offset(9)));
+    replication.update_follower_max_offset("b".to_string(), Some(Offset(3)));
+    assert_eq!(replication.high_watermark(), Offset(4));
+
+    // Only b has not caught up since a second ago
+    tokio::time::advance(Duration::from_secs(2)).await;
+    replication.update_follower_max_offset("a".to_string(), Some(Offset(9)));
+    assert_eq!(
+        replication.check_in_sync(Instant::now()),
+        [InSyncChange::TimedOut {
+            follower: "b".to_string()
+        }]
+    );
+    assert_eq!(replication.in_sync().collect::<Vec<_>>(), ["a"]);
+
+    // Acknowledged by the member left in sync
+    assert_eq!(replication.high_watermark(), Offset(10));
+    assert!(replication.enough_in_sync());
+
+    // Back in once it has the committed records
+    let changes = replication.update_follower_max_offset("b".to_string(), Some(Offset(7)));
+    assert!(changes.is_empty());
+    let changes = replication.update_follower_max_offset("b".to_string(), Some(Offset(9)));
+    assert_eq!(
+        changes,
+        [InSyncChange::Joined {
+            follower: "b".to_string()
+        }]
+    );
+    assert!(replication.check_in_sync(Instant::now()).is_empty());
+}
+
+#[test]
+fn members_too_far_behind_leave_the_in_sync_replicas() {
+    let in_sync = InSyncConfig::new().with_max_lag_records(5);
+    let mut replication = Replication::new(1, Offset(10)).with_in_sync(in_sync);
+    replication.set_members(ClusterMembers::new(["a", "b"]));
+
+    assert_eq!(
+        replication.update_follower_max_offset("a".to_string(), Some(Offset(1))),
+        [InSyncChange::Lagged {
+            follower: "a".to_string(),
+            records: 8,
+        }]
+    );
+    assert_eq!(replication.in_sync().collect::<Vec<_>>(), ["b"]);
+    assert_eq!(replication.high_watermark(), Offset(0));
+
+    // Close enough again
+    assert_eq!(
+        replication.update_follower_max_offset("a".to_string(), Some(Offset(5))),
+        [InSyncChange::Joined {
+            follower: "a".to_string()
+        }]
+    );
+    assert_eq!(replication.high_watermark(), Offset(6));
+}
+
+#[tokio::test(start_paused = true)]
+async fn puts_fail_once_too_few_members_are_in_sync() {
+    let in_sync = InSyncConfig::new().with_max_lag(Duration::from_secs(1));
+    let mut replication = Replication::new(1, Offset(0)).with_in_sync(in_sync);
+    replication.set_members(ClusterMembers::new(["a"]));
+    assert!(replication.enough_in_sync());
+    assert_eq!(replication.min_insync_replicas(), 1);
+
+    replication.update_log_end(Offset(1));
+    let (wake_tx, mut wake_rx) = oneshot::channel();
+    replication.register_wait(Offset(0), wake_tx);
+
+    tokio::time::advance(Duration::from_secs(2)).await;
+    replication.check_in_sync(Instant::now());
+    assert!(!replication.enough_in_sync());
+    assert_eq!(
+        wake_rx.try_recv(),
+        Err(oneshot::error::TryRecvError::Closed)
+    );
+    assert_eq!(replication.high_watermark(), Offset(0));
+}
+
+#[test]
+fn without_followers_everything_durable_is_committed() {
+    let mut replication = Replication::new(0, Offset(3));
+    assert_eq!(replication.high_watermark(), Offset(3));
+    replication.update_log_end(Offset(7));
+    assert_eq!(replication.high_watermark(), Offset(7));
+}
+
+#[tokio::test]
+async fn committed_reads_stop_at_the_high_watermark() {
+    let topic = topic(1).await;
+    let partition = topic.partition(0).unwrap();
+    let records: Vec<_> = (0..10u32)
+        .map(|i| Record::new("key", i.to_be_bytes()))
+        .collect();
+    partition.storage().put_records(records).await.unwrap();
+
+    // Nothing is committed until the follower has the records
+    let (read, next_offset) = partition
+        .get_committed_records(Offset(0), 100)
+        .await
+        .unwrap();
+    assert!(read.is_empty());
+    assert_eq!(next_offset, Offset(0));
+
+    {
+        let mut replication = partition.replication().lock().unwrap();
+        replication.set_members(ClusterMembers::new(["follower"]));
+        replication.update_log_end(Offset(10));
+        replication.update_follower_max_offset("follower".to_string(), Some(Offset(3)));
+    }
+    assert_eq!(partition.high_watermark(), Offset(4));
+
+    let (read, next_offset) = partition
+        .get_committed_records(Offset(0), 100)
+        .await
+        .unwrap();
+    assert_eq!(read.len(), 4);
+    assert_eq!(next_offset, Offset(4));
+    let (read, next_offset) = partition
+        .get_committed_records(Offset(4), 100)
+        .await
+        .unwrap();
+    assert!(read.is_empty());
+    assert_eq!(next_offset, Offset(4));
+
+    let (frames, next_offset) = partition
+        .get_committed_frames(Offset(2), 100)
+        .await
+        .unwrap();
+    assert_eq!(next_offset, Offset(4));
+    assert_eq!(frames.record_count(), 2);
+
+    // Uncommitted records are still there for anyone asking for them
+    let (read, _) = partition
+        .storage()
+        .get_records(Offset(0), 100)
+        .await
+        .unwrap();
+    assert_eq!(read.len(), 10);
+}
+
+#[tokio::test]
+async fn restarting_moves_the_high_watermark_to_the_new_log_start() {
+    let topic = topic(1).await;
+    let partition = topic.partition(0).unwrap();
+    let records = vec![Record::new("key", "value"); 5];
+    partition.storage().put_records(records).await.unwrap();
+    partition
+        .replication()
+        .lock()
+        .unwrap()
+        .set_high_watermark(Offset(3));
+
+    partition.restart_at(Offset(100)).await.unwrap();
+    assert_eq!(partition.high_watermark(), Offset(100));
+    let (read, next_offset) = partition
+        .get_committed_records(Offset(100), 10)
+        .await
+        .unwrap();
+    assert!(read.is_empty());
+    assert_eq!(next_offset, Offset(100));
+
+    // Records copied after the restart are committed once the leader says so
+    let records = vec![Record::new("key", "value"); 2];
+    partition.storage().put_records(records).await.unwrap();
+    partition
+        .replication()
+        .lock()
+        .unwrap()
+        .set_high_watermark(Offset(101));
+    let (read, next_offset) = partition
+        .get_committed_records(Offset(100), 10)
+        .await
+        .unwrap();
+    assert_eq!(read.len(), 1);
+    assert_eq!(next_offset, Offset(101));
+}
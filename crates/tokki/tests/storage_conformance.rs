@@ -0,0 +1,441 @@
+//! Behaviour every `Storage` engine must share. The checks are generic over
+//! the engine, `conformance_suite!` runs all of them against one.
+
+mod common;
+
+use std::{future::Future, sync::Arc};
+
+use proptest::{collection::vec, prelude::*, test_runner::TestCaseError};
+use tempfile::TempDir;
+use tokki::storage::{
+    DiskStorage, InMemoryChannelStorage, InMemoryLockFree, InMemoryStorage, RetentionPolicy,
+    Storage, StorageError,
+};
+use tokki_api::get_records::GetRecordsResponse;
+use tokki_common::{Compression, Offset, Record, RecordBatch};
+
+use crate::common::{disk_config, records};
+
+const WRITERS: usize = 8;
+const BATCHES: usize = 20;
+const BATCH_LEN: usize = 5;
+
+/// An engine under test, along with anything it needs kept alive
+struct Engine {
+    storage: Arc<dyn Storage>,
+    _data_dir: Option<TempDir>,
+}
+
+impl Engine {
+    fn new(storage: impl Storage + 'static) -> Self {
+        Self {
+            storage: Arc::new(storage),
+            _data_dir: None,
+        }
+    }
+}
+
+async fn in_memory() -> Engine {
+    Engine::new(InMemoryStorage::default())
+}
+
+async fn in_memory_channel() -> Engine {
+    Engine::new(InMemoryChannelStorage::new().await.unwrap())
+}
+
+async fn in_memory_lockfree() -> Engine {
+    Engine::new(InMemoryLockFree::new())
+}
+
+async fn disk() -> Engine {
+    let data_dir = tempfile::tempdir().unwrap();
+    // Small segments so reads and writes straddle segment boundaries
+    let config = disk_config(&data_dir).with_segment_bytes(256);
+    Engine {
+        storage: Arc::new(DiskStorage::open(config).unwrap()),
+        _data_dir: Some(data_dir),
+    }
+}
+
+async fn assert_empty_log(storage: &dyn Storage) {
+    assert_eq!(storage.max_offset().await.unwrap(), None);
+    assert_eq!(storage.log_start_offset().await.unwrap(), Offset(0));
+
+    for offset in [0, 5] {
+        let (read, next_offset) = storage.get_records(Offset(offset), 10).await.unwrap();
+        assert!(read.is_empty());
+        assert_eq!(next_offset, Offset(offset));
+
+        let (frames, next_offset) = storage.get_frames(Offset(offset), 10).await.unwrap();
+        assert!(frames.is_empty());
+        assert_eq!(next_offset, Offset(offset));
+    }
+
+    let empty = storage.put_records(Vec::new()).await.unwrap();
+    assert_eq!(empty, Offset(0)..Offset(0));
+    assert_eq!(storage.max_offset().await.unwrap(), None);
+}
+
+/// Every write is given the offsets straight after the previous one
+async fn assert_offsets_are_monotonic(storage: &dyn Storage) {
+    let mut next = Offset(0);
+    for (i, len) in [1, 3, 0, 7, 1, 2].into_iter().enumerate() {
+        let written = records(&format!("put-{i}"), len);
+        let offsets = if len == 1 {
+            let offset = storage.put_record(written[0].clone()).await.unwrap();
+            offset..offset + 1
+        } else {
+            storage.put_records(written).await.unwrap()
+        };
+
+        assert_eq!(offsets, next..next + len);
+        next = offsets.end;
+        let max_offset = (next.0 > 0).then(|| next - 1);
+        assert_eq!(storage.max_offset().await.unwrap(), max_offset);
+    }
+}
+
+/// `next_offset` is the offset straight after the last record returned, or
+/// the offset read from when there was nothing to return
+async fn assert_next_offset(storage: &dyn Storage) {
+    let written = records("record", 10);
+    storage.put_records(written[..4].to_vec()).await.unwrap();
+    storage
+        .put_batch(RecordBatch::new(&written[4..], Compression::Lz4).unwrap())
+        .await
+        .unwrap();
+
+    for offset in 0..=written.len() + 2 {
+        for max_records in [0, 1, 3, written.len() + 5] {
+            let (read, next_offset) = storage
+                .get_records(Offset(offset), max_records)
+                .await
+                .unwrap();
+            let end = written.len().min(offset.saturating_add(max_records));
+            let expected = written.get(offset..end).unwrap_or_default();
+            assert_eq!(read, expected, "reading {max_records} from {offset}");
+            assert_eq!(next_offset, Offset(offset + read.len()));
+        }
+    }
+
+    // Following `next_offset` reads everything once
+    let mut read = Vec::new();
+    let mut offset = Offset(0);
+    loop {
+        let (page, next_offset) = storage.get_records(offset, 3).await.unwrap();
+        if page.is_empty() {
+            assert_eq!(next_offset, offset);
+            break;
+        }
+        read.extend(page);
+        offset = next_offset;
+    }
+    assert_eq!(read, written);
+
+    let (frames, next_offset) = storage.get_frames(Offset(2), 100).await.unwrap();
+    let response = GetRecordsResponse::from_frames(&frames.chunks().concat(), next_offset).unwrap();
+    assert_eq!(response.records(), &written[2..]);
+    assert_eq!(next_offset, Offset(written.len()));
+}
+
+/// Truncation drops every record after the offset, even part way through a
+/// batch, and the next write carries on from there
+async fn assert_truncate_after(storage: &dyn Storage) {
+    let written = records("record", 10);
+    storage.put_records(written[..4].to_vec()).await.unwrap();
+    storage
+        .put_batch(RecordBatch::new(&written[4..], Compression::Lz4).unwrap())
+        .await
+        .unwrap();
+
+    // Past the end there is nothing to drop
+    storage.truncate_after(Some(Offset(20))).await.unwrap();
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(9)));
+
+    storage.truncate_after(Some(Offset(6))).await.unwrap();
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(6)));
+
+    let (read, next_offset) = storage.get_records(Offset(0), 100).await.unwrap();
+    assert_eq!(read, written[..7]);
+    assert_eq!(next_offset, Offset(7));
+
+    let (frames, next_offset) = storage.get_frames(Offset(2), 100).await.unwrap();
+    let response = GetRecordsResponse::from_frames(&frames.chunks().concat(), next_offset).unwrap();
+    assert_eq!(response.records(), &written[2..7]);
+    assert_eq!(next_offset, Offset(7));
+
+    let replacements = records("replacement", 3);
+    let offsets = storage.put_records(replacements.clone()).await.unwrap();
+    assert_eq!(offsets, Offset(7)..Offset(10));
+    let (read, _) = storage.get_records(Offset(5), 100).await.unwrap();
+    assert_eq!(read[..2], written[5..7]);
+    assert_eq!(read[2..], replacements);
+
+    // Nothing left at all
+    storage.truncate_after(None).await.unwrap();
+    assert_eq!(storage.max_offset().await.unwrap(), None);
+    let (read, next_offset) = storage.get_records(Offset(0), 100).await.unwrap();
+    assert!(read.is_empty());
+    assert_eq!(next_offset, Offset(0));
+    let offset = storage.put_record(written[0].clone()).await.unwrap();
+    assert_eq!(offset, Offset(0));
+}
+
+/// Retention removes records from the start of the log, which reads from
+/// before it then report. Engines remove records in whole puts or segments,
+/// so only the limits of what goes are checked.
+async fn assert_retention(storage: &dyn Storage) {
+    let written = records("record", 10);
+    for record in written.clone() {
+        storage.put_record(record).await.unwrap();
+    }
+
+    let policy = RetentionPolicy {
+        max_records: Some(3),
+        ..RetentionPolicy::default()
+    };
+    let log_start = storage.enforce_retention(&policy).await.unwrap();
+    assert!(
+        (Offset(1)..=Offset(7)).contains(&log_start),
+        "Log starts at {log_start:?}"
+    );
+    assert_eq!(storage.log_start_offset().await.unwrap(), log_start);
+
+    let (read, _) = storage.get_records(log_start, 100).await.unwrap();
+    assert_eq!(read, written[log_start.0..]);
+    for offset in [Offset(0), log_start - 1] {
+        let read = storage.get_records(offset, 100).await;
+        assert!(matches!(
+            read,
+            Err(StorageError::OffsetOutOfRange { log_start_offset, .. }) if log_start_offset == log_start
+        ));
+        let read = storage.get_frames(offset, 100).await;
+        assert!(matches!(read, Err(StorageError::OffsetOutOfRange { .. })));
+    }
+
+    // Truncation never reaches back past the log start
+    storage.truncate_after(Some(Offset(0))).await.unwrap();
+    assert_eq!(storage.max_offset().await.unwrap(), Some(log_start - 1));
+    let offset = storage.put_record(written[0].clone()).await.unwrap();
+    assert_eq!(offset, log_start);
+}
+
+/// Restarting drops the whole log and carries on from the given offset,
+/// which becomes the log start
+async fn assert_restart_at(storage: &dyn Storage) {
+    let written = records("record", 3);
+    storage.put_records(written.clone()).await.unwrap();
+
+    storage.restart_at(Offset(100)).await.unwrap();
+    assert_eq!(storage.log_start_offset().await.unwrap(), Offset(100));
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(99)));
+    assert!(matches!(
+        storage.get_records(Offset(0), 100).await,
+        Err(StorageError::OffsetOutOfRange { .. })
+    ));
+    let (read, next_offset) = storage.get_records(Offset(100), 100).await.unwrap();
+    assert!(read.is_empty());
+    assert_eq!(next_offset, Offset(100));
+
+    let offsets = storage.put_records(written.clone()).await.unwrap();
+    assert_eq!(offsets, Offset(100)..Offset(103));
+    let batch = RecordBatch::new(&written, Compression::Lz4).unwrap();
+    let offsets = storage.put_batch_at(Offset(103), batch).await.unwrap();
+    assert_eq!(offsets, Offset(103)..Offset(106));
+    let (read, _) = storage.get_records(Offset(100), 100).await.unwrap();
+    assert_eq!(read, [written.clone(), written].concat());
+}
+
+/// Concurrent batches must each get a contiguous run of offsets holding
+/// exactly their records, and readers must only ever see whole batches
+async fn assert_concurrent_appends(storage: Arc<dyn Storage>) {
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let mut written = Vec::new();
+                for batch in 0..BATCHES {
+                    let records = records(&format!("{writer}-{batch}"), BATCH_LEN);
+                    let offsets = storage.put_records(records.clone()).await.unwrap();
+                    assert_eq!((offsets.end - offsets.start).0, BATCH_LEN);
+                    written.push((offsets.start, records));
+                }
+                written
+            })
+        })
+        .collect();
+
+    let total = WRITERS * BATCHES * BATCH_LEN;
+    let reader = {
+        let storage = storage.clone();
+        tokio::spawn(async move {
+            loop {
+                let (read, next_offset) = storage.get_records(Offset(0), total).await.unwrap();
+                assert_eq!(next_offset, Offset(read.len()));
+                assert_eq!(read.len() % BATCH_LEN, 0);
+                for batch in read.chunks(BATCH_LEN) {
+                    let key = std::str::from_utf8(batch[0].key()).unwrap();
+                    let prefix = key.strip_suffix("-0").unwrap();
+                    assert_eq!(batch, records(prefix, BATCH_LEN));
+                }
+                if read.len() == total {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+    };
+
+    for writer in writers {
+        for (start, records) in writer.await.unwrap() {
+            let (read, _) = storage.get_records(start, BATCH_LEN).await.unwrap();
+            assert_eq!(read, records);
+        }
+    }
+    reader.await.unwrap();
+
+    assert_eq!(storage.max_offset().await.unwrap(), Some(Offset(total - 1)));
+
+    let empty = storage.put_records(Vec::new()).await.unwrap();
+    assert_eq!(empty, Offset(total)..Offset(total));
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Put(usize),
+    PutBatch(usize, Compression),
+    Read { offset: usize, max_records: usize },
+    Truncate(Option<usize>),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let compression = prop_oneof![
+        Just(Compression::None),
+        Just(Compression::Lz4),
+        Just(Compression::Zstd),
+    ];
+    prop_oneof![
+        (0..6usize).prop_map(Op::Put),
+        (1..6usize, compression).prop_map(|(len, compression)| Op::PutBatch(len, compression)),
+        (0..40usize, 0..12usize).prop_map(|(offset, max_records)| Op::Read {
+            offset,
+            max_records
+        }),
+        proptest::option::of(0..40usize).prop_map(Op::Truncate),
+    ]
+}
+
+/// Run `ops` against the engine and against a plain list of records, which
+/// must agree throughout
+async fn assert_matches_model(storage: &dyn Storage, ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let mut model: Vec<Record> = Vec::new();
+
+    for (i, op) in ops.into_iter().enumerate() {
+        match op {
+            Op::Put(len) => {
+                let written = records(&format!("put-{i}"), len);
+                let offsets = storage.put_records(written.clone()).await.unwrap();
+                prop_assert_eq!(offsets, Offset(model.len())..Offset(model.len() + len));
+                model.extend(written);
+            }
+            Op::PutBatch(len, compression) => {
+                let written = records(&format!("batch-{i}"), len);
+                let batch = RecordBatch::new(&written, compression).unwrap();
+                let offsets = storage.put_batch(batch).await.unwrap();
+                prop_assert_eq!(offsets, Offset(model.len())..Offset(model.len() + len));
+                model.extend(written);
+            }
+            Op::Read {
+                offset,
+                max_records,
+            } => {
+                let (read, next_offset) = storage
+                    .get_records(Offset(offset), max_records)
+                    .await
+                    .unwrap();
+                let end = model.len().min(offset + max_records);
+                let expected = model.get(offset..end).unwrap_or_default();
+                prop_assert_eq!(&read[..], expected);
+                prop_assert_eq!(next_offset, Offset(offset + read.len()));
+            }
+            Op::Truncate(offset) => {
+                storage.truncate_after(offset.map(Offset)).await.unwrap();
+                model.truncate(offset.map_or(0, |offset| offset + 1));
+            }
+        }
+
+        let max_offset = model.len().checked_sub(1).map(Offset);
+        prop_assert_eq!(storage.max_offset().await.unwrap(), max_offset);
+    }
+
+    Ok(())
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// Run every conformance check against the engine built by `$engine`
+macro_rules! conformance_suite {
+    ($name:ident, $engine:expr) => {
+        mod $name {
+            use super::*;
+
+            #[tokio::test]
+            async fn empty_log() {
+                assert_empty_log(&*$engine.await.storage).await;
+            }
+
+            #[tokio::test]
+            async fn offsets_are_monotonic() {
+                assert_offsets_are_monotonic(&*$engine.await.storage).await;
+            }
+
+            #[tokio::test]
+            async fn next_offset() {
+                assert_next_offset(&*$engine.await.storage).await;
+            }
+
+            #[tokio::test]
+            async fn truncate_after() {
+                assert_truncate_after(&*$engine.await.storage).await;
+            }
+
+            #[tokio::test]
+            async fn retention() {
+                assert_retention(&*$engine.await.storage).await;
+            }
+
+            #[tokio::test]
+            async fn restart_at() {
+                assert_restart_at(&*$engine.await.storage).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread")]
+            async fn concurrent_appends() {
+                assert_concurrent_appends($engine.await.storage).await;
+            }
+
+            proptest! {
+                #![proptest_config(ProptestConfig::with_cases(64))]
+
+                #[test]
+                fn matches_model(ops in vec(op(), 1..40)) {
+                    block_on(async {
+                        let engine = $engine.await;
+                        assert_matches_model(&*engine.storage, ops).await
+                    })?;
+                }
+            }
+        }
+    };
+}
+
+conformance_suite!(in_memory_storage, in_memory());
+conformance_suite!(in_memory_channel_storage, in_memory_channel());
+conformance_suite!(in_memory_lockfree_storage, in_memory_lockfree());
+conformance_suite!(disk_storage, disk());
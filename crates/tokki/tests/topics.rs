@@ -0,0 +1,328 @@
+mod common;
+
+use std::fs;
+
+use tokki::{
+    storage::DiskStorageConfig,
+    topics::{TopicError, TopicStorage, Topics, TopicsConfig},
+};
+use tokki_api::topics::{TopicConfig, TopicDescription};
+use tokki_common::{Offset, Record};
+
+use crate::common::eventually;
+
+fn disk_config(data_dir: &tempfile::TempDir) -> TopicsConfig {
+    let storage = DiskStorageConfig::new(data_dir.path().join("topics"));
+    TopicsConfig::new(TopicStorage::Disk(storage)).with_metadata_dir(data_dir.path())
+}
+
+#[tokio::test]
+async fn topics_keep_separate_logs() {
+    let topics = Topics::open(TopicsConfig::new(TopicStorage::InMemoryMutex))
+        .await
+        .unwrap();
+    let orders = topics.create("orders", TopicConfig::new()).await.unwrap();
+    let payments = topics.create("payments", TopicConfig::new()).await.unwrap();
+
+    orders
+        .partition(0)
+        .unwrap()
+        .storage()
+        .put_records(vec![Record::new("order", "1"), Record::new("order", "2")])
+        .await
+        .unwrap();
+    let offsets = payments
+        .partition(0)
+        .unwrap()
+        .storage()
+        .put_records(vec![Record::new("payment", "1")])
+        .await
+        .unwrap();
+    assert_eq!(offsets, Offset(0)..Offset(1));
+
+    let (read, _) = topics
+        .get("orders")
+        .unwrap()
+        .partition(0)
+        .unwrap()
+        .storage()
+        .get_records(Offset(0), 10)
+        .await
+        .unwrap();
+    assert_eq!(read.len(), 2);
+
+    let names: Vec<_> = topics.list().into_iter().map(|topic| topic.name).collect();
+    assert_eq!(names, ["orders", "payments"]);
+}
+
+#[tokio::test]
+async fn create_and_delete_fail_clearly() {
+    let topics = Topics::open(TopicsConfig::new(TopicStorage::InMemoryMutex))
+        .await
+        .unwrap();
+    topics.create("events", TopicConfig::new()).await.unwrap();
+
+    assert!(matches!(
+        topics.create("events", TopicConfig::new()).await,
+        Err(TopicError::AlreadyExists { .. })
+    ));
+    for name in ["", ".", "..", "a/b", "spaced out", &"x".repeat(250)] {
+        assert!(
+            matches!(
+                topics.create(name, TopicConfig::new()).await,
+                Err(TopicError::InvalidName { .. })
+            ),
+            "{name:?}"
+        );
+    }
+
+    topics.delete("events").await.unwrap();
+    assert!(matches!(
+        topics.get("events"),
+        Err(TopicError::NotFound { .. })
+    ));
+    assert!(matches!(
+        topics.delete("events").await,
+        Err(TopicError::NotFound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn unset_settings_come_from_the_defaults() {
+    let defaults = TopicConfig::new()
+        .with_retention_max_records(100)
+        .with_compaction(true);
+    let config = TopicsConfig::new(TopicStorage::InMemoryMutex).with_default_config(defaults);
+    let topics = Topics::open(config).await.unwrap();
+
+    let topic = topics
+        .create("events", TopicConfig::new().with_retention_max_records(5))
+        .await
+        .unwrap();
+    assert_eq!(
+        topic.description().config,
+        TopicConfig::new()
+            .with_retention_max_records(5)
+            .with_compaction(true)
+    );
+}
+
+#[tokio::test]
+async fn topics_survive_a_restart() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = TopicConfig::new().with_retention_max_age_secs(3600);
+
+    let topics = Topics::open(disk_config(&data_dir)).await.unwrap();
+    let topic = topics.create("events", config.clone()).await.unwrap();
+    topic
+        .partition(0)
+        .unwrap()
+        .storage()
+        .put_record(Record::new("key", "value"))
+        .await
+        .unwrap();
+    let description = topic.description().clone();
+    topics.create("gone", TopicConfig::new()).await.unwrap();
+    topics.delete("gone").await.unwrap();
+    drop((topic, topics));
+
+    assert!(!data_dir.path().join("topics").join("gone").exists());
+
+    let topics = Topics::open(disk_config(&data_dir)).await.unwrap();
+    assert_eq!(topics.list(), [description]);
+    let (read, _) = topics
+        .get("events")
+        .unwrap()
+        .partition(0)
+        .unwrap()
+        .storage()
+        .get_records(Offset(0), 10)
+        .await
+        .unwrap();
+    assert_eq!(read, [Record::new("key", "value")]);
+}
+
+#[tokio::test]
+async fn ensure_replaces_a_topic_created_separately() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics = Topics::open(disk_config(&data_dir)).await.unwrap();
+    let topic = topics.create("events", TopicConfig::new()).await.unwrap();
+    topic
+        .partition(0)
+        .unwrap()
+        .storage()
+        .put_record(Record::new("key", "value"))
+        .await
+        .unwrap();
+
+    // The same topic is left alone
+    let same = topics.ensure(topic.description().clone()).await.unwrap();
+    assert_eq!(
+        same.partition(0)
+            .unwrap()
+            .storage()
+            .max_offset()
+            .await
+            .unwrap(),
+        Some(Offset(0))
+    );
+
+    // One recreated under the same name starts out empty
+    let recreated = TopicDescription::new("events", topic.description().id + 1, TopicConfig::new());
+    let replaced = topics.ensure(recreated.clone()).await.unwrap();
+    assert_eq!(
+        replaced
+            .partition(0)
+            .unwrap()
+            .storage()
+            .max_offset()
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(topics.list(), std::slice::from_ref(&recreated));
+
+    let metadata = fs::read(data_dir.path().join("topics.json")).unwrap();
+    let persisted: Vec<TopicDescription> = serde_json::from_slice(&metadata).unwrap();
+    assert_eq!(persisted, [recreated]);
+}
+
+#[tokio::test]
+async fn records_with_the_same_key_share_a_partition() {
+    let topics = Topics::open(TopicsConfig::new(TopicStorage::InMemoryMutex))
+        .await
+        .unwrap();
+    let topic = topics
+        .create("events", TopicConfig::new().with_partitions(4))
+        .await
+        .unwrap();
+    assert_eq!(topic.partitions().len(), 4);
+
+    let records: Vec<_> = (0..200u32)
+        .map(|i| Record::new(format!("key-{}", i % 20), i.to_be_bytes()))
+        .collect();
+    let routed = topic.route(records, None).unwrap();
+    assert!(routed.len() > 1, "20 keys all hashed to one partition");
+
+    let mut partition_of_key = std::collections::HashMap::new();
+    let mut previous_partition = None;
+    for (partition, records) in routed {
+        assert!(previous_partition < Some(partition.index()));
+        previous_partition = Some(partition.index());
+
+        let mut previous_value = None;
+        for record in &records {
+            assert_eq!(topic.partition_for(record), partition.index());
+            let first = partition_of_key.insert(record.key().to_vec(), partition.index());
+            assert!(first.is_none_or(|first| first == partition.index()));
+
+            // Records on a partition keep the order they came in
+            assert!(previous_value < Some(record.value().to_vec()));
+            previous_value = Some(record.value().to_vec());
+        }
+        partition.storage().put_records(records).await.unwrap();
+    }
+    assert_eq!(partition_of_key.len(), 20);
+
+    let explicit = topic
+        .route(vec![Record::new("key-1", "explicit")], Some(3))
+        .unwrap();
+    assert_eq!(explicit[0].0.index(), 3);
+    assert!(matches!(
+        topic.route(vec![Record::new("key-1", "missing")], Some(4)),
+        Err(TopicError::PartitionNotFound { partition: 4, .. })
+    ));
+}
+
+#[tokio::test]
+async fn partition_counts_are_checked() {
+    let topics = Topics::open(TopicsConfig::new(TopicStorage::InMemoryMutex))
+        .await
+        .unwrap();
+    for partitions in [0, 1025] {
+        assert!(matches!(
+            topics
+                .create("events", TopicConfig::new().with_partitions(partitions))
+                .await,
+            Err(TopicError::InvalidPartitionCount { .. })
+        ));
+    }
+
+    // Unset counts come from the defaults, and fall back to a single partition
+    let topic = topics.create("single", TopicConfig::new()).await.unwrap();
+    assert_eq!(topic.partitions().len(), 1);
+    assert!(matches!(
+        topic.partition(1),
+        Err(TopicError::PartitionNotFound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn partitions_keep_separate_logs_on_disk() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics = Topics::open(disk_config(&data_dir)).await.unwrap();
+    let topic = topics
+        .create("events", TopicConfig::new().with_partitions(3))
+        .await
+        .unwrap();
+    for partition in topic.partitions() {
+        let records = vec![Record::new("key", "value"); partition.index() as usize + 1];
+        partition.storage().put_records(records).await.unwrap();
+    }
+    drop((topic, topics));
+
+    for partition in 0..3 {
+        let dir = data_dir
+            .path()
+            .join("topics/events")
+            .join(partition.to_string());
+        assert!(dir.is_dir(), "{}", dir.display());
+    }
+
+    let topics = Topics::open(disk_config(&data_dir)).await.unwrap();
+    let topic = topics.get("events").unwrap();
+    for partition in topic.partitions() {
+        let max_offset = partition.storage().max_offset().await.unwrap();
+        assert_eq!(max_offset, Some(Offset(partition.index() as usize)));
+    }
+}
+
+#[tokio::test]
+async fn deleted_topics_stay_readable_while_in_use() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let topics_dir = data_dir.path().join("topics");
+    let topics = Topics::open(disk_config(&data_dir)).await.unwrap();
+    let in_use = topics.create("events", TopicConfig::new()).await.unwrap();
+    let storage = in_use.partition(0).unwrap().storage().clone();
+    storage
+        .put_record(Record::new("key", "value"))
+        .await
+        .unwrap();
+
+    // A request still holding the topic reads on, while one created under
+    // the same name starts out empty
+    topics.delete("events").await.unwrap();
+    let recreated = topics.create("events", TopicConfig::new()).await.unwrap();
+    let partition = recreated.partition(0).unwrap();
+    assert_eq!(partition.storage().max_offset().await.unwrap(), None);
+    let (read, _) = storage.get_records(Offset(0), 10).await.unwrap();
+    assert_eq!(read, [Record::new("key", "value")]);
+
+    // The old data goes once the last request is done with it
+    let deleted = || {
+        fs::read_dir(&topics_dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name() != "events")
+            .count()
+    };
+    assert_eq!(deleted(), 1);
+    drop((storage, in_use));
+    eventually(|| async { (deleted() == 0).then_some(()) }).await;
+
+    // Along with anything left behind by a crash
+    let left_behind = topics_dir.join("old~1.deleted");
+    fs::create_dir_all(left_behind.join("0")).unwrap();
+    drop((recreated, topics));
+    Topics::open(disk_config(&data_dir)).await.unwrap();
+    assert!(!left_behind.exists());
+}
@@ -0,0 +1,13 @@
+#!/usr/bin/env bash
+# Model check the lock-free storage engine with loom. Setting RUSTFLAGS
+# overrides the target features in .cargo/config.toml, so they are passed
+# again here. Extra arguments go to `cargo test`, such as a test name.
+set -euo pipefail
+
+case "$(uname -m)" in
+    arm64 | aarch64) features="+neon" ;;
+    *) features="+aes,+sse2" ;;
+esac
+
+RUSTFLAGS="--cfg tokki_loom -C target-feature=$features" \
+    cargo test -p tokki --test lockfree_loom --release --target-dir target/loom "$@"